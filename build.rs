@@ -0,0 +1,15 @@
+// Puts the git commit into `squealogd --version`, "unknown" when not building from a checkout.
+fn main() {
+    let hash = std::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok());
+    println!(
+        "cargo:rustc-env=SQUEALOG_GIT_HASH={}",
+        hash.as_deref().map_or("unknown", str::trim)
+    );
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
@@ -0,0 +1,98 @@
+//! Runs a program, storing what it writes to stdout and stderr into squealogd's database as
+//! messages from the `supervise` socket:
+//!
+//! ```text
+//! cargo run --example supervise -- /var/log/log.db my-worker --verbose
+//! ```
+//!
+//! Lines are stored with the program's name as the appname and its pid, stdout ones at info
+//! and stderr ones at err, and are flushed whenever it's been quiet for a second.
+
+use squealog::ingest::Ingestor;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+use syslog_loose::{Message, ProcId, Protocol, SyslogFacility, SyslogSeverity};
+
+fn lines(
+    from: impl Read + Send + 'static,
+    severity: SyslogSeverity,
+    tx: mpsc::Sender<(SyslogSeverity, String)>,
+) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(from).lines() {
+            match line {
+                Ok(line) => {
+                    if tx.send((severity, line)).is_err() {
+                        return;
+                    }
+                }
+                Err(_) => return,
+            }
+        }
+    });
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args_os().skip(1);
+    let (db, program) = match (args.next(), args.next()) {
+        (Some(db), Some(program)) => (db, program),
+        _ => anyhow::bail!("usage: supervise DB PROGRAM [ARGS...]"),
+    };
+    let mut ingestor = Ingestor::open(db)?;
+    let mut child = Command::new(&program)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let appname = std::path::Path::new(&program)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let pid = child.id() as i32;
+
+    let (tx, rx) = mpsc::channel();
+    lines(
+        child.stdout.take().unwrap(),
+        SyslogSeverity::SEV_INFO,
+        tx.clone(),
+    );
+    lines(child.stderr.take().unwrap(), SyslogSeverity::SEV_ERR, tx);
+    loop {
+        match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok((severity, line)) => {
+                let msg = Message {
+                    protocol: Protocol::RFC3164,
+                    facility: Some(SyslogFacility::LOG_DAEMON),
+                    severity: Some(severity),
+                    timestamp: None,
+                    hostname: None,
+                    appname: Some(appname.as_str()),
+                    procid: Some(ProcId::PID(pid)),
+                    msgid: None,
+                    structured_data: vec![],
+                    msg: line.as_str(),
+                };
+                // Busy for longer than the timeout: the lines stay queued for the next try.
+                if let Err(e) = ingestor.ingest_message("supervise", msg) {
+                    eprintln!("supervise: {}", e);
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if let Err(e) = ingestor.flush() {
+                    eprintln!("supervise: {}", e);
+                }
+            }
+            // Both pipes were closed, it's exiting.
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    let status = child.wait()?;
+    ingestor.flush()?;
+    if ingestor.dropped() > 0 {
+        eprintln!("supervise: {} lines dropped", ingestor.dropped());
+    }
+    std::process::exit(status.code().unwrap_or(1));
+}
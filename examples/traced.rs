@@ -0,0 +1,39 @@
+//! A pretend service logging with `tracing` into squealog, to see what its events turn into:
+//!
+//! ```text
+//! cargo run --features tracing --example traced -- /var/run/log
+//! squealog -t traced -S -1m -o logfmt
+//! ```
+//!
+//! With a path ending in `.db`, it stores into that database itself instead of sending to the
+//! daemon's socket.
+
+use squealog::subscriber::Layer;
+use tracing::{info, info_span, warn};
+use tracing_subscriber::prelude::*;
+
+fn handle(request: u32) {
+    let span = info_span!("request", id = request, path = "/things");
+    let _entered = span.enter();
+    info!(status = 200, bytes = 512, "handled");
+    if request % 3 == 0 {
+        warn!(elapsed_ms = 1500, "slow request");
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let to = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "/var/run/log".to_owned());
+    let layer = if to.ends_with(".db") {
+        Layer::ingestor(squealog::ingest::Ingestor::open(&to)?)
+    } else {
+        Layer::socket(&to)?
+    };
+    tracing_subscriber::registry().with(layer).init();
+    info!(version = env!("CARGO_PKG_VERSION"), "starting");
+    for request in 1..=5 {
+        handle(request);
+    }
+    Ok(())
+}
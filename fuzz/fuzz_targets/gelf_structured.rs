@@ -0,0 +1,89 @@
+//! GELF objects with the fields it looks at, plain or compressed.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use serde_json::{json, Map, Value};
+use squealog::payload::{Gelf, PayloadParser, SourceCtx};
+use std::io::Write;
+
+#[derive(Arbitrary, Debug)]
+enum Field {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Null,
+}
+
+impl Field {
+    fn value(self) -> Value {
+        match self {
+            Field::Str(s) => Value::String(s),
+            Field::Int(n) => json!(n),
+            Field::Float(f) => json!(f),
+            Field::Bool(b) => json!(b),
+            Field::Null => Value::Null,
+        }
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+enum Compression {
+    None,
+    Gzip,
+    Zlib,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Object {
+    short_message: Option<Field>,
+    full_message: Option<Field>,
+    host: Option<Field>,
+    timestamp: Option<Field>,
+    level: Option<Field>,
+    facility: Option<Field>,
+    additional: Vec<(String, Field)>,
+    compression: Compression,
+}
+
+fuzz_target!(|obj: Object| {
+    let mut fields = Map::new();
+    for (name, field) in [
+        ("short_message", obj.short_message),
+        ("full_message", obj.full_message),
+        ("host", obj.host),
+        ("timestamp", obj.timestamp),
+        ("level", obj.level),
+        ("facility", obj.facility),
+    ] {
+        if let Some(field) = field {
+            fields.insert(name.to_owned(), field.value());
+        }
+    }
+    for (name, field) in obj.additional {
+        fields.insert(name, field.value());
+    }
+    let json = serde_json::to_vec(&fields).unwrap();
+    let bytes = match obj.compression {
+        Compression::None => json,
+        Compression::Gzip => {
+            let mut e = flate2::write::GzEncoder::new(vec![], flate2::Compression::fast());
+            e.write_all(&json).unwrap();
+            e.finish().unwrap()
+        }
+        Compression::Zlib => {
+            let mut e = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::fast());
+            e.write_all(&json).unwrap();
+            e.finish().unwrap()
+        }
+    };
+    let ctx = SourceCtx {
+        socket: "fuzz",
+        local: true,
+        boot_time: chrono::Utc::now(),
+        received: chrono::Utc::now(),
+    };
+    let _ = Gelf.parse(&bytes, &ctx);
+});
@@ -0,0 +1,59 @@
+//! JSON objects with the keys the `json` parser gives a meaning to.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use serde_json::{json, Map, Value};
+use squealog::payload::{Json, PayloadParser, SourceCtx};
+
+#[derive(Arbitrary, Debug)]
+enum Key {
+    Severity,
+    Facility,
+    App,
+    Msgid,
+    Msg,
+    Other(String),
+}
+
+#[derive(Arbitrary, Debug)]
+enum Field {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Null,
+    List(Vec<String>),
+}
+
+fuzz_target!(|fields: Vec<(Key, Field)>| {
+    let mut object = Map::new();
+    for (key, field) in fields {
+        let key = match key {
+            Key::Severity => "severity".to_owned(),
+            Key::Facility => "facility".to_owned(),
+            Key::App => "app".to_owned(),
+            Key::Msgid => "msgid".to_owned(),
+            Key::Msg => "msg".to_owned(),
+            Key::Other(k) => k,
+        };
+        let value = match field {
+            Field::Str(s) => Value::String(s),
+            Field::Int(n) => json!(n),
+            Field::Float(f) => json!(f),
+            Field::Bool(b) => json!(b),
+            Field::Null => Value::Null,
+            Field::List(l) => json!(l),
+        };
+        object.insert(key, value);
+    }
+    let bytes = serde_json::to_vec(&object).unwrap();
+    let ctx = SourceCtx {
+        socket: "fuzz",
+        local: true,
+        boot_time: chrono::Utc::now(),
+        received: chrono::Utc::now(),
+    };
+    let _ = Json.parse(&bytes, &ctx);
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|line: &str| {
+    let boot = chrono::Utc::now();
+    let msg = squealog::klog::parse_line(line, &boot);
+    assert!(line.contains(msg.msg));
+});
@@ -0,0 +1,30 @@
+//! Lines that get past the `<PRI>` and `[seconds]` parsers, with whatever numbers in them.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+struct Line<'a> {
+    pri: Option<u64>,
+    seconds: Option<u64>,
+    /// Makes it the largest number with that many digits, for ones past any integer type.
+    nines: Option<u8>,
+    rest: &'a str,
+}
+
+fuzz_target!(|line: Line| {
+    let mut text = String::new();
+    if let Some(pri) = line.pri {
+        text += &format!("<{}>", pri);
+    }
+    match (line.seconds, line.nines) {
+        (_, Some(n)) => text += &format!("[{}]", "9".repeat(n as usize)),
+        (Some(s), None) => text += &format!("[{}]", s),
+        (None, None) => {}
+    }
+    text += line.rest;
+    let boot = chrono::Utc::now();
+    squealog::klog::parse_line(&text, &boot);
+});
@@ -0,0 +1,22 @@
+//! Every built-in payload parser, on the same bytes.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use squealog::payload::{self, Registry, SourceCtx};
+
+fuzz_target!(|bytes: &[u8]| {
+    let registry = Registry::default();
+    let ctx = SourceCtx {
+        socket: "fuzz",
+        local: true,
+        boot_time: chrono::Utc::now(),
+        received: chrono::Utc::now(),
+    };
+    let text = String::from_utf8_lossy(bytes);
+    for name in registry.names() {
+        let parser = registry.get(name).unwrap();
+        let _ = parser.parse(bytes, &ctx);
+        payload::parse_or_keep(&*parser, bytes, &text, &ctx);
+    }
+});
@@ -0,0 +1,784 @@
+//! A synthetic load for a running squealogd, to see whether a change makes it faster:
+//!
+//! ```text
+//! squealog-bench --unix /var/run/log --db /var/log/log.db --count 1000000 --rate 50000
+//! ```
+//!
+//! Messages of the sizes in `--sizes` (a weighted list), a mix of RFC 3164 and RFC 5424 and
+//! `--appnames` different appnames are sent over a unix datagram socket or UDP, `--burst` at a
+//! time, at `--rate` a second overall (or as fast as they go). Each one carries the run, its
+//! number and the time it was sent. With `--db`, the rows are then looked up once the daemon
+//! stops storing more of them, for the insert rate, the latency from sending to `recv_time`
+//! and how many never made it. The results are one JSON object on stdout, to compare runs.
+//!
+//! `squealog-bench import --lines 1000000` writes that many such lines to a file instead and
+//! times `squealog import` storing them in a new database.
+//!
+//! `squealog-bench ingest --count 200000` runs squealogd in this process instead, on a socket
+//! and database of its own, and sends it the messages as fast as it takes them. Allocations
+//! are counted on every thread but the one sending, for the allocations per message stored.
+//! `--sources 500` has it listen on that many sockets, which are sent to in turn, and
+//! `--squealogd PATH` runs that binary instead (like one built from an earlier commit, to
+//! compare with), where allocations can't be counted. With `--no-wait`, messages the sockets
+//! have no room for are counted and left out, like a program that doesn't wait would lose them.
+//!
+//! `squealog-bench lookup --sources 500` times finding a source by its poller key in the slab
+//! the daemon keeps them in, and in a `Vec` searched from the front like it was before.
+
+use chrono::prelude::*;
+use clap::Parser;
+use rusqlite::{Connection, OpenFlags};
+use serde_json::json;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Send synthetic syslog traffic to squealogd and measure how it's stored
+#[derive(Parser)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Args {
+    #[clap(subcommand)]
+    mode: Option<Mode>,
+    #[clap(flatten)]
+    load: Load,
+}
+
+#[derive(clap::Subcommand)]
+enum Mode {
+    /// Time `squealog import` of a generated file
+    Import(Import),
+    /// Run squealogd in this process and count what storing messages costs it
+    Ingest(Ingest),
+    /// Time looking sources up by their poller key
+    Lookup(Lookup),
+}
+
+#[derive(clap::Args)]
+struct Load {
+    /// Unix datagram socket to send to
+    #[clap(long, required_unless_present = "udp", conflicts_with = "udp")]
+    unix: Option<PathBuf>,
+    /// UDP address:port to send to
+    #[clap(long)]
+    udp: Option<String>,
+    /// The daemon's database, to measure what was stored
+    #[clap(long)]
+    db: Option<PathBuf>,
+    /// Messages to send
+    #[clap(long, default_value = "100000")]
+    count: u64,
+    /// Messages a second, 0 for as fast as they go
+    #[clap(long, default_value = "0")]
+    rate: u64,
+    /// Messages sent back to back before pausing for the rate
+    #[clap(long, default_value = "1")]
+    burst: u64,
+    #[clap(flatten)]
+    shape: Shape,
+    /// How long to wait for more rows once none came, in seconds
+    #[clap(long, default_value = "5")]
+    settle: u64,
+}
+
+#[derive(clap::Args)]
+struct Import {
+    /// Lines in the file
+    #[clap(long, default_value = "1000000")]
+    lines: u64,
+    /// The squealog to run [default: the one next to squealog-bench]
+    #[clap(long)]
+    squealog: Option<PathBuf>,
+    #[clap(flatten)]
+    shape: Shape,
+}
+
+#[derive(clap::Args)]
+struct Ingest {
+    /// Messages to send
+    #[clap(long, default_value = "200000")]
+    count: u64,
+    /// Unix sockets for the daemon to listen on, sent to in turn
+    #[clap(long, default_value = "1")]
+    sources: usize,
+    /// Run this squealogd instead of the one built in
+    #[clap(long)]
+    squealogd: Option<PathBuf>,
+    /// Don't wait while a socket is full, count what it refused instead
+    #[clap(long)]
+    no_wait: bool,
+    #[clap(flatten)]
+    shape: Shape,
+    /// How long to wait for more rows once none came, in seconds
+    #[clap(long, default_value = "5")]
+    settle: u64,
+    /// More flags for squealogd, after `--`
+    #[clap(last = true)]
+    daemon_args: Vec<OsString>,
+}
+
+#[derive(clap::Args)]
+struct Lookup {
+    /// Sources registered
+    #[clap(long, default_value = "500")]
+    sources: usize,
+    /// Lookups for each way of looking up
+    #[clap(long, default_value = "10000000")]
+    lookups: u64,
+    /// Seed for the keys looked up [default: the time]
+    #[clap(long)]
+    seed: Option<u64>,
+}
+
+/// What the messages look like.
+#[derive(clap::Args)]
+struct Shape {
+    /// Message sizes in bytes with their weights
+    #[clap(
+        long,
+        default_value = "100:80,500:15,2000:5",
+        value_name = "SIZE:WEIGHT,..."
+    )]
+    sizes: Sizes,
+    /// Percentage of messages in RFC 5424 format, the rest are RFC 3164
+    #[clap(long, default_value = "50", value_name = "PERCENT")]
+    rfc5424: u64,
+    /// Number of different appnames
+    #[clap(long, default_value = "20")]
+    appnames: u64,
+    /// Seed for the generator [default: the time]
+    #[clap(long)]
+    seed: Option<u64>,
+}
+
+struct Sizes(Vec<(usize, u64)>);
+
+impl FromStr for Sizes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sizes = s
+            .split(',')
+            .map(|part| {
+                let (size, weight) = part.split_once(':').unwrap_or((part, "1"));
+                Some((size.trim().parse().ok()?, weight.trim().parse().ok()?))
+            })
+            .collect::<Option<Vec<(usize, u64)>>>()
+            .filter(|sizes| sizes.iter().any(|&(_, w)| w > 0))
+            .ok_or_else(|| format!("invalid sizes '{}'", s))?;
+        Ok(Sizes(sizes))
+    }
+}
+
+/// The system allocator, counting what every thread but the `uncounted` ones allocates.
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static UNCOUNTED: Cell<bool> = const { Cell::new(false) };
+}
+
+impl Counting {
+    fn count(size: usize) {
+        // Not while the thread is going away.
+        if UNCOUNTED.try_with(|u| !u.get()).unwrap_or(false) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED.fetch_add(size as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Leaves what this thread allocates out from now on.
+    fn uncounted() {
+        UNCOUNTED.with(|u| u.set(true));
+    }
+
+    /// The allocations and the bytes allocated so far.
+    fn so_far() -> (u64, u64) {
+        (
+            ALLOCATIONS.load(Ordering::Relaxed),
+            ALLOCATED.load(Ordering::Relaxed),
+        )
+    }
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        Counting::count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        Counting::count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        Counting::count(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// xorshift64*, plenty for picking sizes and formats.
+struct Random(u64);
+
+impl Random {
+    /// From `seed`, or the time.
+    fn seeded(seed: Option<u64>) -> Random {
+        let seed = seed.unwrap_or_else(|| {
+            let now = Utc::now();
+            now.timestamp_nanos_opt()
+                .unwrap_or_else(|| now.timestamp_micros()) as u64
+        });
+        Random(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+}
+
+enum Target {
+    Unix(UnixDatagram, PathBuf),
+    Udp(UdpSocket),
+}
+
+impl Target {
+    fn open(args: &Load) -> anyhow::Result<Target> {
+        Ok(match (&args.unix, &args.udp) {
+            (Some(path), _) => Target::Unix(UnixDatagram::unbound()?, path.clone()),
+            (None, Some(addr)) => {
+                let sock = UdpSocket::bind(if addr.starts_with('[') {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                })?;
+                sock.connect(addr)?;
+                Target::Udp(sock)
+            }
+            (None, None) => unreachable!("clap requires one"),
+        })
+    }
+
+    fn send(&self, line: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Target::Unix(sock, path) => sock.send_to(line, path),
+            Target::Udp(sock) => sock.send(line),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Target::Unix(..) => "unix",
+            Target::Udp(_) => "udp",
+        }
+    }
+}
+
+/// How every message starts, for finding them again: `sqb RUN SEQ SENT_MICROS`.
+const MARK: &str = "sqb";
+
+fn message(rng: &mut Random, shape: &Shape, run: &str, seq: u64) -> String {
+    let total: u64 = shape.sizes.0.iter().map(|&(_, w)| w).sum();
+    let mut pick = rng.below(total);
+    let size = shape
+        .sizes
+        .0
+        .iter()
+        .find(|&&(_, w)| {
+            let found = pick < w;
+            pick = pick.saturating_sub(w);
+            found
+        })
+        .map_or(0, |&(size, _)| size);
+    let now = Utc::now();
+    let app = rng.below(shape.appnames);
+    let pid = 1000 + app;
+    // local0.info
+    let mut line = if rng.below(100) < shape.rfc5424 {
+        format!(
+            "<134>1 {} bench-host app{} {} - - ",
+            now.to_rfc3339_opts(SecondsFormat::Micros, true),
+            app,
+            pid
+        )
+    } else {
+        format!(
+            "<134>{} bench-host app{}[{}]: ",
+            now.with_timezone(&Local).format("%b %e %T"),
+            app,
+            pid
+        )
+    };
+    line.push_str(&format!(
+        "{} {} {} {}",
+        MARK,
+        run,
+        seq,
+        now.timestamp_micros()
+    ));
+    let marked = line.len();
+    while line.len() < size {
+        line.push(' ');
+        line.push_str("lorem ipsum dolor sit amet");
+    }
+    line.truncate(size.max(marked));
+    line
+}
+
+struct Sent {
+    sent: u64,
+    failed: u64,
+    bytes: u64,
+    elapsed: Duration,
+}
+
+fn send(target: &Target, args: &Load, run: &str) -> Sent {
+    let mut rng = Random::seeded(args.shape.seed);
+    let (mut sent, mut failed, mut bytes) = (0, 0, 0);
+    let started = Instant::now();
+    let mut seq = 0;
+    while seq < args.count {
+        for _ in 0..args.burst.max(1).min(args.count - seq) {
+            let line = message(&mut rng, &args.shape, run, seq);
+            seq += 1;
+            match target.send(line.as_bytes()) {
+                Ok(n) => {
+                    sent += 1;
+                    bytes += n as u64;
+                }
+                // Like ENOBUFS or ECONNREFUSED after an ICMP error.
+                Err(_) => failed += 1,
+            }
+        }
+        if args.rate > 0 {
+            let due = started + Duration::from_secs_f64(seq as f64 / args.rate as f64);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+        }
+    }
+    Sent {
+        sent,
+        failed,
+        bytes,
+        elapsed: started.elapsed(),
+    }
+}
+
+fn max_id(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row("SELECT coalesce(max(id), 0) FROM log", [], |row| row.get(0))
+}
+
+/// Waits until no rows of the run came for `settle`, then returns when each was sent and
+/// received.
+fn stored(
+    conn: &Connection,
+    after: i64,
+    run: &str,
+    settle: Duration,
+) -> anyhow::Result<Vec<(i64, DateTime<Utc>)>> {
+    let pattern = format!("{} {} %", MARK, run);
+    let mut count = conn.prepare("SELECT count(*) FROM log WHERE id > ? AND msg LIKE ?")?;
+    let (mut last, mut changed) = (-1, Instant::now());
+    while changed.elapsed() < settle {
+        let n: i64 = count.query_row(rusqlite::params![after, pattern], |row| row.get(0))?;
+        if n != last {
+            last = n;
+            changed = Instant::now();
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    let mut rows = conn.prepare("SELECT msg, recv_time FROM log WHERE id > ? AND msg LIKE ?")?;
+    let found = rows
+        .query_map(rusqlite::params![after, pattern], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, DateTime<Utc>>(1)?))
+        })?
+        .filter_map(|row| {
+            let (msg, recv_time) = row.ok()?;
+            let sent = msg.split(' ').nth(3)?.parse().ok()?;
+            Some((sent, recv_time))
+        })
+        .collect();
+    Ok(found)
+}
+
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    match sorted.len() {
+        0 => 0.0,
+        n => sorted[((n - 1) as f64 * p).round() as usize],
+    }
+}
+
+fn run_id() -> String {
+    format!("{}.{}", std::process::id(), Utc::now().timestamp())
+}
+
+fn load(args: &Load) -> anyhow::Result<serde_json::Value> {
+    let run = run_id();
+    let conn = match args.db {
+        Some(ref db) => Some(Connection::open_with_flags(
+            db,
+            OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )?),
+        None => None,
+    };
+    let after = match conn {
+        Some(ref conn) => max_id(conn)?,
+        None => 0,
+    };
+    let target = Target::open(args)?;
+    let sent = send(&target, args, &run);
+    let secs = sent.elapsed.as_secs_f64().max(1e-9);
+    let mut result = json!({
+        "run": run,
+        "transport": target.name(),
+        "sent": sent.sent,
+        "send_failed": sent.failed,
+        "bytes": sent.bytes,
+        "send_seconds": secs,
+        "send_rate": sent.sent as f64 / secs,
+    });
+    if let Some(ref conn) = conn {
+        let found = stored(conn, after, &run, Duration::from_secs(args.settle))?;
+        let mut latencies: Vec<f64> = found
+            .iter()
+            .map(|&(sent, recv)| (recv.timestamp_micros() - sent) as f64 / 1000.0)
+            .collect();
+        latencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let first = found.iter().map(|&(_, recv)| recv).min();
+        let last = found.iter().map(|&(_, recv)| recv).max();
+        let span = match (first, last) {
+            (Some(first), Some(last)) => (last - first).num_microseconds().unwrap_or(0),
+            _ => 0,
+        };
+        result["stored"] = json!(found.len());
+        result["dropped"] = json!(sent.sent.saturating_sub(found.len() as u64));
+        result["insert_rate"] = json!(found.len() as f64 / (span as f64 / 1e6).max(1e-6));
+        result["latency_ms"] = json!({
+            "p50": percentile(&latencies, 0.5),
+            "p90": percentile(&latencies, 0.9),
+            "p99": percentile(&latencies, 0.99),
+            "max": percentile(&latencies, 1.0),
+        });
+    }
+    Ok(result)
+}
+
+/// Writes the lines to a file and imports it into a new database in WAL mode, as the daemon
+/// leaves it. The file is written first, so only the import is timed.
+fn import(args: &Import) -> anyhow::Result<serde_json::Value> {
+    let run = run_id();
+    let squealog = match args.squealog {
+        Some(ref path) => path.clone(),
+        None => std::env::current_exe()?.with_file_name("squealog"),
+    };
+    let dir = std::env::temp_dir().join(format!("squealog-bench.{}", run));
+    std::fs::create_dir_all(&dir)?;
+    let (file, db) = (dir.join("messages"), dir.join("log.db"));
+    let result = (|| {
+        let mut rng = Random::seeded(args.shape.seed);
+        let mut out = BufWriter::new(File::create(&file)?);
+        for seq in 0..args.lines {
+            writeln!(out, "{}", message(&mut rng, &args.shape, &run, seq))?;
+        }
+        out.into_inner()?.sync_all()?;
+        Connection::open(&db)?.pragma_update(None, "journal_mode", "WAL")?;
+
+        let started = Instant::now();
+        let output = Command::new(&squealog)
+            .arg("--db")
+            .arg(&db)
+            .arg("import")
+            .arg(&file)
+            .output()?;
+        let secs = started.elapsed().as_secs_f64().max(1e-9);
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} import: {}: {}",
+                squealog.display(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let rows: i64 =
+            Connection::open(&db)?.query_row("SELECT count(*) FROM log", [], |row| row.get(0))?;
+        Ok(json!({
+            "run": run,
+            "lines": args.lines,
+            "bytes": std::fs::metadata(&file)?.len(),
+            "rows": rows,
+            "import_seconds": secs,
+            "import_rate": rows as f64 / secs,
+        }))
+    })();
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+enum Daemon {
+    Thread(std::thread::JoinHandle<anyhow::Result<()>>),
+    Process(std::process::Child),
+}
+
+impl Daemon {
+    /// Starts squealogd, listening on the `sockets` and storing into `dir`/log.db, and returns
+    /// once they're all there.
+    fn start(dir: &std::path::Path, sockets: &[PathBuf], args: &Ingest) -> anyhow::Result<Daemon> {
+        let mut daemon_args = vec!["--db".into()];
+        daemon_args.push(dir.join("log.db").into());
+        let config = dir.join("squealogd.toml");
+        std::fs::write(&config, "")?;
+        daemon_args.push("--config".into());
+        daemon_args.push(config.into());
+        for (i, socket) in sockets.iter().enumerate() {
+            daemon_args.push(format!("--listen-unix=bench{}={}", i, socket.display()).into());
+        }
+        daemon_args.extend(["--no-klog".into(), "--keep-root".into()]);
+        daemon_args.extend(args.daemon_args.iter().cloned());
+        // Not meant for this one.
+        let vars = [
+            "SQUEALOG_HTTP",
+            "NOTIFY_SOCKET",
+            "LISTEN_FDS",
+            "LISTEN_FDNAMES",
+            "LISTEN_PID",
+        ];
+        let mut daemon = match args.squealogd {
+            Some(ref path) => {
+                let mut cmd = Command::new(path);
+                for var in vars {
+                    cmd.env_remove(var);
+                }
+                Daemon::Process(cmd.args(daemon_args).spawn()?)
+            }
+            None => {
+                for var in vars {
+                    std::env::remove_var(var);
+                }
+                daemon_args.insert(0, "squealogd".into());
+                let settings = squealog::squealogd::Settings::parse_from(daemon_args);
+                Daemon::Thread(
+                    std::thread::Builder::new()
+                        .name("squealogd".to_owned())
+                        .spawn(move || squealog::squealogd::run(settings))?,
+                )
+            }
+        };
+        let started = Instant::now();
+        while !sockets.iter().all(|socket| socket.exists()) {
+            if daemon.finished()? {
+                anyhow::bail!("squealogd stopped before binding its sockets");
+            }
+            if started.elapsed() > Duration::from_secs(10) {
+                daemon.stop()?;
+                anyhow::bail!("squealogd didn't bind its sockets within 10 seconds");
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        Ok(daemon)
+    }
+
+    fn finished(&mut self) -> anyhow::Result<bool> {
+        Ok(match self {
+            Daemon::Thread(thread) => thread.is_finished(),
+            Daemon::Process(child) => child.try_wait()?.is_some(),
+        })
+    }
+
+    /// Stops it like a kill(1) from outside would, and waits for it.
+    fn stop(self) -> anyhow::Result<()> {
+        match self {
+            Daemon::Thread(thread) => {
+                // The daemon's own handler takes it.
+                unsafe { libc::kill(std::process::id() as libc::pid_t, libc::SIGTERM) };
+                thread.join().expect("squealogd panicked")
+            }
+            Daemon::Process(mut child) => {
+                unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+                let status = child.wait()?;
+                anyhow::ensure!(status.success(), "squealogd: {}", status);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn ingest(args: &Ingest) -> anyhow::Result<serde_json::Value> {
+    Counting::uncounted();
+    let run = run_id();
+    let dir = std::env::temp_dir().join(format!("squealog-bench.{}", run));
+    std::fs::create_dir_all(&dir)?;
+    let sockets: Vec<PathBuf> = match args.sources {
+        0 | 1 => vec![dir.join("log")],
+        n => (0..n).map(|i| dir.join(format!("log.{}", i))).collect(),
+    };
+    let result = (|| {
+        let daemon = Daemon::start(&dir, &sockets, args)?;
+        let measured = (|| {
+            // Made up front, so that only the daemon has the CPU while it's measured.
+            let mut rng = Random::seeded(args.shape.seed);
+            let lines: Vec<String> = (0..args.count)
+                .map(|seq| message(&mut rng, &args.shape, &run, seq))
+                .collect();
+            let conn =
+                Connection::open_with_flags(dir.join("log.db"), OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+            let after = max_id(&conn)?;
+            let sender = UnixDatagram::unbound()?;
+            // Otherwise sending blocks while the socket is full, so none are lost on the way.
+            sender.set_nonblocking(args.no_wait)?;
+            let (allocations, allocated) = Counting::so_far();
+            let started = Instant::now();
+            let mut refused = 0;
+            for (line, socket) in lines.iter().zip(sockets.iter().cycle()) {
+                match sender.send_to(line.as_bytes(), socket) {
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => refused += 1,
+                    sent => {
+                        sent?;
+                    }
+                }
+            }
+            // Until there are as many rows as were sent (the daemon's own messages among
+            // them, but those are few) or none came for a while.
+            let settle = Duration::from_secs(args.settle);
+            let (mut last, mut changed) = (after, Instant::now());
+            loop {
+                let id = max_id(&conn)?;
+                if id - after >= (args.count - refused) as i64 {
+                    break;
+                }
+                if id != last {
+                    (last, changed) = (id, Instant::now());
+                } else if changed.elapsed() >= settle {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            let secs = started.elapsed().as_secs_f64().max(1e-9);
+            let (allocations, allocated) = {
+                let (n, bytes) = Counting::so_far();
+                (n - allocations, bytes - allocated)
+            };
+            let stored: i64 = conn.query_row(
+                "SELECT count(*) FROM log WHERE id > ? AND msg LIKE ?",
+                rusqlite::params![after, format!("{} {} %", MARK, run)],
+                |row| row.get(0),
+            )?;
+            // Another process's aren't seen here.
+            let per_message = |n: u64| match args.squealogd {
+                Some(_) => None,
+                None => Some(n as f64 / (stored.max(1) as f64)),
+            };
+            Ok(json!({
+                "run": run,
+                "sources": sockets.len(),
+                "sent": args.count - refused,
+                "refused": refused,
+                "stored": stored,
+                "seconds": secs,
+                "insert_rate": stored as f64 / secs,
+                "us_per_message": secs * 1e6 / stored.max(1) as f64,
+                "allocations_per_message": per_message(allocations),
+                "bytes_allocated_per_message": per_message(allocated),
+            }))
+        })();
+        daemon.stop()?;
+        measured
+    })();
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+/// Something the size of a source, which knows its key.
+struct Source {
+    key: usize,
+    seen: u64,
+    _rest: [u64; 16],
+}
+
+/// Times `lookups` lookups of the `keys`, in turn, with `find`, in nanoseconds per lookup.
+fn time_lookups(keys: &[usize], lookups: u64, mut find: impl FnMut(usize) -> bool) -> f64 {
+    let started = Instant::now();
+    for &key in keys.iter().cycle().take(lookups as usize) {
+        assert!(find(std::hint::black_box(key)));
+    }
+    started.elapsed().as_secs_f64() * 1e9 / lookups.max(1) as f64
+}
+
+fn lookup(args: &Lookup) -> serde_json::Value {
+    let mut slab = squealog::squealogd::slab::Slab::default();
+    let mut scanned = vec![];
+    for _ in 0..args.sources.max(1) {
+        let key = slab.insert_with(|key| Source {
+            key,
+            seen: 0,
+            _rest: [0; 16],
+        });
+        scanned.push(Source {
+            key,
+            seen: 0,
+            _rest: [0; 16],
+        });
+    }
+    let mut rng = Random::seeded(args.seed);
+    let keys: Vec<usize> = (0..1 << 16)
+        .map(|_| rng.below(args.sources.max(1) as u64) as usize)
+        .collect();
+    let slab_ns = time_lookups(&keys, args.lookups, |key| match slab.get_mut(key) {
+        Some(source) => {
+            source.seen += 1;
+            true
+        }
+        None => false,
+    });
+    let scan_ns = time_lookups(&keys, args.lookups, |key| {
+        match scanned.iter_mut().find(|source| source.key == key) {
+            Some(source) => {
+                source.seen += 1;
+                true
+            }
+            None => false,
+        }
+    });
+    json!({
+        "sources": args.sources.max(1),
+        "lookups": args.lookups,
+        "slab_ns": slab_ns,
+        "scan_ns": scan_ns,
+    })
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let result = match args.mode {
+        None => load(&args.load)?,
+        Some(Mode::Import(ref import_args)) => import(import_args)?,
+        Some(Mode::Ingest(ref ingest_args)) => ingest(ingest_args)?,
+        Some(Mode::Lookup(ref lookup_args)) => lookup(lookup_args),
+    };
+    println!("{}", result);
+    Ok(())
+}
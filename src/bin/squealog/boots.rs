@@ -0,0 +1,23 @@
+use crate::output;
+use rusqlite::Connection;
+
+pub fn run(conn: &Connection) -> anyhow::Result<()> {
+    let boots = squealog::boot::list(conn)?;
+    let n = boots.len() as i64;
+    println!(
+        "{:>4} {:<36} {:<19} {:<19} {:<19} {:>9}",
+        "IDX", "BOOT ID", "BOOT TIME", "FIRST", "LAST", "ROWS"
+    );
+    for (i, b) in boots.into_iter().enumerate() {
+        println!(
+            "{:>4} {:<36} {:<19} {:<19} {:<19} {:>9}",
+            i as i64 - (n - 1),
+            b.uuid,
+            output::display_time(Some(b.boot_time)),
+            output::display_time(b.first),
+            output::display_time(b.last),
+            b.rows
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,151 @@
+//! The command line tool's part of the config file, see `squealog::config`.
+//!
+//! ```toml
+//! [alias.authfail]
+//! severity = "warning.."
+//! facility = "auth"
+//! grep = "Failed password"
+//! ```
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Saved queries, used as `squealog @name`.
+    pub alias: BTreeMap<String, Alias>,
+}
+
+/// A string or a list of strings, for filters that can be given more than once.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(from = "OneOrMany")]
+pub struct List(pub Vec<String>);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<OneOrMany> for List {
+    fn from(v: OneOrMany) -> Self {
+        match v {
+            OneOrMany::One(s) => List(vec![s]),
+            OneOrMany::Many(v) => List(v),
+        }
+    }
+}
+
+/// The filters of a saved query, spelled like the command line options.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Alias {
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub boot: Option<String>,
+    pub severity: Option<String>,
+    pub facility: List,
+    pub appname: List,
+    pub socket: List,
+    pub pid: Option<i64>,
+    pub unit: Option<String>,
+    pub grep: List,
+}
+
+impl Alias {
+    /// The command line this alias stands for.
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        let mut push = |flag: &str, value: &str| {
+            args.push(flag.to_owned());
+            if value.is_empty() || value.contains(char::is_whitespace) {
+                args.push(format!("'{}'", value));
+            } else {
+                args.push(value.to_owned());
+            }
+        };
+        let single = [
+            ("-S", &self.since),
+            ("-U", &self.until),
+            ("-b", &self.boot),
+            ("-p", &self.severity),
+            ("-u", &self.unit),
+        ];
+        for (flag, value) in single {
+            if let Some(value) = value {
+                push(flag, value);
+            }
+        }
+        if let Some(pid) = self.pid {
+            push("--pid", &pid.to_string());
+        }
+        let lists = [
+            ("--facility", &self.facility),
+            ("-t", &self.appname),
+            ("--socket", &self.socket),
+            ("-g", &self.grep),
+        ];
+        for (flag, values) in lists {
+            for value in &values.0 {
+                push(flag, value);
+            }
+        }
+        args
+    }
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Config> {
+        squealog::config::load()
+    }
+
+    pub fn alias(&self, name: &str) -> anyhow::Result<&Alias> {
+        if let Some(alias) = self.alias.get(name) {
+            return Ok(alias);
+        }
+        let close: Vec<_> = self
+            .alias
+            .keys()
+            .filter(|known| known.starts_with(name) || edit_distance(known, name) <= 2)
+            .map(|known| format!("@{}", known))
+            .collect();
+        if close.is_empty() {
+            anyhow::bail!("No alias named @{} (see `squealog aliases`)", name);
+        }
+        anyhow::bail!(
+            "No alias named @{}, did you mean {}?",
+            name,
+            close.join(" or ")
+        );
+    }
+}
+
+/// Levenshtein distance, plenty fast for a handful of short names.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = prev[j] + usize::from(ca != cb);
+            cur.push(substitute.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+pub fn list(config: &Config) {
+    let width = config.alias.keys().map(|k| k.len() + 1).max().unwrap_or(0);
+    for (name, alias) in &config.alias {
+        let at = format!("@{}", name);
+        println!(
+            "{:width$}  {}",
+            at,
+            alias.to_args().join(" "),
+            width = width
+        );
+    }
+}
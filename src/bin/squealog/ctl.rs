@@ -0,0 +1,49 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The daemon's control socket
+    #[clap(long, default_value = "/var/run/squealogd.ctl")]
+    socket: PathBuf,
+    /// PING, STATS, SOURCES, FLUSH, CHECKPOINT, ROTATE, RELOAD, LEVEL <severity> or UPGRADE
+    #[clap(required = true)]
+    command: Vec<String>,
+}
+
+/// Sends one command and reads the reply: its lines, or why the daemon refused.
+pub fn request(path: &Path, line: &str) -> anyhow::Result<Result<Vec<String>, String>> {
+    let mut stream = UnixStream::connect(path)
+        .map_err(|e| anyhow::format_err!("could not connect to {:?}: {}", path, e))?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    writeln!(stream, "{}", line)?;
+    let mut lines = vec![];
+    for reply in BufReader::new(stream).lines() {
+        let reply = reply?;
+        if reply == "OK" {
+            return Ok(Ok(lines));
+        }
+        if let Some(why) = reply.strip_prefix("ERR ") {
+            return Ok(Err(why.to_owned()));
+        }
+        lines.push(reply);
+    }
+    anyhow::bail!("the daemon hung up without replying")
+}
+
+pub fn run(args: Args) -> anyhow::Result<()> {
+    match request(&args.socket, &args.command.join(" "))? {
+        Ok(lines) => {
+            for line in lines {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        Err(why) => {
+            eprintln!("squealog: {}", why);
+            std::process::exit(1);
+        }
+    }
+}
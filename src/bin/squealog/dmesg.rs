@@ -0,0 +1,84 @@
+use crate::output::{ColorMode, Dmesg, Format};
+use rusqlite::Connection;
+use squealog::{filter::Filter, names, query::select, query::Row};
+use std::io::Write;
+
+const FOLLOW_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Which boot: 0 is the current one, -1 the previous one, or a boot UUID
+    #[clap(short = 'b', long, default_value = "0", allow_hyphen_values = true)]
+    boot: String,
+    /// Keep printing new messages as they arrive
+    #[clap(short, long)]
+    follow: bool,
+    /// When to color the output: always, auto or never
+    #[clap(long, default_value = "auto")]
+    color: ColorMode,
+}
+
+/// Whether `row` continues the message in `prev`. The kernel puts a priority in front of every
+/// new message, so a line without one is the rest of a printf that got split up.
+fn continues(prev: &Row, row: &Row) -> bool {
+    row.severity.is_none() && prev.severity.is_some() && row.socket == prev.socket
+}
+
+/// Folds continuation lines into the message they belong to.
+pub fn merge_continuations(rows: Vec<Row>) -> Vec<Row> {
+    let mut merged: Vec<Row> = Vec::with_capacity(rows.len());
+    for row in rows {
+        match merged.last_mut() {
+            Some(prev) if continues(prev, &row) => {
+                prev.msg.push('\n');
+                prev.msg.push_str(&row.msg);
+            }
+            _ => merged.push(row),
+        }
+    }
+    merged
+}
+
+pub fn run(conn: &Connection, args: Args) -> anyhow::Result<()> {
+    let filter = Filter {
+        boot: Some(squealog::boot::resolve(conn, &args.boot)?),
+        sockets: names::KERNEL_SOCKETS
+            .iter()
+            .map(|&s| s.to_owned())
+            .collect(),
+        ..Default::default()
+    };
+    let mut format = Dmesg::new(args.color);
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+
+    let mut rows = vec![];
+    select(conn, &filter, None, None, false, None, |row| {
+        rows.push(row);
+        Ok(())
+    })?;
+    let mut last = rows.iter().map(|r| r.id).max();
+    let mut rows = merge_continuations(rows);
+    // Rows arrive in id order, which is almost but not quite the order the kernel wrote them.
+    rows.sort_by_key(|row| row.time);
+    for row in rows {
+        format.print(&mut out, &row)?;
+    }
+
+    if args.follow {
+        loop {
+            out.flush()?;
+            std::thread::sleep(FOLLOW_INTERVAL);
+            let mut rows = vec![];
+            select(conn, &filter, last, None, false, None, |row| {
+                last = Some(row.id);
+                rows.push(row);
+                Ok(())
+            })?;
+            for row in merge_continuations(rows) {
+                format.print(&mut out, &row)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use crate::output::{self, ColorMode, Format, OutputMode};
+use rusqlite::Connection;
+use squealog::{
+    digest,
+    filter::{Filter, SeverityRange},
+    query::{select, Row},
+    time::TimeSpec,
+};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Start of the window: the current boot, or a point in time.
+#[derive(Clone, Copy, Debug)]
+pub enum Since {
+    Boot,
+    At(TimeSpec),
+}
+
+impl FromStr for Since {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "boot" => Ok(Since::Boot),
+            _ => s.parse().map(Since::At),
+        }
+    }
+}
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Start of the window: "boot" for the current boot, or a time like 1h
+    #[clap(short = 'S', long, default_value = "boot", allow_hyphen_values = true)]
+    since: Since,
+    /// When to color the output: always, auto or never
+    #[clap(long, default_value = "auto")]
+    color: ColorMode,
+}
+
+pub fn run(conn: &Connection, args: Args) -> anyhow::Result<()> {
+    let mut filter = Filter {
+        severity: Some(SeverityRange(0, 3)),
+        ..Default::default()
+    };
+    match args.since {
+        Since::Boot => filter.boot = Some(squealog::boot::resolve(conn, "0")?),
+        Since::At(t) => filter.since = Some(t.resolve()),
+    }
+    let mut rows = vec![];
+    select(conn, &filter, None, None, true, None, |row| {
+        rows.push(row);
+        Ok(())
+    })?;
+    let mut format = OutputMode::Default.format(args.color, false);
+    output::to_stdout(|out| print(out, rows, &mut *format))?;
+    Ok(())
+}
+
+/// The rows with runs of the same message collapsed, each with how often it repeated and how
+/// many rows of the window are similar to it.
+fn print(out: &mut dyn Write, rows: Vec<Row>, format: &mut dyn Format) -> io::Result<()> {
+    let similar = digest::count_similar(&rows);
+    writeln!(out, "{:>7} {:>7}  MESSAGE", "REPEAT", "SIMILAR")?;
+    for group in digest::dedup(rows) {
+        let repeat = if group.count > 1 {
+            format!("×{}", group.count)
+        } else {
+            String::new()
+        };
+        let n = similar.get(&digest::similar_key(&group.row)).unwrap_or(&0);
+        write!(out, "{:>7} {:>7}  ", repeat, n)?;
+        format.print(out, &group.row)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Fields;
+
+    /// Rows of (appname, msgid, severity, msg), from one host.
+    fn rows(rows: &[(&str, Option<&str>, i64, &str)]) -> Vec<Row> {
+        rows.iter()
+            .enumerate()
+            .map(|(id, &(appname, msgid, severity, msg))| Row {
+                id: id as i64,
+                time: None,
+                severity: Some(severity),
+                socket: "log".to_owned(),
+                hostname: Some("web1".to_owned()),
+                appname: Some(appname.to_owned()),
+                pid: Some(42),
+                msgid: msgid.map(str::to_owned),
+                msg: msg.to_owned(),
+                boot_time: None,
+                sdata: None,
+            })
+            .collect()
+    }
+
+    fn printed(rows: Vec<Row>) -> Vec<String> {
+        let mut format = Fields::new("appname,msg".parse().unwrap(), false);
+        let mut out = vec![];
+        print(&mut out, rows, &mut format).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        out.lines().skip(1).map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn groups_repeats_and_counts_similar() {
+        type Case = (
+            &'static str,
+            &'static [(&'static str, Option<&'static str>, i64, &'static str)],
+            &'static [&'static str],
+        );
+        let cases: &[Case] = &[
+            ("nothing", &[], &[]),
+            (
+                "one",
+                &[("sshd", None, 3, "failed")],
+                &["              1  sshd\tfailed"],
+            ),
+            (
+                "a run",
+                &[
+                    ("sshd", None, 3, "failed"),
+                    ("sshd", None, 3, "failed"),
+                    ("sshd", None, 3, "failed"),
+                ],
+                &["     ×3       3  sshd\tfailed"],
+            ),
+            (
+                "runs broken up by another message",
+                &[
+                    ("sshd", None, 3, "failed"),
+                    ("sshd", None, 3, "failed"),
+                    ("cron", None, 3, "bad crontab"),
+                    ("sshd", None, 3, "failed"),
+                ],
+                &[
+                    "     ×2       3  sshd\tfailed",
+                    "              1  cron\tbad crontab",
+                    "              3  sshd\tfailed",
+                ],
+            ),
+            (
+                "a different severity is another message",
+                &[
+                    ("disk", None, 3, "I/O error"),
+                    ("disk", None, 2, "I/O error"),
+                ],
+                &[
+                    "              2  disk\tI/O error",
+                    "              2  disk\tI/O error",
+                ],
+            ),
+            (
+                "similar by appname and msgid, whatever the text",
+                &[
+                    ("app", Some("OOM"), 3, "out of memory at 1"),
+                    ("app", Some("OOM"), 3, "out of memory at 2"),
+                    ("app", Some("OOM"), 3, "out of memory at 2"),
+                    ("app", Some("IO"), 3, "out of memory at 2"),
+                    ("app", None, 3, "out of memory at 2"),
+                ],
+                &[
+                    "              3  app\tout of memory at 1",
+                    "     ×2       3  app\tout of memory at 2",
+                    "              1  app\tout of memory at 2",
+                    "              1  app\tout of memory at 2",
+                ],
+            ),
+        ];
+        for &(name, stream, expected) in cases {
+            assert_eq!(printed(rows(stream)), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn another_host_breaks_a_run() {
+        let mut stream = rows(&[("sshd", None, 3, "failed"), ("sshd", None, 3, "failed")]);
+        stream[1].hostname = None;
+        assert_eq!(
+            printed(stream),
+            [
+                "              2  sshd\tfailed",
+                "              2  sshd\tfailed",
+            ]
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use arrow::array::{
+    ArrayRef, Int64Builder, Int8Builder, StringBuilder, TimestampMicrosecondBuilder,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use chrono::prelude::*;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use parquet::schema::types::ColumnPath;
+use rusqlite::{types::ToSql, Connection};
+use squealog::time::TimeSpec;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Rows per Arrow batch, which is also what gets buffered in memory at most.
+const BATCH: usize = 64 * 1024;
+/// Rows per Parquet row group.
+const ROW_GROUP: usize = 4 * BATCH;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Parquet,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "parquet" => Ok(ExportFormat::Parquet),
+            _ => Err(format!("unsupported export format '{}'", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Partition {
+    Day,
+}
+
+impl FromStr for Partition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(Partition::Day),
+            _ => Err(format!("unsupported partitioning '{}'", s)),
+        }
+    }
+}
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Output format (only parquet for now)
+    #[clap(long, default_value = "parquet")]
+    format: ExportFormat,
+    /// Export messages received since this time
+    #[clap(short = 'S', long, allow_hyphen_values = true)]
+    since: Option<TimeSpec>,
+    /// Export messages received before this time
+    #[clap(short = 'U', long, allow_hyphen_values = true)]
+    until: Option<TimeSpec>,
+    /// Output file, or directory with --partition-by
+    #[clap(short, long)]
+    out: PathBuf,
+    /// Write one file per UTC day, as DIR/date=YYYY-MM-DD/part-<first id>.parquet
+    #[clap(long)]
+    partition_by: Option<Partition>,
+}
+
+fn schema() -> SchemaRef {
+    let timestamp = DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()));
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("time", timestamp.clone(), true),
+        Field::new("recv_time", timestamp, true),
+        Field::new("facility", DataType::Int8, true),
+        Field::new("severity", DataType::Int8, true),
+        Field::new("socket", DataType::Utf8, false),
+        Field::new("hostname", DataType::Utf8, true),
+        Field::new("appname", DataType::Utf8, true),
+        Field::new("pid", DataType::Int64, true),
+        Field::new("msgid", DataType::Utf8, true),
+        Field::new("msg", DataType::Utf8, false),
+        // The sdata column as stored, see `squealog::sdata`.
+        Field::new("sd", DataType::Utf8, true),
+        Field::new("boot", DataType::Utf8, true),
+    ]))
+}
+
+/// Messages are too varied for dictionaries to pay off, everything else repeats a lot.
+fn properties() -> WriterProperties {
+    WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_max_row_group_size(ROW_GROUP)
+        .set_column_dictionary_enabled(ColumnPath::from("msg"), false)
+        .set_column_dictionary_enabled(ColumnPath::from("sd"), false)
+        .set_column_dictionary_enabled(ColumnPath::from("id"), false)
+        .build()
+}
+
+#[derive(Default)]
+struct Batch {
+    len: usize,
+    id: Int64Builder,
+    time: TimestampMicrosecondBuilder,
+    recv_time: TimestampMicrosecondBuilder,
+    facility: Int8Builder,
+    severity: Int8Builder,
+    socket: StringBuilder,
+    hostname: StringBuilder,
+    appname: StringBuilder,
+    pid: Int64Builder,
+    msgid: StringBuilder,
+    msg: StringBuilder,
+    sd: StringBuilder,
+    boot: StringBuilder,
+}
+
+impl Batch {
+    fn push(&mut self, row: &rusqlite::Row) -> rusqlite::Result<()> {
+        let time: Option<DateTime<FixedOffset>> = row.get(1)?;
+        let recv_time: Option<DateTime<Utc>> = row.get(2)?;
+        self.id.append_value(row.get(0)?);
+        self.time.append_option(time.map(|t| t.timestamp_micros()));
+        self.recv_time
+            .append_option(recv_time.map(|t| t.timestamp_micros()));
+        self.facility.append_option(row.get::<_, Option<i8>>(3)?);
+        self.severity.append_option(row.get::<_, Option<i8>>(4)?);
+        self.socket.append_value(row.get::<_, String>(5)?);
+        self.hostname
+            .append_option(row.get::<_, Option<String>>(6)?);
+        self.appname.append_option(row.get::<_, Option<String>>(7)?);
+        self.pid.append_option(row.get::<_, Option<i64>>(8)?);
+        self.msgid.append_option(row.get::<_, Option<String>>(9)?);
+        self.msg.append_value(row.get::<_, String>(10)?);
+        self.sd.append_option(row.get::<_, Option<String>>(11)?);
+        self.boot.append_option(row.get::<_, Option<String>>(12)?);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self, schema: &SchemaRef) -> anyhow::Result<RecordBatch> {
+        self.len = 0;
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.id.finish()),
+            Arc::new(self.time.finish().with_timezone("UTC")),
+            Arc::new(self.recv_time.finish().with_timezone("UTC")),
+            Arc::new(self.facility.finish()),
+            Arc::new(self.severity.finish()),
+            Arc::new(self.socket.finish()),
+            Arc::new(self.hostname.finish()),
+            Arc::new(self.appname.finish()),
+            Arc::new(self.pid.finish()),
+            Arc::new(self.msgid.finish()),
+            Arc::new(self.msg.finish()),
+            Arc::new(self.sd.finish()),
+            Arc::new(self.boot.finish()),
+        ];
+        Ok(RecordBatch::try_new(schema.clone(), columns)?)
+    }
+}
+
+/// Where rows go: one file, or one file per day.
+struct Output {
+    schema: SchemaRef,
+    out: PathBuf,
+    partition: Option<Partition>,
+    current: Option<(Option<NaiveDate>, ArrowWriter<File>)>,
+    batch: Batch,
+    files: usize,
+}
+
+impl Output {
+    fn path(&self, day: NaiveDate, first_id: i64) -> PathBuf {
+        self.out
+            .join(format!("date={}", day.format("%Y-%m-%d")))
+            .join(format!("part-{}.parquet", first_id))
+    }
+
+    fn open(&mut self, day: Option<NaiveDate>, first_id: i64) -> anyhow::Result<()> {
+        let path = match day {
+            Some(day) => self.path(day, first_id),
+            None => self.out.clone(),
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = File::create(&path)?;
+        let writer = ArrowWriter::try_new(file, self.schema.clone(), Some(properties()))?;
+        self.current = Some((day, writer));
+        self.files += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        if self.batch.len == 0 {
+            return Ok(());
+        }
+        let batch = self.batch.finish(&self.schema)?;
+        if let Some((_, ref mut writer)) = self.current {
+            writer.write(&batch)?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> anyhow::Result<()> {
+        self.flush()?;
+        if let Some((_, writer)) = self.current.take() {
+            writer.close()?;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, row: &rusqlite::Row) -> anyhow::Result<()> {
+        let day = match self.partition {
+            Some(Partition::Day) => {
+                let recv_time: Option<DateTime<Utc>> = row.get(2)?;
+                Some(recv_time.unwrap_or_else(Utc::now).date_naive())
+            }
+            None => None,
+        };
+        if !matches!(self.current, Some((d, _)) if d == day) {
+            self.close()?;
+            self.open(day, row.get(0)?)?;
+        }
+        self.batch.push(row)?;
+        if self.batch.len >= BATCH {
+            self.flush()?;
+        }
+        Ok(())
+    }
+}
+
+pub fn run(conn: &Connection, args: Args) -> anyhow::Result<()> {
+    let ExportFormat::Parquet = args.format;
+    let mut conds = vec![];
+    let mut params: Vec<Box<dyn ToSql>> = vec![];
+    if let Some(since) = args.since {
+        conds.push("log.recv_time >= ?");
+        params.push(Box::new(since.resolve()));
+    }
+    if let Some(until) = args.until {
+        conds.push("log.recv_time < ?");
+        params.push(Box::new(until.resolve()));
+    }
+    let mut sql = "SELECT log.id, log.time, log.recv_time, log.facility, log.severity, log.socket,
+            log.hostname, log.appname, log.pid, log.msgid, log.msg, log.sdata, boot.uuid
+        FROM log LEFT JOIN boot ON boot.id = log.boot"
+        .to_owned();
+    if !conds.is_empty() {
+        sql += " WHERE ";
+        sql += &conds.join(" AND ");
+    }
+    sql += " ORDER BY log.id";
+
+    let mut output = Output {
+        schema: schema(),
+        out: args.out,
+        partition: args.partition_by,
+        current: None,
+        batch: Batch::default(),
+        files: 0,
+    };
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(rusqlite::params_from_iter(params.iter()))?;
+    let mut count = 0u64;
+    while let Some(row) = rows.next()? {
+        output.push(row)?;
+        count += 1;
+    }
+    output.close()?;
+    eprintln!("{} rows exported to {} file(s)", count, output.files);
+    if output.files == 0 && output.partition.is_none() {
+        // Still leave a valid (empty) file behind for pipelines that expect one.
+        write_empty(&output.out, &output.schema)?;
+    }
+    Ok(())
+}
+
+fn write_empty(path: &Path, schema: &SchemaRef) -> anyhow::Result<()> {
+    let writer = ArrowWriter::try_new(File::create(path)?, schema.clone(), Some(properties()))?;
+    writer.close()?;
+    Ok(())
+}
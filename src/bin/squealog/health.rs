@@ -0,0 +1,199 @@
+use chrono::prelude::*;
+use rusqlite::{Connection, OptionalExtension};
+use squealog::serialize;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use syslog_loose::{SyslogFacility, SyslogSeverity};
+
+/// A length of time like 30s, 1h or 2d.
+#[derive(Clone, Copy, Debug)]
+pub struct Age(chrono::Duration);
+
+impl std::str::FromStr for Age {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        squealog::time::parse_duration(s)
+            .map(Age)
+            .ok_or_else(|| format!("invalid duration '{}'", s))
+    }
+}
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Warn when the newest message was received longer ago than this
+    #[clap(long, default_value = "1h")]
+    warn: Age,
+    /// Critical when the newest message was received longer ago than this
+    #[clap(long, default_value = "6h")]
+    crit: Age,
+    /// Check that the process in this pidfile is running
+    #[clap(long)]
+    pidfile: Option<PathBuf>,
+    /// Send a message to the daemon's socket and wait for it to be stored
+    #[clap(long)]
+    probe: bool,
+    /// Socket for --probe
+    #[clap(long, default_value = "/var/run/log")]
+    socket: PathBuf,
+    /// How long --probe waits for its message, in seconds
+    #[clap(long, default_value = "10")]
+    timeout: u64,
+    /// Check that the daemon answers on this control socket
+    #[clap(long)]
+    control: Option<PathBuf>,
+}
+
+/// Nagios plugin exit codes, in order of badness.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Status {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+}
+
+fn daemon(args: &Args) -> (Status, String) {
+    let path = match args.pidfile {
+        Some(ref path) => path,
+        None => return (Status::Ok, String::new()),
+    };
+    let pid = match std::fs::read_to_string(path)
+        .ok()
+        .and_then(|s| s.trim().parse::<libc::pid_t>().ok())
+    {
+        Some(pid) => pid,
+        None => return (Status::Critical, format!("no pid in {:?}", path)),
+    };
+    // EPERM still means there is such a process.
+    let alive = unsafe { libc::kill(pid, 0) } == 0
+        || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    if alive {
+        (Status::Ok, format!("pid {} running", pid))
+    } else {
+        (Status::Critical, format!("pid {} not running", pid))
+    }
+}
+
+fn control(args: &Args) -> (Status, String) {
+    let path = match args.control {
+        Some(ref path) => path,
+        None => return (Status::Ok, String::new()),
+    };
+    match crate::ctl::request(path, "PING") {
+        Ok(Ok(_)) => (Status::Ok, "control socket answers".to_owned()),
+        Ok(Err(why)) => (Status::Critical, format!("control socket: {}", why)),
+        Err(e) => (Status::Critical, e.to_string()),
+    }
+}
+
+fn newest(conn: &Connection, args: &Args) -> anyhow::Result<(Status, String)> {
+    let last: Option<DateTime<Utc>> = conn
+        .query_row(
+            "SELECT recv_time FROM log ORDER BY id DESC LIMIT 1",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let last = match last {
+        Some(last) => last,
+        None => return Ok((Status::Critical, "no messages stored".to_owned())),
+    };
+    let age = Utc::now() - last;
+    let status = if age > args.crit.0 {
+        Status::Critical
+    } else if age > args.warn.0 {
+        Status::Warning
+    } else {
+        Status::Ok
+    };
+    Ok((status, format!("newest message {}s old", age.num_seconds())))
+}
+
+fn quick_check(conn: &Connection) -> anyhow::Result<(Status, String)> {
+    let result: String = conn.query_row("PRAGMA quick_check", [], |row| row.get(0))?;
+    Ok(match result.as_str() {
+        "ok" => (Status::Ok, "quick_check ok".to_owned()),
+        _ => (Status::Critical, format!("quick_check: {}", result)),
+    })
+}
+
+/// Sends a message with a unique token and waits for it to show up in the database.
+fn probe(conn: &Connection, args: &Args) -> anyhow::Result<(Status, String)> {
+    let after: i64 =
+        conn.query_row("SELECT coalesce(max(id), 0) FROM log", [], |row| row.get(0))?;
+    // Only has to be unique, microseconds do outside the range nanoseconds fit in.
+    let now = Utc::now();
+    let token = format!(
+        "probe {}.{}",
+        std::process::id(),
+        now.timestamp_nanos_opt()
+            .unwrap_or_else(|| now.timestamp_micros())
+    );
+    // A complete RFC 5424 line (syslog.info), so that MSG is the token and nothing else.
+    let pid = std::process::id().to_string();
+    let rec = serialize::Record {
+        facility: Some(SyslogFacility::LOG_SYSLOG as u8),
+        severity: Some(SyslogSeverity::SEV_INFO as u8),
+        time: now.into(),
+        hostname: None,
+        appname: Some("squealog-health".into()),
+        procid: Some(pid.into()),
+        msgid: None,
+        sdata: vec![],
+        msg: token.as_str().into(),
+    };
+    let mut line = String::new();
+    serialize::rfc5424(&mut line, &rec, None);
+    let started = Instant::now();
+    if let Err(e) = UnixDatagram::unbound()?.send_to(line.as_bytes(), &args.socket) {
+        return Ok((
+            Status::Critical,
+            format!("could not send to {:?}: {}", args.socket, e),
+        ));
+    }
+    let timeout = Duration::from_secs(args.timeout);
+    let mut stmt = conn.prepare("SELECT count(*) FROM log WHERE id > ? AND msg = ?")?;
+    while started.elapsed() < timeout {
+        let found: i64 = stmt.query_row(rusqlite::params![after, token], |row| row.get(0))?;
+        if found > 0 {
+            return Ok((
+                Status::Ok,
+                format!("probe stored in {} ms", started.elapsed().as_millis()),
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    Ok((
+        Status::Critical,
+        format!("probe not stored within {}s", args.timeout),
+    ))
+}
+
+/// Prints one line like `SQUEALOG OK - newest message 3s old, quick_check ok` and exits with 0
+/// (ok), 1 (warning) or 2 (critical), for Nagios, monit and the like.
+pub fn run(conn: &Connection, args: Args) -> anyhow::Result<()> {
+    let failed = |e: anyhow::Error| (Status::Critical, e.to_string());
+    let mut results = vec![
+        daemon(&args),
+        control(&args),
+        newest(conn, &args).unwrap_or_else(failed),
+        quick_check(conn).unwrap_or_else(failed),
+    ];
+    if args.probe {
+        results.push(probe(conn, &args).unwrap_or_else(failed));
+    }
+    let status = results.iter().map(|r| r.0).max().unwrap_or(Status::Ok);
+    let summary: Vec<_> = results
+        .into_iter()
+        .map(|r| r.1)
+        .filter(|s| !s.is_empty())
+        .collect();
+    let word = match status {
+        Status::Ok => "OK",
+        Status::Warning => "WARNING",
+        Status::Critical => "CRITICAL",
+    };
+    println!("SQUEALOG {} - {}", word, summary.join(", "));
+    std::process::exit(status as i32);
+}
@@ -0,0 +1,238 @@
+use chrono::prelude::*;
+use rusqlite::{Connection, OptionalExtension};
+use squealog::{bulk::Bulk, schema};
+use std::{
+    cell::Cell,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+use syslog_loose::ProcId;
+
+/// Rows per transaction.
+const BATCH: u64 = 50000;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Plain, gzip or bzip2 compressed syslog files
+    #[clap(required = true)]
+    files: Vec<PathBuf>,
+    /// Socket name to record for the imported rows
+    #[clap(long, default_value = "imported")]
+    socket_name: String,
+    /// Import files even if the same content has been imported before
+    #[clap(long)]
+    force: bool,
+}
+
+struct Counting<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Opens a possibly compressed file, counting the (compressed) bytes read into `count`.
+fn open(path: &Path, count: Rc<Cell<u64>>) -> io::Result<Box<dyn BufRead>> {
+    let mut file = File::open(path)?;
+    let mut magic = [0u8; 3];
+    let n = file.read(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+    let inner = Counting { inner: file, count };
+    Ok(match &magic[..n] {
+        [0x1f, 0x8b, ..] => Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(inner))),
+        [b'B', b'Z', b'h'] => Box::new(BufReader::new(bzip2::read::MultiBzDecoder::new(inner))),
+        _ => Box::new(BufReader::new(inner)),
+    })
+}
+
+fn for_each_line(
+    path: &Path,
+    mut f: impl FnMut(&[u8], u64) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let count = Rc::new(Cell::new(0));
+    let mut reader = open(path, count.clone())?;
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        let trimmed = line.strip_suffix(b"\n").unwrap_or(&line);
+        if !trimmed.is_empty() {
+            f(trimmed, count.get())?;
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+/// The month of an RFC 3164 line ("Jan  1 00:00:00 ..."), possibly after a PRI.
+fn month_of(line: &[u8]) -> Option<u32> {
+    let line = match line.strip_prefix(b"<") {
+        Some(rest) => &rest[rest.iter().position(|&c| c == b'>')? + 1..],
+        None => line,
+    };
+    const MONTHS: [&[u8]; 12] = [
+        b"Jan", b"Feb", b"Mar", b"Apr", b"May", b"Jun", b"Jul", b"Aug", b"Sep", b"Oct", b"Nov",
+        b"Dec",
+    ];
+    MONTHS
+        .iter()
+        .position(|m| line.starts_with(m))
+        .map(|i| i as u32 + 1)
+}
+
+/// FNV-1a, enough to recognize a file that has been imported already.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |h, &b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+struct Scan {
+    checksum: String,
+    /// Number of times the month goes backwards (December to January).
+    rollovers: i32,
+    last_month: Option<u32>,
+}
+
+fn scan(path: &Path) -> anyhow::Result<Scan> {
+    let mut hash = 0xcbf29ce484222325;
+    let mut rollovers = 0;
+    let mut last_month = None;
+    for_each_line(path, |line, _| {
+        hash = fnv1a(fnv1a(hash, line), b"\n");
+        if let Some(m) = month_of(line) {
+            if last_month.is_some_and(|l| m < l) {
+                rollovers += 1;
+            }
+            last_month = Some(m);
+        }
+        Ok(())
+    })?;
+    Ok(Scan {
+        checksum: format!("{:016x}", hash),
+        rollovers,
+        last_month,
+    })
+}
+
+fn progress(path: &Path, done: u64, total: u64, lines: u64) {
+    let frac = if total > 0 {
+        (done as f64 / total as f64).min(1.0)
+    } else {
+        1.0
+    };
+    let filled = (frac * 30.0) as usize;
+    eprint!(
+        "\r{} [{:<30}] {:>3}% {} lines",
+        path.display(),
+        "#".repeat(filled),
+        (frac * 100.0) as u32,
+        lines
+    );
+    let _ = io::stderr().flush();
+}
+
+fn import_file(conn: &Connection, path: &Path, args: &Args) -> anyhow::Result<()> {
+    let scan = scan(path)?;
+    let done: Option<String> = conn
+        .query_row(
+            "SELECT path FROM import WHERE checksum = ?",
+            [&scan.checksum],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let (Some(prev), false) = (done, args.force) {
+        eprintln!(
+            "{}: already imported (as {}), skipping",
+            path.display(),
+            prev
+        );
+        return Ok(());
+    }
+
+    // RFC 3164 timestamps have no year: the file was last written at its mtime, so that's the
+    // year of the last line (unless the last line is from a later month, i.e. last year),
+    // and counting back the rollovers gives the year of the first line.
+    let meta = std::fs::metadata(path)?;
+    let mtime = DateTime::<Local>::from(meta.modified()?);
+    let end_year = mtime.year() - (scan.last_month > Some(mtime.month())) as i32;
+    let mut year = end_year - scan.rollovers;
+    let mut last_month = None;
+
+    let (mut malformed, pos) = (0u64, Cell::new(0));
+    let mut bulk = Bulk::begin(conn, BATCH)?;
+    bulk.on_commit(|rows| progress(path, pos.get(), meta.len(), rows));
+    for_each_line(path, |line, at| {
+        pos.set(at);
+        if let Some(m) = month_of(line) {
+            if last_month.is_some_and(|l| m < l) {
+                year += 1;
+            }
+            last_month = Some(m);
+        }
+        let text = String::from_utf8_lossy(line);
+        let msg = squealog::payload::parse_syslog(&text, year);
+        if msg.timestamp.is_none() || matches!(text, std::borrow::Cow::Owned(_)) {
+            malformed += 1;
+        }
+        bulk.insert(&schema::Row {
+            facility: msg.facility.map(|x| x as i64),
+            severity: msg.severity.map(|x| x as i64),
+            socket: &args.socket_name,
+            hostname: msg.hostname,
+            hostname_source: msg.hostname.map(|_| "claimed"),
+            appname: msg.appname,
+            pid: msg.procid.and_then(|p| match p {
+                ProcId::PID(i) => Some(i),
+                _ => None,
+            }),
+            msgid: msg.msgid,
+            time: msg.timestamp,
+            // Use the original time, so that time ranges find imported messages.
+            recv_time: msg
+                .timestamp
+                .map(|t| t.with_timezone(&Utc))
+                .unwrap_or_else(Utc::now),
+            boot: None,
+            msg: msg.msg,
+            sdata: squealog::sdata::to_json(&msg.structured_data),
+        })?;
+        Ok(())
+    })?;
+    let rows = bulk.rows();
+    // With the last batch, so that a file that failed halfway is not taken as imported.
+    conn.execute(
+        "INSERT OR REPLACE INTO import (checksum, path, time, rows) VALUES (?, ?, ?, ?)",
+        rusqlite::params![
+            scan.checksum,
+            path.display().to_string(),
+            Utc::now(),
+            rows as i64
+        ],
+    )?;
+    bulk.finish()?;
+    progress(path, meta.len(), meta.len(), rows);
+    eprintln!();
+    eprintln!(
+        "{}: imported {} rows, {} of them malformed (stored as-is)",
+        path.display(),
+        rows,
+        malformed
+    );
+    Ok(())
+}
+
+pub fn run(conn: &mut Connection, args: Args) -> anyhow::Result<()> {
+    conn.busy_timeout(std::time::Duration::from_secs(10))?;
+    schema::migrations().to_latest(conn)?;
+    for path in &args.files {
+        import_file(conn, path, &args)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+//! `squealog log`: logger(1), with structured data. Sends its arguments as one message, or
+//! without any every line of stdin as one.
+
+use squealog::client::{self, Logger};
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::time::Duration;
+use syslog_loose::{SyslogFacility, SyslogSeverity};
+
+/// How often, and how long apart, to retry what couldn't be sent before giving up on it.
+const RETRIES: u32 = 10;
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The message [default: every line of stdin]
+    message: Vec<String>,
+    /// facility.severity, or just a severity
+    #[clap(
+        short,
+        long,
+        default_value = "user.notice",
+        value_name = "PRI",
+        parse(try_from_str = parse_priority)
+    )]
+    priority: (SyslogFacility, SyslogSeverity),
+    /// The appname [default: $USER]
+    #[clap(short, long)]
+    tag: Option<String>,
+    /// The MSGID
+    #[clap(long)]
+    msgid: Option<String>,
+    /// A structured data param, in the fields@32473 element
+    #[clap(long, value_name = "KEY=VALUE", parse(try_from_str = parse_param))]
+    sd: Vec<(String, String)>,
+    /// The daemon's socket
+    #[clap(short, long, default_value = client::DEFAULT_PATH)]
+    socket: PathBuf,
+}
+
+fn parse_priority(s: &str) -> Result<(SyslogFacility, SyslogSeverity), String> {
+    let (facility, severity) = match s.split_once('.') {
+        Some((facility, severity)) => (Some(facility), severity),
+        None => (None, s),
+    };
+    let facility = match facility {
+        Some(name) => squealog::names::parse_facility(name)
+            .and_then(|f| syslog_loose::decompose_pri(f << 3).0)
+            .ok_or_else(|| format!("invalid facility '{}'", name))?,
+        None => SyslogFacility::LOG_USER,
+    };
+    let severity = squealog::names::parse_severity(severity)
+        .and_then(|s| syslog_loose::decompose_pri(s).1)
+        .ok_or_else(|| format!("invalid severity '{}'", severity))?;
+    Ok((facility, severity))
+}
+
+fn parse_param(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((k, v)) if !k.is_empty() => Ok((k.to_owned(), v.to_owned())),
+        _ => Err(format!("'{}' should look like KEY=VALUE", s)),
+    }
+}
+
+pub fn run(args: Args) -> anyhow::Result<()> {
+    let (facility, severity) = args.priority;
+    let tag = args
+        .tag
+        .or_else(|| std::env::var("USER").ok())
+        .unwrap_or_else(|| "logger".to_owned());
+    let mut logger = Logger::new(&args.socket)?.appname(&tag).facility(facility);
+    let params: Vec<(&str, &str)> = args
+        .sd
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    let msgid = args.msgid.as_deref();
+    let mut dropped = 0;
+    let mut send = |logger: &mut Logger, msg: &str| {
+        if let Err(e) = logger.log(severity, msgid, &params, msg) {
+            if dropped == 0 {
+                eprintln!("squealog: could not send to {:?}: {}", args.socket, e);
+            }
+            dropped += 1;
+        }
+    };
+    if args.message.is_empty() {
+        for line in std::io::stdin().lock().lines() {
+            send(&mut logger, &line?);
+        }
+    } else {
+        send(&mut logger, &args.message.join(" "));
+    }
+    for _ in 0..RETRIES {
+        match logger.flush() {
+            Err(_) => dropped += 1,
+            Ok(()) if logger.pending() == 0 => break,
+            Ok(()) => {}
+        }
+        std::thread::sleep(RETRY_DELAY);
+    }
+    let lost = dropped + logger.pending();
+    if lost > 0 {
+        anyhow::bail!(
+            "{} message{} not sent",
+            lost,
+            if lost == 1 { "" } else { "s" }
+        );
+    }
+    Ok(())
+}
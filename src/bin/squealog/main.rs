@@ -0,0 +1,142 @@
+use clap::{Parser, Subcommand};
+use rusqlite::{Connection, OpenFlags};
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+mod boots;
+mod config;
+mod ctl;
+mod dmesg;
+mod errors;
+#[cfg(feature = "parquet")]
+mod export;
+mod health;
+mod import;
+mod log;
+mod merge;
+mod output;
+mod pager;
+mod prune;
+mod query;
+mod replay;
+mod report;
+mod stats;
+#[cfg(feature = "tui")]
+mod tui;
+mod verify;
+
+/// Query the squealog database
+#[derive(Parser)]
+#[clap(version)]
+struct Args {
+    /// Path to the database
+    #[clap(long, env = "SQUEALOG_DB", default_value = "/var/log/log.db")]
+    db: PathBuf,
+    /// Show times in UTC, and read them in UTC
+    #[clap(long, global = true, conflicts_with = "tz")]
+    utc: bool,
+    /// Show and read times in this zone, like Europe/Berlin, instead of the local one
+    #[clap(long, global = true)]
+    tz: Option<squealog::time::Zone>,
+    #[clap(flatten)]
+    query: query::Args,
+    #[clap(subcommand)]
+    cmd: Option<Cmd>,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+    /// Summarize the contents of the database
+    Stats(stats::Args),
+    /// Rank the noisiest appnames and hosts and show the message rate over time
+    Report(report::Args),
+    /// Show kernel messages from one boot with timestamps relative to it
+    Dmesg(dmesg::Args),
+    /// Show errors and worse, newest first, with repeats collapsed
+    Errors(errors::Args),
+    /// List boots (index 0 is the current one)
+    Boots,
+    /// List the saved queries from the config file
+    Aliases,
+    /// Write messages to Parquet files for offline analysis
+    #[cfg(feature = "parquet")]
+    Export(export::Args),
+    /// Import messages from syslog text files
+    Import(import::Args),
+    /// Copy all rows of another database into one
+    Merge(merge::Args),
+    /// Delete old messages to reclaim space
+    Prune(prune::Args),
+    /// Check the database for corruption
+    Verify,
+    /// Parse and store what squealogd --record captured again, into a new database
+    Replay(replay::Args),
+    /// Check that logging works, for monitoring systems (exits with 0, 1 or 2)
+    Health(health::Args),
+    /// Send a command to the running daemon's control socket
+    Ctl(ctl::Args),
+    /// Send a message to the daemon, like logger(1) but with structured data
+    Log(log::Args),
+    /// Browse messages interactively
+    #[cfg(feature = "tui")]
+    Tui,
+}
+
+/// Takes a leading `@name` off the command line, since clap would see a subcommand.
+fn take_alias(argv: &mut Vec<OsString>) -> Option<String> {
+    let name = argv.get(1)?.to_str()?.strip_prefix('@')?.to_owned();
+    argv.remove(1);
+    Some(name)
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut argv: Vec<OsString> = std::env::args_os().collect();
+    let alias = take_alias(&mut argv);
+    let mut args = Args::parse_from(argv);
+    squealog::time::set_zone(match args.tz {
+        _ if args.utc => squealog::time::Zone::Utc,
+        Some(zone) => zone,
+        None => squealog::time::Zone::Local,
+    });
+    if let Some(name) = alias {
+        args.query
+            .apply(&name, config::Config::load()?.alias(&name)?)?;
+    }
+    let open = |flags| Connection::open_with_flags(&args.db, flags);
+    let read_only = || open(OpenFlags::SQLITE_OPEN_READ_ONLY);
+    let read_write = || open(OpenFlags::SQLITE_OPEN_READ_WRITE);
+    match args.cmd {
+        None => query::run(&read_only()?, args.query),
+        Some(Cmd::Stats(a)) => stats::run(&read_only()?, &args.db, a),
+        Some(Cmd::Report(a)) => report::run(&read_only()?, a),
+        Some(Cmd::Dmesg(a)) => dmesg::run(&read_only()?, a),
+        Some(Cmd::Errors(a)) => errors::run(&read_only()?, a),
+        Some(Cmd::Boots) => boots::run(&read_only()?),
+        Some(Cmd::Aliases) => {
+            config::list(&config::Config::load()?);
+            Ok(())
+        }
+        Some(Cmd::Import(a)) => import::run(
+            &mut open(OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE)?,
+            a,
+        ),
+        #[cfg(feature = "parquet")]
+        Some(Cmd::Export(a)) => export::run(&read_only()?, a),
+        Some(Cmd::Merge(a)) => merge::run(a),
+        Some(Cmd::Prune(a)) => prune::run(&read_write()?, a),
+        Some(Cmd::Verify) => {
+            let code = verify::run(&read_only()?)?;
+            std::process::exit(code)
+        }
+        Some(Cmd::Health(a)) => health::run(&read_only()?, a),
+        Some(Cmd::Ctl(a)) => ctl::run(a),
+        Some(Cmd::Log(a)) => log::run(a),
+        Some(Cmd::Replay(a)) => replay::run(a),
+        #[cfg(feature = "tui")]
+        Some(Cmd::Tui) => {
+            let conn = read_only()?;
+            let filter = args.query.filter(&conn)?;
+            tui::run(&conn, filter)
+        }
+    }
+}
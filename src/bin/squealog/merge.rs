@@ -0,0 +1,193 @@
+use rusqlite::{named_params, Connection, OpenFlags, OptionalExtension};
+use squealog::schema;
+use std::path::{Path, PathBuf};
+
+/// Source rows (by id range) copied per transaction.
+const CHUNK: i64 = 10000;
+/// The oldest source schema that has every column copied below (`hostname_source`).
+const MIN_SOURCE_VERSION: usize = 8;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The database to copy rows into
+    #[clap(long)]
+    into: PathBuf,
+    /// Host name for rows that don't have one, also used to prefix boot IDs
+    /// (defaults to the source file name without extension)
+    #[clap(long)]
+    host: Option<String>,
+    /// The database to copy rows from
+    source: PathBuf,
+}
+
+/// Checks that the source has a schema this version can copy from, without changing it: it's
+/// only read from, and could be another host's live database.
+fn check_source(source: &Path) -> anyhow::Result<()> {
+    let conn = Connection::open_with_flags(
+        source,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    let version = schema::current_version(&conn)?;
+    if !(MIN_SOURCE_VERSION..=schema::latest_version()).contains(&version) {
+        anyhow::bail!(
+            "{:?} has schema version {}, merge copies from versions {} to {}{}",
+            source,
+            version,
+            MIN_SOURCE_VERSION,
+            schema::latest_version(),
+            if version < MIN_SOURCE_VERSION {
+                ", upgrade it first (by running this version's squealogd on it, or merging a copy)"
+            } else {
+                ", it's from a newer squealog"
+            }
+        );
+    }
+    Ok(())
+}
+
+pub fn run(args: Args) -> anyhow::Result<()> {
+    let source = args.source.canonicalize()?;
+    let host = match args.host {
+        Some(h) => h,
+        None => source
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .ok_or_else(|| anyhow::format_err!("Can't guess a host name, pass --host"))?,
+    };
+
+    check_source(&source)?;
+
+    let mut conn = Connection::open(&args.into)?;
+    conn.busy_timeout(std::time::Duration::from_secs(10))?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    schema::migrations().to_latest(&mut conn)?;
+    conn.execute(
+        "ATTACH ? AS src",
+        [format!("file:{}?mode=ro", source.display())],
+    )?;
+
+    let key = source.display().to_string();
+    let mut last: i64 = conn
+        .query_row(
+            "SELECT last_id FROM merge_source WHERE source = ?",
+            [&key],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(0);
+    let max: i64 = conn.query_row("SELECT coalesce(max(id), 0) FROM src.log", [], |row| {
+        row.get(0)
+    })?;
+    if last > 0 {
+        eprintln!("resuming after source id {}", last);
+    }
+
+    conn.execute(
+        "INSERT OR IGNORE INTO main.boot (uuid, boot_time)
+         SELECT :host || ':' || uuid, boot_time FROM src.boot ORDER BY id",
+        named_params! { ":host": host },
+    )?;
+
+    let mut copied = 0;
+    while last < max {
+        let hi = (last + CHUNK).min(max);
+        let tx = conn.transaction()?;
+        copied += tx.execute(
+            "INSERT INTO main.log
+                (facility, severity, socket, hostname, hostname_source, appname, pid, msgid, time,
+                recv_time, boot, msg, sdata)
+             SELECT s.facility, s.severity, s.socket, coalesce(s.hostname, :host),
+                CASE WHEN s.hostname IS NULL THEN 'merge' ELSE s.hostname_source END, s.appname, s.pid,
+                s.msgid, s.time, s.recv_time,
+                (SELECT b.id FROM main.boot b JOIN src.boot sb ON b.uuid = :host || ':' || sb.uuid
+                 WHERE sb.id = s.boot),
+                s.msg, s.sdata
+             FROM src.log s
+             WHERE s.id > :last AND s.id <= :hi
+             AND NOT EXISTS (
+                SELECT 1 FROM main.log d
+                WHERE d.time = s.time AND d.hostname IS coalesce(s.hostname, :host) AND d.msg = s.msg
+             )
+             ORDER BY s.id",
+            named_params! { ":host": host, ":last": last, ":hi": hi },
+        )?;
+        tx.execute(
+            "INSERT OR REPLACE INTO merge_source (source, last_id) VALUES (?, ?)",
+            rusqlite::params![key, hi],
+        )?;
+        tx.commit()?;
+        last = hi;
+        eprint!("\rcopied {} rows, at source id {} of {}", copied, last, max);
+    }
+    eprintln!();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_db(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("squealog-merge-{}-{}.db", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn version(path: &Path) -> usize {
+        schema::current_version(&Connection::open(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn refuses_old_sources_without_upgrading_them() {
+        let source = temp_db("old-source");
+        let mut conn = Connection::open(&source).unwrap();
+        schema::migrations().to_version(&mut conn, 4).unwrap();
+        drop(conn);
+        let into = temp_db("old-into");
+        let err = run(Args {
+            into: into.clone(),
+            host: Some("old".to_owned()),
+            source: source.clone(),
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("schema version 4"), "{}", err);
+        assert_eq!(version(&source), 4);
+        let _ = std::fs::remove_file(source);
+        let _ = std::fs::remove_file(into);
+    }
+
+    #[test]
+    fn copies_from_current_sources() {
+        let source = temp_db("source");
+        let mut conn = Connection::open(&source).unwrap();
+        schema::migrations().to_latest(&mut conn).unwrap();
+        conn.execute(
+            "INSERT INTO log (socket, msg, time, recv_time) VALUES ('log', 'hi', '2024-01-01 00:00:00+00:00', '2024-01-01 00:00:00+00:00')",
+            [],
+        )
+        .unwrap();
+        drop(conn);
+        let into = temp_db("into");
+        run(Args {
+            into: into.clone(),
+            host: Some("web1".to_owned()),
+            source: source.clone(),
+        })
+        .unwrap();
+        let (hostname, source_of): (String, String) = Connection::open(&into)
+            .unwrap()
+            .query_row("SELECT hostname, hostname_source FROM log", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((hostname.as_str(), source_of.as_str()), ("web1", "merge"));
+        let _ = std::fs::remove_file(source);
+        let _ = std::fs::remove_file(&into);
+        for ext in ["-wal", "-shm"] {
+            let mut p = into.clone().into_os_string();
+            p.push(ext);
+            let _ = std::fs::remove_file(p);
+        }
+    }
+}
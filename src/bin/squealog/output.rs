@@ -0,0 +1,978 @@
+use chrono::prelude::*;
+use squealog::{names, query::Row, time};
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+
+const SPACES: &str = "                                                                ";
+
+pub fn display_time(t: Option<DateTime<Utc>>) -> String {
+    t.map(|t| time::display(&t).format(TIME_FORMAT).to_string())
+        .unwrap_or_else(|| "-".to_owned())
+}
+
+/// Has `print` write to a locked stdout. Stdout being closed early (`| head`) ends the output
+/// instead of failing, like the pager quitting does for queries.
+pub fn to_stdout(print: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match print(&mut out).and_then(|()| out.flush()) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    Always,
+    Auto,
+    Never,
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(ColorMode::Always),
+            "auto" => Ok(ColorMode::Auto),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(format!("invalid color mode '{}'", s)),
+        }
+    }
+}
+
+pub fn stdout_is_tty() -> bool {
+    unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
+
+fn terminal_width() -> Option<usize> {
+    let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) } == 0 && ws.ws_col > 0
+    {
+        return Some(ws.ws_col as usize);
+    }
+    std::env::var("COLUMNS").ok()?.parse().ok()
+}
+
+impl ColorMode {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Auto => stdout_is_tty() && std::env::var_os("NO_COLOR").is_none(),
+            ColorMode::Never => false,
+        }
+    }
+}
+
+fn severity_color(sev: Option<i64>) -> &'static str {
+    match sev {
+        Some(0..=3) => RED,
+        Some(4) => YELLOW,
+        Some(7) => DIM,
+        _ => "",
+    }
+}
+
+fn digits(mut n: i64) -> usize {
+    let mut d = if n < 0 { 2 } else { 1 };
+    while n / 10 != 0 {
+        n /= 10;
+        d += 1;
+    }
+    d
+}
+
+fn write_spaces(out: &mut dyn Write, mut n: usize) -> io::Result<()> {
+    while n > 0 {
+        let chunk = n.min(SPACES.len());
+        out.write_all(&SPACES.as_bytes()[..chunk])?;
+        n -= chunk;
+    }
+    Ok(())
+}
+
+/// Writes `text` broken into lines of at most `width - indent` characters (preferring to break
+/// at whitespace), with every line after the first indented by `indent` spaces.
+fn write_wrapped(out: &mut dyn Write, text: &str, indent: usize, width: usize) -> io::Result<()> {
+    let avail = width.saturating_sub(indent).max(20);
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            out.write_all(b"\n")?;
+            write_spaces(out, indent)?;
+        }
+        let mut rest = line;
+        loop {
+            let end = match rest.char_indices().nth(avail) {
+                Some((end, _)) => end,
+                None => {
+                    out.write_all(rest.as_bytes())?;
+                    break;
+                }
+            };
+            let (chunk, next) = match rest[..end].rfind(char::is_whitespace) {
+                Some(ws) if ws > 0 => (&rest[..ws], rest[ws..].trim_start()),
+                _ => (&rest[..end], &rest[end..]),
+            };
+            out.write_all(chunk.as_bytes())?;
+            if next.is_empty() {
+                break;
+            }
+            out.write_all(b"\n")?;
+            write_spaces(out, indent)?;
+            rest = next;
+        }
+    }
+    Ok(())
+}
+
+/// How `squealog` lays out rows; pick one with `--output`.
+pub trait Format {
+    fn print(&mut self, out: &mut dyn Write, row: &Row) -> io::Result<()>;
+
+    /// A journalctl-style `-- something happened --` line.
+    fn separator(&mut self, out: &mut dyn Write, text: fmt::Arguments) -> io::Result<()> {
+        writeln!(out, "-- {} --", text)
+    }
+
+    /// grep's `--` between groups of context lines.
+    fn group_break(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(b"--\n")
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputMode {
+    Default,
+    Short,
+    ShortIso,
+    ShortMonotonic,
+    Logfmt,
+    Fields,
+}
+
+impl FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(OutputMode::Default),
+            "short" => Ok(OutputMode::Short),
+            "short-iso" => Ok(OutputMode::ShortIso),
+            "short-monotonic" => Ok(OutputMode::ShortMonotonic),
+            "logfmt" => Ok(OutputMode::Logfmt),
+            "fields" => Ok(OutputMode::Fields),
+            _ => Err(format!("invalid output mode '{}'", s)),
+        }
+    }
+}
+
+impl OutputMode {
+    pub fn format(self, color: ColorMode, wrap: bool) -> Box<dyn Format> {
+        match self {
+            OutputMode::Default => Box::new(Printer::new(color.enabled(), wrap)),
+            OutputMode::Short => Box::new(Short::new(ShortTime::Syslog)),
+            OutputMode::ShortIso => Box::new(Short::new(ShortTime::Iso)),
+            OutputMode::ShortMonotonic => Box::new(Short::new(ShortTime::Monotonic)),
+            OutputMode::Logfmt => Box::new(Logfmt),
+            OutputMode::Fields => Box::new(Fields::new(FieldList::default(), false)),
+        }
+    }
+}
+
+/// Prints rows as `time severity appname[pid]: msg`, optionally colored and wrapped.
+/// Rows without an appname show the socket name instead.
+#[derive(Clone, Copy, Debug)]
+struct Printer {
+    color: bool,
+    wrap: Option<usize>,
+}
+
+impl Printer {
+    fn new(color: bool, wrap: bool) -> Printer {
+        Printer {
+            color,
+            wrap: if wrap {
+                Some(terminal_width().unwrap_or(80))
+            } else {
+                None
+            },
+        }
+    }
+}
+
+impl Format for Printer {
+    fn separator(&mut self, out: &mut dyn Write, text: fmt::Arguments) -> io::Result<()> {
+        if self.color {
+            writeln!(out, "{}-- {} --{}", DIM, text, RESET)
+        } else {
+            writeln!(out, "-- {} --", text)
+        }
+    }
+
+    fn print(&mut self, out: &mut dyn Write, row: &Row) -> io::Result<()> {
+        let (dim, bold, sev_color, reset) = if self.color {
+            (DIM, BOLD, severity_color(row.severity), RESET)
+        } else {
+            ("", "", "", "")
+        };
+        let sev_reset = if sev_color.is_empty() { "" } else { RESET };
+        let sev = row.severity.and_then(names::severity_name).unwrap_or("-");
+        let app = row.appname.as_deref().unwrap_or(&row.socket);
+        out.write_all(dim.as_bytes())?;
+        match row.time {
+            Some(t) => write!(out, "{}", time::display(&t).format(TIME_FORMAT))?,
+            None => out.write_all(b"-")?,
+        }
+        write!(
+            out,
+            "{} {}{:<7}{} {}{}{}",
+            reset, sev_color, sev, sev_reset, bold, app, reset
+        )?;
+        if let Some(pid) = row.pid {
+            write!(out, "[{}]", pid)?;
+        }
+        out.write_all(b": ")?;
+        match self.wrap {
+            Some(width) => {
+                let indent = if row.time.is_some() { 19 } else { 1 }
+                    + 1
+                    + sev.len().max(7)
+                    + 1
+                    + app.chars().count()
+                    + row.pid.map(|p| digits(p) + 2).unwrap_or(0)
+                    + 2;
+                let indent = if indent > width / 2 { 4 } else { indent };
+                write_wrapped(out, &row.msg, indent, width)?;
+            }
+            None => out.write_all(row.msg.as_bytes())?,
+        }
+        out.write_all(b"\n")
+    }
+}
+
+/// Microseconds from the start of the row's boot to the row.
+fn monotonic_micros(row: &Row) -> Option<i64> {
+    let boot = row.boot_time?;
+    let us = (row.time?.with_timezone(&Utc) - boot).num_microseconds()?;
+    Some(us.max(0))
+}
+
+/// dmesg's `[  123.456789]`.
+fn write_monotonic(out: &mut dyn fmt::Write, row: &Row) -> fmt::Result {
+    match monotonic_micros(row) {
+        Some(us) => write!(out, "[{:>5}.{:06}]", us / 1_000_000, us % 1_000_000),
+        None => out.write_str("[           -]"),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ShortTime {
+    /// `Jan 02 15:04:05`
+    Syslog,
+    /// `2006-01-02T15:04:05+0100`
+    Iso,
+    /// `[  123.456789]`, seconds since the row's boot
+    Monotonic,
+}
+
+/// journalctl's `short` family: `time hostname appname[pid]: msg`, with continuation lines of
+/// multi-line messages indented to line up under the first one.
+#[derive(Debug)]
+struct Short {
+    time: ShortTime,
+    /// Used for rows that came without a hostname, which is most local ones.
+    hostname: String,
+    prefix: String,
+}
+
+impl Short {
+    fn new(time: ShortTime) -> Short {
+        Short {
+            time,
+            hostname: squealog::sys::hostname().unwrap_or_else(|_| "localhost".to_owned()),
+            prefix: String::new(),
+        }
+    }
+
+    fn write_time(&mut self, row: &Row) -> fmt::Result {
+        use fmt::Write as _;
+        let time = row.time.map(|t| time::display(&t));
+        match (self.time, time) {
+            (ShortTime::Syslog, Some(t)) => write!(self.prefix, "{}", t.format("%b %d %H:%M:%S")),
+            (ShortTime::Iso, Some(t)) => write!(self.prefix, "{}", t.format("%Y-%m-%dT%H:%M:%S%z")),
+            (ShortTime::Monotonic, _) => write_monotonic(&mut self.prefix, row),
+            (_, None) => self.prefix.write_str("-"),
+        }
+    }
+}
+
+impl Format for Short {
+    fn print(&mut self, out: &mut dyn Write, row: &Row) -> io::Result<()> {
+        use fmt::Write as _;
+        self.prefix.clear();
+        let app = match row.appname {
+            Some(ref app) => app.as_str(),
+            None if names::KERNEL_SOCKETS.contains(&row.socket.as_str()) => "kernel",
+            None => row.socket.as_str(),
+        };
+        // Writing to a String can't fail.
+        let _ = self.write_time(row);
+        let host = row.hostname.as_deref().unwrap_or(&self.hostname);
+        let _ = write!(self.prefix, " {} {}", host, app);
+        if let Some(pid) = row.pid {
+            let _ = write!(self.prefix, "[{}]", pid);
+        }
+        self.prefix.push_str(": ");
+        out.write_all(self.prefix.as_bytes())?;
+        let indent = self.prefix.chars().count();
+        for (i, line) in row.msg.split('\n').enumerate() {
+            if i > 0 {
+                out.write_all(b"\n")?;
+                write_spaces(out, indent)?;
+            }
+            out.write_all(line.as_bytes())?;
+        }
+        out.write_all(b"\n")
+    }
+}
+
+/// Kernel messages the way dmesg shows them: `[  123.456789] msg`.
+#[derive(Debug)]
+pub struct Dmesg {
+    color: bool,
+    prefix: String,
+}
+
+impl Dmesg {
+    pub fn new(color: ColorMode) -> Dmesg {
+        Dmesg {
+            color: color.enabled(),
+            prefix: String::new(),
+        }
+    }
+}
+
+impl Format for Dmesg {
+    fn print(&mut self, out: &mut dyn Write, row: &Row) -> io::Result<()> {
+        self.prefix.clear();
+        // Writing to a String can't fail.
+        let _ = write_monotonic(&mut self.prefix, row);
+        self.prefix.push(' ');
+        let indent = self.prefix.chars().count();
+        let (dim, msg_color, reset) = if self.color {
+            (DIM, severity_color(row.severity), RESET)
+        } else {
+            ("", "", "")
+        };
+        write!(out, "{}{}{}{}", dim, self.prefix, reset, msg_color)?;
+        for (i, line) in row.msg.split('\n').enumerate() {
+            if i > 0 {
+                out.write_all(b"\n")?;
+                write_spaces(out, indent)?;
+            }
+            out.write_all(line.as_bytes())?;
+        }
+        if !msg_color.is_empty() {
+            out.write_all(RESET.as_bytes())?;
+        }
+        out.write_all(b"\n")
+    }
+}
+
+/// One `key=value` line per row. The keys are part of the interface, keep them stable:
+///
+/// - `time`: the message time (or receive time) in RFC 3339
+/// - `host`: the hostname the message came with, if any
+/// - `socket`: the socket it was received on
+/// - `app`, `pid`: as sent
+/// - `sev`: the severity name, like `err`
+/// - `msg`: the message text
+/// - `sd.<sdid>.<param>`: one key per structured data parameter
+///
+/// Fields without a value are left out. Values are quoted when they contain spaces, `=`, quotes
+/// or control characters, with `\"`, `\\`, `\n`, `\r` and `\t` escapes inside the quotes.
+#[derive(Clone, Copy, Debug)]
+struct Logfmt;
+
+fn write_logfmt_value(out: &mut dyn Write, value: &str) -> io::Result<()> {
+    let quote = value
+        .chars()
+        .any(|c| c == ' ' || c == '=' || c == '"' || c == '\\' || c.is_control());
+    if !quote {
+        return out.write_all(value.as_bytes());
+    }
+    out.write_all(b"\"")?;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        let escape = match c {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            _ => continue,
+        };
+        out.write_all(&value.as_bytes()[start..i])?;
+        out.write_all(escape.as_bytes())?;
+        start = i + c.len_utf8();
+    }
+    out.write_all(&value.as_bytes()[start..])?;
+    out.write_all(b"\"")
+}
+
+impl Logfmt {
+    fn field(out: &mut dyn Write, first: &mut bool, key: &str, value: &str) -> io::Result<()> {
+        if value.is_empty() {
+            return Ok(());
+        }
+        if !*first {
+            out.write_all(b" ")?;
+        }
+        *first = false;
+        write!(out, "{}=", key)?;
+        write_logfmt_value(out, value)
+    }
+}
+
+impl Format for Logfmt {
+    fn print(&mut self, out: &mut dyn Write, row: &Row) -> io::Result<()> {
+        let mut first = true;
+        let time = row.time.map(|t| t.to_rfc3339()).unwrap_or_default();
+        Logfmt::field(out, &mut first, "time", &time)?;
+        Logfmt::field(
+            out,
+            &mut first,
+            "host",
+            row.hostname.as_deref().unwrap_or(""),
+        )?;
+        Logfmt::field(out, &mut first, "socket", &row.socket)?;
+        Logfmt::field(out, &mut first, "app", row.appname.as_deref().unwrap_or(""))?;
+        let pid = row.pid.map(|p| p.to_string()).unwrap_or_default();
+        Logfmt::field(out, &mut first, "pid", &pid)?;
+        let sev = row.severity.and_then(names::severity_name).unwrap_or("");
+        Logfmt::field(out, &mut first, "sev", sev)?;
+        Logfmt::field(out, &mut first, "msg", &row.msg)?;
+        if let Some(ref sdata) = row.sdata {
+            for (id, param, value) in squealog::sdata::params(sdata) {
+                Logfmt::field(out, &mut first, &format!("sd.{}.{}", id, param), &value)?;
+            }
+        }
+        out.write_all(b"\n")
+    }
+}
+
+/// A column for `--output fields`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Id,
+    /// Seconds since the epoch, with microseconds.
+    Time,
+    /// RFC 3339.
+    IsoTime,
+    Severity,
+    Socket,
+    Hostname,
+    Appname,
+    Pid,
+    Msgid,
+    Msg,
+    /// The structured data JSON.
+    Sdata,
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "id" => Field::Id,
+            "time" => Field::Time,
+            "iso-time" => Field::IsoTime,
+            "severity" => Field::Severity,
+            "socket" => Field::Socket,
+            "hostname" => Field::Hostname,
+            "appname" => Field::Appname,
+            "pid" => Field::Pid,
+            "msgid" => Field::Msgid,
+            "msg" => Field::Msg,
+            "sdata" => Field::Sdata,
+            _ => return Err(format!("unknown field '{}'", s)),
+        })
+    }
+}
+
+/// A comma separated list of fields.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldList(pub Vec<Field>);
+
+impl Default for FieldList {
+    fn default() -> Self {
+        FieldList(vec![
+            Field::Time,
+            Field::Severity,
+            Field::Appname,
+            Field::Msg,
+        ])
+    }
+}
+
+impl FromStr for FieldList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|f| f.trim().parse())
+            .collect::<Result<_, _>>()
+            .map(FieldList)
+    }
+}
+
+/// Script-friendly columns without any decoration.
+///
+/// Normally a record is a line of tab separated fields, with backslash, tab and newline in
+/// values escaped as `\\`, `\t` and `\n`. With `-z`, every field is terminated by a NUL instead
+/// and values are written as they are, so a record is exactly as many NUL-terminated fields as
+/// were asked for. NUL bytes can't be told apart from terminators, so they turn into U+2400.
+#[derive(Clone, Debug)]
+pub struct Fields {
+    fields: FieldList,
+    zero: bool,
+}
+
+impl Fields {
+    pub fn new(fields: FieldList, zero: bool) -> Fields {
+        Fields { fields, zero }
+    }
+
+    fn value(row: &Row, field: Field) -> Cow<'_, str> {
+        let owned = match field {
+            Field::Id => row.id.to_string(),
+            Field::Time => match row.time {
+                Some(t) => {
+                    let us = t.timestamp_micros();
+                    format!(
+                        "{}.{:06}",
+                        us.div_euclid(1_000_000),
+                        us.rem_euclid(1_000_000)
+                    )
+                }
+                None => String::new(),
+            },
+            Field::IsoTime => row.time.map(|t| t.to_rfc3339()).unwrap_or_default(),
+            Field::Severity => {
+                return row
+                    .severity
+                    .and_then(names::severity_name)
+                    .unwrap_or("")
+                    .into()
+            }
+            Field::Socket => return row.socket.as_str().into(),
+            Field::Hostname => return row.hostname.as_deref().unwrap_or("").into(),
+            Field::Appname => return row.appname.as_deref().unwrap_or("").into(),
+            Field::Pid => row.pid.map(|p| p.to_string()).unwrap_or_default(),
+            Field::Msgid => return row.msgid.as_deref().unwrap_or("").into(),
+            Field::Msg => return row.msg.as_str().into(),
+            Field::Sdata => return row.sdata.as_deref().unwrap_or("").into(),
+        };
+        owned.into()
+    }
+}
+
+impl Format for Fields {
+    fn print(&mut self, out: &mut dyn Write, row: &Row) -> io::Result<()> {
+        for (i, &field) in self.fields.0.iter().enumerate() {
+            let value = Fields::value(row, field);
+            if self.zero {
+                out.write_all(value.replace('\0', "\u{2400}").as_bytes())?;
+                out.write_all(b"\0")?;
+                continue;
+            }
+            if i > 0 {
+                out.write_all(b"\t")?;
+            }
+            let mut start = 0;
+            for (j, c) in value.char_indices() {
+                let escape = match c {
+                    '\\' => "\\\\",
+                    '\t' => "\\t",
+                    '\n' => "\\n",
+                    _ => continue,
+                };
+                out.write_all(value[start..j].as_bytes())?;
+                out.write_all(escape.as_bytes())?;
+                start = j + 1;
+            }
+            out.write_all(value[start..].as_bytes())?;
+        }
+        if !self.zero {
+            out.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn separator(&mut self, _out: &mut dyn Write, _text: fmt::Arguments) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn group_break(&mut self, _out: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What `PidTracker` makes of a row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unit {
+    Same,
+    /// The unit is running under a new pid from this row on.
+    PidChanged(i64, i64),
+    /// A kernel message that doesn't mention any of the unit's pids.
+    Unrelated,
+}
+
+/// Follows one appname across restarts: notices pid changes, and picks out the kernel messages
+/// that mention one of the pids the unit has had (OOM kills, signal exits and such).
+#[derive(Debug)]
+pub struct PidTracker {
+    appname: String,
+    pids: std::collections::HashSet<i64>,
+    current: Option<i64>,
+}
+
+/// Numbers following "pid " or "process ", as in FreeBSD's "pid 123 (foo), jid 0, uid 0: exited
+/// on signal 11" or Linux's "Killed process 123 (foo)".
+pub fn mentioned_pids(msg: &str) -> impl Iterator<Item = i64> + '_ {
+    ["pid ", "process "].into_iter().flat_map(move |word| {
+        msg.match_indices(word).filter_map(move |(i, _)| {
+            let rest = &msg[i + word.len()..];
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            rest[..end].parse().ok()
+        })
+    })
+}
+
+impl PidTracker {
+    pub fn new(appname: &str) -> PidTracker {
+        PidTracker {
+            appname: appname.to_owned(),
+            pids: Default::default(),
+            current: None,
+        }
+    }
+
+    pub fn appname(&self) -> &str {
+        &self.appname
+    }
+
+    pub fn check(&mut self, row: &Row) -> Unit {
+        if row.appname.as_deref() != Some(self.appname.as_str()) {
+            return if mentioned_pids(&row.msg).any(|p| self.pids.contains(&p)) {
+                Unit::Same
+            } else {
+                Unit::Unrelated
+            };
+        }
+        let pid = match row.pid {
+            Some(pid) => pid,
+            None => return Unit::Same,
+        };
+        self.pids.insert(pid);
+        match self.current.replace(pid) {
+            Some(old) if old != pid => Unit::PidChanged(old, pid),
+            _ => Unit::Same,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(id: i64, msg: &str) -> Row {
+        Row {
+            id,
+            time: Some("2024-01-02T03:04:05.000006+00:00".parse().unwrap()),
+            severity: Some(3),
+            socket: "log".to_owned(),
+            hostname: None,
+            appname: Some("app\tname".to_owned()),
+            pid: Some(42),
+            msgid: None,
+            msg: msg.to_owned(),
+            boot_time: None,
+            sdata: None,
+        }
+    }
+
+    const MESSAGES: &[&str] = &[
+        "plain",
+        "two\nlines\n",
+        "tab\tand back\\slash",
+        "\0 before, after \0, and\0\0between",
+        "\n\t\0",
+        "",
+    ];
+
+    fn print(fields: &str, zero: bool) -> Vec<u8> {
+        let mut format = Fields::new(fields.parse().unwrap(), zero);
+        let mut out = vec![];
+        for (i, msg) in MESSAGES.iter().enumerate() {
+            format.print(&mut out, &row(i as i64, msg)).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn nul_terminated_fields_are_one_record_per_row() {
+        let out = String::from_utf8(print("id,time,severity,appname,hostname,msg", true)).unwrap();
+        let fields: Vec<&str> = out.strip_suffix('\0').unwrap().split('\0').collect();
+        let records: Vec<&[&str]> = fields.chunks(6).collect();
+        assert_eq!(records.len(), MESSAGES.len());
+        for (i, (record, msg)) in records.iter().zip(MESSAGES).enumerate() {
+            assert_eq!(
+                record,
+                &[
+                    &i.to_string()[..],
+                    "1704164645.000006",
+                    "err",
+                    "app\tname",
+                    "",
+                    &msg.replace('\0', "\u{2400}")[..],
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn tab_separated_fields_are_one_line_per_row() {
+        let out = String::from_utf8(print("appname,msg", false)).unwrap();
+        let lines: Vec<&str> = out.strip_suffix('\n').unwrap().split('\n').collect();
+        assert_eq!(lines.len(), MESSAGES.len());
+        for (line, msg) in lines.iter().zip(MESSAGES) {
+            let unescaped: Vec<String> = line
+                .split('\t')
+                .map(|value| {
+                    let mut s = String::new();
+                    let mut chars = value.chars();
+                    while let Some(c) = chars.next() {
+                        s.push(match c {
+                            '\\' => match chars.next() {
+                                Some('t') => '\t',
+                                Some('n') => '\n',
+                                Some(c) => c,
+                                None => panic!("lone backslash in {:?}", line),
+                            },
+                            c => c,
+                        });
+                    }
+                    s
+                })
+                .collect();
+            assert_eq!(unescaped, ["app\tname", msg]);
+        }
+    }
+
+    #[test]
+    fn pid_tracker_follows_restarts() {
+        use Unit::*;
+        // A daemon that crashes twice, with the kernel and others logging in between.
+        let rows: &[(Option<&str>, Option<i64>, &str, Unit)] = &[
+            (Some("other"), Some(7), "starting", Unrelated),
+            (
+                Some("kernel"),
+                None,
+                "pid 100 (d), jid 0, uid 0: exited on signal 11",
+                Unrelated,
+            ),
+            (Some("d"), Some(100), "starting", Same),
+            (Some("d"), Some(100), "serving", Same),
+            (Some("d"), None, "from a helper without a pid", Same),
+            (
+                Some("kernel"),
+                None,
+                "pid 100 (d), jid 0, uid 0: exited on signal 11",
+                Same,
+            ),
+            (Some("d"), Some(101), "starting", PidChanged(100, 101)),
+            (
+                Some("kernel"),
+                None,
+                "Out of memory: Killed process 101 (d)",
+                Same,
+            ),
+            (
+                Some("kernel"),
+                None,
+                "Killed process 1010 (other)",
+                Unrelated,
+            ),
+            (Some("d"), Some(102), "starting", PidChanged(101, 102)),
+            // The old pids are still the unit's.
+            (Some("kernel"), None, "pid 100 (d): core dumped", Same),
+            (None, None, "no appname at all", Unrelated),
+            (
+                Some("d"),
+                Some(101),
+                "a child of the old one",
+                PidChanged(102, 101),
+            ),
+        ];
+        let mut tracker = PidTracker::new("d");
+        for (i, &(appname, pid, msg, expected)) in rows.iter().enumerate() {
+            let mut row = row(i as i64, msg);
+            row.appname = appname.map(str::to_owned);
+            row.pid = pid;
+            assert_eq!(tracker.check(&row), expected, "row {}: {:?}", i, msg);
+        }
+    }
+
+    #[test]
+    fn mentioned_pids_are_after_pid_or_process() {
+        let cases: &[(&str, &[i64])] = &[
+            ("pid 123 (foo), jid 0, uid 0: exited on signal 11", &[123]),
+            ("Killed process 456 (bar) total-vm:1kB", &[456]),
+            ("pid 1 and pid 2 and process 3", &[1, 2, 3]),
+            ("pid x, pids 4", &[]),
+            ("pid", &[]),
+            ("no numbers here", &[]),
+        ];
+        for &(msg, pids) in cases {
+            assert_eq!(mentioned_pids(msg).collect::<Vec<_>>(), pids, "{:?}", msg);
+        }
+    }
+
+    /// What a row says, the way the ingest side sees it: time, host, app, pid, severity, text,
+    /// and the structured data params sorted.
+    type Seen = (
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<i64>,
+        Option<i64>,
+        String,
+        Vec<(String, String, String)>,
+    );
+
+    fn seen(row: &Row) -> Seen {
+        let mut params = row
+            .sdata
+            .as_deref()
+            .map(squealog::sdata::params)
+            .unwrap_or_default();
+        params.sort();
+        (
+            row.time.map(|t| t.to_rfc3339()),
+            row.hostname.clone(),
+            row.appname.clone(),
+            row.pid,
+            row.severity,
+            row.msg.clone(),
+            params,
+        )
+    }
+
+    fn ingested(line: &[u8]) -> Seen {
+        use squealog::payload::{Logfmt, PayloadParser, SourceCtx};
+        let ctx = SourceCtx {
+            socket: "logfmt",
+            local: true,
+            boot_time: Utc::now(),
+            received: Utc::now(),
+        };
+        let msg = Logfmt.parse(line, &ctx).unwrap();
+        let mut params: Vec<(String, String, String)> = msg
+            .structured_data
+            .iter()
+            .flat_map(|e| {
+                e.params
+                    .iter()
+                    .map(|(k, v)| (e.id.to_string(), k.to_string(), v.to_string()))
+            })
+            .collect();
+        params.sort();
+        (
+            msg.timestamp.map(|t| t.to_rfc3339()),
+            msg.hostname.map(|h| h.into_owned()),
+            msg.appname.map(|a| a.into_owned()),
+            msg.procid.map(|p| match p {
+                syslog_loose::ProcId::PID(pid) => pid as i64,
+                syslog_loose::ProcId::Name(name) => panic!("pid {:?}", name),
+            }),
+            msg.severity.map(|s| s as i64),
+            msg.msg.into_owned(),
+            params,
+        )
+    }
+
+    #[test]
+    fn logfmt_is_ingested_as_it_was_stored() {
+        let sdata = |elements: &[(&str, &[(&str, &str)])]| {
+            let elements: Vec<syslog_loose::StructuredElement<&str>> = elements
+                .iter()
+                .map(|&(id, params)| syslog_loose::StructuredElement {
+                    id,
+                    params: params.to_vec(),
+                })
+                .collect();
+            squealog::sdata::to_json(&elements)
+        };
+        let mut rows: Vec<Row> = MESSAGES
+            .iter()
+            .chain(&[
+                "key=value and \"quotes\" \\ backslashes",
+                "carriage\rreturn",
+                "ünïcödé ☃",
+                "trailing space ",
+                " ",
+                "=",
+            ])
+            .enumerate()
+            .map(|(i, msg)| row(i as i64, msg))
+            .collect();
+        rows[1].hostname = Some("web1".to_owned());
+        rows[2].appname = None;
+        rows[2].pid = None;
+        rows[2].severity = None;
+        rows[3].sdata = sdata(&[
+            (
+                "origin@32473.1",
+                &[("ip", "10.0.0.1"), ("software", "a b \"c\"")],
+            ),
+            ("x", &[("k", "v=w"), ("empty", "")]),
+        ]);
+        rows[4].time = None;
+
+        let mut out = vec![];
+        for row in &rows {
+            Logfmt.print(&mut out, row).unwrap();
+        }
+        let lines: Vec<&[u8]> = out
+            .strip_suffix(b"\n")
+            .unwrap()
+            .split(|&b| b == b'\n')
+            .collect();
+        assert_eq!(lines.len(), rows.len());
+        for (row, line) in rows.iter().zip(lines) {
+            let mut expected = seen(row);
+            // Left out for being empty, on the way out.
+            expected.6.retain(|(_, _, value)| !value.is_empty());
+            assert_eq!(
+                ingested(line),
+                expected,
+                "{}",
+                String::from_utf8_lossy(line)
+            );
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Piping long output through `$PAGER` the way `journalctl` does.
+//!
+//! Rows are written to the pager as they're read, so a query matching millions of rows starts
+//! showing them right away. When the pager quits, a watcher thread interrupts whatever
+//! statement is running on the connection, so the scan stops even if it's in the middle of a
+//! long stretch of rows that don't match.
+
+use crate::output::{stdout_is_tty, ColorMode};
+use rusqlite::Connection;
+use std::io::{self, LineWriter, Write};
+use std::process::{ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+const DEFAULT_PAGER: &str = "less -RFX";
+
+pub struct Pager {
+    stdin: Option<LineWriter<ChildStdin>>,
+    quit: Arc<AtomicBool>,
+    watcher: Option<JoinHandle<()>>,
+    color: bool,
+}
+
+/// `SQUEALOG_PAGER`, then `PAGER`; empty or `cat` means no pager.
+fn command() -> Option<String> {
+    let cmd = std::env::var("SQUEALOG_PAGER")
+        .or_else(|_| std::env::var("PAGER"))
+        .unwrap_or_else(|_| DEFAULT_PAGER.to_owned());
+    let cmd = cmd.trim();
+    if cmd.is_empty() || cmd == "cat" {
+        return None;
+    }
+    Some(cmd.to_owned())
+}
+
+/// Whether the pager will show escape sequences as colors rather than as `ESC[31m` garbage.
+fn passes_color(cmd: &str, less_env: &str) -> bool {
+    let mut words = cmd.split_whitespace();
+    let program = words.next().unwrap_or("");
+    if program.rsplit('/').next() != Some("less") {
+        return false;
+    }
+    let flag = |w: &str| w.starts_with('-') && !w.starts_with("--") && w.contains(['R', 'r']);
+    words.any(flag) || less_env.contains(['R', 'r'])
+}
+
+impl Pager {
+    /// Starts the pager if stdout is a terminal and paging wasn't turned off.
+    pub fn start(conn: &Connection, disabled: bool) -> io::Result<Option<Pager>> {
+        if disabled || !stdout_is_tty() {
+            return Ok(None);
+        }
+        let cmd = match command() {
+            Some(cmd) => cmd,
+            None => return Ok(None),
+        };
+        // Same defaults as git for a bare `less`: quit if it fits a screen, pass colors.
+        let less_env = std::env::var("LESS").unwrap_or_else(|_| "FRX".to_owned());
+        let mut child = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(&cmd)
+            .env("LESS", &less_env)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().map(LineWriter::new);
+        let quit = Arc::new(AtomicBool::new(false));
+        let interrupt = conn.get_interrupt_handle();
+        let watcher = {
+            let quit = quit.clone();
+            std::thread::spawn(move || {
+                let _ = child.wait();
+                quit.store(true, Ordering::SeqCst);
+                interrupt.interrupt();
+            })
+        };
+        Ok(Some(Pager {
+            stdin,
+            quit,
+            watcher: Some(watcher),
+            color: passes_color(&cmd, &less_env),
+        }))
+    }
+
+    /// What `--color auto` means with this pager in between.
+    pub fn color(&self, mode: ColorMode) -> ColorMode {
+        match mode {
+            ColorMode::Auto if !self.color => ColorMode::Never,
+            mode => mode,
+        }
+    }
+
+    /// Whether `err` is just the pager having gone away, which ends the query early.
+    pub fn cancelled(&self, err: &anyhow::Error) -> bool {
+        self.quit.load(Ordering::SeqCst)
+            || err
+                .downcast_ref::<io::Error>()
+                .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+    }
+
+    /// Closes the pipe and waits for the user to quit the pager.
+    pub fn finish(mut self) -> io::Result<()> {
+        let flushed = match self.stdin.take() {
+            Some(mut stdin) => stdin.flush(),
+            None => Ok(()),
+        };
+        if let Some(watcher) = self.watcher.take() {
+            let _ = watcher.join();
+        }
+        match flushed {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            r => r,
+        }
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.stdin {
+            Some(ref mut stdin) => stdin.write(buf),
+            None => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stdin {
+            Some(ref mut stdin) => stdin.flush(),
+            None => Ok(()),
+        }
+    }
+}
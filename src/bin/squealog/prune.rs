@@ -0,0 +1,209 @@
+use chrono::prelude::*;
+use rusqlite::{Connection, OptionalExtension};
+use squealog::stats::{human_size, parse_size};
+use squealog::time::TimeSpec;
+use std::str::FromStr;
+
+/// Rows deleted per transaction, so that the daemon never waits on us for long.
+const CHUNK: i64 = 10000;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Size(pub u64);
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_size(s)
+            .map(Size)
+            .ok_or_else(|| format!("invalid size '{}'", s))
+    }
+}
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Delete messages received before this time
+    #[clap(long)]
+    before: Option<TimeSpec>,
+    /// Delete messages received more than this many days ago
+    #[clap(long)]
+    keep_days: Option<u32>,
+    /// Delete the oldest messages until the data takes up about this much space (e.g. 2G)
+    #[clap(long)]
+    target_size: Option<Size>,
+    /// Only print how much would be deleted
+    #[clap(long)]
+    dry_run: bool,
+}
+
+fn pragma(conn: &Connection, name: &str) -> rusqlite::Result<i64> {
+    conn.pragma_query_value(None, name, |row| row.get(0))
+}
+
+/// Bytes used by pages that aren't free.
+fn used_bytes(conn: &Connection) -> rusqlite::Result<i64> {
+    Ok(
+        (pragma(conn, "page_count")? - pragma(conn, "freelist_count")?)
+            * pragma(conn, "page_size")?,
+    )
+}
+
+/// What's deleted: rows received before `?1` (if it isn't NULL), and rows before id `?2`.
+/// Ids don't follow receive times: `import` and `merge` add old messages with new ids.
+/// Rows from before `recv_time` was stored go by their own time, like in `log_summary`.
+const DOOMED: &str = "(coalesce(recv_time, time) < ?1 OR id < ?2)";
+
+pub fn run(conn: &Connection, args: Args) -> anyhow::Result<()> {
+    if args.before.is_none() && args.keep_days.is_none() && args.target_size.is_none() {
+        anyhow::bail!("Refusing to prune without --before, --keep-days or --target-size");
+    }
+    conn.busy_timeout(std::time::Duration::from_secs(10))?;
+
+    let (first, max): (Option<i64>, Option<i64>) =
+        conn.query_row("SELECT min(id), max(id) FROM log", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+    let (first, end) = match (first, max) {
+        (Some(first), Some(max)) => (first, max + 1),
+        _ => {
+            println!("Nothing to prune, the log is empty");
+            return Ok(());
+        }
+    };
+    let rows: i64 = conn.query_row("SELECT count(*) FROM log", [], |row| row.get(0))?;
+    let bytes_per_row = used_bytes(conn)? as f64 / rows.max(1) as f64;
+
+    let before = args
+        .before
+        .map(TimeSpec::resolve)
+        .into_iter()
+        .chain(
+            args.keep_days
+                .map(|d| Utc::now() - chrono::Duration::days(d.into())),
+        )
+        .max();
+    // Rows before it are deleted for size, the oldest by id.
+    let mut cutoff = first;
+    if let Some(Size(target)) = args.target_size {
+        let excess = used_bytes(conn)? - target as i64;
+        if excess > 0 {
+            let n = (excess as f64 / bytes_per_row).ceil() as i64;
+            let id = conn
+                .query_row(
+                    "SELECT id FROM log ORDER BY id LIMIT 1 OFFSET ?",
+                    [n],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .unwrap_or(end);
+            cutoff = cutoff.max(id);
+        }
+    }
+
+    let doomed: i64 = conn.query_row(
+        &format!("SELECT count(*) FROM log WHERE {}", DOOMED),
+        rusqlite::params![before, cutoff],
+        |row| row.get(0),
+    )?;
+    println!(
+        "{} {} of {} rows (about {})",
+        if args.dry_run {
+            "Would delete"
+        } else {
+            "Deleting"
+        },
+        doomed,
+        rows,
+        human_size((doomed as f64 * bytes_per_row) as u64)
+    );
+    if args.dry_run || doomed == 0 {
+        return Ok(());
+    }
+
+    let delete = format!("DELETE FROM log WHERE id >= ?3 AND id < ?4 AND {}", DOOMED);
+    let mut lo = first;
+    while lo < end {
+        let hi = end.min(lo + CHUNK);
+        conn.execute(&delete, rusqlite::params![before, cutoff, lo, hi])?;
+        lo = hi;
+    }
+
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+    if pragma(conn, "auto_vacuum")? == 2 {
+        conn.execute_batch("PRAGMA incremental_vacuum")?;
+    } else {
+        println!("The database isn't in incremental auto_vacuum mode, the freed space will be reused but the file won't shrink without a VACUUM");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db(rows: &[(i64, &str)]) -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        squealog::schema::migrations().to_latest(&mut conn).unwrap();
+        for &(id, recv_time) in rows {
+            conn.execute(
+                "INSERT INTO log (id, socket, msg, recv_time) VALUES (?, 'log', 'hi', ?)",
+                rusqlite::params![id, recv_time],
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    fn ids(conn: &Connection) -> Vec<i64> {
+        conn.prepare("SELECT id FROM log ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    fn before(t: &str, dry_run: bool) -> Args {
+        Args {
+            before: Some(t.parse().unwrap()),
+            keep_days: None,
+            target_size: None,
+            dry_run,
+        }
+    }
+
+    #[test]
+    fn before_goes_by_receive_time_not_id() {
+        // 3 and 4 were imported or merged after 2 arrived, with their old receive times.
+        let conn = db(&[
+            (1, "2020-01-01 00:00:00+00:00"),
+            (2, "2024-01-01 00:00:00+00:00"),
+            (3, "2019-06-01 00:00:00+00:00"),
+            (4, "2021-06-01 00:00:00+00:00"),
+            (5, "2024-02-01 00:00:00+00:00"),
+        ]);
+        run(&conn, before("2022-01-01T00:00:00Z", true)).unwrap();
+        assert_eq!(ids(&conn), [1, 2, 3, 4, 5]);
+        run(&conn, before("2022-01-01T00:00:00Z", false)).unwrap();
+        assert_eq!(ids(&conn), [2, 5]);
+    }
+
+    #[test]
+    fn deletes_across_chunks() {
+        let rows: Vec<(i64, &str)> = (1..=CHUNK * 2 + 5)
+            .map(|id| {
+                let t = if id % 2 == 0 {
+                    "2020-01-01 00:00:00+00:00"
+                } else {
+                    "2024-01-01 00:00:00+00:00"
+                };
+                (id, t)
+            })
+            .collect();
+        let conn = db(&rows);
+        run(&conn, before("2022-01-01T00:00:00Z", false)).unwrap();
+        let left = ids(&conn);
+        assert_eq!(left.len() as i64, CHUNK + 3);
+        assert!(left.iter().all(|id| id % 2 == 1));
+    }
+}
@@ -0,0 +1,457 @@
+use crate::config::Alias;
+use crate::output::{ColorMode, FieldList, Fields, Format, OutputMode, PidTracker, Unit};
+use crate::pager::Pager;
+use rusqlite::Connection;
+use squealog::{
+    digest,
+    filter::{Facility, Filter, SeverityRange},
+    query::{select, select_context, Row},
+    time::TimeSpec,
+};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+const FOLLOW_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Show messages received since this time
+    #[clap(short = 'S', long)]
+    since: Option<TimeSpec>,
+    /// Show messages received before this time
+    #[clap(short = 'U', long)]
+    until: Option<TimeSpec>,
+    /// Show messages from one boot: 0 is the current one, -1 the previous one, or a boot UUID
+    #[clap(short = 'b', long, allow_hyphen_values = true)]
+    boot: Option<String>,
+    /// Severity or range of severities, e.g. "err", "debug..notice"
+    #[clap(short = 'p', long)]
+    priority: Option<SeverityRange>,
+    /// Facility name or number (can be repeated)
+    #[clap(long)]
+    facility: Vec<Facility>,
+    /// Application name (can be repeated)
+    #[clap(short = 't', long)]
+    appname: Vec<String>,
+    /// Socket name (can be repeated)
+    #[clap(long)]
+    socket: Vec<String>,
+    /// Process ID; only the newest process that had it is shown, see --all-incarnations
+    #[clap(long)]
+    pid: Option<i64>,
+    /// With --pid, show every process that had the pid, not just the newest one
+    #[clap(long, requires = "pid")]
+    all_incarnations: bool,
+    /// Follow an application across restarts, marking where its pid changes
+    #[clap(short = 'u', long)]
+    unit: Option<String>,
+    /// With --unit, also show kernel messages about its processes (e.g. signal exits)
+    #[clap(long, requires = "unit")]
+    with_kernel: bool,
+    /// Only show messages containing this text (can be repeated, all have to match)
+    #[clap(short = 'g', long)]
+    grep: Vec<String>,
+    /// Also show N messages before each match from the same socket and host
+    #[clap(short = 'B', long, conflicts_with_all = &["follow", "unit"])]
+    before_context: Option<usize>,
+    /// Also show N messages after each match from the same socket and host
+    #[clap(short = 'A', long, conflicts_with_all = &["follow", "unit"])]
+    after_context: Option<usize>,
+    /// Same as -B N -A N
+    #[clap(short = 'C', long, conflicts_with_all = &["follow", "unit"])]
+    context: Option<usize>,
+    /// Only show the newest N messages
+    #[clap(short = 'n', long)]
+    lines: Option<usize>,
+    /// Show the newest messages first
+    #[clap(short, long)]
+    reverse: bool,
+    /// Keep printing new messages as they arrive
+    #[clap(short, long)]
+    follow: bool,
+    /// Output format: default, short, short-iso, short-monotonic, logfmt or fields
+    #[clap(short, long, default_value = "default")]
+    output: OutputMode,
+    /// Columns for --output fields: id, time, iso-time, severity, socket, hostname, appname,
+    /// pid, msgid, msg, sdata (implies --output fields)
+    #[clap(long)]
+    fields: Option<FieldList>,
+    /// Terminate every field with a NUL instead of tab separated lines (implies --output fields)
+    #[clap(short = 'z', long)]
+    zero: bool,
+    /// When to color the output: always, auto or never
+    #[clap(long, default_value = "auto")]
+    color: ColorMode,
+    /// Wrap long messages to the terminal width
+    #[clap(long)]
+    wrap: bool,
+    /// Don't pipe the output into $PAGER (less -RFX by default)
+    #[clap(long)]
+    no_pager: bool,
+}
+
+impl Args {
+    pub fn filter(&self, conn: &Connection) -> anyhow::Result<Filter> {
+        Ok(Filter {
+            since: self.since.map(TimeSpec::resolve),
+            until: self.until.map(TimeSpec::resolve),
+            boot: match self.boot {
+                Some(ref b) => Some(squealog::boot::resolve(conn, b)?),
+                None => None,
+            },
+            severity: self.priority,
+            severities: vec![],
+            facilities: self.facility.clone(),
+            appnames: self.appname.clone(),
+            sockets: self.socket.clone(),
+            pid: self.pid,
+            unit: self.unit.clone(),
+            unit_kernel: self.with_kernel,
+            greps: self.grep.clone(),
+        })
+    }
+
+    /// Fills in the filters of a saved query. Whatever was given on the command line wins,
+    /// except for `-g`, where the alias's texts and the command line's all have to match.
+    pub fn apply(&mut self, name: &str, alias: &Alias) -> anyhow::Result<()> {
+        fn parse<T: std::str::FromStr<Err = String>>(name: &str, v: &str) -> anyhow::Result<T> {
+            v.parse()
+                .map_err(|e| anyhow::format_err!("In alias @{}: {}", name, e))
+        }
+        if self.since.is_none() {
+            self.since = alias.since.as_deref().map(|v| parse(name, v)).transpose()?;
+        }
+        if self.until.is_none() {
+            self.until = alias.until.as_deref().map(|v| parse(name, v)).transpose()?;
+        }
+        if self.boot.is_none() {
+            self.boot = alias.boot.clone();
+        }
+        if self.priority.is_none() {
+            self.priority = alias
+                .severity
+                .as_deref()
+                .map(|v| parse(name, v))
+                .transpose()?;
+        }
+        if self.facility.is_empty() {
+            for v in &alias.facility.0 {
+                self.facility.push(parse(name, v)?);
+            }
+        }
+        if self.appname.is_empty() {
+            self.appname = alias.appname.0.clone();
+        }
+        if self.socket.is_empty() {
+            self.socket = alias.socket.0.clone();
+        }
+        self.pid = self.pid.or(alias.pid);
+        if self.unit.is_none() {
+            self.unit = alias.unit.clone();
+        }
+        self.grep.splice(0..0, alias.grep.0.iter().cloned());
+        Ok(())
+    }
+
+    fn format(&self, color: ColorMode) -> Box<dyn Format> {
+        if self.zero || self.fields.is_some() || self.output == OutputMode::Fields {
+            let fields = self.fields.clone().unwrap_or_default();
+            return Box::new(Fields::new(fields, self.zero));
+        }
+        self.output.format(color, self.wrap)
+    }
+
+    /// How many rows to show (before, after) each match, if any.
+    fn context(&self) -> Option<(usize, usize)> {
+        let before = self.before_context.or(self.context);
+        let after = self.after_context.or(self.context);
+        if before.is_none() && after.is_none() {
+            return None;
+        }
+        Some((before.unwrap_or(0), after.unwrap_or(0)))
+    }
+}
+
+/// Prints matches with their context, putting a `--` line between groups that aren't
+/// contiguous, like grep does.
+fn run_context(
+    conn: &Connection,
+    args: &Args,
+    filter: &Filter,
+    out: &mut dyn Write,
+    format: &mut dyn Format,
+    (before, after): (usize, usize),
+) -> anyhow::Result<()> {
+    let mut positions: HashMap<(String, Option<String>), i64> = HashMap::new();
+    let mut started = false;
+    let mut emit = |out: &mut dyn Write, row: &Row, rn: i64| -> io::Result<()> {
+        let prev = positions.insert((row.socket.clone(), row.hostname.clone()), rn);
+        if started && prev.is_none_or(|p| (p - rn).abs() != 1) {
+            format.group_break(out)?;
+        }
+        started = true;
+        format.print(out, row)
+    };
+    match args.lines {
+        Some(n) => {
+            let mut rows = vec![];
+            select_context(conn, filter, before, after, true, Some(n), |row, rn| {
+                rows.push((row, rn));
+                Ok(())
+            })?;
+            if !args.reverse {
+                rows.reverse();
+            }
+            for (row, rn) in rows {
+                emit(out, &row, rn)?;
+            }
+        }
+        None => select_context(
+            conn,
+            filter,
+            before,
+            after,
+            args.reverse,
+            None,
+            |row, rn| Ok(emit(out, &row, rn)?),
+        )?,
+    }
+    Ok(())
+}
+
+/// With `--pid`: splits the rows into the processes that had the pid and prints the newest one
+/// (or all of them), each under a separator line. Returns the last id printed.
+fn print_incarnations(
+    conn: &Connection,
+    args: &Args,
+    filter: &Filter,
+    out: &mut dyn Write,
+    format: &mut dyn Format,
+) -> anyhow::Result<Option<i64>> {
+    let mut rows = vec![];
+    select(conn, filter, None, None, false, None, |row| {
+        rows.push(row);
+        Ok(())
+    })?;
+    let last = rows.last().map(|r| r.id);
+    let groups = digest::incarnations(rows, digest::INCARNATION_GAP);
+    let total = groups.len();
+    let shown = if args.all_incarnations { total } else { 1 };
+    let hidden = total.saturating_sub(shown);
+    let mut groups: Vec<(usize, Vec<Row>)> = groups.into_iter().enumerate().skip(hidden).collect();
+    if let Some(n) = args.lines {
+        // The newest n rows overall, so older groups get cut or dropped first.
+        let mut keep = n;
+        for (_, group) in groups.iter_mut().rev() {
+            group.drain(..group.len().saturating_sub(keep));
+            keep -= group.len();
+        }
+        groups.retain(|(_, g)| !g.is_empty());
+    }
+    if args.reverse {
+        groups.reverse();
+        groups.iter_mut().for_each(|(_, g)| g.reverse());
+    }
+    let note = match hidden {
+        0 => String::new(),
+        n => format!(" ({} older, see --all-incarnations)", n),
+    };
+    for (i, group) in groups {
+        format.separator(
+            out,
+            format_args!(
+                "pid {} incarnation {}/{}: {}, {} messages{}",
+                args.pid.unwrap_or_default(),
+                i + 1,
+                total,
+                group[0].appname.as_deref().unwrap_or("-"),
+                group.len(),
+                note,
+            ),
+        )?;
+        for row in &group {
+            format.print(out, row)?;
+        }
+    }
+    Ok(last)
+}
+
+pub fn run(conn: &Connection, args: Args) -> anyhow::Result<()> {
+    let filter = args.filter(conn)?;
+    // Following never ends, so there'd be nothing to page.
+    let mut pager = match Pager::start(conn, args.no_pager || args.follow)? {
+        Some(pager) => pager,
+        None => {
+            let mut format = args.format(args.color);
+            let stdout = std::io::stdout();
+            return print(conn, &args, &filter, &mut stdout.lock(), &mut *format);
+        }
+    };
+    let mut format = args.format(pager.color(args.color));
+    let result = match print(conn, &args, &filter, &mut pager, &mut *format) {
+        Err(e) if pager.cancelled(&e) => Ok(()),
+        result => result,
+    };
+    pager.finish()?;
+    result
+}
+
+fn print(
+    conn: &Connection,
+    args: &Args,
+    filter: &Filter,
+    out: &mut dyn Write,
+    format: &mut dyn Format,
+) -> anyhow::Result<()> {
+    if let Some(context) = args.context() {
+        return run_context(conn, args, filter, out, format, context);
+    }
+    let mut tracker = args.unit.as_deref().map(PidTracker::new);
+    let mut emit = |out: &mut dyn Write, format: &mut dyn Format, row: &Row| -> io::Result<()> {
+        if let Some(ref mut tracker) = tracker {
+            match tracker.check(row) {
+                Unit::Unrelated => return Ok(()),
+                Unit::PidChanged(old, new) => format.separator(
+                    out,
+                    format_args!("{}: pid {} -> {}", tracker.appname(), old, new),
+                )?,
+                Unit::Same => (),
+            }
+        }
+        format.print(out, row)
+    };
+    let max_id: Option<i64> = conn.query_row("SELECT max(id) FROM log", [], |row| row.get(0))?;
+    let mut last = None;
+    match args.lines.or(if args.follow { Some(10) } else { None }) {
+        _ if args.pid.is_some() => {
+            last = print_incarnations(conn, args, filter, out, format)?;
+        }
+        Some(n) => {
+            let mut rows = vec![];
+            select(conn, filter, None, None, true, Some(n), |row| {
+                rows.push(row);
+                Ok(())
+            })?;
+            if !args.reverse {
+                rows.reverse();
+            }
+            for row in rows {
+                last = last.max(Some(row.id));
+                emit(out, format, &row)?;
+            }
+        }
+        None => select(conn, filter, None, None, args.reverse, None, |row| {
+            last = last.max(Some(row.id));
+            Ok(emit(out, format, &row)?)
+        })?,
+    }
+    if args.follow {
+        let mut last = last.or(max_id);
+        loop {
+            out.flush()?;
+            std::thread::sleep(FOLLOW_INTERVAL);
+            select(conn, filter, last, None, false, None, |row| {
+                last = Some(row.id);
+                Ok(emit(out, format, &row)?)
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use clap::Parser;
+
+    #[derive(clap::Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        args: Args,
+    }
+
+    const CONFIG: &str = r#"
+        [alias.authfail]
+        since = "2024-01-01T00:00:00Z"
+        until = "2024-02-01T00:00:00Z"
+        severity = "warning.."
+        facility = ["auth", "authpriv"]
+        appname = "sshd"
+        pid = 7
+        grep = ["Failed password", "for root"]
+
+        [alias.broken]
+        severity = "loud"
+    "#;
+
+    fn filter(alias: &str, argv: &[&str]) -> anyhow::Result<Filter> {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let mut args = Cli::try_parse_from(std::iter::once("squealog").chain(argv.iter().copied()))
+            .unwrap()
+            .args;
+        args.apply(alias, config.alias(alias)?)?;
+        let mut conn = Connection::open_in_memory().unwrap();
+        squealog::schema::migrations().to_latest(&mut conn).unwrap();
+        args.filter(&conn)
+    }
+
+    fn at(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn an_alias_alone_is_its_filters() {
+        let f = filter("authfail", &[]).unwrap();
+        assert_eq!(f.since, at("2024-01-01T00:00:00Z"));
+        assert_eq!(f.until, at("2024-02-01T00:00:00Z"));
+        assert_eq!(f.severity, Some("warning..".parse().unwrap()));
+        assert_eq!(f.facilities, [Facility(4), Facility(10)]);
+        assert_eq!(f.appnames, ["sshd"]);
+        assert_eq!(f.pid, Some(7));
+        assert_eq!(f.greps, ["Failed password", "for root"]);
+    }
+
+    #[test]
+    fn the_command_line_overrides_the_time_range_and_filters() {
+        let f = filter(
+            "authfail",
+            &[
+                "-S",
+                "2024-01-15T00:00:00Z",
+                "-p",
+                "err",
+                "--facility",
+                "daemon",
+                "-t",
+                "su",
+                "-t",
+                "sudo",
+                "--pid",
+                "8",
+            ],
+        )
+        .unwrap();
+        assert_eq!(f.since, at("2024-01-15T00:00:00Z"));
+        // Not given, so still the alias's.
+        assert_eq!(f.until, at("2024-02-01T00:00:00Z"));
+        assert_eq!(f.severity, Some("err".parse().unwrap()));
+        assert_eq!(f.facilities, [Facility(3)]);
+        assert_eq!(f.appnames, ["su", "sudo"]);
+        assert_eq!(f.pid, Some(8));
+    }
+
+    #[test]
+    fn greps_add_up() {
+        let f = filter("authfail", &["-g", "from 10.0.0.1"]).unwrap();
+        assert_eq!(f.greps, ["Failed password", "for root", "from 10.0.0.1"]);
+    }
+
+    #[test]
+    fn alias_errors_say_where() {
+        let e = filter("broken", &[]).unwrap_err().to_string();
+        assert!(e.starts_with("In alias @broken: "), "{}", e);
+        let e = filter("authfial", &[]).unwrap_err().to_string();
+        assert_eq!(e, "No alias named @authfial, did you mean @authfail?");
+    }
+}
@@ -0,0 +1,66 @@
+//! `squealog replay`: what `squealogd --record` captured, parsed and stored again by the
+//! daemon itself (`squealogd --replay`) into a database of its own, for checking what a
+//! device's messages come out as, or what a parser change does to them.
+//!
+//! The daemon runs with the given config, or with none at all: outputs in the system's config
+//! (relays especially) would send everything on again.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Directory of capture files
+    dir: PathBuf,
+    /// The database to store into, which can't exist yet
+    #[clap(long, value_name = "DB")]
+    into: PathBuf,
+    /// fast, or original to wait between payloads as long as they arrived apart
+    #[clap(long, default_value = "fast", possible_values = &["fast", "original"])]
+    timing: String,
+    /// The daemon's config, for [[listen]] parsers, [enrich] and the rules [default: none]
+    #[clap(long)]
+    config: Option<PathBuf>,
+    /// The daemon to replay with [default: the one next to this binary, or on the PATH]
+    #[clap(long, value_name = "PATH")]
+    squealogd: Option<PathBuf>,
+}
+
+fn squealogd() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join("squealogd")))
+        .filter(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from("squealogd"))
+}
+
+pub fn run(args: Args) -> anyhow::Result<()> {
+    if args.into.exists() {
+        anyhow::bail!(
+            "{:?} already exists: replays go into a database of their own",
+            args.into
+        );
+    }
+    let exe = args.squealogd.unwrap_or_else(squealogd);
+    let mut cmd = Command::new(&exe);
+    cmd.arg("--replay")
+        .arg(&args.dir)
+        .arg("--db")
+        .arg(&args.into)
+        .arg("--config")
+        .arg(args.config.as_deref().unwrap_or(Path::new("/dev/null")))
+        // Writes where whoever runs it can, without a user to change to.
+        .arg("--keep-root")
+        .env_remove("SQUEALOG_HTTP")
+        .env_remove("NOTIFY_SOCKET");
+    if args.timing == "original" {
+        cmd.arg("--original-timing");
+    }
+    let status = cmd
+        .status()
+        .map_err(|e| anyhow::format_err!("could not run {:?}: {}", exe, e))?;
+    if !status.success() {
+        anyhow::bail!("{:?} failed: {}", exe, status);
+    }
+    Ok(())
+}
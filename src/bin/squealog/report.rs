@@ -0,0 +1,200 @@
+use crate::output;
+use chrono::prelude::*;
+use rusqlite::Connection;
+use squealog::{names, stats::Window, time::TimeSpec};
+use std::io::{self, Write};
+
+/// Bucket sizes to pick from, the smallest one that gives at most `MAX_BUCKETS` wins.
+const BUCKET_SIZES: [i64; 8] = [60, 300, 900, 3600, 3 * 3600, 6 * 3600, 12 * 3600, 86400];
+const MAX_BUCKETS: i64 = 72;
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Start of the window
+    #[clap(short = 'S', long, default_value = "-24h", allow_hyphen_values = true)]
+    since: TimeSpec,
+    /// End of the window
+    #[clap(short = 'U', long, default_value = "now", allow_hyphen_values = true)]
+    until: TimeSpec,
+    /// How many appnames and hostnames to list
+    #[clap(long, default_value = "10")]
+    top: usize,
+    /// Print JSON for machine consumption
+    #[clap(long)]
+    json: bool,
+}
+
+pub struct Report {
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub total: i64,
+    pub severities: Vec<(Option<i64>, i64)>,
+    pub appnames: Vec<(Option<String>, i64)>,
+    pub hostnames: Vec<(Option<String>, i64)>,
+    /// Length of a histogram bucket in seconds.
+    pub bucket: i64,
+    /// Start of each bucket (aligned to the Unix epoch) and its message count.
+    pub histogram: Vec<(i64, i64)>,
+}
+
+impl Report {
+    pub fn collect(
+        conn: &Connection,
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+        top: usize,
+    ) -> anyhow::Result<Report> {
+        let window = Window::new(conn, Some(since), Some(until))?;
+        let severities = window.group_by(conn, "severity", "severity")?;
+        let top = format!("n DESC LIMIT {}", top);
+        let appnames = window.group_by(conn, "appname", &top)?;
+        let hostnames = window.group_by(conn, "hostname", &top)?;
+
+        let span = (until - since).num_seconds().max(1);
+        let bucket = BUCKET_SIZES
+            .into_iter()
+            .find(|size| span / size < MAX_BUCKETS)
+            .unwrap_or(86400 * ((span / MAX_BUCKETS) / 86400 + 1));
+        // Hour multiples can come from the summary, smaller buckets need the receive times.
+        let counts: Vec<(i64, i64)> = if bucket % 3600 == 0 {
+            window.group_by(
+                conn,
+                &format!("CAST(strftime('%s', hour) AS INTEGER) / {0} * {0}", bucket),
+                "1",
+            )?
+        } else {
+            conn.prepare(&format!(
+                "SELECT CAST(strftime('%s', recv_time) AS INTEGER) / {0} * {0} AS b, count(*)
+                 FROM log WHERE recv_time >= ? AND recv_time < ? GROUP BY b ORDER BY b",
+                bucket
+            ))?
+            .query_map([since, until], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?
+        };
+        let first = since.timestamp() / bucket * bucket;
+        let mut histogram: Vec<(i64, i64)> = (first..until.timestamp())
+            .step_by(bucket as usize)
+            .map(|start| (start, 0))
+            .collect();
+        for (start, n) in counts {
+            if let Some(slot) = histogram.get_mut(((start - first) / bucket) as usize) {
+                slot.1 += n;
+            }
+        }
+
+        Ok(Report {
+            since,
+            until,
+            total: severities.iter().map(|(_, n)| n).sum(),
+            severities,
+            appnames,
+            hostnames,
+            bucket,
+            histogram,
+        })
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let time = |t: i64| Utc.timestamp_opt(t, 0).single().map(|t| t.to_rfc3339());
+        serde_json::json!({
+            "since": self.since.to_rfc3339(),
+            "until": self.until.to_rfc3339(),
+            "total": self.total,
+            "severities": self.severities.iter().map(|(sev, n)| serde_json::json!({
+                "severity": sev,
+                "name": sev.and_then(names::severity_name),
+                "count": n,
+            })).collect::<Vec<_>>(),
+            "appnames": self.appnames.iter().map(|(app, n)| serde_json::json!({
+                "appname": app,
+                "count": n,
+            })).collect::<Vec<_>>(),
+            "hostnames": self.hostnames.iter().map(|(host, n)| serde_json::json!({
+                "hostname": host,
+                "count": n,
+            })).collect::<Vec<_>>(),
+            "bucket_seconds": self.bucket,
+            "histogram": self.histogram.iter().map(|(start, n)| serde_json::json!({
+                "start": time(*start),
+                "count": n,
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn sparkline(counts: &[(i64, i64)]) -> String {
+    let max = counts.iter().map(|(_, n)| *n).max().unwrap_or(0);
+    counts
+        .iter()
+        .map(|(_, n)| match (*n, max) {
+            (0, _) => ' ',
+            (n, max) => SPARKS[(n * (SPARKS.len() as i64 - 1) / max) as usize],
+        })
+        .collect()
+}
+
+fn bucket_name(secs: i64) -> String {
+    if secs % 3600 == 0 {
+        format!("{}h", secs / 3600)
+    } else {
+        format!("{}min", secs / 60)
+    }
+}
+
+/// A ranking line with a bar proportional to the share of all messages.
+fn print_ranked(out: &mut dyn Write, name: &str, n: i64, total: i64) -> io::Result<()> {
+    let bar = if total > 0 {
+        (n * 30 / total) as usize
+    } else {
+        0
+    };
+    writeln!(out, "  {:<20} {:>9} {}", name, n, "#".repeat(bar))
+}
+
+pub fn run(conn: &Connection, args: Args) -> anyhow::Result<()> {
+    let report = Report::collect(conn, args.since.resolve(), args.until.resolve(), args.top)?;
+    output::to_stdout(|out| {
+        if args.json {
+            return writeln!(out, "{}", report.to_json());
+        }
+        print(out, &report)
+    })?;
+    Ok(())
+}
+
+fn print(out: &mut dyn Write, report: &Report) -> io::Result<()> {
+    writeln!(
+        out,
+        "{} messages from {} to {}",
+        report.total,
+        output::display_time(Some(report.since)),
+        output::display_time(Some(report.until))
+    )?;
+    if let Some(&(start, n)) = report.histogram.iter().max_by_key(|(_, n)| *n) {
+        let start = Utc.timestamp_opt(start, 0).single();
+        writeln!(
+            out,
+            "\nrate per {} (peak {} at {}):",
+            bucket_name(report.bucket),
+            n,
+            output::display_time(start)
+        )?;
+        writeln!(out, "  |{}|", sparkline(&report.histogram))?;
+    }
+    writeln!(out, "\nseverity:")?;
+    for (sev, n) in &report.severities {
+        let name = sev.and_then(names::severity_name).unwrap_or("-");
+        print_ranked(out, name, *n, report.total)?;
+    }
+    writeln!(out, "\nappname:")?;
+    for (app, n) in &report.appnames {
+        print_ranked(out, app.as_deref().unwrap_or("-"), *n, report.total)?;
+    }
+    writeln!(out, "\nhostname:")?;
+    for (host, n) in &report.hostnames {
+        print_ranked(out, host.as_deref().unwrap_or("(local)"), *n, report.total)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use crate::output;
+use rusqlite::Connection;
+use squealog::{
+    names, schema,
+    stats::{human_size, Stats},
+    time::TimeSpec,
+};
+use std::io::{self, Write};
+use std::path::Path;
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Only count messages received since this time
+    #[clap(long)]
+    since: Option<TimeSpec>,
+    /// Print JSON for machine consumption
+    #[clap(long)]
+    json: bool,
+}
+
+pub fn run(conn: &Connection, path: &Path, args: Args) -> anyhow::Result<()> {
+    let stats = Stats::collect(conn, path, args.since.map(TimeSpec::resolve))?;
+    output::to_stdout(|out| {
+        if args.json {
+            return writeln!(out, "{}", stats.to_json());
+        }
+        print(out, &stats)
+    })?;
+    Ok(())
+}
+
+fn print(out: &mut dyn Write, stats: &Stats) -> io::Result<()> {
+    writeln!(out, "rows:     {}", stats.rows)?;
+    if let Some(since) = stats.since {
+        writeln!(out, "since:    {}", output::display_time(Some(since)))?;
+    }
+    if let Some(rate) = stats.rate() {
+        writeln!(out, "rate:     {:.3} msg/s", rate)?;
+    }
+    writeln!(out, "oldest:   {}", output::display_time(stats.oldest))?;
+    writeln!(out, "newest:   {}", output::display_time(stats.newest))?;
+    writeln!(
+        out,
+        "database: {} (WAL {})",
+        human_size(stats.db_size),
+        human_size(stats.wal_size)
+    )?;
+    writeln!(
+        out,
+        "schema:   {} (latest {})",
+        stats.schema_version,
+        schema::latest_version()
+    )?;
+    writeln!(out, "\nseverity:")?;
+    for (sev, n) in &stats.severities {
+        writeln!(
+            out,
+            "  {:<10} {}",
+            sev.and_then(names::severity_name).unwrap_or("-"),
+            n
+        )?;
+    }
+    writeln!(out, "\nappname:")?;
+    for (app, n) in &stats.appnames {
+        writeln!(out, "  {:<20} {}", app.as_deref().unwrap_or("-"), n)?;
+    }
+    writeln!(out, "\nsocket:")?;
+    for (sock, n) in &stats.sockets {
+        writeln!(out, "  {:<20} {}", sock, n)?;
+    }
+    if !stats.filters.is_empty() {
+        writeln!(out, "\ndropped by filter (last one):")?;
+        for (rule, n, last) in &stats.filters {
+            writeln!(
+                out,
+                "  {:<20} {} ({})",
+                rule,
+                n,
+                output::display_time(*last)
+            )?;
+        }
+    }
+    if !stats.sampled.is_empty() {
+        writeln!(
+            out,
+            "\nleft out by sampling (kept of seen, runs, last one):"
+        )?;
+        for s in &stats.sampled {
+            let source = format!(
+                "{} {}",
+                s.socket,
+                if s.appname.is_empty() {
+                    "-"
+                } else {
+                    &s.appname
+                }
+            );
+            writeln!(
+                out,
+                "  {:<20} {} of {}, {} ({})",
+                source,
+                s.kept,
+                s.seen,
+                s.runs,
+                output::display_time(s.last)
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,428 @@
+//! `squealog tui`: an interactive browser that only ever holds a window of rows in memory.
+//!
+//! Rows are loaded in pages keyed on the id (`id < first` going back, `id > last` going
+//! forward), so opening a huge database costs the same as a small one. The daemon only ever
+//! appends with increasing ids, so following is just another forward page and can't produce
+//! duplicates; rows pruned in the meantime just stay in the window until it moves on.
+
+use crate::output;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Wrap},
+    DefaultTerminal, Frame,
+};
+use rusqlite::{types::ValueRef, Connection};
+use squealog::{
+    filter::Filter,
+    names,
+    query::{self, Row},
+};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Rows fetched per query.
+const PAGE: usize = 200;
+/// Rows kept in memory, the ones furthest from the selection get dropped.
+const WINDOW: usize = 2000;
+const TICK: Duration = Duration::from_millis(500);
+const DETAIL_HEIGHT: u16 = 12;
+
+struct Browser<'c> {
+    conn: &'c Connection,
+    /// The filter from the command line, the interactive parts are added on top.
+    base: Filter,
+    text: String,
+    shown: [bool; 8],
+    rows: VecDeque<Row>,
+    selected: usize,
+    /// Index of the first row on screen.
+    offset: usize,
+    /// Height of the log pane at the last draw.
+    height: usize,
+    /// There is nothing older than `rows.front()`.
+    at_oldest: bool,
+    follow: bool,
+    editing: bool,
+    detail: Option<Vec<(String, String)>>,
+}
+
+fn severity_style(sev: Option<i64>) -> Style {
+    match sev {
+        Some(0..=3) => Style::default().fg(Color::Red),
+        Some(4) => Style::default().fg(Color::Yellow),
+        Some(7) => Style::default().add_modifier(Modifier::DIM),
+        _ => Style::default(),
+    }
+}
+
+fn row_line(row: &Row) -> String {
+    let time = row
+        .time
+        .map(|t| {
+            squealog::time::display(&t)
+                .format(output::TIME_FORMAT)
+                .to_string()
+        })
+        .unwrap_or_else(|| "-".to_owned());
+    let sev = row.severity.and_then(names::severity_name).unwrap_or("-");
+    let app = row.appname.as_deref().unwrap_or(&row.socket);
+    let msg = row.msg.lines().next().unwrap_or("");
+    match row.pid {
+        Some(pid) => format!("{} {:<7} {}[{}]: {}", time, sev, app, pid, msg),
+        None => format!("{} {:<7} {}: {}", time, sev, app, msg),
+    }
+}
+
+fn value_text(value: ValueRef) -> String {
+    match value {
+        ValueRef::Null => "-".to_owned(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(f) => f.to_string(),
+        ValueRef::Text(t) => String::from_utf8_lossy(t).into_owned(),
+        ValueRef::Blob(b) => format!("<{} bytes>", b.len()),
+    }
+}
+
+impl<'c> Browser<'c> {
+    fn new(conn: &'c Connection, base: Filter) -> Browser<'c> {
+        Browser {
+            conn,
+            base,
+            text: String::new(),
+            shown: [true; 8],
+            rows: VecDeque::new(),
+            selected: 0,
+            offset: 0,
+            height: 1,
+            at_oldest: false,
+            follow: false,
+            editing: false,
+            detail: None,
+        }
+    }
+
+    fn filter(&self) -> Filter {
+        let mut filter = self.base.clone();
+        if !self.text.is_empty() {
+            filter.greps.push(self.text.clone());
+        }
+        if self.shown.contains(&false) {
+            filter.severities = (0..8u8).filter(|&s| self.shown[s as usize]).collect();
+        }
+        filter
+    }
+
+    fn fetch(
+        &self,
+        after: Option<i64>,
+        before: Option<i64>,
+        newest_first: bool,
+    ) -> anyhow::Result<Vec<Row>> {
+        let mut rows = vec![];
+        query::select(
+            self.conn,
+            &self.filter(),
+            after,
+            before,
+            newest_first,
+            Some(PAGE),
+            |row| {
+                rows.push(row);
+                Ok(())
+            },
+        )?;
+        Ok(rows)
+    }
+
+    /// Jumps to the newest rows.
+    fn newest(&mut self) -> anyhow::Result<()> {
+        let mut rows = self.fetch(None, None, true)?;
+        self.at_oldest = rows.len() < PAGE;
+        rows.reverse();
+        self.rows = rows.into();
+        self.selected = self.rows.len().saturating_sub(1);
+        self.offset = self.selected.saturating_sub(self.height.saturating_sub(1));
+        self.refresh_detail()
+    }
+
+    /// Jumps to the oldest rows.
+    fn oldest(&mut self) -> anyhow::Result<()> {
+        self.follow = false;
+        self.rows = self.fetch(None, None, false)?.into();
+        self.at_oldest = true;
+        self.selected = 0;
+        self.offset = 0;
+        self.refresh_detail()
+    }
+
+    fn load_older(&mut self) -> anyhow::Result<()> {
+        let first = match self.rows.front() {
+            Some(row) if !self.at_oldest => row.id,
+            _ => return Ok(()),
+        };
+        let rows = self.fetch(None, Some(first), true)?;
+        self.at_oldest = rows.len() < PAGE;
+        self.selected += rows.len();
+        self.offset += rows.len();
+        for row in rows {
+            self.rows.push_front(row);
+        }
+        while self.rows.len() > WINDOW && self.selected < self.rows.len() - 1 - PAGE {
+            self.rows.pop_back();
+        }
+        Ok(())
+    }
+
+    /// Appends rows newer than the window, returns how many there were.
+    fn load_newer(&mut self) -> anyhow::Result<usize> {
+        let mut total = 0;
+        loop {
+            let last = self.rows.back().map(|row| row.id);
+            let rows = self.fetch(last, None, false)?;
+            let n = rows.len();
+            total += n;
+            self.rows.extend(rows);
+            while self.rows.len() > WINDOW && self.selected > PAGE {
+                self.rows.pop_front();
+                self.selected -= 1;
+                self.offset = self.offset.saturating_sub(1);
+                self.at_oldest = false;
+            }
+            // Only keep going when following, otherwise a page at a time is plenty.
+            if n < PAGE || !self.follow {
+                return Ok(total);
+            }
+        }
+    }
+
+    fn move_by(&mut self, delta: isize) -> anyhow::Result<()> {
+        if delta < 0 {
+            self.follow = false;
+            if self.selected < PAGE / 4 + delta.unsigned_abs() {
+                self.load_older()?;
+            }
+            self.selected = self.selected.saturating_sub(delta.unsigned_abs());
+        } else {
+            if self.selected + (delta as usize) + PAGE / 4 >= self.rows.len() {
+                self.load_newer()?;
+            }
+            self.selected = (self.selected + delta as usize).min(self.rows.len().saturating_sub(1));
+        }
+        self.refresh_detail()
+    }
+
+    fn tick(&mut self) -> anyhow::Result<()> {
+        if self.follow && self.load_newer()? > 0 {
+            self.selected = self.rows.len().saturating_sub(1);
+            self.refresh_detail()?;
+        }
+        Ok(())
+    }
+
+    /// Re-reads the selected row with all of its columns, if the detail pane is open.
+    fn refresh_detail(&mut self) -> anyhow::Result<()> {
+        if self.detail.is_none() {
+            return Ok(());
+        }
+        let id = match self.rows.get(self.selected) {
+            Some(row) => row.id,
+            None => {
+                self.detail = Some(vec![]);
+                return Ok(());
+            }
+        };
+        let mut stmt = self.conn.prepare_cached("SELECT * FROM log WHERE id = ?")?;
+        let names: Vec<String> = stmt.column_names().into_iter().map(str::to_owned).collect();
+        let mut fields = vec![];
+        let mut rows = stmt.query([id])?;
+        if let Some(row) = rows.next()? {
+            for (i, name) in names.into_iter().enumerate() {
+                let value = row.get_ref(i)?;
+                if name == "sdata" {
+                    if let ValueRef::Text(t) = value {
+                        let json = String::from_utf8_lossy(t);
+                        for (sdid, param, value) in squealog::sdata::params(&json) {
+                            fields.push((format!("{}.{}", sdid, param), value));
+                        }
+                    }
+                    continue;
+                }
+                fields.push((name, value_text(value)));
+            }
+        }
+        self.detail = Some(fields);
+        Ok(())
+    }
+
+    /// Handles a key press, returns false to quit.
+    fn key(&mut self, key: KeyEvent) -> anyhow::Result<bool> {
+        if self.editing {
+            match key.code {
+                KeyCode::Esc | KeyCode::Enter => self.editing = false,
+                KeyCode::Backspace => {
+                    self.text.pop();
+                    self.newest()?;
+                }
+                KeyCode::Char(c) => {
+                    self.text.push(c);
+                    self.newest()?;
+                }
+                _ => {}
+            }
+            return Ok(true);
+        }
+        let page = self.height.max(1) as isize;
+        match key.code {
+            KeyCode::Char('q') => return Ok(false),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(false)
+            }
+            KeyCode::Char('j') | KeyCode::Down => self.move_by(1)?,
+            KeyCode::Char('k') | KeyCode::Up => self.move_by(-1)?,
+            KeyCode::Char('d') | KeyCode::PageDown => self.move_by(page)?,
+            KeyCode::Char('u') | KeyCode::PageUp => self.move_by(-page)?,
+            KeyCode::Char('g') | KeyCode::Home => self.oldest()?,
+            KeyCode::Char('G') | KeyCode::End => self.newest()?,
+            KeyCode::Char('/') => self.editing = true,
+            KeyCode::Char('f') => {
+                self.follow = !self.follow;
+                if self.follow {
+                    self.newest()?;
+                }
+            }
+            KeyCode::Char(c @ '0'..='7') => {
+                let sev = c as usize - '0' as usize;
+                // Hiding everything makes no sense, and an empty list means no restriction.
+                if !self.shown[sev] || self.shown.iter().filter(|s| **s).count() > 1 {
+                    self.shown[sev] = !self.shown[sev];
+                    self.newest()?;
+                }
+            }
+            KeyCode::Enter => {
+                self.detail = match self.detail {
+                    Some(_) => None,
+                    None => Some(vec![]),
+                };
+                self.refresh_detail()?;
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn status(&self) -> Line<'static> {
+        let mut spans = vec![];
+        if self.follow {
+            spans.push(Span::styled(
+                "[follow] ",
+                Style::default().add_modifier(Modifier::BOLD),
+            ));
+        }
+        spans.push(Span::raw(format!("/{}", self.text)));
+        if self.editing {
+            spans.push(Span::styled(
+                "_",
+                Style::default().add_modifier(Modifier::SLOW_BLINK),
+            ));
+        }
+        spans.push(Span::raw("  sev "));
+        for (sev, shown) in self.shown.iter().enumerate() {
+            let style = if *shown {
+                severity_style(Some(sev as i64))
+            } else {
+                Style::default().add_modifier(Modifier::CROSSED_OUT | Modifier::DIM)
+            };
+            spans.push(Span::styled(sev.to_string(), style));
+        }
+        spans.push(Span::styled(
+            "  q quit  / filter  0-7 severities  f follow  g/G oldest/newest  enter details",
+            Style::default().add_modifier(Modifier::DIM),
+        ));
+        Line::from(spans)
+    }
+
+    fn draw(&mut self, f: &mut Frame) {
+        let detail_height = if self.detail.is_some() {
+            DETAIL_HEIGHT
+        } else {
+            0
+        };
+        let [list, detail, status] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(detail_height),
+            Constraint::Length(1),
+        ])
+        .areas(f.area());
+
+        self.height = list.height as usize;
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + self.height {
+            self.offset = self.selected + 1 - self.height;
+        }
+        let lines: Vec<Line> = self
+            .rows
+            .iter()
+            .enumerate()
+            .skip(self.offset)
+            .take(self.height)
+            .map(|(i, row)| {
+                let style = if i == self.selected {
+                    Style::default().add_modifier(Modifier::REVERSED)
+                } else {
+                    severity_style(row.severity)
+                };
+                Line::styled(row_line(row), style)
+            })
+            .collect();
+        f.render_widget(Paragraph::new(lines), list);
+
+        if let Some(ref fields) = self.detail {
+            let lines: Vec<Line> = fields
+                .iter()
+                .map(|(k, v)| {
+                    Line::from(vec![
+                        Span::styled(
+                            format!("{:<16} ", k),
+                            Style::default().add_modifier(Modifier::BOLD),
+                        ),
+                        Span::raw(v.as_str()),
+                    ])
+                })
+                .collect();
+            f.render_widget(
+                Paragraph::new(lines)
+                    .block(Block::default().borders(Borders::TOP))
+                    .wrap(Wrap { trim: false }),
+                detail,
+            );
+        }
+        f.render_widget(Paragraph::new(self.status()), status);
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<()> {
+        self.newest()?;
+        loop {
+            terminal.draw(|f| self.draw(f))?;
+            if !event::poll(TICK)? {
+                self.tick()?;
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !self.key(key)? {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+pub fn run(conn: &Connection, filter: Filter) -> anyhow::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = Browser::new(conn, filter).run(&mut terminal);
+    ratatui::restore();
+    result
+}
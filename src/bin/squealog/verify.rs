@@ -0,0 +1,63 @@
+use rusqlite::{Connection, OptionalExtension};
+use squealog::schema;
+
+/// Returns the exit code: 0 when everything checks out and 1 on structural problems.
+pub fn run(conn: &Connection) -> anyhow::Result<i32> {
+    let mut ok = true;
+
+    let problems = conn
+        .prepare("PRAGMA integrity_check")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if problems.len() == 1 && problems[0] == "ok" {
+        println!("integrity: ok");
+    } else {
+        ok = false;
+        println!("integrity: {} problems", problems.len());
+        for p in problems {
+            println!("  {}", p);
+        }
+    }
+
+    let version = schema::current_version(conn)?;
+    let latest = schema::latest_version();
+    if version == latest {
+        println!("schema:    ok (version {})", version);
+    } else {
+        ok = false;
+        println!(
+            "schema:    version {}, expected {} ({})",
+            version,
+            latest,
+            if version < latest {
+                "not migrated yet, start squealogd"
+            } else {
+                "created by a newer squealogd"
+            }
+        );
+    }
+
+    let max: Option<i64> = conn.query_row("SELECT max(id) FROM log", [], |row| row.get(0))?;
+    let seq: Option<i64> = conn
+        .query_row(
+            "SELECT seq FROM sqlite_sequence WHERE name = 'log'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    // The ids are the rowid, which SQLite keeps unique and in order itself, so what's left to
+    // check is that none is past the counter new ones start from.
+    if max > seq {
+        ok = false;
+        println!(
+            "sequence:  max id {} is above the autoincrement counter {}",
+            max.unwrap_or(0),
+            seq.unwrap_or(0)
+        );
+    } else {
+        println!("sequence:  ok");
+    }
+
+    println!("chain:     not enabled");
+    Ok(if ok { 0 } else { 1 })
+}
@@ -0,0 +1,3 @@
+fn main() -> anyhow::Result<()> {
+    squealog::squealogd::run(squealog::squealogd::Settings::from_args())
+}
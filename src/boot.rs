@@ -0,0 +1,246 @@
+use chrono::prelude::*;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::io::Read;
+
+/// How far apart two boot time readings can be while still referring to the same boot.
+/// The boot time is usually derived from the wall clock and the uptime, so it jitters a bit.
+const SLACK_SECS: i64 = 30;
+
+fn kernel_boot_id() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .ok()
+        .map(|id| id.trim().to_owned())
+}
+
+fn random_uuid() -> std::io::Result<String> {
+    let mut b = [0u8; 16];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut b)?;
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let hex: String = b.iter().map(|x| format!("{:02x}", x)).collect();
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+/// Returns the id of the row in the boot table for the running OS boot, creating it if needed.
+///
+/// A daemon restart within the same OS boot must not start a new boot, so the last boot is
+/// reused when its boot time matches (or, where the kernel provides one, the boot id does).
+pub fn current(conn: &Connection, boot_time: DateTime<Utc>) -> anyhow::Result<i64> {
+    find_or_add(conn, boot_time, kernel_boot_id())
+}
+
+fn find_or_add(
+    conn: &Connection,
+    boot_time: DateTime<Utc>,
+    kernel_id: Option<String>,
+) -> anyhow::Result<i64> {
+    let existing = match kernel_id {
+        Some(ref uuid) => conn
+            .query_row("SELECT id FROM boot WHERE uuid = ?", [uuid], |row| {
+                row.get(0)
+            })
+            .optional()?,
+        None => conn
+            .query_row(
+                "SELECT id, boot_time FROM boot ORDER BY id DESC LIMIT 1",
+                [],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, DateTime<Utc>>(1)?)),
+            )
+            .optional()?
+            .filter(|(_, t)| (*t - boot_time).num_seconds().abs() <= SLACK_SECS)
+            .map(|(id, _)| id),
+    };
+    if let Some(id) = existing {
+        return Ok(id);
+    }
+    let uuid = match kernel_id {
+        Some(uuid) => uuid,
+        None => random_uuid()?,
+    };
+    conn.execute(
+        "INSERT INTO boot (uuid, boot_time) VALUES (?, ?)",
+        params![uuid, boot_time],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Resolves a boot given like journalctl's `-b`: 0 is the latest boot, -1 the one before it,
+/// positive numbers count from the oldest boot (starting at 1), anything else is a boot UUID.
+pub fn resolve(conn: &Connection, spec: &str) -> anyhow::Result<i64> {
+    let id = match spec.parse::<i64>() {
+        Ok(n) if n <= 0 => conn
+            .query_row(
+                "SELECT id FROM boot ORDER BY id DESC LIMIT 1 OFFSET ?",
+                [-n],
+                |row| row.get(0),
+            )
+            .optional()?,
+        Ok(n) => conn
+            .query_row(
+                "SELECT id FROM boot ORDER BY id LIMIT 1 OFFSET ?",
+                [n - 1],
+                |row| row.get(0),
+            )
+            .optional()?,
+        Err(_) => conn
+            .query_row("SELECT id FROM boot WHERE uuid = ?", [spec], |row| {
+                row.get(0)
+            })
+            .optional()?,
+    };
+    id.ok_or_else(|| anyhow::format_err!("No boot '{}' in the database", spec))
+}
+
+#[derive(Debug)]
+pub struct Boot {
+    pub id: i64,
+    pub uuid: String,
+    pub boot_time: DateTime<Utc>,
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+    pub rows: i64,
+}
+
+fn time_of(
+    stmt: &mut rusqlite::Statement,
+    id: Option<i64>,
+) -> rusqlite::Result<Option<DateTime<Utc>>> {
+    match id {
+        Some(id) => stmt
+            .query_row([id], |row| row.get(0))
+            .optional()
+            .map(Option::flatten),
+        None => Ok(None),
+    }
+}
+
+/// All known boots, oldest first, including ones that never got any messages.
+pub fn list(conn: &Connection) -> rusqlite::Result<Vec<Boot>> {
+    let mut time = conn.prepare("SELECT coalesce(recv_time, time) FROM log WHERE id = ?")?;
+    let mut stmt = conn.prepare(
+        "SELECT b.id, b.uuid, b.boot_time, min(l.id), max(l.id), count(l.id)
+         FROM boot b LEFT JOIN log l ON l.boot = b.id
+         GROUP BY b.id ORDER BY b.id",
+    )?;
+    let boots = stmt
+        .query_map([], |row| {
+            Ok(Boot {
+                id: row.get(0)?,
+                uuid: row.get(1)?,
+                boot_time: row.get(2)?,
+                first: time_of(&mut time, row.get(3)?)?,
+                last: time_of(&mut time, row.get(4)?)?,
+                rows: row.get(5)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(boots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::schema::migrations().to_latest(&mut conn).unwrap();
+        conn
+    }
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn uuid(conn: &Connection, spec: &str) -> String {
+        let id = resolve(conn, spec).unwrap();
+        conn.query_row("SELECT uuid FROM boot WHERE id = ?", [id], |r| r.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn restarts_without_a_boot_id_are_the_same_boot() {
+        let conn = db();
+        let first = find_or_add(&conn, at("2024-01-01T00:00:00Z"), None).unwrap();
+        // Restarts of the daemon, reading the boot time a little differently each time.
+        for t in [
+            "2024-01-01T00:00:02Z",
+            "2023-12-31T23:59:40Z",
+            "2024-01-01T00:00:30Z",
+        ] {
+            assert_eq!(
+                find_or_add(&conn, at(t), None).unwrap(),
+                first,
+                "restart at {}",
+                t
+            );
+        }
+        let second = find_or_add(&conn, at("2024-01-01T06:00:00Z"), None).unwrap();
+        assert_ne!(second, first);
+        assert_eq!(
+            find_or_add(&conn, at("2024-01-01T06:00:05Z"), None).unwrap(),
+            second
+        );
+
+        assert_eq!(resolve(&conn, "0").unwrap(), second);
+        assert_eq!(resolve(&conn, "-1").unwrap(), first);
+        assert_eq!(resolve(&conn, "1").unwrap(), first);
+        assert_eq!(resolve(&conn, "2").unwrap(), second);
+        assert!(resolve(&conn, "-2").is_err());
+        assert!(resolve(&conn, "3").is_err());
+        let previous = uuid(&conn, "-1");
+        assert_eq!(resolve(&conn, &previous).unwrap(), first);
+    }
+
+    #[test]
+    fn restarts_with_a_boot_id_are_the_same_boot_whatever_the_clock_says() {
+        let conn = db();
+        let a = || Some("a1b2c3d4-0000-4000-8000-000000000001".to_owned());
+        let b = || Some("a1b2c3d4-0000-4000-8000-000000000002".to_owned());
+        let first = find_or_add(&conn, at("2024-01-01T00:00:00Z"), a()).unwrap();
+        // The clock was set after the first start, moving the boot time by hours.
+        assert_eq!(
+            find_or_add(&conn, at("2024-01-01T05:00:00Z"), a()).unwrap(),
+            first
+        );
+        // The next boot's time is close to the last one's, but its id isn't the same.
+        let second = find_or_add(&conn, at("2024-01-01T05:00:10Z"), b()).unwrap();
+        assert_ne!(second, first);
+        assert_eq!(
+            find_or_add(&conn, at("2024-01-01T05:00:10Z"), b()).unwrap(),
+            second
+        );
+
+        assert_eq!(resolve(&conn, "-1").unwrap(), first);
+        assert_eq!(uuid(&conn, "-1"), a().unwrap());
+        assert_eq!(uuid(&conn, "0"), b().unwrap());
+        assert!(resolve(&conn, "a1b2c3d4-0000-4000-8000-000000000003").is_err());
+    }
+
+    #[test]
+    fn boots_without_rows_are_listed() {
+        let conn = db();
+        let first = find_or_add(&conn, at("2024-01-01T00:00:00Z"), None).unwrap();
+        find_or_add(&conn, at("2024-01-02T00:00:00Z"), None).unwrap();
+        conn.execute(
+            "INSERT INTO log (facility, severity, socket, time, recv_time, boot, msg)
+            VALUES (3, 6, 'log', '2024-01-01T00:01:00Z', '2024-01-01T00:01:00Z', ?1, 'a'),
+                   (3, 6, 'log', '2024-01-01T00:02:00Z', '2024-01-01T00:02:00Z', ?1, 'b')",
+            [first],
+        )
+        .unwrap();
+        let boots = list(&conn).unwrap();
+        assert_eq!(boots.len(), 2);
+        assert_eq!(boots[0].rows, 2);
+        assert_eq!(boots[0].first, Some(at("2024-01-01T00:01:00Z")));
+        assert_eq!(boots[0].last, Some(at("2024-01-01T00:02:00Z")));
+        assert_eq!(boots[1].rows, 0);
+        assert_eq!((boots[1].first, boots[1].last), (None, None));
+    }
+}
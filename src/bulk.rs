@@ -0,0 +1,114 @@
+//! Inserting many messages at once, for `squealog import`: the daemon stores messages one at a
+//! time as they come in, which is the right thing for a trickle and far too slow for a file of
+//! millions of lines.
+//!
+//! A `Bulk` stores with `storage::Sqlite` in batches of `batch` rows. For as long as it's open,
+//! the connection syncs only on checkpoints and keeps a bigger page cache. Both are
+//! per-connection pragmas, and are put back the way they were once it's finished or dropped.
+//! `journal_mode` is left alone: it's stored in the database, and a running daemon depends on
+//! WAL. Checkpoints are left alone too: turning them off until the end lets the WAL grow to
+//! the size of the whole import, and every page read has to be looked up in it, which made a
+//! million-line import twice as slow (`squealog-bench import`).
+//!
+//! The indexes on `log` (`recv_time`, `time`, `boot`), the per-row triggers (`delete_old`, the
+//! hourly summary) and the summary's own index are kept up to date row by row. Dropping the
+//! indexes and building them again afterwards would be quicker for a big import into an empty
+//! database, but queries would go without them meanwhile, so it isn't done. With the batches
+//! fitting into the page cache, most index pages are only written out once per batch anyway.
+
+use crate::schema;
+use crate::storage::{self, Storage};
+use rusqlite::Connection;
+
+/// The page cache while a `Bulk` is open, in kibibytes (SQLite's negative `cache_size`).
+const CACHE_KIB: i64 = 64 << 10;
+
+pub struct Bulk<'c> {
+    conn: &'c Connection,
+    storage: storage::Sqlite<'c>,
+    batch: u64,
+    rows: u64,
+    /// How the tuned pragmas were, to put back.
+    saved: Vec<(&'static str, i64)>,
+    on_commit: Option<Box<dyn FnMut(u64) + 'c>>,
+    open: bool,
+}
+
+impl<'c> Bulk<'c> {
+    /// Starts inserting, committing every `batch` rows. Until it's finished, `conn` is in a
+    /// transaction, and anything else done on it goes into the same batch.
+    pub fn begin(conn: &'c Connection, batch: u64) -> rusqlite::Result<Bulk<'c>> {
+        let mut saved = vec![];
+        for (pragma, value) in [("synchronous", 1), ("cache_size", -CACHE_KIB)] {
+            saved.push((
+                pragma,
+                conn.pragma_query_value(None, pragma, |row| row.get(0))?,
+            ));
+            conn.pragma_update(None, pragma, value)?;
+        }
+        let mut bulk = Bulk {
+            conn,
+            storage: storage::Sqlite::new(conn)?,
+            batch: batch.max(1),
+            rows: 0,
+            saved,
+            on_commit: None,
+            open: false,
+        };
+        bulk.storage.begin_batch()?;
+        bulk.open = true;
+        Ok(bulk)
+    }
+
+    /// Calls `f` with the number of rows so far every time a batch is committed.
+    pub fn on_commit(&mut self, f: impl FnMut(u64) + 'c) {
+        self.on_commit = Some(Box::new(f));
+    }
+
+    pub fn insert(&mut self, row: &schema::Row) -> rusqlite::Result<()> {
+        // A failure ends the import, the open batch is rolled back when dropped.
+        self.storage.insert(row).map_err(|f| f.error)?;
+        self.rows += 1;
+        if self.rows.is_multiple_of(self.batch) {
+            self.storage.commit().map_err(|f| f.error)?;
+            self.storage.begin_batch()?;
+            if let Some(ref mut f) = self.on_commit {
+                f(self.rows);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    /// Commits the last batch, checkpoints and puts the pragmas back. Returns the number of
+    /// rows inserted.
+    pub fn finish(mut self) -> rusqlite::Result<u64> {
+        self.open = false;
+        self.storage.commit().map_err(|f| f.error)?;
+        self.restore()?;
+        // The last batch, without waiting for readers.
+        self.conn
+            .query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |_| Ok(()))?;
+        Ok(self.rows)
+    }
+
+    fn restore(&mut self) -> rusqlite::Result<()> {
+        for (pragma, value) in self.saved.drain(..) {
+            self.conn.pragma_update(None, pragma, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Bulk<'_> {
+    fn drop(&mut self) {
+        // Without `finish` (after an error), everything since the last batch is rolled back.
+        if self.open {
+            let _ = self.conn.execute_batch("ROLLBACK");
+        }
+        let _ = self.restore();
+    }
+}
@@ -0,0 +1,180 @@
+//! Capture files, of payloads exactly as squealogd received them (`--record DIR`), for
+//! replaying them through parsing and storing again later (`squealog replay DIR`).
+//!
+//! Every daemon process writes a file of its own into the directory, named after when it
+//! started, and `files` lists them oldest first. A file starts with `MAGIC` and the boot time
+//! the recording process had (what klog's timestamps count from) as microseconds since the
+//! epoch, then holds one entry after another. An entry is its length as a big-endian u32
+//! (not counting those 4 bytes), then the arrival time (big-endian i64 microseconds), the
+//! transport (a byte, see `Transport`), the socket name's length (a byte) and name, and the
+//! rest is the payload. A file that ends in the middle of an entry (the daemon was killed
+//! while writing) is read up to the last whole one.
+
+use chrono::{DateTime, TimeZone, Utc};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+pub const MAGIC: &[u8; 8] = b"SQLGCAP1";
+/// What capture files are named with.
+pub const EXTENSION: &str = "cap";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Udp = 1,
+    Unix = 2,
+    Klog = 3,
+}
+
+impl Transport {
+    fn from_u8(n: u8) -> Option<Transport> {
+        match n {
+            1 => Some(Transport::Udp),
+            2 => Some(Transport::Unix),
+            3 => Some(Transport::Klog),
+            _ => None,
+        }
+    }
+}
+
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    pub socket: String,
+    pub transport: Transport,
+    pub payload: Vec<u8>,
+}
+
+fn invalid(what: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what)
+}
+
+pub struct Writer {
+    file: File,
+    path: PathBuf,
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    /// Creates a new capture file in `dir`, for a boot that started at `boot_time`.
+    pub fn create(dir: &Path, boot_time: DateTime<Utc>) -> io::Result<Writer> {
+        let path = dir.join(format!(
+            "{}-{}.{}",
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            std::process::id(),
+            EXTENSION
+        ));
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&boot_time.timestamp_micros().to_be_bytes());
+        file.write_all(&header)?;
+        Ok(Writer {
+            file,
+            path,
+            buf: vec![],
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends one payload, with one write so that a failure doesn't leave half an entry.
+    pub fn write(
+        &mut self,
+        time: DateTime<Utc>,
+        socket: &str,
+        transport: Transport,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let name = &socket.as_bytes()[..socket.len().min(255)];
+        let len = 8 + 1 + 1 + name.len() + payload.len();
+        let buf = &mut self.buf;
+        buf.clear();
+        buf.extend_from_slice(&(len as u32).to_be_bytes());
+        buf.extend_from_slice(&time.timestamp_micros().to_be_bytes());
+        buf.push(transport as u8);
+        buf.push(name.len() as u8);
+        buf.extend_from_slice(name);
+        buf.extend_from_slice(payload);
+        self.file.write_all(buf)
+    }
+}
+
+pub struct Reader {
+    from: BufReader<File>,
+    /// When the boot the file was recorded in started.
+    pub boot_time: DateTime<Utc>,
+}
+
+fn micros(n: [u8; 8]) -> io::Result<DateTime<Utc>> {
+    let n = i64::from_be_bytes(n);
+    Utc.timestamp_opt(
+        n.div_euclid(1_000_000),
+        n.rem_euclid(1_000_000) as u32 * 1000,
+    )
+    .single()
+    .ok_or_else(|| invalid(format!("time out of range: {}", n)))
+}
+
+impl Reader {
+    pub fn open(path: &Path) -> io::Result<Reader> {
+        let mut from = BufReader::new(File::open(path)?);
+        let mut header = [0u8; 16];
+        from.read_exact(&mut header)
+            .map_err(|_| invalid(format!("{:?} isn't a capture file", path)))?;
+        if &header[..8] != MAGIC {
+            return Err(invalid(format!("{:?} isn't a capture file", path)));
+        }
+        Ok(Reader {
+            from,
+            boot_time: micros(header[8..].try_into().unwrap())?,
+        })
+    }
+
+    /// The next entry, `None` at the end (or at an entry cut short).
+    pub fn next_entry(&mut self) -> io::Result<Option<Entry>> {
+        let mut len = [0u8; 4];
+        match self.from.read_exact(&mut len) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            r => r?,
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len < 10 {
+            return Err(invalid(format!("entry of {} bytes is too short", len)));
+        }
+        let mut entry = vec![0u8; len];
+        match self.from.read_exact(&mut entry) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            r => r?,
+        }
+        let name_len = entry[9] as usize;
+        if 10 + name_len > len {
+            return Err(invalid(format!("entry of {} bytes is too short", len)));
+        }
+        let transport = Transport::from_u8(entry[8])
+            .ok_or_else(|| invalid(format!("unknown transport {}", entry[8])))?;
+        Ok(Some(Entry {
+            time: micros(entry[..8].try_into().unwrap())?,
+            socket: String::from_utf8_lossy(&entry[10..10 + name_len]).into_owned(),
+            transport,
+            payload: entry.split_off(10 + name_len),
+        }))
+    }
+}
+
+/// The capture files in `dir`, oldest first.
+pub fn files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == EXTENSION) {
+            files.push(path);
+        }
+    }
+    // The names start with the time.
+    files.sort();
+    Ok(files)
+}
@@ -0,0 +1,156 @@
+//! Sending messages with structured data to squealogd's unix socket, for programs that would
+//! otherwise format syslog lines themselves (`squealog log` is this, for scripts):
+//!
+//! ```no_run
+//! use squealog::client::Logger;
+//! use syslog_loose::SyslogSeverity;
+//! let mut logger = Logger::new("/var/run/log")?;
+//! logger.log(SyslogSeverity::SEV_INFO, Some("login"), &[("user", "greg")], "logged in")?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Messages are RFC 5424, with the program's name as the appname, its pid as the PROCID and
+//! the params in the `fields@32473` element (like `subscriber`'s event fields). The hostname is
+//! left for the daemon to fill in.
+//!
+//! Sending never blocks: while the socket isn't there (the daemon is restarting) or its buffer
+//! is full, up to `PENDING` messages wait to be sent with the next one (or `flush`). Past that
+//! the oldest is dropped, and the call that dropped it returns the error it was kept for. A
+//! message the socket refuses for good, like one that's too long, is dropped right away.
+
+use crate::serialize::{self, Element};
+use chrono::Local;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use syslog_loose::{SyslogFacility, SyslogSeverity};
+
+pub const FIELDS_ID: &str = "fields@32473";
+/// The daemon's usual socket.
+pub const DEFAULT_PATH: &str = "/var/run/log";
+/// Messages kept while they can't be sent.
+pub const PENDING: usize = 64;
+/// The daemon's default receive buffer for unix sockets, longer messages would be cut off.
+const DATAGRAM_LIMIT: usize = 8192;
+
+pub struct Logger {
+    sock: UnixDatagram,
+    path: PathBuf,
+    appname: String,
+    facility: SyslogFacility,
+    pending: VecDeque<String>,
+}
+
+/// The program's name, as it was started.
+fn program() -> String {
+    std::env::args_os()
+        .next()
+        .as_deref()
+        .and_then(|arg0| Path::new(arg0).file_name())
+        .map_or_else(
+            || "-".to_owned(),
+            |name| name.to_string_lossy().into_owned(),
+        )
+}
+
+/// Whether a message that couldn't be sent may go through later.
+fn transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+    ) || e.raw_os_error() == Some(libc::ENOBUFS)
+}
+
+impl Logger {
+    /// Sends to the daemon's socket at `path`, which doesn't have to be there yet.
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Logger> {
+        let sock = UnixDatagram::unbound()?;
+        sock.set_nonblocking(true)?;
+        Ok(Logger {
+            sock,
+            path: path.as_ref().to_owned(),
+            appname: program(),
+            facility: SyslogFacility::LOG_USER,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Sends with `appname` instead of the program's name.
+    pub fn appname(mut self, appname: &str) -> Logger {
+        self.appname = appname.to_owned();
+        self
+    }
+
+    /// Sends with `facility` instead of user.
+    pub fn facility(mut self, facility: SyslogFacility) -> Logger {
+        self.facility = facility;
+        self
+    }
+
+    /// Sends a message, or keeps it to send later, see the module docs.
+    pub fn log(
+        &mut self,
+        severity: SyslogSeverity,
+        msgid: Option<&str>,
+        params: &[(&str, &str)],
+        msg: &str,
+    ) -> io::Result<()> {
+        let line = {
+            let mut sdata = vec![];
+            if !params.is_empty() {
+                sdata.push(Element {
+                    id: Cow::Borrowed(FIELDS_ID),
+                    params: params
+                        .iter()
+                        .map(|&(k, v)| (Cow::Borrowed(k), Cow::Borrowed(v)))
+                        .collect(),
+                });
+            }
+            let rec = serialize::Record {
+                facility: Some(self.facility as u8),
+                severity: Some(severity as u8),
+                time: Local::now().into(),
+                hostname: None,
+                appname: Some(Cow::Borrowed(&self.appname)),
+                procid: Some(std::process::id().to_string().into()),
+                msgid: msgid.map(Cow::Borrowed),
+                sdata,
+                msg: Cow::Borrowed(msg),
+            };
+            let mut line = String::new();
+            serialize::rfc5424(&mut line, &rec, Some(DATAGRAM_LIMIT));
+            line
+        };
+        self.pending.push_back(line);
+        self.flush()
+    }
+
+    /// Sends what's waiting, oldest first, stopping at the first that still can't be.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut dropped = None;
+        while let Some(line) = self.pending.front() {
+            match self.sock.send_to(line.as_bytes(), &self.path) {
+                Ok(_) => {}
+                Err(e) if transient(&e) => {
+                    if self.pending.len() <= PENDING {
+                        break;
+                    }
+                    dropped = Some(e);
+                }
+                Err(e) => dropped = Some(e),
+            }
+            self.pending.pop_front();
+        }
+        match dropped {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Messages waiting to be sent.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
@@ -0,0 +1,28 @@
+//! `/etc/squealog.toml`, or whatever `SQUEALOG_CONFIG` points to. `squealog` and `squealogd`
+//! read the same file, each only looking at its own tables.
+
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_PATH: &str = "/etc/squealog.toml";
+
+/// Reads the config file. A missing file is fine unless `SQUEALOG_CONFIG` named it.
+pub fn load<T: DeserializeOwned + Default>() -> anyhow::Result<T> {
+    let path = std::env::var_os("SQUEALOG_CONFIG").map(PathBuf::from);
+    load_from(path.as_deref())
+}
+
+/// Reads the config file at `path`, or the default one where a missing file is fine.
+pub fn load_from<T: DeserializeOwned + Default>(path: Option<&Path>) -> anyhow::Result<T> {
+    let (path, explicit) = match path {
+        Some(path) => (path, true),
+        None => (Path::new(DEFAULT_PATH), false),
+    };
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => return Ok(T::default()),
+        Err(e) => return Err(e).with_context(|| format!("Could not read {:?}", path)),
+    };
+    toml::from_str(&text).with_context(|| format!("Could not parse {:?}", path))
+}
@@ -0,0 +1,200 @@
+//! Post-processing over query results for summaries like `squealog errors`.
+
+use crate::query::Row;
+use chrono::Duration;
+use std::collections::HashMap;
+
+/// A row standing for a run of consecutive identical messages.
+#[derive(Clone, Debug)]
+pub struct Repeated {
+    /// The first row of the run, in the order the rows came in.
+    pub row: Row,
+    pub count: usize,
+}
+
+/// Whether two rows are the same message repeated.
+fn same_message(a: &Row, b: &Row) -> bool {
+    // The msgid too, or a run could take in rows `similar_key` counts apart.
+    a.msg == b.msg
+        && a.appname == b.appname
+        && a.msgid == b.msgid
+        && a.hostname == b.hostname
+        && a.severity == b.severity
+}
+
+/// Collapses runs of identical messages, like `uniq -c`.
+pub struct Dedup<I: Iterator<Item = Row>> {
+    rows: std::iter::Peekable<I>,
+}
+
+impl<I: Iterator<Item = Row>> Iterator for Dedup<I> {
+    type Item = Repeated;
+
+    fn next(&mut self) -> Option<Repeated> {
+        let row = self.rows.next()?;
+        let mut count = 1;
+        while self.rows.next_if(|next| same_message(&row, next)).is_some() {
+            count += 1;
+        }
+        Some(Repeated { row, count })
+    }
+}
+
+pub fn dedup<I: IntoIterator<Item = Row>>(rows: I) -> Dedup<I::IntoIter> {
+    Dedup {
+        rows: rows.into_iter().peekable(),
+    }
+}
+
+/// What makes messages "similar": the same appname and msgid.
+pub type SimilarKey = (Option<String>, Option<String>);
+
+pub fn similar_key(row: &Row) -> SimilarKey {
+    (row.appname.clone(), row.msgid.clone())
+}
+
+/// How many rows there are of each `SimilarKey`.
+pub fn count_similar<'a>(rows: impl IntoIterator<Item = &'a Row>) -> HashMap<SimilarKey, usize> {
+    let mut counts = HashMap::new();
+    for row in rows {
+        *counts.entry(similar_key(row)).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// How long one pid can go quiet before its next message is taken to be from a new process.
+pub const INCARNATION_GAP: Duration = Duration::hours(6);
+
+/// Whether `row` looks like it came from a different process than `prev`, given that both
+/// claim the same pid: another program, another boot, or a long silence in between.
+fn new_incarnation(prev: &Row, row: &Row, gap: Duration) -> bool {
+    if prev.appname != row.appname || prev.boot_time != row.boot_time {
+        return true;
+    }
+    match (prev.time, row.time) {
+        (Some(a), Some(b)) => b - a > gap,
+        _ => false,
+    }
+}
+
+/// Splits the rows of one pid, oldest first, into the separate processes that had it.
+pub fn incarnations(rows: impl IntoIterator<Item = Row>, gap: Duration) -> Vec<Vec<Row>> {
+    let mut out: Vec<Vec<Row>> = vec![];
+    for row in rows {
+        match out.last_mut() {
+            Some(cur) if !new_incarnation(cur.last().unwrap(), &row, gap) => cur.push(row),
+            _ => out.push(vec![row]),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+
+    /// A row of pid 42 from `appname`, in the boot started at `boot` hours, `minutes` into it.
+    fn row(id: i64, appname: &str, boot: i64, minutes: Option<i64>) -> Row {
+        let boot_time =
+            DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap() + Duration::hours(boot);
+        Row {
+            id,
+            time: minutes.map(|m| (boot_time + Duration::minutes(m)).into()),
+            severity: Some(6),
+            socket: "log".to_owned(),
+            hostname: None,
+            appname: Some(appname.to_owned()).filter(|a| !a.is_empty()),
+            pid: Some(42),
+            msgid: None,
+            msg: format!("message {}", id),
+            boot_time: Some(boot_time),
+            sdata: None,
+        }
+    }
+
+    #[test]
+    fn incarnations_are_split_at_restarts() {
+        // The rows as `row`'s arguments, and the ids in each incarnation.
+        type Case = (
+            &'static str,
+            &'static [(&'static str, i64, Option<i64>)],
+            &'static [&'static [i64]],
+        );
+        let cases: &[Case] = &[
+            ("nothing", &[], &[]),
+            ("one row", &[("sshd", 0, Some(1))], &[&[1]]),
+            (
+                "one process",
+                &[
+                    ("sshd", 0, Some(1)),
+                    ("sshd", 0, Some(2)),
+                    ("sshd", 0, Some(3)),
+                ],
+                &[&[1, 2, 3]],
+            ),
+            (
+                "another program got the pid",
+                &[
+                    ("sshd", 0, Some(1)),
+                    ("cron", 0, Some(2)),
+                    ("cron", 0, Some(3)),
+                ],
+                &[&[1], &[2, 3]],
+            ),
+            (
+                "and then the first one again",
+                &[
+                    ("sshd", 0, Some(1)),
+                    ("cron", 0, Some(2)),
+                    ("sshd", 0, Some(3)),
+                ],
+                &[&[1], &[2], &[3]],
+            ),
+            (
+                "the same program in the next boot",
+                &[("sshd", 0, Some(1)), ("sshd", 1, Some(1))],
+                &[&[1], &[2]],
+            ),
+            (
+                "quiet for just under the gap",
+                &[("sshd", 0, Some(0)), ("sshd", 0, Some(6 * 60))],
+                &[&[1, 2]],
+            ),
+            (
+                "quiet for longer than the gap",
+                &[
+                    ("sshd", 0, Some(0)),
+                    ("sshd", 0, Some(6 * 60 + 1)),
+                    ("sshd", 0, Some(7 * 60)),
+                ],
+                &[&[1], &[2, 3]],
+            ),
+            (
+                "no time to tell by",
+                &[
+                    ("sshd", 0, Some(0)),
+                    ("sshd", 0, None),
+                    ("sshd", 0, Some(12 * 60)),
+                ],
+                &[&[1, 2, 3]],
+            ),
+            (
+                "no appname is an appname too",
+                &[("", 0, Some(1)), ("", 0, Some(2)), ("sshd", 0, Some(3))],
+                &[&[1, 2], &[3]],
+            ),
+        ];
+        for &(name, rows, expected) in cases {
+            let rows = rows
+                .iter()
+                .enumerate()
+                .map(|(i, &(appname, boot, minutes))| row(i as i64 + 1, appname, boot, minutes));
+            let got: Vec<Vec<i64>> = incarnations(rows, INCARNATION_GAP)
+                .iter()
+                .map(|rows| rows.iter().map(|r| r.id).collect())
+                .collect();
+            assert_eq!(got, expected, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use crate::names;
+use chrono::prelude::*;
+use rusqlite::types::ToSql;
+use std::str::FromStr;
+
+/// An inclusive range of severities in numeric terms (0 = emerg).
+///
+/// A single severity means that one and everything more severe (like journalctl's `-p`),
+/// `a..b` is an explicit range, `a..` is the same as `a`, `..b` is `b` and everything less severe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SeverityRange(pub u8, pub u8);
+
+impl FromStr for SeverityRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sev =
+            |x: &str| names::parse_severity(x).ok_or_else(|| format!("unknown severity '{}'", x));
+        match s.split_once("..") {
+            None => Ok(SeverityRange(0, sev(s)?)),
+            Some((a, "")) => Ok(SeverityRange(0, sev(a)?)),
+            Some(("", b)) => Ok(SeverityRange(sev(b)?, 7)),
+            Some((a, b)) => {
+                let (a, b) = (sev(a)?, sev(b)?);
+                Ok(SeverityRange(a.min(b), a.max(b)))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Facility(pub u8);
+
+impl FromStr for Facility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        names::parse_facility(s)
+            .map(Facility)
+            .ok_or_else(|| format!("unknown facility '{}'", s))
+    }
+}
+
+/// Which rows a query selects. Empty lists don't restrict anything.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub boot: Option<i64>,
+    pub severity: Option<SeverityRange>,
+    /// Individual severities, for when a range doesn't cut it.
+    pub severities: Vec<u8>,
+    pub facilities: Vec<Facility>,
+    pub appnames: Vec<String>,
+    pub sockets: Vec<String>,
+    pub pid: Option<i64>,
+    /// An appname to follow across restarts, the CLI marks where its pid changes.
+    pub unit: Option<String>,
+    /// Also select kernel messages that might mention the unit's processes.
+    pub unit_kernel: bool,
+    /// Substrings that must all be present in the message.
+    pub greps: Vec<String>,
+}
+
+fn push_in<T: ToSql + Clone + 'static>(
+    column: &str,
+    values: &[T],
+    conds: &mut Vec<String>,
+    params: &mut Vec<Box<dyn ToSql>>,
+) {
+    if values.is_empty() {
+        return;
+    }
+    conds.push(format!(
+        "{} IN ({})",
+        column,
+        vec!["?"; values.len()].join(", ")
+    ));
+    params.extend(values.iter().map(|v| Box::new(v.clone()) as Box<dyn ToSql>));
+}
+
+impl Filter {
+    /// Appends the conditions (to be joined with AND) and their positional parameters.
+    pub fn to_sql(&self, conds: &mut Vec<String>, params: &mut Vec<Box<dyn ToSql>>) {
+        self.scope_to_sql(conds, params);
+        self.match_to_sql(conds, params);
+    }
+
+    /// Just the conditions narrowing down where to look (time range, boot, sockets), which
+    /// context lines have to satisfy too.
+    pub fn scope_to_sql(&self, conds: &mut Vec<String>, params: &mut Vec<Box<dyn ToSql>>) {
+        if let Some(since) = self.since {
+            conds.push("recv_time >= ?".to_owned());
+            params.push(Box::new(since));
+        }
+        if let Some(until) = self.until {
+            conds.push("recv_time < ?".to_owned());
+            params.push(Box::new(until));
+        }
+        if let Some(boot) = self.boot {
+            conds.push("boot = ?".to_owned());
+            params.push(Box::new(boot));
+        }
+        push_in("socket", &self.sockets, conds, params);
+    }
+
+    /// The conditions picking out the interesting rows themselves.
+    pub fn match_to_sql(&self, conds: &mut Vec<String>, params: &mut Vec<Box<dyn ToSql>>) {
+        if let Some(SeverityRange(from, to)) = self.severity {
+            conds.push("severity BETWEEN ? AND ?".to_owned());
+            params.push(Box::new(from));
+            params.push(Box::new(to));
+        }
+        push_in("severity", &self.severities, conds, params);
+        let facilities: Vec<u8> = self.facilities.iter().map(|f| f.0).collect();
+        push_in("facility", &facilities, conds, params);
+        push_in("appname", &self.appnames, conds, params);
+        if let Some(pid) = self.pid {
+            conds.push("pid = ?".to_owned());
+            params.push(Box::new(pid));
+        }
+        if let Some(ref unit) = self.unit {
+            if self.unit_kernel {
+                conds.push(format!(
+                    "(appname = ? OR (socket IN ('{}') AND (instr(msg, 'pid ') > 0 OR instr(msg, 'process ') > 0)))",
+                    names::KERNEL_SOCKETS.join("', '")
+                ));
+            } else {
+                conds.push("appname = ?".to_owned());
+            }
+            params.push(Box::new(unit.clone()));
+        }
+        for grep in &self.greps {
+            conds.push("instr(msg, ?) > 0".to_owned());
+            params.push(Box::new(grep.clone()));
+        }
+    }
+}
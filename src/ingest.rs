@@ -0,0 +1,328 @@
+//! Storing messages from another program into the same database as squealogd, for something
+//! like a supervisor that wants its children's output next to everything else:
+//!
+//! ```no_run
+//! let mut ingestor = squealog::ingest::Ingestor::open("/var/log/log.db")?;
+//! ingestor.ingest_line("supervisor", "<30>worker[123]: started")?;
+//! ingestor.flush()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Messages are queued in memory and stored together, `batch` at a time, a second after the
+//! first of them at the latest (checked when the next one comes, so call `flush` when going
+//! idle) and on drop.
+//!
+//! Running alongside the daemon works because the database is in WAL mode: readers never wait,
+//! but there's only one writer at a time. squealogd stores every message on its own, and a
+//! flush stores the whole queue in one transaction, so each waits for the other for as long as
+//! that takes, which is milliseconds. Either one waits up to its busy timeout (10 seconds here,
+//! 5 in the daemon) for the other before giving up; a flush that gave up keeps the queue for
+//! the next one, and once `MAX_QUEUE` are waiting the oldest are dropped. Messages the
+//! database refuses for good (failing a constraint) are dropped too, both being counted in
+//! `dropped`. The daemon's checkpoints are the other thing a flush can be waiting for.
+
+use crate::schema::{self, Row};
+use crate::storage::{self, Record, Storage};
+use chrono::{Datelike, Utc};
+use rusqlite::{Connection, ErrorCode};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use syslog_loose::{Message, ProcId};
+use systemstat::Platform;
+
+const BATCH: usize = 1000;
+/// How long the oldest queued message waits at most, as long as more are coming.
+const MAX_DELAY: Duration = Duration::from_secs(1);
+const MAX_QUEUE: usize = 100_000;
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Ingestor {
+    conn: Connection,
+    boot: Option<i64>,
+    hostname: Option<String>,
+    queue: VecDeque<Record>,
+    /// When the oldest of `queue` was queued.
+    since: Option<Instant>,
+    batch: usize,
+    dropped: u64,
+}
+
+impl Ingestor {
+    /// Opens the database, creating it and bringing the schema up to date if needed.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Ingestor> {
+        let path = path.as_ref();
+        let conn = Connection::open(path)
+            .map_err(|e| anyhow::format_err!("Could not open the database {:?}: {}", path, e))?;
+        Ingestor::with_connection(conn)
+    }
+
+    /// Stores into an already open database, like one also used for queries. Its connection
+    /// gets the busy timeout, the schema is brought up to date.
+    pub fn with_connection(mut conn: Connection) -> anyhow::Result<Ingestor> {
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        // Like the daemon does, for when this is the first to open it.
+        conn.pragma_update(None, "auto_vacuum", "INCREMENTAL")?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        schema::migrations().to_latest(&mut conn)?;
+        let boot = crate::boot::current(&conn, systemstat::System::new().boot_time()?)?;
+        Ok(Ingestor {
+            conn,
+            boot: Some(boot),
+            hostname: crate::sys::hostname().ok(),
+            queue: VecDeque::new(),
+            since: None,
+            batch: BATCH,
+            dropped: 0,
+        })
+    }
+
+    /// Stores `rows` messages at a time instead of 1000.
+    pub fn batch(mut self, rows: usize) -> Ingestor {
+        self.batch = rows.max(1);
+        self
+    }
+
+    /// Parses a line like the daemon parses a datagram, and queues it.
+    pub fn ingest_line(&mut self, source: &str, line: &str) -> rusqlite::Result<()> {
+        let year = chrono::Local::now().year();
+        self.ingest_message(source, crate::payload::parse_syslog(line, year))
+    }
+
+    /// Queues a message as if it came from the `source` socket, with this machine's hostname
+    /// if it has none. Stores the queue when it's time to, returning why that failed.
+    pub fn ingest_message(&mut self, source: &str, msg: Message<&str>) -> rusqlite::Result<()> {
+        // Ids are only known once stored.
+        let record = Record::new(0, &row(source, &msg, self.hostname.as_deref(), self.boot));
+        if self.queue.len() >= MAX_QUEUE {
+            self.queue.pop_front();
+            self.dropped += 1;
+        }
+        self.queue.push_back(record);
+        let since = *self.since.get_or_insert_with(Instant::now);
+        if self.queue.len() >= self.batch || since.elapsed() >= MAX_DELAY {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Stores what's queued, returning how many messages that was.
+    pub fn flush(&mut self) -> rusqlite::Result<u64> {
+        if self.queue.is_empty() {
+            return Ok(0);
+        }
+        let mut storage = storage::Sqlite::new(&self.conn)?;
+        storage.begin_batch()?;
+        let failed = self.queue.iter().enumerate().find_map(|(i, record)| {
+            let failure = storage.insert(&record.row()).err()?;
+            Some((i, failure.error))
+        });
+        if let Some((i, error)) = failed {
+            if !self.conn.is_autocommit() {
+                let _ = self.conn.execute_batch("ROLLBACK");
+            }
+            // Would fail again every time, and keep the rest from being stored.
+            if matches!(error, rusqlite::Error::SqliteFailure(ref e, _)
+                if e.code == ErrorCode::ConstraintViolation)
+            {
+                self.queue.remove(i);
+                self.dropped += 1;
+            }
+            return Err(error);
+        }
+        let stored = storage.commit().map_err(|f| f.error)?;
+        self.queue.clear();
+        self.since = None;
+        Ok(stored)
+    }
+
+    /// Messages waiting for the next flush.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Messages dropped because the queue was full or the database refused them.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// `msg` as it's stored from `source`, received now, with `hostname` if it has none.
+fn row<'a>(
+    source: &'a str,
+    msg: &Message<&'a str>,
+    hostname: Option<&'a str>,
+    boot: Option<i64>,
+) -> Row<'a> {
+    let (hostname, hostname_source) = match (msg.hostname, hostname) {
+        (Some(name), _) => (Some(name), Some("claimed")),
+        (None, Some(name)) => (Some(name), Some("local")),
+        (None, None) => (None, None),
+    };
+    Row {
+        facility: msg.facility.map(|x| x as i64),
+        severity: msg.severity.map(|x| x as i64),
+        socket: source,
+        hostname,
+        hostname_source,
+        appname: msg.appname,
+        pid: msg.procid.as_ref().and_then(|p| match p {
+            ProcId::PID(i) => Some(*i),
+            _ => None,
+        }),
+        msgid: msg.msgid,
+        time: msg.timestamp,
+        recv_time: Utc::now(),
+        boot,
+        msg: msg.msg,
+        sdata: crate::sdata::to_json(&msg.structured_data),
+    }
+}
+
+impl Drop for Ingestor {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload::{self, PayloadParser, SourceCtx};
+    use crate::storage::Memory;
+
+    /// `lines` through `parser` and into `storage`, one batch of them.
+    fn store(storage: &mut Memory, parser: &dyn PayloadParser, lines: &[&str]) -> u64 {
+        let ctx = SourceCtx {
+            socket: "test",
+            local: true,
+            boot_time: Utc::now(),
+            received: "2024-06-01T00:00:00Z".parse().unwrap(),
+        };
+        storage.begin_batch().unwrap();
+        for line in lines {
+            let msg = parser.parse(line.as_bytes(), &ctx).unwrap();
+            let msg = payload::borrowed(&msg);
+            storage
+                .insert(&row("test", &msg, Some("here"), Some(3)))
+                .unwrap();
+        }
+        storage.commit().unwrap()
+    }
+
+    #[test]
+    fn parsed_lines_are_stored_as_rows() {
+        let mut storage = Memory::default();
+        let stored = store(
+            &mut storage,
+            &payload::Syslog,
+            &[
+                "<30>Jun  1 02:03:04 router dnsmasq[1187]: query",
+                "<13>1 2024-06-01T02:03:04.5Z - app worker ID1 [a@1 k=\"v\"] with sdata",
+                "no priority at all",
+            ],
+        );
+        assert_eq!(stored, 3);
+        stored_as(
+            &storage.rows,
+            &[
+                (
+                    1,
+                    Some(3),
+                    Some(6),
+                    "router",
+                    "claimed",
+                    Some("dnsmasq"),
+                    Some(1187),
+                    "query",
+                ),
+                (
+                    2,
+                    Some(1),
+                    Some(5),
+                    "here",
+                    "local",
+                    Some("app"),
+                    None,
+                    "with sdata",
+                ),
+                (
+                    3,
+                    None,
+                    None,
+                    "here",
+                    "local",
+                    None,
+                    None,
+                    "no priority at all",
+                ),
+            ],
+        );
+        assert_eq!(
+            storage.rows[0].time,
+            Some("2024-06-01T02:03:04Z".parse().unwrap())
+        );
+        assert_eq!(storage.rows[1].msgid.as_deref(), Some("ID1"));
+        assert_eq!(
+            storage.rows[1].sdata.as_deref(),
+            Some(r#"{"a@1":{"k":"v"}}"#)
+        );
+        assert!(storage
+            .rows
+            .iter()
+            .all(|r| r.boot == Some(3) && r.socket == "test"));
+
+        let stored = store(
+            &mut storage,
+            &payload::Json,
+            &[r#"{"severity": "err", "facility": "daemon", "app": "web", "msg": "down"}"#],
+        );
+        assert_eq!(stored, 1);
+        stored_as(
+            &storage.rows[3..],
+            &[(
+                4,
+                Some(3),
+                Some(3),
+                "here",
+                "local",
+                Some("web"),
+                None,
+                "down",
+            )],
+        );
+        let stats = storage.stats();
+        assert_eq!((stats.inserted, stats.batches, stats.failed), (4, 2, 0));
+    }
+
+    type Stored<'a> = (
+        i64,
+        Option<i64>,
+        Option<i64>,
+        &'a str,
+        &'a str,
+        Option<&'a str>,
+        Option<i32>,
+        &'a str,
+    );
+
+    /// Id, facility, severity, hostname and where it's from, appname, pid and msg.
+    fn stored_as(rows: &[Record], expected: &[Stored]) {
+        let got: Vec<Stored> = rows
+            .iter()
+            .map(|r| {
+                (
+                    r.id,
+                    r.facility,
+                    r.severity,
+                    r.hostname.as_deref().unwrap(),
+                    r.hostname_source.as_deref().unwrap(),
+                    r.appname.as_deref(),
+                    r.pid,
+                    r.msg.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(got, expected);
+    }
+}
@@ -0,0 +1,99 @@
+//! FreeBSD's kernel messages, as read from `/dev/klog`: a line at a time, each with an optional
+//! `<PRI>` and an optional `[seconds]` since boot in front.
+
+use chrono::{DateTime, Utc};
+use syslog_loose::{Message, Protocol};
+
+/// Parses one line, timestamped from `boottime` if it says when it was since boot. Lines that
+/// don't parse are kept as they are.
+pub fn parse_line<'a>(input: &'a str, boottime: &DateTime<Utc>) -> Message<&'a str> {
+    use nom::{
+        bytes::complete::tag,
+        character::complete::digit1,
+        combinator::{map, map_res, opt, rest},
+        sequence::{delimited, tuple},
+        IResult,
+    };
+    use std::str::FromStr;
+
+    fn digits<T>(input: &str) -> IResult<&str, T>
+    where
+        T: FromStr,
+    {
+        map_res(digit1, FromStr::from_str)(input)
+    }
+
+    tuple((
+        map(
+            opt(delimited(
+                tag("<"),
+                map(digits, syslog_loose::decompose_pri),
+                tag(">"),
+            )),
+            |pri| pri.unwrap_or((None, None)),
+        ),
+        // Any more seconds than fit in a u32 aren't an uptime, and would overflow the time.
+        opt(delimited(tag("["), digits::<u32>, tag("]"))),
+        rest,
+    ))(input)
+    .map(|(_, ((facility, severity), ts, rest))| Message {
+        protocol: Protocol::RFC5424(69),
+        facility,
+        severity,
+        timestamp: ts.map(|n| (*boottime + chrono::Duration::seconds(n.into())).into()),
+        hostname: None,
+        appname: None,
+        procid: None,
+        msgid: None,
+        structured_data: vec![],
+        msg: rest.trim(),
+    })
+    .unwrap_or(Message {
+        facility: None,
+        severity: None,
+        timestamp: None,
+        hostname: None,
+        appname: None,
+        procid: None,
+        msgid: None,
+        protocol: Protocol::RFC3164,
+        structured_data: vec![],
+        msg: input,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn seconds_since_boot() {
+        let boot = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let msg = parse_line("<4>[90] pid 77 (sh): exited on signal 11", &boot);
+        assert_eq!(
+            msg.severity,
+            Some(syslog_loose::SyslogSeverity::SEV_WARNING)
+        );
+        assert_eq!(
+            msg.timestamp.map(|t| t.with_timezone(&Utc)),
+            Some(boot + chrono::Duration::seconds(90))
+        );
+        assert_eq!(msg.msg, "pid 77 (sh): exited on signal 11");
+    }
+
+    /// Used to panic in `Duration::seconds`, found by the `klog_structured` fuzz target.
+    #[test]
+    fn seconds_past_a_u32_are_text() {
+        let boot = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).unwrap();
+        let line = include_str!("../fuzz/regressions/klog-seconds-overflow");
+        let msg = parse_line(line, &boot);
+        assert_eq!(msg.severity, Some(syslog_loose::SyslogSeverity::SEV_INFO));
+        assert_eq!(msg.timestamp, None);
+        assert_eq!(msg.msg, "[9223372036854775807] kernel: boom");
+
+        let msg = parse_line("[99999999999999999999999999] x", &boot);
+        assert_eq!(msg.timestamp, None);
+        assert_eq!(msg.msg, "[99999999999999999999999999] x");
+    }
+}
@@ -0,0 +1,24 @@
+pub mod boot;
+pub mod bulk;
+pub mod capture;
+pub mod client;
+pub mod config;
+pub mod digest;
+pub mod filter;
+pub mod ingest;
+pub mod klog;
+pub mod names;
+pub mod payload;
+pub mod query;
+pub mod schema;
+pub mod sdata;
+pub mod selector;
+pub mod serialize;
+pub mod squealogd;
+pub mod stats;
+pub mod storage;
+#[cfg(feature = "tracing")]
+pub mod subscriber;
+pub mod sys;
+pub mod time;
+pub mod transport;
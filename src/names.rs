@@ -0,0 +1,53 @@
+pub const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+pub const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv",
+    "ftp", "ntp", "security", "console", "cron2", "local0", "local1", "local2", "local3", "local4",
+    "local5", "local6", "local7",
+];
+
+/// Socket names used for kernel messages.
+pub const KERNEL_SOCKETS: [&str; 2] = ["klog", "kmsg"];
+
+pub fn severity_name(sev: i64) -> Option<&'static str> {
+    usize::try_from(sev)
+        .ok()
+        .and_then(|i| SEVERITIES.get(i))
+        .copied()
+}
+
+pub fn facility_name(fac: i64) -> Option<&'static str> {
+    usize::try_from(fac)
+        .ok()
+        .and_then(|i| FACILITIES.get(i))
+        .copied()
+}
+
+/// Accepts a severity name (including the usual aliases) or number.
+pub fn parse_severity(s: &str) -> Option<u8> {
+    let s = s.to_ascii_lowercase();
+    let canonical = match s.as_str() {
+        "emergency" | "panic" => "emerg",
+        "critical" => "crit",
+        "error" => "err",
+        "warn" => "warning",
+        "information" | "informational" => "info",
+        other => other,
+    };
+    SEVERITIES
+        .iter()
+        .position(|&n| n == canonical)
+        .map(|i| i as u8)
+        .or_else(|| s.parse().ok().filter(|&n: &u8| n < 8))
+}
+
+pub fn parse_facility(s: &str) -> Option<u8> {
+    let s = s.to_ascii_lowercase();
+    FACILITIES
+        .iter()
+        .position(|&n| n == s)
+        .map(|i| i as u8)
+        .or_else(|| s.parse().ok().filter(|&n: &u8| n < 24))
+}
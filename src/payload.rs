@@ -0,0 +1,788 @@
+//! Turning what a source received into a message, by a parser picked per source by name:
+//!
+//! ```toml
+//! [[listen]]
+//! name = "graylog"
+//! udp = "[::]:12201"
+//! parser = "gelf"
+//! ```
+//!
+//! The built-in ones are `syslog` (RFC 5424 and 3164, loosely, the default for sockets),
+//! `klog` (FreeBSD kernel lines, the default for `/dev/klog`), `gelf` (Graylog's JSON, plain or
+//! zlib/gzip compressed but not chunked), `json` (an object of our own, see `Json`), `logfmt`
+//! (what `squealog --output logfmt` writes, see `Logfmt`) and `raw` (the whole payload as the
+//! message text).
+//! Another program linking the library adds its own formats to a `Registry` under a name of
+//! their own, after which config can refer to them by it.
+//!
+//! A parser gets the payload as bytes, for binary formats, and returns a message that borrows
+//! from them where it can: `Message` is syslog_loose's with `Cow` strings, so that text which
+//! is in the payload as it is (all of it, for syslog) isn't copied, and text the parser had to
+//! decode or unescape can still be returned. A payload that isn't valid UTF-8 is tried again
+//! with the invalid parts replaced, and one that doesn't parse either way is kept as `raw`,
+//! so nothing is lost for not parsing (see `parse_or_keep`).
+
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use std::sync::Arc;
+use syslog_loose::{ProcId, Protocol, StructuredElement};
+
+/// A parsed message, borrowing from the payload where it could.
+pub type Message<'a> = syslog_loose::Message<Cow<'a, str>>;
+
+/// Where a payload came from.
+pub struct SourceCtx<'a> {
+    /// The socket name it's stored with.
+    pub socket: &'a str,
+    /// Whether it's a source on this machine.
+    pub local: bool,
+    /// When this boot started, for formats that count from it.
+    pub boot_time: DateTime<Utc>,
+    /// When it arrived, which RFC 3164 timestamps get their year from.
+    pub received: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct ParseError(String);
+
+impl ParseError {
+    pub fn new(reason: impl Into<String>) -> ParseError {
+        ParseError(reason.into())
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub trait PayloadParser: Send + Sync {
+    fn parse<'a>(&self, bytes: &'a [u8], ctx: &SourceCtx) -> Result<Message<'a>, ParseError>;
+}
+
+/// The payload as text, for parsers of text formats.
+pub fn text(bytes: &[u8]) -> Result<&str, ParseError> {
+    std::str::from_utf8(bytes).map_err(|e| ParseError::new(format!("not UTF-8: {}", e)))
+}
+
+/// A message as syslog_loose parses it, borrowing all of it.
+pub fn from_borrowed<'a>(msg: syslog_loose::Message<&'a str>) -> Message<'a> {
+    syslog_loose::Message {
+        protocol: msg.protocol,
+        facility: msg.facility,
+        severity: msg.severity,
+        timestamp: msg.timestamp,
+        hostname: msg.hostname.map(Cow::Borrowed),
+        appname: msg.appname.map(Cow::Borrowed),
+        procid: msg.procid.map(|p| match p {
+            ProcId::PID(pid) => ProcId::PID(pid),
+            ProcId::Name(name) => ProcId::Name(Cow::Borrowed(name)),
+        }),
+        msgid: msg.msgid.map(Cow::Borrowed),
+        structured_data: msg
+            .structured_data
+            .into_iter()
+            .map(|e| StructuredElement {
+                id: Cow::Borrowed(e.id),
+                params: e
+                    .params
+                    .into_iter()
+                    .map(|(k, v)| (Cow::Borrowed(k), Cow::Borrowed(v)))
+                    .collect(),
+            })
+            .collect(),
+        msg: Cow::Borrowed(msg.msg),
+    }
+}
+
+/// The message with plain `&str`s, as the rest of the daemon takes it. Only the structured
+/// data is copied, the strings are borrowed from `msg`.
+pub fn borrowed<'b>(msg: &'b Message<'_>) -> syslog_loose::Message<&'b str> {
+    syslog_loose::Message {
+        protocol: match msg.protocol {
+            Protocol::RFC3164 => Protocol::RFC3164,
+            Protocol::RFC5424(version) => Protocol::RFC5424(version),
+        },
+        facility: msg.facility,
+        severity: msg.severity,
+        timestamp: msg.timestamp,
+        hostname: msg.hostname.as_deref(),
+        appname: msg.appname.as_deref(),
+        procid: msg.procid.as_ref().map(|p| match p {
+            ProcId::PID(pid) => ProcId::PID(*pid),
+            ProcId::Name(name) => ProcId::Name(name.as_ref()),
+        }),
+        msgid: msg.msgid.as_deref(),
+        structured_data: msg
+            .structured_data
+            .iter()
+            .map(|e| StructuredElement {
+                id: e.id.as_ref(),
+                params: e
+                    .params
+                    .iter()
+                    .map(|(k, v)| (k.as_ref(), v.as_ref()))
+                    .collect(),
+            })
+            .collect(),
+        msg: &msg.msg,
+    }
+}
+
+/// Parses `bytes` with `parser`, then `text` (the same payload, made valid UTF-8), and if it
+/// can't make anything of either keeps `text` as the message, returning why it had to.
+pub fn parse_or_keep<'a>(
+    parser: &dyn PayloadParser,
+    bytes: &'a [u8],
+    text: &'a str,
+    ctx: &SourceCtx,
+) -> (Message<'a>, Option<ParseError>) {
+    let e = match parser.parse(bytes, ctx) {
+        Ok(msg) => return (msg, None),
+        Err(e) => e,
+    };
+    if text.as_bytes() != bytes {
+        if let Ok(msg) = parser.parse(text.as_bytes(), ctx) {
+            return (msg, None);
+        }
+    }
+    (raw(Cow::Borrowed(text)), Some(e))
+}
+
+fn raw(text: Cow<'_, str>) -> Message<'_> {
+    syslog_loose::Message {
+        protocol: Protocol::RFC3164,
+        facility: None,
+        severity: None,
+        timestamp: None,
+        hostname: None,
+        appname: None,
+        procid: None,
+        msgid: None,
+        structured_data: vec![],
+        msg: text,
+    }
+}
+
+/// `syslog_loose::parse_message_with_year`, except that a line with an RFC 3164 timestamp that
+/// isn't a real time (`Feb 30`, `25:61:00`) is kept whole as the message, like one that doesn't
+/// parse, instead of making chrono panic.
+pub fn parse_syslog(line: &str, year: i32) -> syslog_loose::Message<&str> {
+    if impossible_3164_time(line.trim(), year) {
+        return syslog_loose::Message {
+            protocol: Protocol::RFC3164,
+            facility: None,
+            severity: None,
+            timestamp: None,
+            hostname: None,
+            appname: None,
+            procid: None,
+            msgid: None,
+            structured_data: vec![],
+            msg: line,
+        };
+    }
+    syslog_loose::parse_message_with_year(line, |_| year)
+}
+
+/// Whether the line starts like a RFC 3164 message, as syslog_loose has it, but with a time
+/// that doesn't exist.
+fn impossible_3164_time(line: &str, year: i32) -> bool {
+    use chrono::{Local, NaiveDate};
+    use nom::{
+        bytes::complete::{tag, take},
+        character::complete::{digit1, space0, space1},
+        combinator::{map_res, opt},
+        sequence::{delimited, tuple},
+        IResult,
+    };
+    use std::str::FromStr;
+
+    fn digits<T: FromStr>(input: &str) -> IResult<&str, T> {
+        map_res(digit1, FromStr::from_str)(input)
+    }
+    fn month(input: &str) -> IResult<&str, u32> {
+        const MONTHS: [&str; 12] = [
+            "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+        ];
+        map_res(take(3_usize), |m: &str| {
+            match MONTHS.iter().position(|name| name.eq_ignore_ascii_case(m)) {
+                Some(i) => Ok(i as u32 + 1),
+                None => Err(()),
+            }
+        })(input)
+    }
+
+    fn time(input: &str) -> IResult<&str, (u32, u32, u32)> {
+        let (rest, (h, _, min, _, sec)) =
+            tuple((digits, tag(":"), digits, tag(":"), digits))(input)?;
+        Ok((rest, (h, min, sec)))
+    }
+
+    let line = match tuple((opt(delimited(tag("<"), digits::<u8>, tag(">"))), space0))(line) {
+        Ok((line, _)) => line,
+        Err(_) => return false,
+    };
+    if let Ok((_, (mon, _, day, _, (h, min, sec)))) =
+        tuple((month, space1, digits::<u32>, space1, time))(line)
+    {
+        // Both the day and the time in it have to be there in local time.
+        return [(0, 0, 0), (h, min, sec)].iter().any(|&(h, min, sec)| {
+            Local
+                .with_ymd_and_hms(year, mon, day, h, min, sec)
+                .single()
+                .is_none()
+        });
+    }
+    if let Ok((_, (mon, _, day, _, year, _, (h, min, sec)))) = tuple((
+        month,
+        space1,
+        digits::<u32>,
+        space1,
+        digits::<i32>,
+        space1,
+        time,
+    ))(line)
+    {
+        return NaiveDate::from_ymd_opt(year, mon, day)
+            .and_then(|date| date.and_hms_opt(h, min, sec))
+            .is_none();
+    }
+    false
+}
+
+pub struct Syslog;
+
+impl PayloadParser for Syslog {
+    fn parse<'a>(&self, bytes: &'a [u8], ctx: &SourceCtx) -> Result<Message<'a>, ParseError> {
+        let year = ctx.received.with_timezone(&chrono::Local).year();
+        Ok(from_borrowed(parse_syslog(text(bytes)?, year)))
+    }
+}
+
+pub struct Klog;
+
+impl PayloadParser for Klog {
+    fn parse<'a>(&self, bytes: &'a [u8], ctx: &SourceCtx) -> Result<Message<'a>, ParseError> {
+        Ok(from_borrowed(crate::klog::parse_line(
+            text(bytes)?,
+            &ctx.boot_time,
+        )))
+    }
+}
+
+pub struct Raw;
+
+impl PayloadParser for Raw {
+    fn parse<'a>(&self, bytes: &'a [u8], _: &SourceCtx) -> Result<Message<'a>, ParseError> {
+        Ok(raw(String::from_utf8_lossy(bytes)))
+    }
+}
+
+/// The SD-ID GELF's additional fields are kept under, with the `_` in front taken off.
+pub const GELF_ID: &str = "gelf@32473";
+
+/// The most a compressed GELF payload may inflate to, a datagram that's all zeros otherwise
+/// being enough for some 64M.
+const MAX_INFLATED: u64 = 1 << 20;
+
+pub struct Gelf;
+
+impl PayloadParser for Gelf {
+    fn parse<'a>(&self, bytes: &'a [u8], _: &SourceCtx) -> Result<Message<'a>, ParseError> {
+        let mut inflated = vec![];
+        let mut inflate = |from: &mut dyn Read, what: &str| {
+            // One past the limit, to tell a payload that's exactly as big from a bigger one.
+            match from.take(MAX_INFLATED + 1).read_to_end(&mut inflated) {
+                Ok(n) if n as u64 > MAX_INFLATED => Err(ParseError::new(format!(
+                    "{} inflates to more than {} bytes",
+                    what, MAX_INFLATED
+                ))),
+                Ok(_) => Ok(()),
+                Err(e) => Err(ParseError::new(format!("bad {}: {}", what, e))),
+            }
+        };
+        let json = match bytes {
+            [0x1e, 0x0f, ..] => return Err(ParseError::new("chunked GELF isn't supported")),
+            [0x1f, 0x8b, ..] => {
+                inflate(&mut flate2::read::GzDecoder::new(bytes), "gzip")?;
+                &inflated[..]
+            }
+            [0x78, ..] => {
+                inflate(&mut flate2::read::ZlibDecoder::new(bytes), "zlib")?;
+                &inflated[..]
+            }
+            _ => bytes,
+        };
+        let mut fields: Map<String, Value> = serde_json::from_slice(json)
+            .map_err(|e| ParseError::new(format!("not a GELF object: {}", e)))?;
+        let mut string = |name: &str| match fields.remove(name) {
+            Some(Value::String(s)) => Some(Cow::Owned(s)),
+            _ => None,
+        };
+        let short = string("short_message").ok_or_else(|| ParseError::new("no short_message"))?;
+        let hostname = string("host");
+        let full = string("full_message");
+        let timestamp = match fields.remove("timestamp") {
+            Some(Value::Number(n)) => n.as_f64().and_then(|secs| {
+                let nanos = ((secs.fract() * 1e9) as u32).min(999_999_999);
+                Utc.timestamp_opt(secs.trunc() as i64, nanos).single()
+            }),
+            _ => None,
+        };
+        // GELF says 1 (alert) when it's left out.
+        let level = match fields.remove("level") {
+            Some(Value::Number(n)) => n.as_u64().filter(|&l| l < 8).unwrap_or(1),
+            _ => 1,
+        };
+        let mut params: Vec<(Cow<str>, Cow<str>)> = vec![];
+        if let Some(full) = full {
+            params.push((Cow::Borrowed("full_message"), full));
+        }
+        for (name, value) in fields {
+            let name = match name.strip_prefix('_') {
+                // `_id` is reserved.
+                Some(n) if !n.is_empty() && n != "id" => n.to_owned(),
+                _ => continue,
+            };
+            let value = match value {
+                Value::String(s) => s,
+                Value::Null => continue,
+                other => other.to_string(),
+            };
+            params.push((Cow::Owned(name), Cow::Owned(value)));
+        }
+        Ok(syslog_loose::Message {
+            protocol: Protocol::RFC5424(1),
+            facility: None,
+            severity: syslog_loose::decompose_pri(level as u8).1,
+            timestamp: timestamp.map(|t| t.into()),
+            hostname,
+            appname: None,
+            procid: None,
+            msgid: None,
+            structured_data: if params.is_empty() {
+                vec![]
+            } else {
+                vec![StructuredElement {
+                    id: Cow::Borrowed(GELF_ID),
+                    params,
+                }]
+            },
+            msg: short,
+        })
+    }
+}
+
+/// The SD-ID `json`'s other keys are kept under.
+pub const JSON_ID: &str = "json@32473";
+
+/// A JSON object per payload, for programs that would rather not write syslog headers:
+///
+/// ```json
+/// {"severity": "warning", "app": "backup", "msg": "disk almost full", "free": "2G"}
+/// ```
+///
+/// `severity` and `facility` are names or numbers, `app` is the appname, and `msgid` and `msg`
+/// are what they say (`msg` being empty if it's left out). Every other key becomes a param of
+/// the `json@32473` element, with values that aren't strings as JSON. A field that's missing
+/// is left unset (for squealogd's `defaults` enricher to fill in), and one that isn't what it
+/// should be makes the payload not parse.
+pub struct Json;
+
+fn json_string(value: Value, key: &str) -> Result<String, ParseError> {
+    match value {
+        Value::String(s) => Ok(s),
+        other => Err(ParseError::new(format!(
+            "'{}' should be a string, not {}",
+            key, other
+        ))),
+    }
+}
+
+/// A severity or facility, by `parse`'s idea of a name or number.
+fn json_code(value: Value, key: &str, parse: fn(&str) -> Option<u8>) -> Result<u8, ParseError> {
+    let text = match value {
+        Value::String(s) => s,
+        Value::Number(n) => n.to_string(),
+        other => other.to_string(),
+    };
+    parse(&text).ok_or_else(|| ParseError::new(format!("'{}' can't be {}", key, text)))
+}
+
+impl PayloadParser for Json {
+    fn parse<'a>(&self, bytes: &'a [u8], _: &SourceCtx) -> Result<Message<'a>, ParseError> {
+        let fields: Map<String, Value> = serde_json::from_slice(bytes)
+            .map_err(|e| ParseError::new(format!("not a JSON object: {}", e)))?;
+        let mut msg = raw(Cow::Borrowed(""));
+        msg.protocol = Protocol::RFC5424(1);
+        let mut params: Vec<(Cow<str>, Cow<str>)> = vec![];
+        for (key, value) in fields {
+            match key.as_str() {
+                "severity" => {
+                    let sev = json_code(value, &key, crate::names::parse_severity)?;
+                    msg.severity = syslog_loose::decompose_pri(sev).1;
+                }
+                "facility" => {
+                    let fac = json_code(value, &key, crate::names::parse_facility)?;
+                    msg.facility = syslog_loose::decompose_pri(fac << 3).0;
+                }
+                "app" => msg.appname = Some(Cow::Owned(json_string(value, &key)?)),
+                "msgid" => msg.msgid = Some(Cow::Owned(json_string(value, &key)?)),
+                "msg" => msg.msg = Cow::Owned(json_string(value, &key)?),
+                _ => {
+                    let value = match value {
+                        Value::String(s) => s,
+                        Value::Null => continue,
+                        other => other.to_string(),
+                    };
+                    params.push((Cow::Owned(key), Cow::Owned(value)));
+                }
+            }
+        }
+        if !params.is_empty() {
+            msg.structured_data.push(StructuredElement {
+                id: Cow::Borrowed(JSON_ID),
+                params,
+            });
+        }
+        Ok(msg)
+    }
+}
+
+/// The SD-ID `logfmt`'s keys that aren't fields of their own are kept under.
+pub const LOGFMT_ID: &str = "logfmt@32473";
+
+/// A line of `key=value` pairs, as `squealog --output logfmt` writes them:
+///
+/// ```text
+/// time=2024-01-02T03:04:05+00:00 host=web1 app=backup pid=42 sev=warning msg="disk almost full"
+/// ```
+///
+/// `time` is RFC 3339, `sev` a severity name or number, and `host`, `app`, `pid` and `msg` are
+/// what they say. `sd.<sdid>.<param>` keys are the params of structured data elements (split
+/// at the last dot), `socket` is left out for the socket the line arrives on, and every other
+/// key becomes a param of the `logfmt@32473` element. Values are bare up to the next space, or
+/// quoted with `\"`, `\\`, `\n`, `\r` and `\t` escapes, and empty ones are left unset.
+pub struct Logfmt;
+
+/// A quoted logfmt value, from after the opening quote, and what's left after the closing one.
+fn logfmt_quoted(quoted: &str) -> Option<(Cow<'_, str>, &str)> {
+    // Borrowed if nothing was escaped.
+    let end = quoted.find(['"', '\\'])?;
+    if quoted.as_bytes()[end] == b'"' {
+        return Some((Cow::Borrowed(&quoted[..end]), &quoted[end + 1..]));
+    }
+    let mut value = String::from(&quoted[..end]);
+    let mut chars = quoted[end..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((Cow::Owned(value), &quoted[end + i + 1..])),
+            '\\' => value.push(match chars.next()?.1 {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                c @ ('"' | '\\') => c,
+                _ => return None,
+            }),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// The `key=value` pairs of a logfmt line, in order.
+fn logfmt_pairs(line: &str) -> Result<Vec<(&str, Cow<'_, str>)>, ParseError> {
+    let mut pairs = vec![];
+    let mut rest = line.trim_end_matches(['\n', '\r']).trim_start_matches(' ');
+    while !rest.is_empty() {
+        let key = match rest.find(['=', ' ']) {
+            Some(eq) if eq > 0 && rest.as_bytes()[eq] == b'=' => &rest[..eq],
+            _ => return Err(ParseError::new(format!("expected key=value at '{}'", rest))),
+        };
+        rest = &rest[key.len() + 1..];
+        let value = match rest.strip_prefix('"') {
+            Some(quoted) => {
+                let (value, after) = logfmt_quoted(quoted).ok_or_else(|| {
+                    ParseError::new(format!("'{}' has a bad escape or no closing quote", key))
+                })?;
+                if !(after.is_empty() || after.starts_with(' ')) {
+                    return Err(ParseError::new(format!(
+                        "expected a space after the value of '{}'",
+                        key
+                    )));
+                }
+                rest = after;
+                value
+            }
+            None => {
+                let end = rest.find(' ').unwrap_or(rest.len());
+                let value = Cow::Borrowed(&rest[..end]);
+                rest = &rest[end..];
+                value
+            }
+        };
+        pairs.push((key, value));
+        rest = rest.trim_start_matches(' ');
+    }
+    Ok(pairs)
+}
+
+impl PayloadParser for Logfmt {
+    fn parse<'a>(&self, bytes: &'a [u8], _: &SourceCtx) -> Result<Message<'a>, ParseError> {
+        let mut msg = raw(Cow::Borrowed(""));
+        msg.protocol = Protocol::RFC5424(1);
+        let mut other: Vec<(Cow<str>, Cow<str>)> = vec![];
+        for (key, value) in logfmt_pairs(text(bytes)?)? {
+            if value.is_empty() {
+                continue;
+            }
+            match key {
+                "time" => {
+                    let time = DateTime::parse_from_rfc3339(&value).map_err(|e| {
+                        ParseError::new(format!("'time' isn't an RFC 3339 time: {}", e))
+                    })?;
+                    msg.timestamp = Some(time);
+                }
+                "host" => msg.hostname = Some(value),
+                "app" => msg.appname = Some(value),
+                "pid" => {
+                    msg.procid = Some(match value.parse() {
+                        Ok(pid) => ProcId::PID(pid),
+                        Err(_) => ProcId::Name(value),
+                    })
+                }
+                "sev" => {
+                    let sev = crate::names::parse_severity(&value)
+                        .ok_or_else(|| ParseError::new(format!("'sev' can't be {}", value)))?;
+                    msg.severity = syslog_loose::decompose_pri(sev).1;
+                }
+                "msg" => msg.msg = value,
+                "socket" => (),
+                _ => {
+                    let (id, param) = match key.strip_prefix("sd.").and_then(|k| k.rsplit_once('.'))
+                    {
+                        Some(sd) => sd,
+                        None => {
+                            other.push((Cow::Borrowed(key), value));
+                            continue;
+                        }
+                    };
+                    match msg.structured_data.iter_mut().find(|e| e.id == id) {
+                        Some(element) => element.params.push((Cow::Borrowed(param), value)),
+                        None => msg.structured_data.push(StructuredElement {
+                            id: Cow::Borrowed(id),
+                            params: vec![(Cow::Borrowed(param), value)],
+                        }),
+                    }
+                }
+            }
+        }
+        if !other.is_empty() {
+            msg.structured_data.push(StructuredElement {
+                id: Cow::Borrowed(LOGFMT_ID),
+                params: other,
+            });
+        }
+        Ok(msg)
+    }
+}
+
+/// Parsers by name, the built-in ones and whatever else was registered.
+#[derive(Clone)]
+pub struct Registry {
+    parsers: BTreeMap<String, Arc<dyn PayloadParser>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Registry {
+            parsers: BTreeMap::new(),
+        };
+        registry.register("syslog", Syslog);
+        registry.register("klog", Klog);
+        registry.register("gelf", Gelf);
+        registry.register("json", Json);
+        registry.register("logfmt", Logfmt);
+        registry.register("raw", Raw);
+        registry
+    }
+}
+
+impl Registry {
+    /// Adds a parser, or replaces the one with the same name.
+    pub fn register(&mut self, name: &str, parser: impl PayloadParser + 'static) {
+        self.parsers.insert(name.to_owned(), Arc::new(parser));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn PayloadParser>> {
+        self.parsers.get(name).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.parsers.keys().map(|n| n.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn ctx() -> SourceCtx<'static> {
+        SourceCtx {
+            socket: "test",
+            local: true,
+            boot_time: chrono::Utc::now(),
+            received: chrono::Utc::now(),
+        }
+    }
+
+    /// Used to inflate all the way, a few KB of datagram taking up 4M.
+    #[test]
+    fn gelf_bombs_are_refused() {
+        for (bomb, what) in [
+            (
+                &include_bytes!("../fuzz/regressions/gelf-zlib-bomb")[..],
+                "zlib",
+            ),
+            (
+                &include_bytes!("../fuzz/regressions/gelf-gzip-bomb")[..],
+                "gzip",
+            ),
+        ] {
+            let e = Gelf.parse(bomb, &ctx()).expect_err("should be refused");
+            assert_eq!(
+                e.to_string(),
+                format!("{} inflates to more than {} bytes", what, MAX_INFLATED)
+            );
+        }
+    }
+
+    /// Used to panic in chrono, found by the `payload` fuzz target.
+    #[test]
+    fn impossible_3164_times_are_text() {
+        for line in [
+            include_str!("../fuzz/regressions/syslog-impossible-date"),
+            include_str!("../fuzz/regressions/syslog-impossible-time"),
+        ] {
+            let msg = parse_syslog(line, 2021);
+            assert_eq!(msg.timestamp, None);
+            assert_eq!(msg.msg, line);
+        }
+        let msg = parse_syslog("<34>Feb 28 22:14:15 mymachine su: fine", 2021);
+        assert!(msg.timestamp.is_some());
+        assert_eq!(msg.hostname, Some("mymachine"));
+        assert_eq!(msg.msg, "fine");
+        // Only there in leap years.
+        assert!(parse_syslog("Feb 29 22:14:15 mymachine su: x", 2020)
+            .timestamp
+            .is_some());
+        assert!(parse_syslog("Feb 29 22:14:15 mymachine su: x", 2021)
+            .timestamp
+            .is_none());
+    }
+
+    #[test]
+    fn gelf_compressed() {
+        let bytes = include_bytes!("../fuzz/corpus/payload/gelf-zlib");
+        let msg = Gelf.parse(bytes, &ctx()).unwrap();
+        assert_eq!(msg.msg, "zipped");
+        assert_eq!(msg.hostname.as_deref(), Some("h"));
+    }
+
+    #[test]
+    fn logfmt_keys() {
+        let bytes = include_bytes!("../fuzz/corpus/payload/logfmt");
+        let msg = Logfmt.parse(bytes, &ctx()).unwrap();
+        assert_eq!(msg.msg, "say \"hello\"\n\tagain");
+        assert_eq!(msg.appname.as_deref(), Some("thing"));
+        assert_eq!(
+            msg.severity,
+            Some(syslog_loose::SyslogSeverity::SEV_WARNING)
+        );
+        let elements: Vec<(&str, Vec<(&str, &str)>)> = msg
+            .structured_data
+            .iter()
+            .map(|e| (&*e.id, e.params.iter().map(|(k, v)| (&**k, &**v)).collect()))
+            .collect();
+        assert_eq!(
+            elements,
+            [
+                ("origin@32473", vec![("ip", "10.0.0.1")]),
+                (LOGFMT_ID, vec![("extra", "1")]),
+            ]
+        );
+
+        for bad in [
+            "msg",
+            "=value",
+            "msg=\"no closing quote",
+            "msg=\"bad \\q escape\"",
+            "msg=\"quoted\"right after",
+            "sev=loud",
+            "time=yesterday",
+        ] {
+            assert!(Logfmt.parse(bad.as_bytes(), &ctx()).is_err(), "{:?}", bad);
+        }
+    }
+
+    /// A short stand-in for a fuzzing run: the fuzz corpus and the crashers found so far, and
+    /// some mangled copies of them, through every parser.
+    #[test]
+    fn fuzz_corpus() {
+        let fuzz = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz");
+        let mut inputs = vec![];
+        for dir in ["corpus/klog_parse_line", "corpus/payload", "regressions"] {
+            for entry in std::fs::read_dir(fuzz.join(dir)).unwrap() {
+                inputs.push(std::fs::read(entry.unwrap().path()).unwrap());
+            }
+        }
+        assert!(inputs.len() > 10);
+        // xorshift, for the same mangling every time.
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let registry = Registry::default();
+        let ctx = ctx();
+        for input in &inputs {
+            for round in 0..64 {
+                let mut bytes = input.clone();
+                if round > 0 && !bytes.is_empty() {
+                    for _ in 0..1 + next() % 4 {
+                        let at = next() as usize % bytes.len();
+                        match next() % 4 {
+                            0 => bytes[at] = next() as u8,
+                            1 => bytes.truncate(at),
+                            2 => bytes.insert(at, b"0123456789<>[]{}\"\\"[next() as usize % 18]),
+                            _ => {
+                                let rest = bytes[at..].to_vec();
+                                bytes.extend(rest);
+                            }
+                        }
+                        if bytes.is_empty() {
+                            break;
+                        }
+                    }
+                }
+                let text = String::from_utf8_lossy(&bytes);
+                crate::klog::parse_line(&text, &ctx.boot_time);
+                for name in registry.names() {
+                    let parser = registry.get(name).unwrap();
+                    parse_or_keep(&*parser, &bytes, &text, &ctx);
+                }
+            }
+        }
+    }
+}
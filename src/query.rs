@@ -0,0 +1,172 @@
+use crate::{filter::Filter, names};
+use chrono::prelude::*;
+use rusqlite::{types::ToSql, Connection};
+
+#[derive(Clone, Debug)]
+pub struct Row {
+    pub id: i64,
+    /// The message's own timestamp, or the receive time if it didn't have one.
+    pub time: Option<DateTime<FixedOffset>>,
+    pub severity: Option<i64>,
+    pub socket: String,
+    pub hostname: Option<String>,
+    pub appname: Option<String>,
+    pub pid: Option<i64>,
+    pub msgid: Option<String>,
+    pub msg: String,
+    /// When the boot the row was logged in started.
+    pub boot_time: Option<DateTime<Utc>>,
+    /// Structured data as stored, see `crate::sdata`.
+    pub sdata: Option<String>,
+}
+
+impl Row {
+    const COLUMNS: &'static str = "id, coalesce(time, recv_time), severity, socket, hostname, \
+        appname, pid, msg, (SELECT boot_time FROM boot WHERE boot.id = log.boot), sdata, msgid";
+
+    fn from_sql(row: &rusqlite::Row) -> rusqlite::Result<Row> {
+        Ok(Row {
+            id: row.get(0)?,
+            time: row.get(1)?,
+            severity: row.get(2)?,
+            socket: row.get(3)?,
+            hostname: row.get(4)?,
+            appname: row.get(5)?,
+            pid: row.get(6)?,
+            msg: row.get(7)?,
+            boot_time: row.get(8)?,
+            sdata: row.get(9)?,
+            msgid: row.get(10)?,
+        })
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let sdata = self
+            .sdata
+            .as_deref()
+            .and_then(|s| serde_json::from_str(s).ok())
+            .unwrap_or(serde_json::Value::Null);
+        serde_json::json!({
+            "id": self.id,
+            "time": self.time.map(|t| t.to_rfc3339()),
+            "severity": self.severity,
+            "severity_name": self.severity.and_then(names::severity_name),
+            "socket": self.socket,
+            "hostname": self.hostname,
+            "appname": self.appname,
+            "pid": self.pid,
+            "msgid": self.msgid,
+            "msg": self.msg,
+            "sdata": sdata,
+        })
+    }
+}
+
+/// Rows matching the filter with an id above `after` and below `before`, oldest first unless
+/// `newest_first`.
+pub fn select(
+    conn: &Connection,
+    filter: &Filter,
+    after: Option<i64>,
+    before: Option<i64>,
+    newest_first: bool,
+    limit: Option<usize>,
+    mut f: impl FnMut(Row) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut conds = vec![];
+    let mut params: Vec<Box<dyn ToSql>> = vec![];
+    filter.to_sql(&mut conds, &mut params);
+    if let Some(after) = after {
+        conds.push("id > ?".to_owned());
+        params.push(Box::new(after));
+    }
+    if let Some(before) = before {
+        conds.push("id < ?".to_owned());
+        params.push(Box::new(before));
+    }
+    let mut sql = format!("SELECT {} FROM log", Row::COLUMNS);
+    if !conds.is_empty() {
+        sql += " WHERE ";
+        sql += &conds.join(" AND ");
+    }
+    sql += if newest_first {
+        " ORDER BY id DESC"
+    } else {
+        " ORDER BY id"
+    };
+    if let Some(limit) = limit {
+        sql += &format!(" LIMIT {}", limit);
+    }
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(rusqlite::params_from_iter(params.iter()))?;
+    while let Some(row) = rows.next()? {
+        f(Row::from_sql(row)?)?;
+    }
+    Ok(())
+}
+
+/// Like `select`, but also passes on up to `before`/`after` rows around each match from the same
+/// socket and hostname, along with each row's position within that (socket, hostname) sequence.
+///
+/// Only the filter's scope applies to the context rows. Window functions number the rows of
+/// each sequence and find the nearest match on either side, so overlapping windows come out
+/// once and everything is a single pass over the scope.
+pub fn select_context(
+    conn: &Connection,
+    filter: &Filter,
+    before: usize,
+    after: usize,
+    newest_first: bool,
+    limit: Option<usize>,
+    mut f: impl FnMut(Row, i64) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut matches = vec![];
+    let mut params: Vec<Box<dyn ToSql>> = vec![];
+    filter.match_to_sql(&mut matches, &mut params);
+    let mut scope = vec![];
+    filter.scope_to_sql(&mut scope, &mut params);
+    params.push(Box::new(after as i64));
+    params.push(Box::new(before as i64));
+    let hit = if matches.is_empty() {
+        "1".to_owned()
+    } else {
+        matches.join(" AND ")
+    };
+    let mut sql = format!(
+        "SELECT {columns}, rn FROM (
+            SELECT *,
+                max(hit) OVER (PARTITION BY socket, hostname ORDER BY id
+                    ROWS UNBOUNDED PRECEDING) AS prev_hit,
+                min(hit) OVER (PARTITION BY socket, hostname ORDER BY id
+                    ROWS BETWEEN CURRENT ROW AND UNBOUNDED FOLLOWING) AS next_hit
+            FROM (
+                SELECT *, CASE WHEN {hit} THEN rn END AS hit FROM (
+                    SELECT *, row_number() OVER (PARTITION BY socket, hostname ORDER BY id) AS rn
+                    FROM log{scope}
+                )
+            )
+        ) AS log
+        WHERE rn - prev_hit <= ? OR next_hit - rn <= ?",
+        columns = Row::COLUMNS,
+        hit = hit,
+        scope = if scope.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", scope.join(" AND "))
+        },
+    );
+    sql += if newest_first {
+        " ORDER BY id DESC"
+    } else {
+        " ORDER BY id"
+    };
+    if let Some(limit) = limit {
+        sql += &format!(" LIMIT {}", limit);
+    }
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(rusqlite::params_from_iter(params.iter()))?;
+    while let Some(row) = rows.next()? {
+        f(Row::from_sql(row)?, row.get(11)?)?;
+    }
+    Ok(())
+}
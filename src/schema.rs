@@ -0,0 +1,438 @@
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDateTime, Offset, TimeZone, Timelike, Utc};
+use rusqlite::Statement;
+use rusqlite_migration::{Migrations, M};
+use std::io::Write;
+
+const MIGRATIONS: &[&str] = &[
+    include_str!("sql/1.sql"),
+    include_str!("sql/2.sql"),
+    include_str!("sql/3.sql"),
+    include_str!("sql/4.sql"),
+    include_str!("sql/5.sql"),
+    include_str!("sql/6.sql"),
+    include_str!("sql/7.sql"),
+    include_str!("sql/8.sql"),
+    include_str!("sql/9.sql"),
+    include_str!("sql/10.sql"),
+];
+
+/// Inserts one message, with named parameters for every column. They're numbered in the
+/// order they appear in, which is the order `Row::insert` binds them in.
+pub const INSERT: &str = "INSERT INTO log
+    (facility, severity, socket, hostname, hostname_source, appname, pid, msgid, time, recv_time,
+        boot, msg, sdata)
+    VALUES (:facility, :severity, :socket, :hostname, :hostname_source, :appname, :pid, :msgid,
+        :time, :recv_time, :boot, :msg, :sdata)";
+
+/// A message as `INSERT` stores it, `None` for NULL.
+pub struct Row<'a> {
+    pub facility: Option<i64>,
+    pub severity: Option<i64>,
+    pub socket: &'a str,
+    pub hostname: Option<&'a str>,
+    pub hostname_source: Option<&'a str>,
+    pub appname: Option<&'a str>,
+    pub pid: Option<i32>,
+    pub msgid: Option<&'a str>,
+    pub time: Option<DateTime<FixedOffset>>,
+    pub recv_time: DateTime<Utc>,
+    pub boot: Option<i64>,
+    pub msg: &'a str,
+    pub sdata: Option<String>,
+}
+
+impl Row<'_> {
+    /// Inserts the row with `stmt`, prepared from `INSERT`, binding by position. That's no
+    /// faster than named parameters on a cached statement as far as `squealog-bench` can
+    /// tell, it's for `Row` to be the one place that knows the columns.
+    pub fn insert(&self, stmt: &mut Statement) -> rusqlite::Result<usize> {
+        stmt.raw_bind_parameter(1, self.facility)?;
+        stmt.raw_bind_parameter(2, self.severity)?;
+        stmt.raw_bind_parameter(3, self.socket)?;
+        stmt.raw_bind_parameter(4, self.hostname)?;
+        stmt.raw_bind_parameter(5, self.hostname_source)?;
+        stmt.raw_bind_parameter(6, self.appname)?;
+        stmt.raw_bind_parameter(7, self.pid)?;
+        stmt.raw_bind_parameter(8, self.msgid)?;
+        let mut buf = [0; TIMESTAMP_LEN];
+        match self.time {
+            Some(time) => match timestamp(&time, &mut buf) {
+                Some(text) => stmt.raw_bind_parameter(9, text)?,
+                None => stmt.raw_bind_parameter(9, time)?,
+            },
+            None => stmt.raw_bind_parameter(9, None::<&str>)?,
+        }
+        match timestamp(&self.recv_time, &mut buf) {
+            Some(text) => stmt.raw_bind_parameter(10, text)?,
+            None => stmt.raw_bind_parameter(10, self.recv_time)?,
+        }
+        stmt.raw_bind_parameter(11, self.boot)?;
+        stmt.raw_bind_parameter(12, self.msg)?;
+        stmt.raw_bind_parameter(13, &self.sdata)?;
+        stmt.raw_execute()
+    }
+}
+
+/// `YYYY-MM-DD HH:MM:SS.nnnnnnnnn+HH:MM`, the longest `timestamp` writes.
+const TIMESTAMP_LEN: usize = 35;
+
+/// `time` as rusqlite's chrono `ToSql` has it (`%F %T%.f%:z`), written into `buf`: chrono's
+/// formatting allocates several times for every timestamp, which is most of what storing a
+/// message allocates. None for what's left to chrono, like years past 9999 or leap seconds.
+fn timestamp<'b, Tz: TimeZone>(
+    time: &DateTime<Tz>,
+    buf: &'b mut [u8; TIMESTAMP_LEN],
+) -> Option<&'b str> {
+    let (local, offset): (NaiveDateTime, i32) =
+        (time.naive_local(), time.offset().fix().local_minus_utc());
+    let nanos = local.nanosecond();
+    if !(0..=9999).contains(&local.year()) || nanos >= 1_000_000_000 || offset % 60 != 0 {
+        return None;
+    }
+    let mut out = &mut buf[..];
+    write!(
+        out,
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        local.year(),
+        local.month(),
+        local.day(),
+        local.hour(),
+        local.minute(),
+        local.second()
+    )
+    .ok()?;
+    // Like `%.f`: as many digits as it takes, in threes.
+    match nanos {
+        0 => Ok(()),
+        n if n % 1_000_000 == 0 => write!(out, ".{:03}", n / 1_000_000),
+        n if n % 1_000 == 0 => write!(out, ".{:06}", n / 1_000),
+        n => write!(out, ".{:09}", n),
+    }
+    .ok()?;
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.abs() / 60;
+    write!(out, "{}{:02}:{:02}", sign, offset / 60, offset % 60).ok()?;
+    let len = TIMESTAMP_LEN - out.len();
+    std::str::from_utf8(&buf[..len]).ok()
+}
+
+pub fn migrations() -> Migrations<'static> {
+    Migrations::new(MIGRATIONS.iter().copied().map(M::up).collect())
+}
+
+/// The `user_version` the database has after all migrations are applied.
+pub fn latest_version() -> usize {
+    MIGRATIONS.len()
+}
+
+pub fn current_version(conn: &rusqlite::Connection) -> rusqlite::Result<usize> {
+    conn.pragma_query_value(None, "user_version", |row| row.get::<_, i64>(0))
+        .map(|v| v as usize)
+}
+
+pub fn has_table(conn: &rusqlite::Connection, name: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?",
+        [name],
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|n| n > 0)
+}
+
+pub fn has_column(
+    conn: &rusqlite::Connection,
+    table: &str,
+    column: &str,
+) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT count(*) FROM pragma_table_info(?) WHERE name = ?",
+        [table, column],
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|n| n > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::types::Value;
+    use rusqlite::Connection;
+
+    /// `log_v1` as other programs see it, which no migration may change.
+    const LOG_V1: &[&str] = &[
+        "id",
+        "time",
+        "severity",
+        "severity_name",
+        "facility",
+        "facility_name",
+        "hostname",
+        "appname",
+        "pid",
+        "msgid",
+        "socket",
+        "msg",
+        "sd",
+    ];
+
+    fn v1_rows(conn: &Connection) -> Vec<Vec<Value>> {
+        conn.prepare(&format!(
+            "SELECT {} FROM log_v1 ORDER BY id",
+            LOG_V1.join(", ")
+        ))
+        .unwrap()
+        .query_map([], |r| (0..LOG_V1.len()).map(|i| r.get(i)).collect())
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap()
+    }
+
+    fn has_view(conn: &Connection, name: &str) -> bool {
+        conn.query_row(
+            "SELECT count(*) FROM sqlite_master WHERE type = 'view' AND name = ?",
+            [name],
+            |r| r.get::<_, i64>(0),
+        )
+        .unwrap()
+            > 0
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_owned())
+    }
+
+    #[test]
+    fn log_v1_stays_the_same_across_migrations() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let migrations = migrations();
+        // A row from the first schema, from before there was a recv_time.
+        migrations.to_version(&mut conn, 1).unwrap();
+        conn.execute(
+            "INSERT INTO log (facility, severity, socket, appname, pid, time, msg, sdata)
+            VALUES (3, 6, 'log', 'cron', 42, '2022-03-04 05:06:07.123+01:00', 'ran', NULL)",
+            [],
+        )
+        .unwrap();
+        let mut first = None;
+        for version in 2..=latest_version() {
+            migrations.to_version(&mut conn, version).unwrap();
+            if !has_view(&conn, "log_v1") {
+                continue;
+            }
+            let rows = v1_rows(&conn);
+            match first {
+                None => first = Some(rows),
+                Some(ref first) => assert_eq!(&rows, first, "migration {} changed log_v1", version),
+            }
+        }
+        let columns: Vec<String> = conn
+            .prepare("SELECT name FROM pragma_table_info('log_v1') ORDER BY cid")
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(columns, LOG_V1);
+
+        // And one stored the way the daemon stores them now.
+        let mut stmt = conn.prepare(INSERT).unwrap();
+        Row {
+            facility: Some(23),
+            severity: Some(0),
+            socket: "udp",
+            hostname: Some("web1"),
+            hostname_source: Some("claimed"),
+            appname: Some("nginx"),
+            pid: None,
+            msgid: Some("ID47"),
+            time: None,
+            recv_time: "2024-01-02T03:04:05Z".parse().unwrap(),
+            boot: None,
+            msg: "down",
+            sdata: Some(r#"{"a@1":{"b":"c"}}"#.to_owned()),
+        }
+        .insert(&mut stmt)
+        .unwrap();
+        assert_eq!(
+            v1_rows(&conn),
+            [
+                vec![
+                    Value::Integer(1),
+                    text("2022-03-04T05:06:07.123+01:00"),
+                    Value::Integer(6),
+                    text("info"),
+                    Value::Integer(3),
+                    text("daemon"),
+                    Value::Null,
+                    text("cron"),
+                    Value::Integer(42),
+                    Value::Null,
+                    text("log"),
+                    text("ran"),
+                    Value::Null,
+                ],
+                vec![
+                    Value::Integer(2),
+                    text("2024-01-02T03:04:05+00:00"),
+                    Value::Integer(0),
+                    text("emerg"),
+                    Value::Integer(23),
+                    text("local7"),
+                    text("web1"),
+                    text("nginx"),
+                    Value::Null,
+                    text("ID47"),
+                    text("udp"),
+                    text("down"),
+                    text(r#"{"a@1":{"b":"c"}}"#),
+                ],
+            ]
+        );
+    }
+
+    /// Every column of every row but the id, in order.
+    fn all_rows(conn: &Connection) -> Vec<Vec<Value>> {
+        let columns = "facility, severity, socket, hostname, hostname_source, appname, pid, \
+            msgid, time, recv_time, boot, msg, sdata";
+        conn.prepare(&format!("SELECT {} FROM log ORDER BY id", columns))
+            .unwrap()
+            .query_map([], |r| (0..13).map(|i| r.get(i)).collect())
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    /// The way rows were inserted before `Row::insert`, by name with a cached statement.
+    fn insert_by_name(conn: &Connection, row: &Row) {
+        conn.prepare_cached(INSERT)
+            .unwrap()
+            .execute(rusqlite::named_params! {
+                ":facility": row.facility,
+                ":severity": row.severity,
+                ":socket": row.socket,
+                ":hostname": row.hostname,
+                ":hostname_source": row.hostname_source,
+                ":appname": row.appname,
+                ":pid": row.pid,
+                ":msgid": row.msgid,
+                ":time": row.time,
+                ":recv_time": row.recv_time,
+                ":boot": row.boot,
+                ":msg": row.msg,
+                ":sdata": row.sdata,
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn timestamps_are_written_like_chrono_writes_them() {
+        use rusqlite::types::{ToSql, ToSqlOutput, ValueRef};
+        let chrono = |time: &dyn ToSql| match time.to_sql().unwrap() {
+            ToSqlOutput::Owned(Value::Text(text)) => text,
+            ToSqlOutput::Borrowed(ValueRef::Text(text)) => {
+                String::from_utf8(text.to_vec()).unwrap()
+            }
+            other => panic!("{:?}", other),
+        };
+        for time in [
+            "2024-01-02T03:04:05+01:00",
+            "2024-01-02T03:04:05.5-05:30",
+            "2024-12-31T23:59:59.123456+14:00",
+            "2024-02-29T00:00:00.000000001-12:00",
+            "0000-01-01T00:00:00.010Z",
+            "9999-12-31T23:59:59.999999999Z",
+            "1970-01-01T00:00:00.000100+00:00",
+        ] {
+            let time: DateTime<FixedOffset> = time.parse().unwrap();
+            let mut buf = [0; TIMESTAMP_LEN];
+            assert_eq!(timestamp(&time, &mut buf), Some(chrono(&time).as_str()));
+            let utc = time.with_timezone(&Utc);
+            assert_eq!(timestamp(&utc, &mut buf), Some(chrono(&utc).as_str()));
+        }
+        let mut buf = [0; TIMESTAMP_LEN];
+        let far = Utc.with_ymd_and_hms(10000, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(timestamp(&far, &mut buf), None);
+        let odd = FixedOffset::east_opt(3600 + 30)
+            .unwrap()
+            .with_ymd_and_hms(2024, 1, 1, 0, 0, 0)
+            .unwrap();
+        assert_eq!(timestamp(&odd, &mut buf), None);
+    }
+
+    #[test]
+    fn insert_stores_the_same_as_by_name() {
+        let full = || Row {
+            facility: Some(4),
+            severity: Some(2),
+            socket: "udp",
+            hostname: Some("web1"),
+            hostname_source: Some("claimed"),
+            appname: Some("sshd"),
+            pid: Some(1234),
+            msgid: Some("ID1"),
+            time: Some("2024-01-02T03:04:05.5+01:00".parse().unwrap()),
+            recv_time: "2024-01-02T02:04:06Z".parse().unwrap(),
+            boot: Some(1),
+            msg: "hello",
+            sdata: Some(r#"{"a@1":{"b":"c"}}"#.to_owned()),
+        };
+        let mut rows = vec![full()];
+        // Each optional column NULL on its own, after one where it wasn't, so that nothing
+        // bound for the row before is left over.
+        type Clear = fn(&mut Row);
+        let clears: &[Clear] = &[
+            |r| r.facility = None,
+            |r| r.severity = None,
+            |r| r.hostname = None,
+            |r| r.hostname_source = None,
+            |r| r.appname = None,
+            |r| r.pid = None,
+            |r| r.msgid = None,
+            |r| r.time = None,
+            |r| r.boot = None,
+            |r| r.sdata = None,
+        ];
+        for clear in clears {
+            let mut row = full();
+            clear(&mut row);
+            rows.push(row);
+            rows.push(full());
+        }
+        let mut none = full();
+        for clear in clears {
+            clear(&mut none);
+        }
+        // And empty text, which isn't NULL.
+        none.hostname = Some("");
+        none.msg = "";
+        rows.push(none);
+        rows.push(full());
+
+        let db = || {
+            let mut conn = Connection::open_in_memory().unwrap();
+            migrations().to_latest(&mut conn).unwrap();
+            conn.execute(
+                "INSERT INTO boot (uuid, boot_time) VALUES ('b', '2024-01-01T00:00:00Z')",
+                [],
+            )
+            .unwrap();
+            conn
+        };
+        let (by_name, by_index) = (db(), db());
+        let mut stmt = by_index.prepare(INSERT).unwrap();
+        for row in &rows {
+            insert_by_name(&by_name, row);
+            assert_eq!(row.insert(&mut stmt).unwrap(), 1);
+        }
+        drop(stmt);
+        let stored = all_rows(&by_index);
+        assert_eq!(stored, all_rows(&by_name));
+        assert_eq!(stored.len(), rows.len());
+        for (i, column) in [0, 1, 3, 4, 5, 6, 7, 8, 10, 12].into_iter().enumerate() {
+            assert_eq!(stored[1 + 2 * i][column], Value::Null, "row {}", 1 + 2 * i);
+            assert_ne!(stored[2 + 2 * i][column], Value::Null, "row {}", 2 + 2 * i);
+        }
+        let none = &stored[stored.len() - 2];
+        assert_eq!((&none[3], &none[11]), (&text(""), &text("")));
+    }
+}
@@ -0,0 +1,44 @@
+//! RFC 5424 structured data is kept in the `sdata` column as a JSON object of objects,
+//! `{"sdid": {"param": "value", ...}, ...}`. A repeated SD-ID or param keeps its last value.
+
+use serde_json::{Map, Value};
+use syslog_loose::StructuredElement;
+
+/// The `sdata` column value for a message, `None` if it has no structured data.
+pub fn to_json(elements: &[StructuredElement<&str>]) -> Option<String> {
+    if elements.is_empty() {
+        return None;
+    }
+    let obj: Map<String, Value> = elements
+        .iter()
+        .map(|e| {
+            let params = e
+                .params
+                .iter()
+                .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+                .collect();
+            (e.id.to_owned(), Value::Object(params))
+        })
+        .collect();
+    Some(Value::Object(obj).to_string())
+}
+
+/// The (sdid, param, value) triples stored in an `sdata` column value. Anything that isn't
+/// in the expected shape is skipped.
+pub fn params(json: &str) -> Vec<(String, String, String)> {
+    let obj = match serde_json::from_str::<Map<String, Value>>(json) {
+        Ok(obj) => obj,
+        Err(_) => return vec![],
+    };
+    let mut out = vec![];
+    for (id, params) in obj {
+        if let Value::Object(params) = params {
+            for (k, v) in params {
+                if let Value::String(v) = v {
+                    out.push((id.clone(), k, v));
+                }
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,85 @@
+//! syslog.conf-style facility/severity selectors, for deciding which messages an output gets.
+//!
+//! `mail.err;auth,authpriv.*;*.warning;local7.none`: `;`-separated `facilities.level` pairs,
+//! where facilities are a comma separated list or `*`, and the level is a severity name (that
+//! severity and worse), `=name` (just that one), `*` or `none`. Later pairs override earlier ones
+//! for the facilities they name.
+
+use crate::names;
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// What a message without a priority counts as, same as syslogd: user.notice.
+const DEFAULT_FACILITY: u8 = 1;
+const DEFAULT_SEVERITY: u8 = 5;
+
+/// For each facility, a bitmask of the severities that match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Selector([u8; 24]);
+
+impl Selector {
+    /// Matches everything.
+    pub const ALL: Selector = Selector([0xff; 24]);
+
+    pub fn matches(&self, facility: Option<u8>, severity: Option<u8>) -> bool {
+        let fac = facility.unwrap_or(DEFAULT_FACILITY) as usize;
+        let sev = severity.unwrap_or(DEFAULT_SEVERITY);
+        sev < 8 && self.0.get(fac).is_some_and(|mask| mask & (1 << sev) != 0)
+    }
+}
+
+impl Default for Selector {
+    fn default() -> Self {
+        Selector::ALL
+    }
+}
+
+impl FromStr for Selector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut masks = [0u8; 24];
+        for pair in s.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            let (facs, level) = pair
+                .rsplit_once('.')
+                .ok_or_else(|| format!("selector '{}' should look like facility.level", pair))?;
+            let mask: u8 = match level {
+                "*" => 0xff,
+                "none" => 0,
+                _ => {
+                    let (exact, name) = match level.strip_prefix('=') {
+                        Some(name) => (true, name),
+                        None => (false, level),
+                    };
+                    let sev = names::parse_severity(name)
+                        .ok_or_else(|| format!("invalid severity '{}'", name))?;
+                    if exact {
+                        1 << sev
+                    } else {
+                        // The severity and everything more severe, i.e. numerically lower.
+                        (((1u16 << (sev + 1)) - 1) & 0xff) as u8
+                    }
+                }
+            };
+            for fac in facs.split(',').map(str::trim) {
+                if fac == "*" {
+                    masks = [mask; 24];
+                    continue;
+                }
+                let fac = names::parse_facility(fac)
+                    .ok_or_else(|| format!("invalid facility '{}'", fac))?;
+                masks[fac as usize] = mask;
+            }
+        }
+        Ok(Selector(masks))
+    }
+}
+
+impl TryFrom<String> for Selector {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
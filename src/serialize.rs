@@ -0,0 +1,430 @@
+//! Turning messages and stored rows back into syslog text, for relays, files and the like.
+//!
+//! Everything goes through a `Record`, which borrows where it can. RFC 5424 output follows
+//! the header field rules: a missing or empty field is `-`, characters that aren't allowed in a
+//! header field (anything outside printable ASCII, including spaces) become `_`, and fields are
+//! cut to their maximum lengths. With a length limit, the MSG is cut at a character boundary
+//! first; if the header and structured data alone are still too long, the structured data is
+//! left out, and as a last resort the output is cut at the limit.
+
+use crate::sdata;
+use chrono::prelude::*;
+use std::borrow::Cow;
+use std::fmt::Write;
+use syslog_loose::{Message, ProcId};
+
+/// What a message without a priority counts as, same as syslogd: user.notice.
+pub const DEFAULT_FACILITY: u8 = 1;
+pub const DEFAULT_SEVERITY: u8 = 5;
+
+const MAX_HOSTNAME: usize = 255;
+const MAX_APPNAME: usize = 48;
+const MAX_PROCID: usize = 128;
+const MAX_MSGID: usize = 32;
+const MAX_SD_NAME: usize = 32;
+
+/// One structured data element, `[id name="value" ...]`, with the values unescaped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Element<'a> {
+    pub id: Cow<'a, str>,
+    pub params: Vec<(Cow<'a, str>, Cow<'a, str>)>,
+}
+
+/// A message as far as serializing it goes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub facility: Option<u8>,
+    pub severity: Option<u8>,
+    pub time: DateTime<FixedOffset>,
+    pub hostname: Option<Cow<'a, str>>,
+    pub appname: Option<Cow<'a, str>>,
+    pub procid: Option<Cow<'a, str>>,
+    pub msgid: Option<Cow<'a, str>>,
+    pub sdata: Vec<Element<'a>>,
+    pub msg: Cow<'a, str>,
+}
+
+impl<'a> Record<'a> {
+    /// A parsed message, with `time` standing in for a missing timestamp.
+    pub fn from_message(msg: &'a Message<&'a str>, time: DateTime<FixedOffset>) -> Record<'a> {
+        Record {
+            facility: msg.facility.map(|f| f as u8),
+            severity: msg.severity.map(|s| s as u8),
+            time: msg.timestamp.unwrap_or(time),
+            hostname: msg.hostname.map(Cow::Borrowed),
+            appname: msg.appname.map(Cow::Borrowed),
+            procid: match msg.procid {
+                Some(ProcId::PID(pid)) => Some(Cow::Owned(pid.to_string())),
+                Some(ProcId::Name(name)) => Some(Cow::Borrowed(name)),
+                None => None,
+            },
+            msgid: msg.msgid.map(Cow::Borrowed),
+            sdata: msg
+                .structured_data
+                .iter()
+                .map(|e| Element {
+                    id: Cow::Borrowed(e.id),
+                    params: e
+                        .params
+                        .iter()
+                        .map(|&(k, v)| (Cow::Borrowed(k), unescape(v)))
+                        .collect(),
+                })
+                .collect(),
+            msg: Cow::Borrowed(msg.msg),
+        }
+    }
+
+    /// Structured data from an `sdata` column value.
+    pub fn sdata_from_json(json: &str) -> Vec<Element<'static>> {
+        let mut elements: Vec<Element> = vec![];
+        // The triples come grouped by SD-ID.
+        for (id, k, v) in sdata::params(json) {
+            let v = match unescape(&v) {
+                Cow::Owned(unescaped) => unescaped,
+                Cow::Borrowed(_) => v,
+            };
+            match elements.last_mut() {
+                Some(e) if e.id == id => e.params.push((k.into(), v.into())),
+                _ => elements.push(Element {
+                    id: id.into(),
+                    params: vec![(k.into(), v.into())],
+                }),
+            }
+        }
+        elements
+    }
+
+    pub fn pri(&self) -> u8 {
+        pri(self.facility, self.severity)
+    }
+}
+
+/// The PRI part's value, with user.notice filling in for missing parts.
+pub fn pri(facility: Option<u8>, severity: Option<u8>) -> u8 {
+    (facility.unwrap_or(DEFAULT_FACILITY) & 0x1f) << 3 | (severity.unwrap_or(DEFAULT_SEVERITY) & 7)
+}
+
+/// A header field: `-` when missing, no spaces or non-printable characters, at most `max`.
+fn push_field(buf: &mut String, value: Option<&str>, max: usize) {
+    let value = value.unwrap_or("");
+    if value.is_empty() || value == "-" {
+        buf.push('-');
+        return;
+    }
+    buf.extend(
+        value
+            .chars()
+            .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+            .take(max),
+    );
+}
+
+/// An SD-ID or param name, which additionally can't have `=`, `]` or `"` in it.
+fn push_sd_name(buf: &mut String, name: &str) {
+    buf.extend(
+        name.chars()
+            .map(|c| match c {
+                '=' | ']' | '"' => '_',
+                c if c.is_ascii_graphic() => c,
+                _ => '_',
+            })
+            .take(MAX_SD_NAME),
+    );
+    if name.is_empty() {
+        buf.push('_');
+    }
+}
+
+/// A param value without its `\"`, `\\` and `\]` escapes, which syslog_loose leaves in (and
+/// so the `sdata` column has them too). A backslash before anything else is just a backslash.
+fn unescape(value: &str) -> Cow<'_, str> {
+    if !value.contains('\\') {
+        return Cow::Borrowed(value);
+    }
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(&next) = chars.peek().filter(|&&n| matches!(n, '"' | '\\' | ']')) {
+                out.push(next);
+                chars.next();
+                continue;
+            }
+        }
+        out.push(c);
+    }
+    Cow::Owned(out)
+}
+
+/// Writes one `[id param="value" ...]` structured data element into `buf`.
+fn write_element(buf: &mut String, element: &Element) {
+    buf.push('[');
+    push_sd_name(buf, &element.id);
+    for (k, v) in &element.params {
+        buf.push(' ');
+        push_sd_name(buf, k);
+        buf.push_str("=\"");
+        for c in v.chars() {
+            if matches!(c, '"' | '\\' | ']') {
+                buf.push('\\');
+            }
+            buf.push(c);
+        }
+        buf.push('"');
+    }
+    buf.push(']');
+}
+
+/// Cuts `buf` down to at most `len` bytes, at a character boundary.
+fn cut(buf: &mut String, mut len: usize) {
+    if buf.len() <= len {
+        return;
+    }
+    while !buf.is_char_boundary(len) {
+        len -= 1;
+    }
+    buf.truncate(len);
+}
+
+/// Formats `rec` as RFC 5424 into `buf`, at most `limit` bytes long if there is one. Returns
+/// whether anything had to be cut.
+pub fn rfc5424(buf: &mut String, rec: &Record, limit: Option<usize>) -> bool {
+    buf.clear();
+    let _ = write!(
+        buf,
+        "<{}>1 {} ",
+        rec.pri(),
+        rec.time.to_rfc3339_opts(SecondsFormat::Micros, true)
+    );
+    push_field(buf, rec.hostname.as_deref(), MAX_HOSTNAME);
+    buf.push(' ');
+    push_field(buf, rec.appname.as_deref(), MAX_APPNAME);
+    buf.push(' ');
+    push_field(buf, rec.procid.as_deref(), MAX_PROCID);
+    buf.push(' ');
+    push_field(buf, rec.msgid.as_deref(), MAX_MSGID);
+    buf.push(' ');
+    let header = buf.len();
+    if rec.sdata.is_empty() {
+        buf.push('-');
+    }
+    for element in &rec.sdata {
+        write_element(buf, element);
+    }
+    let limit = match limit {
+        Some(limit) => limit,
+        None => {
+            if !rec.msg.is_empty() {
+                buf.push(' ');
+                buf.push_str(&rec.msg);
+            }
+            return false;
+        }
+    };
+    if buf.len() > limit {
+        buf.truncate(header);
+        buf.push('-');
+        cut(buf, limit);
+        return true;
+    }
+    if !rec.msg.is_empty() {
+        buf.push(' ');
+        buf.push_str(&rec.msg);
+    }
+    let truncated = buf.len() > limit;
+    cut(buf, limit);
+    truncated
+}
+
+/// Formats `rec` the traditional BSD way, `Jan  2 15:04:05 host app[pid]: msg`, in the
+/// display zone (the format has no zone of its own) with the day padded by a space, and `<PRI>` in front if
+/// `with_pri`. The same length limit rules as for `rfc5424` apply, minus structured data,
+/// which this format doesn't have.
+pub fn rfc3164(buf: &mut String, rec: &Record, with_pri: bool, limit: Option<usize>) -> bool {
+    buf.clear();
+    if with_pri {
+        let _ = write!(buf, "<{}>", rec.pri());
+    }
+    let _ = write!(
+        buf,
+        "{} ",
+        crate::time::display(&rec.time).format("%b %e %H:%M:%S")
+    );
+    push_field(buf, rec.hostname.as_deref(), MAX_HOSTNAME);
+    if let Some(app) = rec.appname.as_deref().filter(|a| !a.is_empty()) {
+        buf.push(' ');
+        push_field(buf, Some(app), MAX_APPNAME);
+        if let Some(procid) = rec.procid.as_deref().filter(|p| !p.is_empty()) {
+            buf.push('[');
+            push_field(buf, Some(procid), MAX_PROCID);
+            buf.push(']');
+        }
+        buf.push(':');
+    }
+    buf.push(' ');
+    buf.push_str(&rec.msg);
+    match limit {
+        Some(limit) if buf.len() > limit => {
+            cut(buf, limit);
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload::parse_syslog;
+
+    // No IPv6 addresses: syslog_loose can't read a hostname with colons in it.
+    const HOSTS: &[&str] = &["web1", "10.0.0.7", "host.example.com"];
+    const APPS: &[&str] = &["sshd", "CRON", "kernel", "systemd-logind", "app.name"];
+    const PROCIDS: &[&str] = &["1", "4242", "worker"];
+    const MSGIDS: &[&str] = &["ID47", "auth", "x"];
+    const WORDS: &[&str] = &[
+        "Failed", "password", "for", "root", "from", "[1]", "a=b", "é", "日本", ":", "\"q\"", "\\",
+    ];
+    // No empty values: syslog_loose drops an element with an empty param value.
+    const VALUES: &[&str] = &["v", "with space", "quote\"d", "back\\slash", "close]", "ü"];
+
+    /// A xorshift, so that every run tries the same messages.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+
+        fn pick(&mut self, of: &[&'static str]) -> Cow<'static, str> {
+            Cow::Borrowed(of[self.below(of.len() as u64) as usize])
+        }
+
+        /// One of `of`, or now and then nothing.
+        fn maybe(&mut self, of: &[&'static str]) -> Option<Cow<'static, str>> {
+            (self.below(4) > 0).then(|| self.pick(of))
+        }
+    }
+
+    fn records(n: usize) -> Vec<Record<'static>> {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        (0..n)
+            .map(|_| {
+                // Days in January and July, well away from any DST change.
+                let day = rng.below(28) + [0, 181][rng.below(2) as usize];
+                let offset = [0, 3600, -8 * 3600, 5 * 3600 + 1800][rng.below(4) as usize];
+                let time = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+                    + chrono::Duration::days(day as i64)
+                    + chrono::Duration::seconds(rng.below(86400) as i64)
+                    + chrono::Duration::microseconds(rng.below(1_000_000) as i64);
+                let msg = (0..rng.below(8))
+                    .map(|_| rng.pick(WORDS))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let sdata = (0..rng.below(3))
+                    .map(|i| Element {
+                        id: Cow::Owned(format!("e{}@32473", i)),
+                        params: (0..rng.below(3))
+                            .map(|k| (Cow::Owned(format!("k{}", k)), rng.pick(VALUES)))
+                            .collect(),
+                    })
+                    .collect();
+                Record {
+                    facility: rng.below(25).checked_sub(1).map(|f| f as u8),
+                    severity: rng.below(9).checked_sub(1).map(|s| s as u8),
+                    time: time.with_timezone(&FixedOffset::east_opt(offset).unwrap()),
+                    hostname: rng.maybe(HOSTS),
+                    appname: rng.maybe(APPS),
+                    procid: rng.maybe(PROCIDS),
+                    msgid: rng.maybe(MSGIDS),
+                    sdata,
+                    msg: Cow::Owned(msg),
+                }
+            })
+            .collect()
+    }
+
+    /// What's left of `rec` after a trip through the wire: the defaults filled in.
+    fn sent(rec: &Record<'static>) -> Record<'static> {
+        Record {
+            facility: Some(rec.facility.unwrap_or(DEFAULT_FACILITY)),
+            severity: Some(rec.severity.unwrap_or(DEFAULT_SEVERITY)),
+            ..rec.clone()
+        }
+    }
+
+    #[test]
+    fn rfc5424_round_trips() {
+        let mut buf = String::new();
+        for rec in records(500) {
+            assert!(!rfc5424(&mut buf, &rec, None));
+            let parsed = parse_syslog(&buf, 2024);
+            let back = Record::from_message(&parsed, Utc::now().into());
+            assert_eq!(back, sent(&rec), "{:?}", buf);
+            assert_eq!(back.time.offset(), rec.time.offset(), "{:?}", buf);
+            // And the same once stored.
+            let stored = crate::sdata::to_json(&parsed.structured_data).unwrap_or_default();
+            let with_params: Vec<_> = rec
+                .sdata
+                .into_iter()
+                .filter(|e| !e.params.is_empty())
+                .collect();
+            assert_eq!(Record::sdata_from_json(&stored), with_params, "{:?}", buf);
+        }
+    }
+
+    #[test]
+    fn rfc3164_round_trips_what_it_has_room_for() {
+        let mut buf = String::new();
+        // Without a hostname the appname would be read as one, and without an appname the
+        // message's first word. syslog_loose takes a `[` right after the tag for the start of
+        // structured data.
+        let records = records(500)
+            .into_iter()
+            .filter(|r| r.hostname.is_some() && r.appname.is_some() && !r.msg.starts_with('['));
+        for rec in records {
+            assert!(!rfc3164(&mut buf, &rec, true, None));
+            let parsed = parse_syslog(&buf, 2024);
+            let back = Record::from_message(&parsed, Utc::now().into());
+            let procid = rec.appname.as_ref().and(rec.procid.clone());
+            let expected = Record {
+                // The format has no zone, and no fractions of a second.
+                time: rec
+                    .time
+                    .with_timezone(&Local)
+                    .with_nanosecond(0)
+                    .unwrap()
+                    .fixed_offset(),
+                procid,
+                msgid: None,
+                sdata: vec![],
+                ..sent(&rec)
+            };
+            assert_eq!(back, expected, "{:?}", buf);
+        }
+    }
+
+    #[test]
+    fn cut_output_still_parses() {
+        let mut buf = String::new();
+        for rec in records(200) {
+            // All long enough for the header, which is only cut as a last resort.
+            for limit in [120, 200, 480] {
+                let cut = rfc5424(&mut buf, &rec, Some(limit));
+                assert!(buf.len() <= limit);
+                let parsed = parse_syslog(&buf, 2024);
+                let back = Record::from_message(&parsed, Utc::now().into());
+                assert_eq!(
+                    (back.facility, back.severity),
+                    (Some(rec.pri() >> 3), Some(rec.pri() & 7))
+                );
+                assert!(rec.msg.starts_with(&*back.msg), "{:?}", buf);
+                assert_eq!(cut, back != sent(&rec), "{:?}", buf);
+            }
+        }
+    }
+}
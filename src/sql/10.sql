@@ -0,0 +1,11 @@
+-- What squealogd's sampler left out during storms, by socket and appname ('' for none), so
+-- `squealog stats` can show that rows are missing and why.
+CREATE TABLE sample_stats (
+	socket TEXT NOT NULL,
+	appname TEXT NOT NULL,
+	runs INTEGER NOT NULL,
+	seen INTEGER NOT NULL,
+	kept INTEGER NOT NULL,
+	last_time TEXT,
+	PRIMARY KEY (socket, appname)
+) STRICT;
@@ -0,0 +1,43 @@
+ALTER TABLE log ADD COLUMN recv_time TEXT;
+
+CREATE INDEX log_recv_time ON log (recv_time);
+
+-- Hourly message counts, so that stats/reports don't have to scan the log table.
+-- The hour is taken from the receive time, falling back to the message time for rows from before recv_time existed.
+CREATE TABLE log_summary (
+	hour TEXT,
+	socket TEXT NOT NULL,
+	appname TEXT,
+	severity INTEGER,
+	count INTEGER NOT NULL
+) STRICT;
+
+CREATE INDEX log_summary_key ON log_summary (hour, socket, appname, severity);
+
+INSERT INTO log_summary (hour, socket, appname, severity, count)
+SELECT strftime('%Y-%m-%d %H:00:00', time), socket, appname, severity, count(*)
+FROM log GROUP BY 1, 2, 3, 4;
+
+CREATE TRIGGER summary_insert AFTER INSERT ON log
+BEGIN
+	INSERT INTO log_summary (hour, socket, appname, severity, count)
+	SELECT strftime('%Y-%m-%d %H:00:00', coalesce(NEW.recv_time, NEW.time)), NEW.socket, NEW.appname, NEW.severity, 0
+	WHERE NOT EXISTS (
+		SELECT 1 FROM log_summary
+		WHERE hour IS strftime('%Y-%m-%d %H:00:00', coalesce(NEW.recv_time, NEW.time))
+		AND socket = NEW.socket AND appname IS NEW.appname AND severity IS NEW.severity
+	);
+	UPDATE log_summary SET count = count + 1
+	WHERE hour IS strftime('%Y-%m-%d %H:00:00', coalesce(NEW.recv_time, NEW.time))
+	AND socket = NEW.socket AND appname IS NEW.appname AND severity IS NEW.severity;
+END;
+
+CREATE TRIGGER summary_delete AFTER DELETE ON log
+BEGIN
+	UPDATE log_summary SET count = count - 1
+	WHERE hour IS strftime('%Y-%m-%d %H:00:00', coalesce(OLD.recv_time, OLD.time))
+	AND socket = OLD.socket AND appname IS OLD.appname AND severity IS OLD.severity;
+	DELETE FROM log_summary
+	WHERE count <= 0 AND hour IS strftime('%Y-%m-%d %H:00:00', coalesce(OLD.recv_time, OLD.time))
+	AND socket = OLD.socket AND appname IS OLD.appname AND severity IS OLD.severity;
+END;
@@ -0,0 +1,9 @@
+CREATE TABLE boot (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	uuid TEXT NOT NULL UNIQUE,
+	boot_time TEXT NOT NULL
+) STRICT;
+
+ALTER TABLE log ADD COLUMN boot INTEGER REFERENCES boot (id);
+
+CREATE INDEX log_boot ON log (boot);
@@ -0,0 +1,10 @@
+ALTER TABLE log ADD COLUMN hostname TEXT;
+ALTER TABLE log ADD COLUMN msgid TEXT;
+
+-- Files brought in by `squealog import`, to avoid importing the same content twice.
+CREATE TABLE import (
+	checksum TEXT PRIMARY KEY,
+	path TEXT NOT NULL,
+	time TEXT NOT NULL,
+	rows INTEGER NOT NULL
+) STRICT;
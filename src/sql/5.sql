@@ -0,0 +1,8 @@
+-- Used by `squealog merge` to recognize rows that already arrived over the network.
+CREATE INDEX log_time ON log (time);
+
+-- How far `squealog merge` got with each source database.
+CREATE TABLE merge_source (
+	source TEXT PRIMARY KEY,
+	last_id INTEGER NOT NULL
+) STRICT;
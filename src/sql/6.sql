@@ -0,0 +1,43 @@
+-- Also count per hostname, for `squealog report`. Rebuilt from scratch, like in 2.sql.
+DROP TRIGGER summary_insert;
+DROP TRIGGER summary_delete;
+DROP TABLE log_summary;
+
+CREATE TABLE log_summary (
+	hour TEXT,
+	socket TEXT NOT NULL,
+	hostname TEXT,
+	appname TEXT,
+	severity INTEGER,
+	count INTEGER NOT NULL
+) STRICT;
+
+CREATE INDEX log_summary_key ON log_summary (hour, socket, hostname, appname, severity);
+
+INSERT INTO log_summary (hour, socket, hostname, appname, severity, count)
+SELECT strftime('%Y-%m-%d %H:00:00', coalesce(recv_time, time)), socket, hostname, appname, severity, count(*)
+FROM log GROUP BY 1, 2, 3, 4, 5;
+
+CREATE TRIGGER summary_insert AFTER INSERT ON log
+BEGIN
+	INSERT INTO log_summary (hour, socket, hostname, appname, severity, count)
+	SELECT strftime('%Y-%m-%d %H:00:00', coalesce(NEW.recv_time, NEW.time)), NEW.socket, NEW.hostname, NEW.appname, NEW.severity, 0
+	WHERE NOT EXISTS (
+		SELECT 1 FROM log_summary
+		WHERE hour IS strftime('%Y-%m-%d %H:00:00', coalesce(NEW.recv_time, NEW.time))
+		AND socket = NEW.socket AND hostname IS NEW.hostname AND appname IS NEW.appname AND severity IS NEW.severity
+	);
+	UPDATE log_summary SET count = count + 1
+	WHERE hour IS strftime('%Y-%m-%d %H:00:00', coalesce(NEW.recv_time, NEW.time))
+	AND socket = NEW.socket AND hostname IS NEW.hostname AND appname IS NEW.appname AND severity IS NEW.severity;
+END;
+
+CREATE TRIGGER summary_delete AFTER DELETE ON log
+BEGIN
+	UPDATE log_summary SET count = count - 1
+	WHERE hour IS strftime('%Y-%m-%d %H:00:00', coalesce(OLD.recv_time, OLD.time))
+	AND socket = OLD.socket AND hostname IS OLD.hostname AND appname IS OLD.appname AND severity IS OLD.severity;
+	DELETE FROM log_summary
+	WHERE count <= 0 AND hour IS strftime('%Y-%m-%d %H:00:00', coalesce(OLD.recv_time, OLD.time))
+	AND socket = OLD.socket AND hostname IS OLD.hostname AND appname IS OLD.appname AND severity IS OLD.severity;
+END;
@@ -0,0 +1,7 @@
+-- How many messages each of the daemon's filter rules dropped, so `squealog stats` can show
+-- whether a rule still does anything.
+CREATE TABLE filter_stats (
+	rule TEXT PRIMARY KEY,
+	dropped INTEGER NOT NULL,
+	last_time TEXT
+) STRICT;
@@ -0,0 +1,6 @@
+-- Where a message's hostname came from: 'claimed' by the sender, 'local' when squealogd filled
+-- in its own for a message from a local source (a unix socket, klog, itself) that had none,
+-- 'merge' when `squealog merge --host` did. NULL when there is no hostname, and for rows from
+-- before this column.
+ALTER TABLE log ADD COLUMN hostname_source TEXT;
+UPDATE log SET hostname_source = 'claimed' WHERE hostname IS NOT NULL;
@@ -0,0 +1,31 @@
+-- What other programs should read instead of the log table, which keeps changing. Later
+-- migrations may change how log stores things, but have to recreate log_v1 so that it returns
+-- the same rows and values as this does; new columns go into a log_v2.
+-- time is the message's time (the receive time when it had none) in RFC 3339, the names are
+-- NULL for numbers without one, sd is the sdata JSON object (see sdata.rs).
+CREATE VIEW log_v1 AS
+SELECT
+	id,
+	replace(substr(coalesce(time, recv_time), 1, 19), ' ', 'T') || substr(coalesce(time, recv_time), 20) AS time,
+	severity,
+	CASE severity
+		WHEN 0 THEN 'emerg' WHEN 1 THEN 'alert' WHEN 2 THEN 'crit' WHEN 3 THEN 'err'
+		WHEN 4 THEN 'warning' WHEN 5 THEN 'notice' WHEN 6 THEN 'info' WHEN 7 THEN 'debug'
+	END AS severity_name,
+	facility,
+	CASE facility
+		WHEN 0 THEN 'kern' WHEN 1 THEN 'user' WHEN 2 THEN 'mail' WHEN 3 THEN 'daemon'
+		WHEN 4 THEN 'auth' WHEN 5 THEN 'syslog' WHEN 6 THEN 'lpr' WHEN 7 THEN 'news'
+		WHEN 8 THEN 'uucp' WHEN 9 THEN 'cron' WHEN 10 THEN 'authpriv' WHEN 11 THEN 'ftp'
+		WHEN 12 THEN 'ntp' WHEN 13 THEN 'security' WHEN 14 THEN 'console' WHEN 15 THEN 'cron2'
+		WHEN 16 THEN 'local0' WHEN 17 THEN 'local1' WHEN 18 THEN 'local2' WHEN 19 THEN 'local3'
+		WHEN 20 THEN 'local4' WHEN 21 THEN 'local5' WHEN 22 THEN 'local6' WHEN 23 THEN 'local7'
+	END AS facility_name,
+	hostname,
+	appname,
+	pid,
+	msgid,
+	socket,
+	msg,
+	sdata AS sd
+FROM log;
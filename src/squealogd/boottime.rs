@@ -0,0 +1,156 @@
+//! The wall clock time the kernel booted at, which klog's `[seconds since boot]` timestamps are
+//! added to. It's measured again for every read from klog instead of once at startup: when the
+//! wall clock steps (ntpdate at boot, `date`, resuming from suspend), the seconds since boot
+//! keep counting as before and a fixed anchor would leave kernel messages off by the step.
+
+use chrono::prelude::*;
+
+/// Changes smaller than this are slewing and rounding, not worth a correction (or a message).
+const THRESHOLD_SECS: i64 = 2;
+
+// time_t and long aren't i64 everywhere.
+#[allow(clippy::unnecessary_cast)]
+fn clock(id: libc::clockid_t) -> chrono::Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(id, &mut ts) };
+    chrono::Duration::seconds(ts.tv_sec as i64) + chrono::Duration::nanoseconds(ts.tv_nsec as i64)
+}
+
+/// The boot time that readings of the wall clock and of the time since boot make.
+fn boottime(realtime: chrono::Duration, monotonic: chrono::Duration) -> DateTime<Utc> {
+    DateTime::<Utc>::UNIX_EPOCH + realtime - monotonic
+}
+
+/// The current wall clock time minus the time since boot, from the clocks alone (which works
+/// in capability mode, where `kern.boottime` can't be read).
+pub fn measure() -> DateTime<Utc> {
+    boottime(clock(libc::CLOCK_REALTIME), clock(libc::CLOCK_MONOTONIC))
+}
+
+/// Where an anchor at `anchor` goes for a new measurement, if anywhere, and how far the clock
+/// stepped for that.
+fn reanchor(
+    anchor: DateTime<Utc>,
+    measured: DateTime<Utc>,
+) -> Option<(DateTime<Utc>, chrono::Duration)> {
+    let step = measured - anchor;
+    if step.num_seconds().abs() < THRESHOLD_SECS {
+        return None;
+    }
+    Some((measured, step))
+}
+
+pub struct Anchor {
+    boottime: DateTime<Utc>,
+}
+
+impl Anchor {
+    pub fn new(boottime: DateTime<Utc>) -> Anchor {
+        Anchor { boottime }
+    }
+
+    pub fn get(&self) -> DateTime<Utc> {
+        self.boottime
+    }
+
+    /// Takes a new measurement, returning how far the clock stepped if it moved the anchor.
+    pub fn update(&mut self, measured: DateTime<Utc>) -> Option<chrono::Duration> {
+        let (boottime, step) = reanchor(self.boottime, measured)?;
+        self.boottime = boottime;
+        Some(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    /// Follows the clocks through `readings` of (wall clock, time since boot) in seconds,
+    /// giving the time a klog message from each moment would get, and each step.
+    fn follow(readings: &[(i64, i64)]) -> Vec<(DateTime<Utc>, Option<i64>)> {
+        let at =
+            |(real, mono): (i64, i64)| boottime(Duration::seconds(real), Duration::seconds(mono));
+        let mut anchor = Anchor::new(at(readings[0]));
+        readings
+            .iter()
+            .map(|&(real, mono)| {
+                let step = anchor.update(at((real, mono)));
+                (
+                    anchor.get() + Duration::seconds(mono),
+                    step.map(|s| s.num_seconds()),
+                )
+            })
+            .collect()
+    }
+
+    fn wall(secs: i64) -> DateTime<Utc> {
+        DateTime::<Utc>::from_timestamp(secs, 0).unwrap()
+    }
+
+    #[test]
+    fn follows_a_clock_step() {
+        let boot = 1_700_000_000;
+        let followed = follow(&[
+            (boot + 10, 10),
+            // Slewing and rounding.
+            (boot + 101, 100),
+            // date(1) an hour ahead, then back 30 minutes.
+            (boot + 3600 + 200, 200),
+            (boot + 3600 + 201, 201),
+            (boot + 1800 + 300, 300),
+        ]);
+        assert_eq!(
+            followed,
+            [
+                (wall(boot + 10), None),
+                (wall(boot + 100), None),
+                (wall(boot + 3600 + 200), Some(3600)),
+                (wall(boot + 3600 + 201), None),
+                (wall(boot + 1800 + 300), Some(-1800)),
+            ]
+        );
+    }
+
+    #[test]
+    fn follows_a_suspend_and_resume() {
+        // The time since boot doesn't count the 8 hours suspended, the wall clock does.
+        let boot = 1_700_000_000;
+        let suspended = 8 * 3600;
+        let followed = follow(&[
+            (boot + 60, 60),
+            (boot + 120, 120),
+            (boot + suspended + 121, 121),
+            (boot + suspended + 180, 180),
+        ]);
+        assert_eq!(
+            followed,
+            [
+                (wall(boot + 60), None),
+                (wall(boot + 120), None),
+                (wall(boot + suspended + 121), Some(suspended)),
+                (wall(boot + suspended + 180), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn stays_put_within_the_threshold() {
+        let anchor = wall(1_700_000_000);
+        assert_eq!(
+            reanchor(anchor, anchor + Duration::milliseconds(1999)),
+            None
+        );
+        assert_eq!(
+            reanchor(anchor, anchor - Duration::milliseconds(1999)),
+            None
+        );
+        assert_eq!(
+            reanchor(anchor, anchor + Duration::seconds(2)),
+            Some((anchor + Duration::seconds(2), Duration::seconds(2)))
+        );
+    }
+}
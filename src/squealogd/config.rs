@@ -0,0 +1,413 @@
+//! squealogd's part of the config file, see `crate::config`.
+//!
+//! ```toml
+//! [[relay.udp]]
+//! to = "collector.example.com:514"
+//! select = "*.info;local7.none"
+//!
+//! [[relay.tcp]]
+//! to = "collector.example.com:6514"
+//! tls = true
+//! ```
+
+use crate::selector::Selector;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub listen: Vec<Listen>,
+    pub privileges: Privileges,
+    pub filter: Vec<Filter>,
+    pub rewrite: Vec<Rewrite>,
+    pub enrich: Enrich,
+    pub relay: Relays,
+    pub file: Vec<FileRule>,
+    pub exec: Vec<ExecHook>,
+    pub journald: Option<Journald>,
+    pub pubsub: Option<PubSub>,
+    pub webhook: Vec<Webhook>,
+    pub wall: Wall,
+    pub console: Option<Console>,
+    pub kafka: Option<Kafka>,
+    pub mail: Vec<Mail>,
+    pub mark: Option<Mark>,
+    pub control: Option<Control>,
+    pub memory: Option<Memory>,
+    pub watchdog: Watchdog,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Listen {
+    /// What messages from it are stored with as their socket.
+    pub name: String,
+    /// Path of a unix datagram socket.
+    pub unix: Option<PathBuf>,
+    /// Permissions of the unix socket, `0o666` by default.
+    pub mode: Option<u32>,
+    /// `address:port` of a UDP socket.
+    pub udp: Option<String>,
+    /// Like `16K`, the biggest datagram read from it (longer ones are cut off). By default
+    /// 64K for UDP and 8K for unix sockets. Applies to activated sockets of the same name too.
+    pub buffer: Option<String>,
+    /// What parses its datagrams, like `gelf`: `syslog` by default. Applies to activated
+    /// sockets of the same name too.
+    pub parser: Option<String>,
+    /// Drop datagrams the parser can't make anything of, instead of storing them as text.
+    #[serde(default)]
+    pub strict: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Relays {
+    pub udp: Vec<UdpRelay>,
+    pub tcp: Vec<TcpRelay>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UdpRelay {
+    /// `host:port` of the collector.
+    pub to: String,
+    /// Which messages to send, syslog.conf style; everything by default.
+    #[serde(default)]
+    pub select: Selector,
+    /// Send the datagrams as they came in instead of as RFC 5424.
+    #[serde(default)]
+    pub verbatim: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TcpRelay {
+    /// `host:port` of the collector.
+    pub to: String,
+    /// Needs the `tls` feature.
+    #[serde(default)]
+    pub tls: bool,
+    #[serde(default)]
+    pub select: Selector,
+    /// Names the position file, defaults to `to`.
+    pub name: Option<String>,
+    /// How many rows the relay can fall behind before the oldest are skipped.
+    pub max_backlog: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileRule {
+    pub select: Selector,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExecHook {
+    /// argv, the program first.
+    pub command: Vec<String>,
+    /// Added to the daemon's environment.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub select: Selector,
+    pub appname: Option<String>,
+    pub regex: Option<String>,
+    /// How many lines to queue up while the program is busy.
+    pub buffer: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Journald {
+    #[serde(default)]
+    pub select: Selector,
+    /// Sockets whose messages came from journald in the first place.
+    #[serde(default = "journal_sockets")]
+    pub skip_sockets: Vec<String>,
+    /// Defaults to `/run/systemd/journal/socket`.
+    pub socket: Option<PathBuf>,
+}
+
+fn journal_sockets() -> Vec<String> {
+    vec!["journal".to_owned()]
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PubSub {
+    /// Defaults to `/var/run/squealogd.events`.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Control {
+    /// Defaults to `/var/run/squealogd.ctl`.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Memory {
+    /// Like `64M`, the default.
+    pub max: Option<String>,
+    /// Messages kept to store again while the database can't take them, 10000 by default.
+    pub spill: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "webhook"), allow(dead_code))]
+pub struct Webhook {
+    pub url: String,
+    /// A file with a bearer token in it.
+    pub token_file: Option<PathBuf>,
+    #[serde(default)]
+    pub select: Selector,
+    pub appname: Option<String>,
+    pub regex: Option<String>,
+    /// JSON template for the request body.
+    pub body: Option<String>,
+    /// Like `5m`, how long to hold back further alerts after one was sent.
+    pub window: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Wall {
+    pub enabled: bool,
+    /// Also for alert, not just emerg.
+    pub alert: bool,
+    pub max_per_minute: usize,
+}
+
+impl Default for Wall {
+    fn default() -> Self {
+        Wall {
+            enabled: true,
+            alert: false,
+            max_per_minute: 5,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Watchdog {
+    pub enabled: bool,
+    /// Like `5m`, how long messages may wait for the database before it counts as stalled.
+    pub stall: String,
+    /// Also write the alarm to `/dev/console`.
+    pub console: bool,
+    /// Abort once stalled, for a supervisor to restart the daemon.
+    pub abort: bool,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Watchdog {
+            enabled: true,
+            stall: "5m".to_owned(),
+            console: false,
+            abort: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Console {
+    /// Defaults to `/dev/console`.
+    pub path: Option<PathBuf>,
+    /// Kernel messages, on by default.
+    pub kernel: bool,
+    /// Also messages of this severity and worse, whatever their source.
+    pub severity: Option<String>,
+    pub max_per_minute: usize,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Console {
+            path: None,
+            kernel: true,
+            severity: None,
+            max_per_minute: 60,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+pub struct Kafka {
+    pub brokers: Vec<String>,
+    pub topic: String,
+    #[serde(default)]
+    pub select: Selector,
+    /// `0`, `1` or `all`.
+    #[serde(default = "kafka_acks")]
+    pub acks: String,
+    /// `none`, `gzip`, `snappy`, `lz4` or `zstd`.
+    #[serde(default = "kafka_compression")]
+    pub compression: String,
+    /// How many messages can wait for the brokers before the rest are dropped.
+    #[serde(default = "kafka_queue")]
+    pub queue: usize,
+    /// Any other librdkafka producer properties.
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+}
+
+fn kafka_acks() -> String {
+    "all".to_owned()
+}
+
+fn kafka_compression() -> String {
+    "none".to_owned()
+}
+
+fn kafka_queue() -> usize {
+    100_000
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mail {
+    pub to: Vec<String>,
+    pub from: Option<String>,
+    /// crit and worse by default.
+    #[serde(default = "crit")]
+    pub select: Selector,
+    pub appname: Option<String>,
+    pub regex: Option<String>,
+    /// Like `5m`, how long to collect messages for one mail.
+    pub window: Option<String>,
+    pub max_per_hour: Option<usize>,
+    pub subject: Option<String>,
+    pub body: Option<String>,
+    /// Defaults to `/usr/sbin/sendmail -t -i`.
+    pub command: Option<Vec<String>>,
+}
+
+fn crit() -> Selector {
+    "*.crit".parse().unwrap()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mark {
+    /// Like `20m`, how long without any message before a mark is stored.
+    pub interval: String,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    #[default]
+    Drop,
+    Accept,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    /// What the drops are counted under in `filter_stats`.
+    pub name: Option<String>,
+    #[serde(default)]
+    pub action: FilterAction,
+    #[serde(default)]
+    pub select: Selector,
+    pub socket: Option<String>,
+    pub appname: Option<String>,
+    pub hostname: Option<String>,
+    pub regex: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rewrite {
+    pub regex: String,
+    /// With `$1` or `$name` for capture groups.
+    pub replace: String,
+    #[serde(default)]
+    pub select: Selector,
+    pub appname: Option<String>,
+    /// Also rewrite structured data values.
+    #[serde(default)]
+    pub sdata: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Enrich {
+    /// The enrichers messages go through, in order.
+    pub chain: Vec<String>,
+    /// Chains for the sources with these names, instead of `chain`.
+    pub sources: BTreeMap<String, Vec<String>>,
+    pub appname: EnrichAppname,
+    pub defaults: EnrichDefaults,
+    pub sample: EnrichSample,
+}
+
+impl Default for Enrich {
+    fn default() -> Self {
+        Enrich {
+            chain: vec!["filter".to_owned(), "rewrite".to_owned()],
+            sources: BTreeMap::new(),
+            appname: EnrichAppname::default(),
+            defaults: EnrichDefaults::default(),
+            sample: EnrichSample::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnrichAppname {
+    /// `/usr/sbin/sshd` becomes `sshd`.
+    pub basename: bool,
+    pub lowercase: bool,
+}
+
+impl Default for EnrichAppname {
+    fn default() -> Self {
+        EnrichAppname {
+            basename: true,
+            lowercase: false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnrichDefaults {
+    /// For messages without a priority, which otherwise count as user.notice.
+    pub facility: Option<String>,
+    pub severity: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnrichSample {
+    /// Messages a second from one socket and appname, past which its info and debug ones
+    /// are sampled.
+    pub rate: u64,
+}
+
+impl Default for EnrichSample {
+    fn default() -> Self {
+        EnrichSample { rate: 1000 }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Privileges {
+    /// Who to run as after setting up, `_squealog` by default.
+    pub user: Option<String>,
+}
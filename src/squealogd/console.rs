@@ -0,0 +1,114 @@
+//! Echoing kernel messages (and optionally anything severe enough) to the console, so a
+//! serial console still shows them when the disk is gone:
+//!
+//! ```toml
+//! [console]
+//! severity = "crit"
+//! ```
+//!
+//! The console is opened non-blocking: when it can't keep up, lines are dropped and counted.
+
+use crate::squealogd::config;
+use crate::squealogd::counters;
+use crate::squealogd::internal;
+use crate::squealogd::output::{self, Outgoing, Output};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use syslog_loose::SyslogSeverity;
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+pub struct Console {
+    path: PathBuf,
+    file: Option<File>,
+    kernel: bool,
+    max_severity: Option<u8>,
+    max_per_minute: usize,
+    recent: Vec<Instant>,
+    hostname: String,
+    line: String,
+    failing: bool,
+}
+
+impl Console {
+    pub fn new(cfg: &config::Console) -> anyhow::Result<Console> {
+        let max_severity = match cfg.severity {
+            Some(ref name) => Some(
+                crate::names::parse_severity(name)
+                    .ok_or_else(|| anyhow::format_err!("invalid console severity '{}'", name))?,
+            ),
+            None => None,
+        };
+        Ok(Console {
+            path: cfg
+                .path
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("/dev/console")),
+            file: None,
+            kernel: cfg.kernel,
+            max_severity,
+            max_per_minute: cfg.max_per_minute,
+            recent: vec![],
+            hostname: crate::sys::hostname()?,
+            line: String::new(),
+            failing: false,
+        })
+    }
+
+    fn wanted(&self, out: &Outgoing) -> bool {
+        let kernel = out.socket == "klog" || out.facility() == Some(0);
+        let severe = match (self.max_severity, out.severity()) {
+            (Some(max), Some(sev)) => sev <= max,
+            _ => false,
+        };
+        (self.kernel && kernel) || severe
+    }
+
+    fn write(&mut self) -> io::Result<()> {
+        if self.file.is_none() {
+            self.file = Some(
+                OpenOptions::new()
+                    .write(true)
+                    .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+                    .open(&self.path)?,
+            );
+        }
+        self.file.as_mut().unwrap().write_all(self.line.as_bytes())
+    }
+}
+
+impl Output for Console {
+    fn send(&mut self, out: &Outgoing) {
+        if !self.wanted(out) {
+            return;
+        }
+        self.recent.retain(|t| t.elapsed() < RATE_WINDOW);
+        if self.recent.len() >= self.max_per_minute {
+            counters::CONSOLE_DROPPED.inc();
+            return;
+        }
+        self.recent.push(Instant::now());
+        output::rfc3164(&mut self.line, out, &self.hostname);
+        // Terminals in raw-ish modes don't return the carriage by themselves.
+        self.line.insert(self.line.len() - 1, '\r');
+        match self.write() {
+            Ok(()) => self.failing = false,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => counters::CONSOLE_DROPPED.inc(),
+            Err(e) => {
+                counters::CONSOLE_DROPPED.inc();
+                if !self.failing {
+                    internal::log(
+                        SyslogSeverity::SEV_ERR,
+                        format!("could not write to {:?}: {}", self.path, e),
+                    );
+                }
+                self.failing = true;
+                // Reopened with the next message.
+                self.file = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,314 @@
+//! A unix socket for operating the daemon while it runs, without signals:
+//!
+//! ```toml
+//! [control]
+//! path = "/var/run/squealogd.ctl"
+//! ```
+//!
+//! Clients send one command per line and get any number of lines back, then `OK` or
+//! `ERR <why>`. `squealog ctl` is the client. The commands:
+//!
+//! - `PING`: just the `OK`
+//! - `STATS`: the same snapshot SIGUSR1 logs
+//! - `SOURCES`: each socket that's being read from, with how many messages it got
+//! - `FLUSH`: writes out what outputs have buffered
+//! - `CHECKPOINT`: checkpoints and truncates the WAL
+//! - `ROTATE`: reopens output files, like SIGHUP
+//! - `RELOAD`: reloads the filter and rewrite rules, like SIGHUP
+//! - `LEVEL <severity>`: changes `--log-level`
+//! - `UPGRADE`: execs the binary on disk again without closing the sockets, like SIGUSR2
+//!
+//! The socket is only accessible to root, and connections from anyone but root and the user
+//! the daemon runs as are refused. Clients are read from without blocking, and replies that
+//! don't fit into the socket buffer get the client disconnected, so nothing a client does can
+//! hold up the main loop.
+
+use crate::squealogd::config;
+use crate::squealogd::slab::Keys;
+use polling::{Event, Poller};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub const DEFAULT_PATH: &str = "/var/run/squealogd.ctl";
+const MAX_LINE: usize = 1024;
+const MAX_CLIENTS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Ping,
+    Stats,
+    Sources,
+    Flush,
+    Checkpoint,
+    Rotate,
+    Reload,
+    Level(u8),
+    Upgrade,
+}
+
+impl std::str::FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Command, String> {
+        let mut words = line.split_whitespace();
+        let cmd = words.next().unwrap_or("").to_ascii_uppercase();
+        let arg = words.next();
+        if words.next().is_some() {
+            return Err("too many arguments".to_owned());
+        }
+        Ok(match (cmd.as_str(), arg) {
+            ("PING", None) => Command::Ping,
+            ("STATS", None) => Command::Stats,
+            ("SOURCES", None) => Command::Sources,
+            ("FLUSH", None) => Command::Flush,
+            ("CHECKPOINT", None) => Command::Checkpoint,
+            ("ROTATE", None) => Command::Rotate,
+            ("RELOAD", None) => Command::Reload,
+            ("UPGRADE", None) => Command::Upgrade,
+            ("LEVEL", Some(sev)) => Command::Level(
+                crate::names::parse_severity(sev)
+                    .ok_or_else(|| format!("invalid severity '{}'", sev))?,
+            ),
+            ("LEVEL", None) => return Err("LEVEL needs a severity".to_owned()),
+            ("", _) => return Err("empty command".to_owned()),
+            _ => return Err(format!("unknown command '{}'", cmd)),
+        })
+    }
+}
+
+/// A command from a client, to be answered with `Control::reply`.
+pub struct Request {
+    client: usize,
+    pub command: Result<Command, String>,
+}
+
+struct Client {
+    stream: UnixStream,
+    key: usize,
+    input: Vec<u8>,
+    /// Requests not answered yet.
+    pending: usize,
+    /// Hung up (or misbehaved), to be removed once the pending requests are answered.
+    done: bool,
+}
+
+/// Who's on the other end of `stream`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    let (mut uid, mut gid) = (0, 0);
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(uid)
+}
+
+pub struct Control {
+    path: PathBuf,
+    listener: UnixListener,
+    poller: Arc<Poller>,
+    /// Where the listener's and the clients' poller keys come from.
+    keys: Keys,
+    key: usize,
+    clients: Vec<Client>,
+}
+
+impl Control {
+    pub fn new(cfg: &config::Control, poller: Arc<Poller>, keys: Keys) -> anyhow::Result<Control> {
+        let path: PathBuf = cfg.path.clone().unwrap_or_else(|| DEFAULT_PATH.into());
+        // Left over from the last run, nothing can be listening on it anymore.
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        Control::listen(path, listener, poller, keys)
+    }
+
+    /// Takes over the listener a previous process (in an upgrade) bound.
+    pub fn adopt(
+        cfg: &config::Control,
+        fd: RawFd,
+        poller: Arc<Poller>,
+        keys: Keys,
+    ) -> anyhow::Result<Control> {
+        let path: PathBuf = cfg.path.clone().unwrap_or_else(|| DEFAULT_PATH.into());
+        Control::listen(path, unsafe { UnixListener::from_raw_fd(fd) }, poller, keys)
+    }
+
+    fn listen(
+        path: PathBuf,
+        listener: UnixListener,
+        poller: Arc<Poller>,
+        keys: Keys,
+    ) -> anyhow::Result<Control> {
+        listener.set_nonblocking(true)?;
+        let key = keys.take();
+        if let Err(e) = poller.add(&listener, Event::readable(key)) {
+            keys.give_back(key);
+            return Err(e.into());
+        }
+        Ok(Control {
+            path,
+            listener,
+            poller,
+            keys,
+            key,
+            clients: vec![],
+        })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+
+    fn accept(&mut self) {
+        loop {
+            let mut stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                // WouldBlock, or something like EMFILE that retrying right away won't fix.
+                Err(_) => break,
+            };
+            let euid = unsafe { libc::geteuid() };
+            match peer_uid(&stream) {
+                Ok(uid) if uid == 0 || uid == euid => (),
+                _ => {
+                    let _ = stream.write_all(b"ERR not allowed\n");
+                    continue;
+                }
+            }
+            if self.clients.len() >= MAX_CLIENTS {
+                let _ = stream.write_all(b"ERR too many clients\n");
+                continue;
+            }
+            if stream.set_nonblocking(true).is_err() {
+                continue;
+            }
+            let client = Client {
+                stream,
+                key: self.keys.take(),
+                input: vec![],
+                pending: 0,
+                done: false,
+            };
+            match self.poller.add(&client.stream, Event::readable(client.key)) {
+                Ok(()) => self.clients.push(client),
+                Err(_) => self.keys.give_back(client.key),
+            }
+        }
+        let _ = self
+            .poller
+            .modify(&self.listener, Event::readable(self.key));
+    }
+
+    fn remove(&mut self, i: usize) {
+        let client = self.clients.swap_remove(i);
+        let _ = self.poller.delete(&client.stream);
+        self.keys.give_back(client.key);
+    }
+
+    /// Handles the event if it's for the control socket, returning the complete commands
+    /// clients sent.
+    pub fn event(&mut self, ev: &Event) -> Option<Vec<Request>> {
+        if ev.key == self.key {
+            self.accept();
+            return Some(vec![]);
+        }
+        let i = self.clients.iter().position(|c| c.key == ev.key)?;
+        let client = &mut self.clients[i];
+        let mut buf = [0u8; 1024];
+        let mut alive = true;
+        loop {
+            match client.stream.read(&mut buf) {
+                Ok(0) => {
+                    alive = false;
+                    break;
+                }
+                Ok(n) => client.input.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(_) => {
+                    alive = false;
+                    break;
+                }
+            }
+        }
+        let mut requests = vec![];
+        while let Some(end) = client.input.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = client.input.drain(..=end).collect();
+            requests.push(Request {
+                client: client.key,
+                command: String::from_utf8_lossy(&line).trim().parse(),
+            });
+        }
+        client.pending += requests.len();
+        if client.input.len() > MAX_LINE {
+            alive = false;
+        }
+        if alive {
+            let _ = self
+                .poller
+                .modify(&client.stream, Event::readable(client.key));
+        } else if client.pending == 0 {
+            self.remove(i);
+        } else {
+            // Like `echo STATS | nc -U`, which hangs up right after sending.
+            client.done = true;
+        }
+        Some(requests)
+    }
+
+    /// Sends the reply to a request: its lines and then `OK`, or `ERR` and why.
+    pub fn reply(&mut self, request: &Request, reply: Result<Vec<String>, String>) {
+        let i = match self.clients.iter().position(|c| c.key == request.client) {
+            Some(i) => i,
+            None => return,
+        };
+        let mut text = String::new();
+        match reply {
+            Ok(lines) => {
+                for line in lines {
+                    text.push_str(&line);
+                    text.push('\n');
+                }
+                text.push_str("OK\n");
+            }
+            Err(e) => {
+                text.push_str("ERR ");
+                text.push_str(&e.replace('\n', " "));
+                text.push('\n');
+            }
+        }
+        let client = &mut self.clients[i];
+        client.pending -= 1;
+        // Replies are small, a client that doesn't take one right away isn't worth waiting for.
+        let written = client.stream.write_all(text.as_bytes());
+        if written.is_err() || (client.done && client.pending == 0) {
+            self.remove(i);
+        }
+    }
+
+    pub fn finish(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,126 @@
+//! Counters for things that go wrong (or right) without stopping the daemon, so they at least
+//! leave a trace. With the `http` feature, `GET /counters` shows them.
+
+use crate::squealogd::internal;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use syslog_loose::SyslogSeverity;
+
+/// How often what went wrong since the last time is logged.
+const REPORT_INTERVAL: Duration = Duration::from_secs(300);
+
+pub struct Counter {
+    pub name: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    const fn new(name: &'static str) -> Counter {
+        Counter {
+            name,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// For the few that are the most something ever was, rather than how often.
+    pub fn raise(&self, n: u64) {
+        self.value.fetch_max(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+macro_rules! counters {
+    ($($id:ident = $name:literal;)*) => {
+        $(pub static $id: Counter = Counter::new($name);)*
+        pub static ALL: &[&Counter] = &[$(&$id),*];
+    };
+}
+
+counters! {
+    RELAY_UDP_SENT = "relay_udp_sent";
+    RELAY_UDP_FAILED = "relay_udp_failed";
+    RELAY_TCP_SENT = "relay_tcp_sent";
+    RELAY_TCP_DROPPED = "relay_tcp_dropped";
+    RELAY_TCP_RECONNECTS = "relay_tcp_reconnects";
+    FILE_WRITE_FAILED = "file_write_failed";
+    EXEC_DROPPED = "exec_dropped";
+    JOURNALD_SENT = "journald_sent";
+    JOURNALD_FAILED = "journald_failed";
+    PUBSUB_DISCONNECTED = "pubsub_disconnected";
+    CONSOLE_DROPPED = "console_dropped";
+    KAFKA_SENT = "kafka_sent";
+    KAFKA_FAILED = "kafka_failed";
+    KAFKA_DROPPED = "kafka_dropped";
+    INSERT_BUSY_FAILED = "insert_busy_failed";
+    INSERT_CONSTRAINT_FAILED = "insert_constraint_failed";
+    INSERT_STORAGE_FAILED = "insert_storage_failed";
+    INSERT_OTHER_FAILED = "insert_other_failed";
+    SPILL_DROPPED = "spill_dropped";
+    RECV_FAILED = "recv_failed";
+    PARSE_QUEUE_BLOCKED = "parse_queue_blocked";
+    PARSE_NEWEST_DROPPED = "parse_newest_dropped";
+    PARSE_OLDEST_DROPPED = "parse_oldest_dropped";
+    PARSE_QUEUE_HIGH_WATER = "parse_queue_high_water";
+    PAYLOAD_PARSE_FAILED = "payload_parse_failed";
+    PAYLOAD_DROPPED = "payload_dropped";
+    RECORD_FAILED = "record_failed";
+    SAMPLE_SKIPPED = "sample_skipped";
+}
+
+/// Logs the counters of things going wrong that went up, every `REPORT_INTERVAL`.
+pub struct Report {
+    last: Vec<u64>,
+    next: Instant,
+}
+
+impl Report {
+    pub fn new() -> Report {
+        Report {
+            last: ALL.iter().map(|c| c.get()).collect(),
+            next: Instant::now() + REPORT_INTERVAL,
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        Some(self.next)
+    }
+
+    pub fn tick(&mut self, internal: &internal::Sender) {
+        if Instant::now() < self.next {
+            return;
+        }
+        self.next = Instant::now() + REPORT_INTERVAL;
+        let mut went_up = vec![];
+        for (counter, last) in ALL.iter().zip(self.last.iter_mut()) {
+            let value = counter.get();
+            let problem = ["failed", "dropped", "disconnected", "reconnects"]
+                .iter()
+                .any(|w| counter.name.ends_with(w));
+            if problem && value > *last {
+                went_up.push(format!("{} +{}", counter.name, value - *last));
+            }
+            *last = value;
+        }
+        if !went_up.is_empty() {
+            internal.log(
+                SyslogSeverity::SEV_WARNING,
+                format!(
+                    "in the last {} minutes: {}",
+                    REPORT_INTERVAL.as_secs() / 60,
+                    went_up.join(", ")
+                ),
+            );
+        }
+    }
+}
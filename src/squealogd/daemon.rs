@@ -0,0 +1,143 @@
+//! Running in the background, for rc(8) and anything else that doesn't supervise: with
+//! `--daemonize`, the process forks and the parent waits until the child is set up, exiting
+//! with 0 once it is and 1 if it failed (the child's errors still go to the terminal until
+//! then). `--pidfile <path>` writes the pid to a locked file, refusing to start when another
+//! instance holds the lock, and removes it again on a clean shutdown.
+//!
+//! Running in the foreground stays the default, for systemd, daemon(8) and friends.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    pub daemonize: bool,
+    pub pidfile: Option<PathBuf>,
+}
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+fn cloexec(fd: RawFd) -> io::Result<()> {
+    check(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+    Ok(())
+}
+
+/// The child's end of `--daemonize`.
+pub struct Background {
+    /// Tells the parent it can exit.
+    ready: Option<File>,
+    /// Opened right away, as it may not be possible anymore once the daemon is confined.
+    null: File,
+}
+
+/// Forks into the background, returning in the child only. Must come before any threads are
+/// started.
+pub fn daemonize() -> anyhow::Result<Background> {
+    let null = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    let mut fds = [0; 2];
+    check(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+    let (mut read, write) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    // Programs run later mustn't keep the parent waiting.
+    cloexec(write.as_raw_fd())?;
+    if check(unsafe { libc::fork() })? != 0 {
+        drop(write);
+        let mut byte = [0u8];
+        let code = match read.read(&mut byte) {
+            Ok(1) => 0,
+            _ => 1,
+        };
+        std::process::exit(code);
+    }
+    drop(read);
+    // Away from the terminal and whatever process group started it.
+    check(unsafe { libc::setsid() })?;
+    Ok(Background {
+        ready: Some(write),
+        null,
+    })
+}
+
+impl Background {
+    /// Lets the parent exit, and stops using its terminal.
+    pub fn ready(&mut self) {
+        let mut ready = match self.ready.take() {
+            Some(ready) => ready,
+            None => return,
+        };
+        for fd in 0..3 {
+            unsafe { libc::dup2(self.null.as_raw_fd(), fd) };
+        }
+        let _ = ready.write_all(b"\n");
+    }
+}
+
+pub struct Pidfile {
+    path: PathBuf,
+    file: File,
+}
+
+impl Pidfile {
+    /// Locks `path` and writes the pid into it. The lock is held as long as the process lives,
+    /// so a pidfile left behind by a crash doesn't get in the way.
+    pub fn create(path: &Path) -> anyhow::Result<Pidfile> {
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            // Emptied once it's locked, not to lose another daemon's pid.
+            .truncate(false)
+            .mode(0o644)
+            .open(path)?;
+        if let Err(e) =
+            check(unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) })
+        {
+            if e.kind() != io::ErrorKind::WouldBlock {
+                return Err(e.into());
+            }
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            anyhow::bail!(
+                "squealogd is already running (pid {}, according to {:?})",
+                pid.trim(),
+                path
+            );
+        }
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Pidfile {
+            path: path.to_owned(),
+            file,
+        })
+    }
+
+    /// The pidfile an upgraded process inherited, still locked and with the same pid in it.
+    pub fn adopt(path: &Path, fd: RawFd) -> Pidfile {
+        Pidfile {
+            path: path.to_owned(),
+            file: unsafe { File::from_raw_fd(fd) },
+        }
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    /// Removes the pidfile, or empties it if its directory isn't writable anymore (after
+    /// giving up root, say).
+    pub fn remove(self) {
+        if std::fs::remove_file(&self.path).is_err() {
+            let _ = self.file.set_len(0);
+        }
+    }
+}
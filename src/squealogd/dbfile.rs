@@ -0,0 +1,135 @@
+//! Creating the database file before SQLite opens it, so that it gets a mode of its own choosing
+//! (`--db-mode`, 0640 by default) rather than whatever the umask leaves. SQLite gives the `-wal`
+//! and `-shm` files it creates later the database's mode (and owner), so the mode has to be
+//! right before the first write. A missing directory is created with `--create-db-dir`, and
+//! otherwise explained, instead of ending up as SQLite's "unable to open database file".
+
+use crate::squealogd::preflight::{self, Problem};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Options {
+    pub mode: u32,
+    pub create_dir: bool,
+}
+
+/// The directory the database lives in.
+pub fn dir(db: &Path) -> &Path {
+    match db.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// What would keep SQLite from opening (or creating) `db`, without changing anything.
+pub fn check(db: &Path, opts: Options) -> Option<Problem> {
+    let dir = dir(db);
+    if !dir.exists() {
+        if !opts.create_dir {
+            return Some(Problem::new(
+                format!("The database's directory {:?} doesn't exist", dir),
+                "create it, pass --create-db-dir, or point --db somewhere else",
+            ));
+        }
+        let parent = dir
+            .ancestors()
+            .find(|a| a.exists())
+            .unwrap_or(Path::new("/"));
+        if !preflight::writable(parent) {
+            return Some(Problem::new(
+                format!(
+                    "Can't create the database's directory {:?} in {:?}",
+                    dir, parent
+                ),
+                "create it as a user that can, or point --db somewhere else",
+            ));
+        }
+        return None;
+    }
+    if !preflight::writable(dir) {
+        return Some(Problem::new(
+            format!("The database's directory {:?} is not writable", dir),
+            "SQLite creates the -wal and -shm files next to the database, so run as a user that \
+            can write there",
+        ));
+    }
+    if db.is_dir() {
+        return Some(Problem::new(
+            format!("The database {:?} is a directory", db),
+            "point --db at a file in it",
+        ));
+    }
+    None
+}
+
+/// Makes sure SQLite will be able to open (or create) `db`, creating it with `opts.mode` if it
+/// doesn't exist yet.
+pub fn prepare(db: &Path, opts: Options) -> anyhow::Result<()> {
+    if let Some(problem) = check(db, opts) {
+        anyhow::bail!("{}", problem);
+    }
+    let dir = dir(db);
+    if !dir.exists() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o755)
+            .create(dir)
+            .map_err(|e| anyhow::format_err!("Could not create {:?}: {}", dir, e))?;
+    }
+    match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(opts.mode)
+        .open(db)
+    {
+        // The umask may have taken bits away.
+        Ok(file) => file.set_permissions(std::fs::Permissions::from_mode(opts.mode))?,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+        Err(e) => anyhow::bail!("Could not create the database {:?}: {}", db, e),
+    }
+    Ok(())
+}
+
+pub fn parse_mode(arg: &str) -> Result<u32, String> {
+    u32::from_str_radix(arg.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|&mode| mode <= 0o777)
+        .ok_or_else(|| format!("'{}' is not an octal file mode like 0640", arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn the_wal_and_shm_get_the_mode_whatever_the_umask() {
+        let dir = std::env::temp_dir().join(format!("squealog-dbfile-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = dir.join("logs.db");
+        let opts = Options {
+            mode: 0o644,
+            create_dir: true,
+        };
+
+        // The umask belongs to the whole process, so it's only changed while creating.
+        let umask = unsafe { libc::umask(0o077) };
+        let created = prepare(&db, opts).and_then(|()| {
+            let conn = rusqlite::Connection::open(&db)?;
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            conn.execute_batch("CREATE TABLE t (x); INSERT INTO t VALUES (1);")?;
+            Ok(conn)
+        });
+        unsafe { libc::umask(umask) };
+        let conn = created.unwrap();
+
+        for name in ["logs.db", "logs.db-wal", "logs.db-shm"] {
+            let mode = std::fs::metadata(dir.join(name)).unwrap().mode() & 0o777;
+            assert_eq!(mode, 0o644, "{}: {:o}", name, mode);
+        }
+        drop(conn);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
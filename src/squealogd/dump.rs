@@ -0,0 +1,86 @@
+//! `--debug-ingest`: every message as the parser understood it, on stderr, for checking a
+//! parser against live traffic (with `--no-db` too, without touching the database). At most
+//! `MAX_PER_SECOND` are shown, the rest are counted and the count shown with the next one.
+
+use crate::names;
+use std::io::Write;
+use std::time::{Duration, Instant};
+use syslog_loose::{Message, ProcId};
+
+const MAX_PER_SECOND: usize = 20;
+
+pub struct Dump {
+    window: Instant,
+    shown: usize,
+    skipped: u64,
+}
+
+impl Default for Dump {
+    fn default() -> Dump {
+        Dump {
+            window: Instant::now(),
+            shown: 0,
+            skipped: 0,
+        }
+    }
+}
+
+impl Dump {
+    pub fn show(&mut self, socket: &str, msg: &Message<&str>) {
+        let now = Instant::now();
+        if now >= self.window + Duration::from_secs(1) {
+            self.window = now;
+            self.shown = 0;
+        }
+        if self.shown >= MAX_PER_SECOND {
+            self.skipped += 1;
+            return;
+        }
+        self.shown += 1;
+        let mut out = String::new();
+        if self.skipped > 0 {
+            out.push_str(&format!("({} messages not shown)\n", self.skipped));
+            self.skipped = 0;
+        }
+        let name = |n: Option<i64>, f: fn(i64) -> Option<&'static str>| {
+            n.map_or("-".to_owned(), |n| {
+                f(n).map_or(n.to_string(), str::to_owned)
+            })
+        };
+        out.push_str(&format!(
+            "{}: {}.{} {:?}\n",
+            socket,
+            name(msg.facility.map(|f| f as i64), names::facility_name),
+            name(msg.severity.map(|s| s as i64), names::severity_name),
+            msg.protocol,
+        ));
+        let field = |out: &mut String, key: &str, value: Option<String>| {
+            if let Some(value) = value {
+                out.push_str(&format!("  {:8} {}\n", key, value));
+            }
+        };
+        field(&mut out, "time", msg.timestamp.map(|t| t.to_rfc3339()));
+        field(&mut out, "hostname", msg.hostname.map(str::to_owned));
+        field(&mut out, "appname", msg.appname.map(str::to_owned));
+        field(
+            &mut out,
+            "procid",
+            msg.procid.as_ref().map(|p| match p {
+                ProcId::PID(pid) => pid.to_string(),
+                ProcId::Name(name) => format!("{:?}", name),
+            }),
+        );
+        field(&mut out, "msgid", msg.msgid.map(str::to_owned));
+        for element in &msg.structured_data {
+            let params: String = element
+                .params
+                .iter()
+                .map(|(k, v)| format!(" {}={:?}", k, v))
+                .collect();
+            field(&mut out, "sd", Some(format!("[{}{}]", element.id, params)));
+        }
+        field(&mut out, "msg", Some(format!("{:?}", msg.msg)));
+        // Best effort, like all of stderr.
+        let _ = std::io::stderr().write_all(out.as_bytes());
+    }
+}
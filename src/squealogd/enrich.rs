@@ -0,0 +1,651 @@
+//! What's done to a message between parsing and storing it, as a chain of enrichers that each
+//! get to change it or drop it:
+//!
+//! ```toml
+//! [enrich]
+//! chain = ["defaults", "filter", "appname", "rewrite"]
+//!
+//! [enrich.sources]
+//! udp = ["filter", "rewrite"]
+//!
+//! [enrich.appname]
+//! lowercase = true
+//!
+//! [enrich.defaults]
+//! facility = "daemon"
+//! severity = "info"
+//!
+//! [enrich.sample]
+//! rate = 1000
+//! ```
+//!
+//! The enrichers run in the order of `chain`, or of the chain for the message's source (by
+//! socket name), and the first one that drops a message ends it. There are:
+//!
+//! - `filter`: the `[[filter]]` rules, dropping what they drop
+//! - `rewrite`: the `[[rewrite]]` rules, masking secrets
+//! - `appname`: the basename of appnames that are paths (`basename = false` to keep them),
+//!   lowercased with `lowercase = true`
+//! - `defaults`: a `facility` and `severity` for messages without a priority
+//! - `sample`: during storms, a sample of info and debug messages, see `sampler`
+//!
+//! The chain is `["filter", "rewrite"]` unless configured, and a name that isn't one of these
+//! is a configuration error. Filling in the local hostname comes before any of them. A message
+//! none of them changes isn't copied anywhere: the record borrows it, and only keeps text of
+//! its own for what an enricher replaced. The chains and their options are read again on
+//! SIGHUP, like the rules.
+
+use crate::squealogd::config::{self, Config};
+use crate::squealogd::filters::Filters;
+use crate::squealogd::preflight::Problem;
+use crate::squealogd::rewrite::Rewrites;
+use crate::squealogd::sampler::Sampler;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use syslog_loose::{Message, SyslogFacility, SyslogSeverity};
+
+/// Where a message came from.
+pub struct SourceInfo<'a> {
+    pub socket: &'a str,
+    /// When the message arrived.
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Keep,
+    Drop,
+}
+
+pub trait Enricher {
+    fn enrich(&self, source: &SourceInfo, rec: &mut Record) -> Action;
+}
+
+/// A message going through the chain. The header fields that enrichers only ever set to
+/// something else of the message's are in `msg`, the text they can replace has accessors.
+pub struct Record<'a> {
+    pub msg: Message<&'a str>,
+    raw: &'a str,
+    changes: Changes,
+}
+
+/// What the enrichers replaced in a record.
+#[derive(Default)]
+pub struct Changes {
+    appname: Option<String>,
+    msg: Option<String>,
+    raw: Option<String>,
+    /// Element and param index, with the new value.
+    params: Vec<(usize, usize, String)>,
+}
+
+impl<'a> Record<'a> {
+    /// `raw` is the text as received, which verbatim relays send on.
+    pub fn new(msg: Message<&'a str>, raw: &'a str) -> Record<'a> {
+        Record {
+            msg,
+            raw,
+            changes: Changes::default(),
+        }
+    }
+
+    pub fn appname(&self) -> Option<&str> {
+        self.changes.appname.as_deref().or(self.msg.appname)
+    }
+
+    pub fn set_appname(&mut self, appname: String) {
+        self.changes.appname = Some(appname);
+    }
+
+    /// The message text.
+    pub fn text(&self) -> &str {
+        self.changes.msg.as_deref().unwrap_or(self.msg.msg)
+    }
+
+    pub fn set_text(&mut self, text: String) {
+        self.changes.msg = Some(text);
+    }
+
+    pub fn raw(&self) -> &str {
+        self.changes.raw.as_deref().unwrap_or(self.raw)
+    }
+
+    pub fn set_raw(&mut self, raw: String) {
+        self.changes.raw = Some(raw);
+    }
+
+    /// A structured data value, by element and param index.
+    pub fn param(&self, element: usize, param: usize) -> &str {
+        self.changes
+            .params
+            .iter()
+            .find(|&&(e, p, _)| (e, p) == (element, param))
+            .map_or(
+                self.msg.structured_data[element].params[param].1,
+                |(_, _, v)| v.as_str(),
+            )
+    }
+
+    pub fn set_param(&mut self, element: usize, param: usize, value: String) {
+        let params = &mut self.changes.params;
+        match params
+            .iter()
+            .position(|&(e, p, _)| (e, p) == (element, param))
+        {
+            Some(i) => params[i].2 = value,
+            None => params.push((element, param, value)),
+        }
+    }
+
+    /// The message and received text as they came in, and what to change about them.
+    pub fn into_parts(self) -> (Message<&'a str>, &'a str, Changes) {
+        (self.msg, self.raw, self.changes)
+    }
+}
+
+impl Changes {
+    /// The message with the changes applied, borrowing the new parts from `self`.
+    pub fn apply<'a>(&'a self, mut msg: Message<&'a str>) -> Message<&'a str> {
+        if let Some(ref appname) = self.appname {
+            msg.appname = Some(appname);
+        }
+        if let Some(ref text) = self.msg {
+            msg.msg = text;
+        }
+        for (e, p, value) in &self.params {
+            msg.structured_data[*e].params[*p].1 = value;
+        }
+        msg
+    }
+
+    pub fn raw<'a>(&'a self, raw: &'a str) -> &'a str {
+        self.raw.as_deref().unwrap_or(raw)
+    }
+}
+
+/// This machine's hostname, for messages from local sources that don't carry one, so they
+/// still say where they're from once merged or relayed.
+pub struct LocalHostname {
+    name: Option<String>,
+}
+
+impl LocalHostname {
+    /// `name` is what looking it up gave, none if that failed.
+    pub fn new(name: Option<String>) -> LocalHostname {
+        LocalHostname { name }
+    }
+
+    pub fn get(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Takes what looking it up again (on SIGHUP) gave, returning whether it changed.
+    pub fn relookup(&mut self, name: Option<String>) -> bool {
+        if name == self.name {
+            return false;
+        }
+        self.name = name;
+        true
+    }
+
+    /// Fills in the hostname of a message from a `local` source that has none, returning what
+    /// the message's hostname is for the `hostname_source` column.
+    pub fn fill<'a>(&'a self, msg: &mut Message<&'a str>, local: bool) -> Option<&'static str> {
+        match (msg.hostname, self.get()) {
+            (Some(_), _) => Some("claimed"),
+            (None, Some(name)) if local => {
+                msg.hostname = Some(name);
+                Some("local")
+            }
+            _ => None,
+        }
+    }
+}
+
+struct Appname {
+    basename: bool,
+    lowercase: bool,
+}
+
+impl Enricher for Appname {
+    fn enrich(&self, _: &SourceInfo, rec: &mut Record) -> Action {
+        let name = match rec.appname() {
+            Some(name) => name,
+            None => return Action::Keep,
+        };
+        let base = match name.rsplit('/').next() {
+            Some(base) if self.basename && name.starts_with('/') && !base.is_empty() => base,
+            _ => name,
+        };
+        let new = if self.lowercase && base.chars().any(char::is_uppercase) {
+            base.to_lowercase()
+        } else if base.len() < name.len() {
+            base.to_owned()
+        } else {
+            return Action::Keep;
+        };
+        rec.set_appname(new);
+        Action::Keep
+    }
+}
+
+struct Defaults {
+    facility: Option<SyslogFacility>,
+    severity: Option<SyslogSeverity>,
+}
+
+impl Defaults {
+    fn new(cfg: &config::EnrichDefaults) -> anyhow::Result<Defaults> {
+        let facility = match cfg.facility {
+            Some(ref name) => match crate::names::parse_facility(name) {
+                Some(fac) => syslog_loose::decompose_pri(fac << 3).0,
+                None => anyhow::bail!("[enrich.defaults] has an unknown facility '{}'", name),
+            },
+            None => None,
+        };
+        let severity = match cfg.severity {
+            Some(ref name) => match crate::names::parse_severity(name) {
+                Some(sev) => syslog_loose::decompose_pri(sev).1,
+                None => anyhow::bail!("[enrich.defaults] has an unknown severity '{}'", name),
+            },
+            None => None,
+        };
+        Ok(Defaults { facility, severity })
+    }
+}
+
+impl Enricher for Defaults {
+    fn enrich(&self, _: &SourceInfo, rec: &mut Record) -> Action {
+        if rec.msg.facility.is_none() {
+            rec.msg.facility = self.facility;
+        }
+        if rec.msg.severity.is_none() {
+            rec.msg.severity = self.severity;
+        }
+        Action::Keep
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Filter,
+    Rewrite,
+    Appname,
+    Defaults,
+    Sample,
+}
+
+const NAMES: &[(&str, Kind)] = &[
+    ("filter", Kind::Filter),
+    ("rewrite", Kind::Rewrite),
+    ("appname", Kind::Appname),
+    ("defaults", Kind::Defaults),
+    ("sample", Kind::Sample),
+];
+
+fn chain(names: &[String], what: &str) -> anyhow::Result<Vec<Kind>> {
+    names
+        .iter()
+        .map(|name| match NAMES.iter().find(|&&(n, _)| n == name) {
+            Some(&(_, kind)) => Ok(kind),
+            None => Err(anyhow::format_err!(
+                "{} has an unknown enricher '{}' (there are {})",
+                what,
+                name,
+                NAMES.iter().map(|&(n, _)| n).collect::<Vec<_>>().join(", ")
+            )),
+        })
+        .collect()
+}
+
+/// Every enricher, and which run for which source.
+pub struct Enrichers {
+    pub filters: Filters,
+    rewrites: Rewrites,
+    appname: Appname,
+    defaults: Defaults,
+    pub sampler: Sampler,
+    chain: Vec<Kind>,
+    sources: HashMap<String, Vec<Kind>>,
+}
+
+impl Enrichers {
+    pub fn new(cfg: &Config) -> anyhow::Result<Enrichers> {
+        let enrich = &cfg.enrich;
+        let sources = enrich
+            .sources
+            .iter()
+            .map(|(source, names)| {
+                let what = format!("The [enrich.sources] chain for '{}'", source);
+                Ok((source.clone(), chain(names, &what)?))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Enrichers {
+            filters: Filters::new(&cfg.filter)?,
+            rewrites: Rewrites::new(&cfg.rewrite)?,
+            appname: Appname {
+                basename: enrich.appname.basename,
+                lowercase: enrich.appname.lowercase,
+            },
+            defaults: Defaults::new(&enrich.defaults)?,
+            sampler: Sampler::new(&enrich.sample)?,
+            chain: chain(&enrich.chain, "The [enrich] chain")?,
+            sources,
+        })
+    }
+
+    /// Takes over from `self`, keeping the filter counts that weren't written yet and the
+    /// sampler's runs.
+    pub fn replace(&mut self, new: Enrichers) {
+        let Enrichers {
+            filters,
+            rewrites,
+            appname,
+            defaults,
+            sampler,
+            chain,
+            sources,
+        } = new;
+        self.filters.replace(filters);
+        self.rewrites = rewrites;
+        self.appname = appname;
+        self.defaults = defaults;
+        self.sampler.replace(sampler);
+        self.chain = chain;
+        self.sources = sources;
+    }
+
+    fn get(&self, kind: Kind) -> &dyn Enricher {
+        match kind {
+            Kind::Filter => &self.filters,
+            Kind::Rewrite => &self.rewrites,
+            Kind::Appname => &self.appname,
+            Kind::Defaults => &self.defaults,
+            Kind::Sample => &self.sampler,
+        }
+    }
+
+    /// Runs the source's chain over `rec`.
+    pub fn run(&self, source: &SourceInfo, rec: &mut Record) -> Action {
+        let chain = self.sources.get(source.socket).unwrap_or(&self.chain);
+        for &kind in chain {
+            if self.get(kind).enrich(source, rec) == Action::Drop {
+                return Action::Drop;
+            }
+        }
+        Action::Keep
+    }
+}
+
+/// The preflight's check of the chains and their options.
+pub fn check(cfg: &Config) -> Vec<Problem> {
+    let mut names = vec![(&cfg.enrich.chain, "The [enrich] chain".to_owned())];
+    for (source, chain) in &cfg.enrich.sources {
+        names.push((
+            chain,
+            format!("The [enrich.sources] chain for '{}'", source),
+        ));
+    }
+    let mut problems: Vec<Problem> = names
+        .into_iter()
+        .filter_map(|(names, what)| chain(names, &what).err())
+        .map(|e| Problem::new(e.to_string(), "fix the name, or leave it out"))
+        .collect();
+    if let Err(e) = Defaults::new(&cfg.enrich.defaults) {
+        problems.push(Problem::new(
+            e.to_string(),
+            "use a facility or severity name, or a number",
+        ));
+    }
+    if let Err(e) = Sampler::new(&cfg.enrich.sample) {
+        problems.push(Problem::new(e.to_string(), "set a rate of 1 or more"));
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A message without a priority or hostname.
+    fn message<'a>(appname: Option<&'a str>, text: &'a str) -> Message<&'a str> {
+        Message {
+            protocol: syslog_loose::Protocol::RFC3164,
+            facility: None,
+            severity: None,
+            timestamp: None,
+            hostname: None,
+            appname,
+            procid: None,
+            msgid: None,
+            structured_data: vec![],
+            msg: text,
+        }
+    }
+
+    /// The enrichers a config sets up.
+    fn enrichers(config: &str) -> Enrichers {
+        Enrichers::new(&toml::from_str(config).unwrap()).unwrap()
+    }
+
+    /// What a record comes out of a chain as, unless it's dropped.
+    #[derive(Debug, PartialEq)]
+    struct Out {
+        facility: Option<SyslogFacility>,
+        severity: Option<SyslogSeverity>,
+        appname: Option<String>,
+        text: String,
+        /// Whether any of its text had to be replaced (and so copied).
+        copied: bool,
+    }
+
+    /// Runs messages of (appname, text) from `socket` through the chain.
+    fn run(
+        enrichers: &Enrichers,
+        socket: &str,
+        messages: &[(Option<&str>, &str)],
+    ) -> Vec<Option<Out>> {
+        let source = SourceInfo {
+            socket,
+            time: Utc::now(),
+        };
+        messages
+            .iter()
+            .map(|&(appname, text)| {
+                let mut rec = Record::new(message(appname, text), text);
+                if enrichers.run(&source, &mut rec) == Action::Drop {
+                    return None;
+                }
+                let (msg, raw, changes) = rec.into_parts();
+                let copied = changes.appname.is_some()
+                    || changes.msg.is_some()
+                    || changes.raw.is_some()
+                    || !changes.params.is_empty();
+                let msg = changes.apply(msg);
+                // The records' raw text is their text, and has to change the same way.
+                assert_eq!(changes.raw(raw), msg.msg);
+                Some(Out {
+                    facility: msg.facility,
+                    severity: msg.severity,
+                    appname: msg.appname.map(str::to_owned),
+                    text: msg.msg.to_owned(),
+                    copied,
+                })
+            })
+            .collect()
+    }
+
+    fn out(appname: Option<&str>, text: &str, copied: bool) -> Option<Out> {
+        Some(Out {
+            facility: None,
+            severity: None,
+            appname: appname.map(str::to_owned),
+            text: text.to_owned(),
+            copied,
+        })
+    }
+
+    const RULES: &str = r#"
+[[filter]]
+appname = "sshd"
+
+[[rewrite]]
+regex = 'token=\S+'
+replace = "token=[redacted]"
+"#;
+
+    #[test]
+    fn untouched_records_arent_copied() {
+        let messages = [(Some("app"), "nothing to see"), (None, "no appname")];
+        for config in [
+            "",
+            RULES,
+            "[enrich]\nchain = [\"appname\", \"defaults\", \"sample\"]",
+        ] {
+            assert_eq!(
+                run(&enrichers(config), "local", &messages),
+                [
+                    out(Some("app"), "nothing to see", false),
+                    out(None, "no appname", false)
+                ],
+                "{:?}",
+                config
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_enrichers_are_config_errors() {
+        for config in [
+            "[enrich]\nchain = [\"filter\", \"nope\"]",
+            "[enrich.sources]\nremote = [\"nope\"]",
+        ] {
+            let config: Config = toml::from_str(config).unwrap();
+            assert!(Enrichers::new(&config).is_err(), "{:?}", config.enrich);
+            assert_eq!(check(&config).len(), 1, "{:?}", config.enrich);
+        }
+    }
+
+    #[test]
+    fn chains_run_in_order() {
+        let messages = [
+            (Some("/usr/sbin/sshd"), "token=abc"),
+            (Some("cron"), "token=abc"),
+        ];
+        let filtered_first = enrichers(&format!(
+            "[enrich]\nchain = [\"filter\", \"appname\", \"rewrite\"]\n{}",
+            RULES
+        ));
+        assert_eq!(
+            run(&filtered_first, "local", &messages),
+            [
+                out(Some("sshd"), "token=[redacted]", true),
+                out(Some("cron"), "token=[redacted]", true),
+            ]
+        );
+        // The basename is what the filter sees now.
+        let renamed_first = enrichers(&format!(
+            "[enrich]\nchain = [\"appname\", \"filter\"]\n{}",
+            RULES
+        ));
+        assert_eq!(
+            run(&renamed_first, "local", &messages),
+            [None, out(Some("cron"), "token=abc", false)]
+        );
+    }
+
+    #[test]
+    fn sources_have_chains_of_their_own() {
+        let enrichers = enrichers(&format!(
+            "[enrich]\nchain = [\"rewrite\"]\n[enrich.sources]\nremote = [\"defaults\", \"filter\"]\n\
+            [enrich.defaults]\nfacility = \"local3\"\nseverity = \"notice\"\n{}",
+            RULES
+        ));
+        let messages = [(Some("sshd"), "token=abc"), (Some("app"), "token=abc")];
+        assert_eq!(
+            run(&enrichers, "local", &messages),
+            [
+                out(Some("sshd"), "token=[redacted]", true),
+                out(Some("app"), "token=[redacted]", true),
+            ]
+        );
+        let defaulted = Out {
+            facility: Some(SyslogFacility::LOG_LOCAL3),
+            severity: Some(SyslogSeverity::SEV_NOTICE),
+            appname: Some("app".to_owned()),
+            text: "token=abc".to_owned(),
+            copied: false,
+        };
+        assert_eq!(
+            run(&enrichers, "remote", &messages),
+            [None, Some(defaulted)]
+        );
+    }
+
+    /// The hostname and its source each message gets, for (claimed hostname, local source).
+    fn filled(
+        local: &LocalHostname,
+        messages: &[(Option<&str>, bool)],
+    ) -> Vec<(Option<String>, Option<&'static str>)> {
+        messages
+            .iter()
+            .map(|&(hostname, is_local)| {
+                let mut msg = message(Some("app"), "hello");
+                msg.hostname = hostname;
+                let source = local.fill(&mut msg, is_local);
+                (msg.hostname.map(str::to_owned), source)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn follows_the_hostname_changing_at_runtime() {
+        let messages = [
+            (None, true),
+            (Some("sender"), true),
+            (None, false),
+            (Some("sender"), false),
+        ];
+        let mut local = LocalHostname::new(Some("before".to_owned()));
+        let claimed = (Some("sender".to_owned()), Some("claimed"));
+        let unknown = (None, None);
+        assert_eq!(
+            filled(&local, &messages),
+            [
+                (Some("before".to_owned()), Some("local")),
+                claimed.clone(),
+                unknown.clone(),
+                claimed.clone()
+            ]
+        );
+
+        assert!(local.relookup(Some("after".to_owned())));
+        assert_eq!(
+            filled(&local, &messages),
+            [
+                (Some("after".to_owned()), Some("local")),
+                claimed.clone(),
+                unknown.clone(),
+                claimed.clone()
+            ]
+        );
+        assert!(!local.relookup(Some("after".to_owned())));
+
+        // Looking it up failed: nothing is better than the name it no longer has.
+        assert!(local.relookup(None));
+        assert_eq!(
+            filled(&local, &messages),
+            [
+                unknown.clone(),
+                claimed.clone(),
+                unknown.clone(),
+                claimed.clone()
+            ]
+        );
+        assert!(local.relookup(Some("again".to_owned())));
+        assert_eq!(
+            filled(&local, &messages)[0],
+            (Some("again".to_owned()), Some("local"))
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! Piping matching messages into a long-running program, like syslog-ng's `program()`:
+//!
+//! ```toml
+//! [[exec]]
+//! command = ["/usr/local/bin/ban-bruteforcers", "--quiet"]
+//! env = { BAN_TIME = "1h" }
+//! select = "auth,authpriv.*"
+//! appname = "sshd"
+//! regex = "Failed password for .* from"
+//! ```
+//!
+//! The program gets one `Jan  2 15:04:05 host app[pid]: msg` line per message on its stdin,
+//! and is restarted (with backoff) when it exits. What it writes to stderr is stored as
+//! messages from the `exec` socket, which don't go to exec hooks themselves. Lines are queued
+//! in memory while it's busy; when the queue is full, or the `[memory]` cap is reached, they're
+//! dropped and counted.
+
+use crate::squealogd::config;
+use crate::squealogd::counters;
+use crate::squealogd::internal::{self, Internal};
+use crate::squealogd::matcher::Matcher;
+use crate::squealogd::memory;
+use crate::squealogd::output::{self, Outgoing, Output};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use syslog_loose::{SyslogFacility, SyslogSeverity};
+
+/// The socket name for what hooks write to stderr.
+pub const SOCKET: &str = "exec";
+
+const DEFAULT_BUFFER: usize = 1000;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How often to check on a child that isn't being sent anything, to reap it if it died.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct ExecHook {
+    matcher: Matcher,
+    hostname: String,
+    line: String,
+    tx: SyncSender<String>,
+    /// Set to have the runner drop what's queued.
+    shed: Arc<AtomicBool>,
+}
+
+impl ExecHook {
+    pub fn new(cfg: &config::ExecHook, internal: internal::Sender) -> anyhow::Result<ExecHook> {
+        if cfg.command.is_empty() {
+            anyhow::bail!("exec hook with an empty command");
+        }
+        let matcher = Matcher::new(cfg.select, cfg.appname.clone(), cfg.regex.as_deref())?;
+        let (tx, rx) = mpsc::sync_channel(cfg.buffer.unwrap_or(DEFAULT_BUFFER));
+        let shed = Arc::new(AtomicBool::new(false));
+        let runner = Runner {
+            command: cfg.command.clone(),
+            env: cfg.env.clone(),
+            internal,
+            shed: shed.clone(),
+        };
+        std::thread::Builder::new()
+            .name(format!("exec {}", cfg.command[0]))
+            .spawn(move || runner.run(rx))?;
+        Ok(ExecHook {
+            matcher,
+            hostname: crate::sys::hostname()?,
+            line: String::new(),
+            tx,
+            shed,
+        })
+    }
+}
+
+impl Output for ExecHook {
+    fn send(&mut self, out: &Outgoing) {
+        if out.socket == SOCKET || !self.matcher.matches(out) {
+            return;
+        }
+        output::rfc3164(&mut self.line, out, &self.hostname);
+        match self.tx.try_send(self.line.clone()) {
+            Ok(()) => memory::EXEC.add(self.line.len()),
+            Err(TrySendError::Full(_)) => counters::EXEC_DROPPED.inc(),
+            // The runner thread is gone, nothing to be done about it.
+            Err(TrySendError::Disconnected(_)) => counters::EXEC_DROPPED.inc(),
+        }
+    }
+
+    fn shed(&mut self, stage: memory::Stage) -> bool {
+        // Once per hook, the runner takes it from there.
+        stage == memory::Stage::Queues && !self.shed.swap(true, Ordering::Relaxed)
+    }
+}
+
+/// Owns the child process, on a thread of its own so that a slow child only ever fills up
+/// the queue.
+struct Runner {
+    command: Vec<String>,
+    env: BTreeMap<String, String>,
+    internal: internal::Sender,
+    shed: Arc<AtomicBool>,
+}
+
+/// Starts `command` with a pipe for its stdin, storing what it writes to stderr as messages
+/// from the `exec` socket.
+pub fn spawn(
+    command: &[String],
+    env: &BTreeMap<String, String>,
+    internal: &internal::Sender,
+) -> std::io::Result<Child> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .envs(env)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stderr = child.stderr.take().unwrap();
+    let appname = std::path::Path::new(&command[0])
+        .file_name()
+        .map_or_else(|| command[0].clone(), |n| n.to_string_lossy().into_owned());
+    let pid = child.id() as i32;
+    let internal = internal.clone();
+    // Ends by itself when the child (and anything it forked off) closes stderr.
+    std::thread::spawn(move || {
+        for line in BufReader::new(stderr).lines() {
+            let msg = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            internal.send(Internal {
+                socket: SOCKET,
+                facility: SyslogFacility::LOG_DAEMON,
+                appname: appname.clone(),
+                pid: Some(pid),
+                severity: SyslogSeverity::SEV_INFO,
+                msg,
+            });
+        }
+    });
+    Ok(child)
+}
+
+impl Runner {
+    /// Under the exec socket too, so hooks don't get complaints about themselves.
+    fn complain(&self, severity: SyslogSeverity, msg: String) {
+        self.internal.send(Internal {
+            socket: SOCKET,
+            facility: SyslogFacility::LOG_SYSLOG,
+            appname: "squealogd".to_owned(),
+            pid: Some(std::process::id() as i32),
+            severity,
+            msg,
+        });
+    }
+
+    fn run(self, rx: mpsc::Receiver<String>) {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            let started = Instant::now();
+            let mut child = match spawn(&self.command, &self.env, &self.internal) {
+                Ok(child) => child,
+                Err(e) => {
+                    self.complain(
+                        SyslogSeverity::SEV_ERR,
+                        format!("could not start {}: {}", self.command[0], e),
+                    );
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    continue;
+                }
+            };
+            let mut stdin = child.stdin.take();
+            loop {
+                let line = match rx.recv_timeout(CHECK_INTERVAL) {
+                    Ok(line) => line,
+                    Err(RecvTimeoutError::Timeout) => match child.try_wait() {
+                        Ok(None) => continue,
+                        _ => break,
+                    },
+                    Err(RecvTimeoutError::Disconnected) => return,
+                };
+                memory::EXEC.sub(line.len());
+                if self.shed.swap(false, Ordering::Relaxed) {
+                    // What's behind this line, which still gets written.
+                    for queued in rx.try_iter() {
+                        memory::EXEC.sub(queued.len());
+                        counters::EXEC_DROPPED.inc();
+                    }
+                }
+                let written = stdin
+                    .as_mut()
+                    .map_or(Ok(()), |s| s.write_all(line.as_bytes()));
+                if written.is_err() {
+                    // The line is lost along with the child that was supposed to get it.
+                    counters::EXEC_DROPPED.inc();
+                    break;
+                }
+            }
+            // Closing stdin first, as some programs only exit at end of input.
+            drop(stdin);
+            let status = match child.wait() {
+                Ok(status) => status.to_string(),
+                Err(e) => e.to_string(),
+            };
+            self.complain(
+                SyslogSeverity::SEV_WARNING,
+                format!("{} exited ({}), restarting", self.command[0], status),
+            );
+            if started.elapsed() > MAX_BACKOFF {
+                backoff = MIN_BACKOFF;
+            }
+            std::thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
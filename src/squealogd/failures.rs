@@ -0,0 +1,150 @@
+//! Messages that couldn't be received or stored. The daemon never stops over one: the message
+//! is dropped (or kept to try again, see `storage::Spill`), counted by what went wrong, and
+//! logged, at most once a minute per kind with how many more there were since.
+
+use crate::squealogd::counters::{self, Counter};
+use crate::squealogd::internal;
+use crate::storage::{Sqlite, Storage};
+use rusqlite::ErrorCode;
+use std::time::{Duration, Instant};
+use syslog_loose::SyslogSeverity;
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    /// Another connection held the lock for longer than the busy timeout.
+    Busy,
+    Constraint,
+    /// Disk full, I/O errors and the like.
+    Storage,
+    Other,
+    /// Reading from a source.
+    Recv,
+}
+
+impl Kind {
+    fn of(e: &rusqlite::Error) -> Kind {
+        let code = match e {
+            rusqlite::Error::SqliteFailure(e, _) => e.code,
+            _ => return Kind::Other,
+        };
+        match code {
+            ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked => Kind::Busy,
+            ErrorCode::ConstraintViolation => Kind::Constraint,
+            ErrorCode::DiskFull
+            | ErrorCode::SystemIoFailure
+            | ErrorCode::ReadOnly
+            | ErrorCode::CannotOpen
+            | ErrorCode::DatabaseCorrupt
+            | ErrorCode::NotADatabase => Kind::Storage,
+            _ => Kind::Other,
+        }
+    }
+
+    fn counter(self) -> &'static Counter {
+        match self {
+            Kind::Busy => &counters::INSERT_BUSY_FAILED,
+            Kind::Constraint => &counters::INSERT_CONSTRAINT_FAILED,
+            Kind::Storage => &counters::INSERT_STORAGE_FAILED,
+            Kind::Other => &counters::INSERT_OTHER_FAILED,
+            Kind::Recv => &counters::RECV_FAILED,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Failures {
+    /// By kind: when one was last logged, and how many weren't since.
+    reported: [Option<Instant>; 5],
+    suppressed: [u64; 5],
+}
+
+impl Failures {
+    pub fn insert_failed(
+        &mut self,
+        socket: &str,
+        e: &rusqlite::Error,
+        internal: &internal::Sender,
+    ) {
+        self.failed(Kind::of(e), internal, |more| {
+            format!(
+                "could not store a message from {}, dropped it: {}{}",
+                socket, e, more
+            )
+        });
+    }
+
+    /// A message that couldn't be stored for now, and was kept to try again.
+    pub fn insert_spilled(
+        &mut self,
+        socket: &str,
+        e: &rusqlite::Error,
+        internal: &internal::Sender,
+    ) {
+        self.failed(Kind::of(e), internal, |more| {
+            format!(
+                "could not store a message from {}, keeping it and the ones after it to try \
+                 again: {}{}",
+                socket, e, more
+            )
+        });
+    }
+
+    /// Committing a batch failed, which undid its `rows` messages. They were kept to try again.
+    pub fn commit_failed(&mut self, rows: usize, e: &rusqlite::Error, internal: &internal::Sender) {
+        self.failed(Kind::of(e), internal, |more| {
+            format!(
+                "could not store the {} messages received together, keeping them to try \
+                 again: {}{}",
+                rows, e, more
+            )
+        });
+    }
+
+    /// Trying again failed, with `left` messages still waiting. Unless it failed for good,
+    /// which costs the one message that it failed on.
+    pub fn retry_failed(&mut self, left: usize, e: &rusqlite::Error, internal: &internal::Sender) {
+        self.failed(Kind::of(e), internal, |more| {
+            if Sqlite::is_transient(e) {
+                format!(
+                    "could not store the {} messages kept to try again yet: {}{}",
+                    left, e, more
+                )
+            } else {
+                format!(
+                    "could not store a message kept to try again, dropped it: {}{}",
+                    e, more
+                )
+            }
+        });
+    }
+
+    pub fn recv_failed(&mut self, socket: &str, e: &std::io::Error, internal: &internal::Sender) {
+        self.failed(Kind::Recv, internal, |more| {
+            format!("could not receive from {}: {}{}", socket, e, more)
+        });
+    }
+
+    /// `msg` gets what to say about the ones that weren't logged.
+    fn failed(
+        &mut self,
+        kind: Kind,
+        internal: &internal::Sender,
+        msg: impl FnOnce(&str) -> String,
+    ) {
+        kind.counter().inc();
+        let i = kind as usize;
+        let now = Instant::now();
+        if matches!(self.reported[i], Some(at) if now < at + REPORT_INTERVAL) {
+            self.suppressed[i] += 1;
+            return;
+        }
+        let more = match std::mem::take(&mut self.suppressed[i]) {
+            0 => String::new(),
+            n => format!(" ({} more since the last one)", n),
+        };
+        self.reported[i] = Some(now);
+        internal.log(SyslogSeverity::SEV_ERR, msg(&more));
+    }
+}
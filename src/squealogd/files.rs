@@ -0,0 +1,195 @@
+//! Plain text files in the traditional syslogd format, for tools (and people) that want
+//! `/var/log/auth.log` and friends:
+//!
+//! ```toml
+//! [[file]]
+//! select = "auth,authpriv.*"
+//! path = "/var/log/auth.log"
+//! ```
+//!
+//! Writes are buffered and flushed within a second, except for crit and worse, which are
+//! flushed and fsynced right away. Files are reopened on SIGHUP, for newsyslog/logrotate.
+//! A file that can't be written to is counted and skipped, it doesn't affect the database.
+
+use crate::selector::Selector;
+use crate::squealogd::config;
+use crate::squealogd::counters;
+use crate::squealogd::internal;
+use crate::squealogd::output::{self, Outgoing, Output};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use syslog_loose::SyslogSeverity;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+struct LogFile {
+    path: PathBuf,
+    file: Option<BufWriter<File>>,
+    /// When the oldest unflushed write happened.
+    dirty: Option<Instant>,
+    /// So a broken file gets complained about once rather than for every message.
+    failing: bool,
+}
+
+impl LogFile {
+    fn open(&mut self) -> io::Result<&mut BufWriter<File>> {
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .mode(0o640)
+                .open(&self.path)?;
+            self.file = Some(BufWriter::new(file));
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    fn result(&mut self, r: io::Result<()>) {
+        match r {
+            Ok(()) => self.failing = false,
+            Err(e) => {
+                counters::FILE_WRITE_FAILED.inc();
+                if !self.failing {
+                    internal::log(
+                        SyslogSeverity::SEV_ERR,
+                        format!("could not write to {:?}: {}", self.path, e),
+                    );
+                }
+                self.failing = true;
+                // Start over with a fresh file handle next time.
+                self.file = None;
+                self.dirty = None;
+            }
+        }
+    }
+
+    fn write(&mut self, line: &str, sync: bool) {
+        let r = self.open().and_then(|f| {
+            f.write_all(line.as_bytes())?;
+            if sync {
+                f.flush()?;
+                f.get_ref().sync_data()?;
+            }
+            Ok(())
+        });
+        if r.is_ok() && !sync {
+            self.dirty.get_or_insert_with(Instant::now);
+        } else {
+            self.dirty = None;
+        }
+        self.result(r);
+    }
+
+    fn flush(&mut self) {
+        self.dirty = None;
+        if let Some(ref mut f) = self.file {
+            let r = f.flush();
+            self.result(r);
+        }
+    }
+}
+
+pub struct FileOutput {
+    rules: Vec<(Selector, usize)>,
+    files: Vec<LogFile>,
+    hostname: String,
+    reopen: Arc<AtomicBool>,
+    line: String,
+}
+
+impl FileOutput {
+    pub fn new(rules: &[config::FileRule]) -> anyhow::Result<FileOutput> {
+        let mut files: Vec<LogFile> = vec![];
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let i = match files.iter().position(|f| f.path == rule.path) {
+                    Some(i) => i,
+                    None => {
+                        files.push(LogFile {
+                            path: rule.path.clone(),
+                            file: None,
+                            dirty: None,
+                            failing: false,
+                        });
+                        files.len() - 1
+                    }
+                };
+                (rule.select, i)
+            })
+            .collect();
+        let reopen = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, reopen.clone())?;
+        Ok(FileOutput {
+            rules,
+            files,
+            hostname: crate::sys::hostname()?,
+            reopen,
+            line: String::new(),
+        })
+    }
+}
+
+impl Output for FileOutput {
+    fn send(&mut self, out: &Outgoing) {
+        let (fac, sev) = (out.facility(), out.severity());
+        let targets: Vec<usize> = self
+            .rules
+            .iter()
+            .filter(|(select, _)| select.matches(fac, sev))
+            .map(|&(_, i)| i)
+            .collect();
+        if targets.is_empty() {
+            return;
+        }
+        output::rfc3164(&mut self.line, out, &self.hostname);
+        let sync = matches!(sev, Some(0..=2));
+        for (n, &i) in targets.iter().enumerate() {
+            // A file listed in more than one matching rule still gets the line once.
+            if !targets[..n].contains(&i) {
+                self.files[i].write(&self.line, sync);
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.reopen.swap(false, Ordering::SeqCst) {
+            self.reopen();
+        }
+        for file in &mut self.files {
+            if file.dirty.is_some_and(|t| t.elapsed() >= FLUSH_INTERVAL) {
+                file.flush();
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        for file in &mut self.files {
+            file.flush();
+        }
+    }
+
+    fn reopen(&mut self) {
+        for file in &mut self.files {
+            file.flush();
+            file.file = None;
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.files
+            .iter()
+            .filter_map(|f| f.dirty)
+            .min()
+            .map(|t| t + FLUSH_INTERVAL)
+    }
+}
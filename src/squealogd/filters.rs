@@ -0,0 +1,165 @@
+//! Rules for messages that aren't worth storing at all, checked before anything else sees them:
+//!
+//! ```toml
+//! [[filter]]
+//! name = "healthcheck"
+//! socket = "log"
+//! appname = "nginx"
+//! regex = "GET /healthz "
+//!
+//! [[filter]]
+//! action = "accept"
+//! select = "*.warning"
+//!
+//! [[filter]]
+//! select = "*.debug"
+//! ```
+//!
+//! The first rule that matches decides, and a message no rule matches is kept. Every condition
+//! of a rule has to match, a rule without any matches everything. Dropped messages are counted
+//! per rule name in the `filter_stats` table, which `squealog stats` shows. The rules are read
+//! again on SIGHUP. They're the `filter` enricher, see `enrich`.
+
+use crate::selector::Selector;
+use crate::squealogd::config;
+use crate::squealogd::enrich::{Action, Enricher, Record, SourceInfo};
+use rusqlite::Connection;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How often the drop counts are written to the database.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Rule {
+    name: String,
+    accept: bool,
+    select: Selector,
+    socket: Option<String>,
+    appname: Option<String>,
+    hostname: Option<String>,
+    regex: Option<regex::Regex>,
+}
+
+impl Rule {
+    fn matches(&self, socket: &str, rec: &Record) -> bool {
+        self.select.matches(
+            rec.msg.facility.map(|f| f as u8),
+            rec.msg.severity.map(|s| s as u8),
+        ) && self.socket.as_deref().is_none_or(|s| s == socket)
+            && self
+                .appname
+                .as_deref()
+                .is_none_or(|a| rec.appname() == Some(a))
+            && self
+                .hostname
+                .as_deref()
+                .is_none_or(|h| rec.msg.hostname == Some(h))
+            && self.regex.as_ref().is_none_or(|r| r.is_match(rec.text()))
+    }
+}
+
+pub struct Filters {
+    rules: Vec<Rule>,
+    /// Drops not written to the database yet, by rule index.
+    pending: Vec<Cell<u64>>,
+    since_flush: Cell<Option<Instant>>,
+}
+
+impl Filters {
+    pub fn new(cfg: &[config::Filter]) -> anyhow::Result<Filters> {
+        let rules = cfg
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                Ok(Rule {
+                    name: rule
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| format!("filter {}", i + 1)),
+                    accept: rule.action == config::FilterAction::Accept,
+                    select: rule.select,
+                    socket: rule.socket.clone(),
+                    appname: rule.appname.clone(),
+                    hostname: rule.hostname.clone(),
+                    regex: rule.regex.as_deref().map(regex::Regex::new).transpose()?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Filters {
+            pending: vec![Cell::new(0); rules.len()],
+            rules,
+            since_flush: Cell::new(None),
+        })
+    }
+
+    /// Takes over the counts that weren't written yet, for rules that are still there.
+    pub fn replace(&mut self, new: Filters) {
+        let counts: HashMap<&str, u64> = self
+            .rules
+            .iter()
+            .zip(&self.pending)
+            .map(|(r, n)| (&r.name[..], n.get()))
+            .collect();
+        for (rule, n) in new.rules.iter().zip(&new.pending) {
+            n.set(counts.get(&rule.name[..]).copied().unwrap_or(0));
+        }
+        new.since_flush.set(self.since_flush.get());
+        *self = new;
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.since_flush.get().map(|t| t + FLUSH_INTERVAL)
+    }
+
+    /// Adds the drop counts to `filter_stats` once they've been waiting long enough.
+    pub fn flush(&mut self, conn: &Connection) -> rusqlite::Result<()> {
+        if self
+            .since_flush
+            .get()
+            .is_none_or(|t| t.elapsed() < FLUSH_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.write(conn)
+    }
+
+    /// Adds the drop counts to `filter_stats` right away.
+    pub fn write(&mut self, conn: &Connection) -> rusqlite::Result<()> {
+        self.since_flush.set(None);
+        let now = chrono::Utc::now();
+        let mut stmt = conn.prepare_cached(
+            "INSERT INTO filter_stats (rule, dropped, last_time) VALUES (?, ?, ?)
+            ON CONFLICT (rule) DO UPDATE
+            SET dropped = dropped + excluded.dropped, last_time = excluded.last_time",
+        )?;
+        for (rule, n) in self.rules.iter().zip(&self.pending) {
+            if n.get() > 0 {
+                stmt.execute(rusqlite::params![rule.name, n.get() as i64, now])?;
+                n.set(0);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Enricher for Filters {
+    fn enrich(&self, source: &SourceInfo, rec: &mut Record) -> Action {
+        let i = match self
+            .rules
+            .iter()
+            .position(|r| r.matches(source.socket, rec))
+        {
+            Some(i) => i,
+            None => return Action::Keep,
+        };
+        if self.rules[i].accept {
+            return Action::Keep;
+        }
+        self.pending[i].set(self.pending[i].get() + 1);
+        if self.since_flush.get().is_none() {
+            self.since_flush.set(Some(Instant::now()));
+        }
+        Action::Drop
+    }
+}
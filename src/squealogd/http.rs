@@ -0,0 +1,235 @@
+//! A read-only JSON API, built with the `http` feature and enabled by setting `SQUEALOG_HTTP`.
+//!
+//! - `GET /query?since=-1h&severity=err&appname=sshd&limit=100`: rows, newest first.
+//!   Takes the same filters as the `squealog` command line (`since`, `until`, `boot`,
+//!   `severity`, `facility`, `appname`, `socket`, `pid`, `unit`, `grep`; lists can be repeated),
+//!   plus `severities=0,1,2` for a set of individual severities.
+//!   Page back with `before=<cursor.before>`, or poll for new rows (oldest first) with
+//!   `after=<cursor.after>`.
+//! - `GET /stats[?since=...]`: what `squealog stats --json` prints.
+//! - `GET /healthz`: whether the database can be read.
+//! - `GET /counters`: the daemon's counters, see `crate::squealogd::counters`.
+//! - `GET /`: a small web UI on top of `/query`, embedded in the binary.
+
+use crate::squealogd::counters;
+use crate::{
+    filter::{Facility, Filter, SeverityRange},
+    query::{self, Row},
+    stats::Stats,
+    time::TimeSpec,
+};
+use rusqlite::{Connection, OpenFlags};
+use serde_json::json;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 10000;
+
+const UI: &str = include_str!("ui.html");
+
+type Reply = Response<Cursor<Vec<u8>>>;
+
+/// `SQUEALOG_HTTP` is an `address:port`, or just a port to listen on localhost.
+pub fn listen_addr(spec: &str) -> String {
+    if spec.parse::<u16>().is_ok() {
+        format!("127.0.0.1:{}", spec)
+    } else {
+        spec.to_owned()
+    }
+}
+
+/// Starts serving on a thread of its own, with its own read-only connection.
+pub fn spawn(addr: &str, db: PathBuf) -> anyhow::Result<()> {
+    let server = Server::http(addr)
+        .map_err(|e| anyhow::format_err!("Could not listen on {}: {}", addr, e))?;
+    let conn = Connection::open_with_flags(&db, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    std::thread::Builder::new()
+        .name("http".to_owned())
+        .spawn(move || {
+            for request in server.incoming_requests() {
+                let reply = handle(&conn, &db, &request);
+                // Errors here mean the client went away.
+                let _ = request.respond(reply);
+            }
+        })?;
+    Ok(())
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes(&b"Content-Type"[..], value.as_bytes()).unwrap()
+}
+
+fn reply(status: u16, body: serde_json::Value) -> Reply {
+    Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(content_type("application/json"))
+}
+
+fn handle(conn: &Connection, db: &Path, request: &Request) -> Reply {
+    if *request.method() != Method::Get {
+        return reply(405, json!({ "error": "only GET is supported" }));
+    }
+    let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+    let params = parse_query(query);
+    let result = match path {
+        "/query" => Query::parse(conn, &params).map(|req| rows(conn, req)),
+        "/stats" => {
+            stats_since(&params).map(|since| Ok(Stats::collect(conn, db, since)?.to_json()))
+        }
+        "/healthz" => Ok(conn
+            .query_row("SELECT max(id) FROM log", [], |row| {
+                row.get::<_, Option<i64>>(0)
+            })
+            .map(|last| json!({ "ok": true, "last": last }))
+            .map_err(anyhow::Error::from)),
+        "/counters" => Ok(Ok(serde_json::Value::Object(
+            counters::ALL
+                .iter()
+                .map(|c| (c.name.to_owned(), c.get().into()))
+                .collect(),
+        ))),
+        "/" => {
+            return Response::from_string(UI).with_header(content_type("text/html; charset=utf-8"))
+        }
+        _ => return reply(404, json!({ "error": "not found" })),
+    };
+    match result {
+        Ok(Ok(body)) => reply(200, body),
+        Ok(Err(e)) => reply(500, json!({ "error": e.to_string() })),
+        Err(e) => reply(400, json!({ "error": e })),
+    }
+}
+
+/// Decodes `application/x-www-form-urlencoded` pairs.
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    fn decode(s: &str) -> String {
+        let bytes = s.as_bytes();
+        let mut out = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'+' => out.push(b' '),
+                b'%' if i + 2 < bytes.len() => {
+                    let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                    match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                        Some(b) => {
+                            out.push(b);
+                            i += 2;
+                        }
+                        None => out.push(b'%'),
+                    }
+                }
+                b => out.push(b),
+            }
+            i += 1;
+        }
+        String::from_utf8_lossy(&out).into_owned()
+    }
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(k), decode(v))
+        })
+        .collect()
+}
+
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: '{}'", key, value))
+}
+
+fn stats_since(
+    params: &[(String, String)],
+) -> Result<Option<chrono::DateTime<chrono::Utc>>, String> {
+    let mut since = None;
+    for (k, v) in params {
+        match k.as_str() {
+            "since" => since = Some(parse::<TimeSpec>(k, v)?.resolve()),
+            _ => return Err(format!("unknown parameter '{}'", k)),
+        }
+    }
+    Ok(since)
+}
+
+/// A parsed `/query` request.
+struct Query {
+    filter: Filter,
+    after: Option<i64>,
+    before: Option<i64>,
+    limit: usize,
+}
+
+impl Query {
+    fn parse(conn: &Connection, params: &[(String, String)]) -> Result<Query, String> {
+        let mut req = Query {
+            filter: Filter::default(),
+            after: None,
+            before: None,
+            limit: DEFAULT_LIMIT,
+        };
+        let filter = &mut req.filter;
+        for (k, v) in params {
+            match k.as_str() {
+                "since" => filter.since = Some(parse::<TimeSpec>(k, v)?.resolve()),
+                "until" => filter.until = Some(parse::<TimeSpec>(k, v)?.resolve()),
+                "boot" => {
+                    let boot = crate::boot::resolve(conn, v).map_err(|e| e.to_string())?;
+                    filter.boot = Some(boot);
+                }
+                "severity" | "priority" => filter.severity = Some(parse::<SeverityRange>(k, v)?),
+                "severities" => {
+                    for sev in v.split(',').filter(|s| !s.is_empty()) {
+                        filter.severities.push(parse(k, sev)?);
+                    }
+                }
+                "facility" => filter.facilities.push(parse::<Facility>(k, v)?),
+                "appname" => filter.appnames.push(v.clone()),
+                "socket" => filter.sockets.push(v.clone()),
+                "pid" => filter.pid = Some(parse(k, v)?),
+                "unit" => filter.unit = Some(v.clone()),
+                "grep" => filter.greps.push(v.clone()),
+                "after" => req.after = Some(parse(k, v)?),
+                "before" => req.before = Some(parse(k, v)?),
+                "limit" => req.limit = parse::<usize>(k, v)?.clamp(1, MAX_LIMIT),
+                _ => return Err(format!("unknown parameter '{}'", k)),
+            }
+        }
+        Ok(req)
+    }
+}
+
+/// Rows for `/query`, with the cursors to continue from.
+///
+/// Paging goes by id rather than OFFSET, so deep pages cost the same as the first one.
+fn rows(conn: &Connection, req: Query) -> anyhow::Result<serde_json::Value> {
+    // Polling for new rows reads forward from the cursor, everything else newest first.
+    let newest_first = req.after.is_none();
+    let mut rows: Vec<Row> = vec![];
+    query::select(
+        conn,
+        &req.filter,
+        req.after,
+        req.before,
+        newest_first,
+        Some(req.limit),
+        |row| {
+            rows.push(row);
+            Ok(())
+        },
+    )?;
+    let min = rows.iter().map(|r| r.id).min();
+    let max = rows.iter().map(|r| r.id).max();
+    Ok(json!({
+        "rows": rows.iter().map(Row::to_json).collect::<Vec<_>>(),
+        "more": rows.len() == req.limit,
+        "cursor": {
+            "before": min.or(req.before),
+            "after": max.or(req.after),
+        },
+    }))
+}
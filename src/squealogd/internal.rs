@@ -0,0 +1,110 @@
+//! Messages that come from inside the daemon rather than from a socket, like the stderr of
+//! exec hooks. Other threads send them here, and the main loop gets woken up to store them.
+//!
+//! The daemon's own warnings and errors are logged with `Sender::log`, or `log` where there's
+//! no sender at hand, as appname `squealogd` with the syslog facility. In the foreground they go
+//! to stderr as well. Failing to store one of them only ends up on stderr, so that it can't
+//! cause another one.
+
+use polling::Poller;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use syslog_loose::{Message, ProcId, Protocol, SyslogFacility, SyslogSeverity};
+
+/// The socket name for the daemon's own messages.
+pub const SOCKET: &str = "squealogd";
+
+pub struct Internal {
+    pub socket: &'static str,
+    pub facility: SyslogFacility,
+    pub appname: String,
+    pub pid: Option<i32>,
+    pub severity: SyslogSeverity,
+    pub msg: String,
+}
+
+impl Internal {
+    pub fn message(&self) -> Message<&str> {
+        Message {
+            protocol: Protocol::RFC3164,
+            facility: Some(self.facility),
+            severity: Some(self.severity),
+            timestamp: None,
+            hostname: None,
+            appname: Some(&self.appname),
+            procid: self.pid.map(ProcId::PID),
+            msgid: None,
+            structured_data: vec![],
+            msg: &self.msg,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Sender {
+    tx: mpsc::Sender<Internal>,
+    poller: Arc<Poller>,
+    /// The least severe of `log`'s messages that are kept, shared by all clones.
+    level: Arc<AtomicU8>,
+    stderr: bool,
+}
+
+impl Sender {
+    pub fn send(&self, msg: Internal) {
+        if self.tx.send(msg).is_ok() {
+            let _ = self.poller.notify();
+        }
+    }
+
+    /// Logs something about the daemon itself.
+    pub fn log(&self, severity: SyslogSeverity, msg: String) {
+        if severity as u8 > self.level.load(Ordering::Relaxed) {
+            return;
+        }
+        self.log_always(severity, msg);
+    }
+
+    pub fn set_level(&self, level: u8) {
+        self.level.store(level, Ordering::Relaxed);
+    }
+
+    /// Like `log`, for what was asked for and so doesn't care about the level.
+    pub fn log_always(&self, severity: SyslogSeverity, msg: String) {
+        if self.stderr {
+            eprintln!("squealogd: {}", msg);
+        }
+        self.send(Internal {
+            socket: SOCKET,
+            facility: SyslogFacility::LOG_SYSLOG,
+            appname: "squealogd".to_owned(),
+            pid: Some(std::process::id() as i32),
+            severity,
+            msg,
+        });
+    }
+}
+
+/// `stderr` is for running in the foreground, where someone might be watching.
+pub fn channel(poller: Arc<Poller>, level: u8, stderr: bool) -> (Sender, mpsc::Receiver<Internal>) {
+    let (tx, rx) = mpsc::channel();
+    let sender = Sender {
+        tx,
+        poller,
+        level: Arc::new(AtomicU8::new(level)),
+        stderr,
+    };
+    *GLOBAL.lock().unwrap() = Some(sender.clone());
+    (sender, rx)
+}
+
+/// For `log`, set up by `channel`.
+static GLOBAL: Mutex<Option<Sender>> = Mutex::new(None);
+
+/// `Sender::log` for code that doesn't have a sender, like the relay threads. Before there's a
+/// channel, this goes to stderr only.
+pub fn log(severity: SyslogSeverity, msg: String) {
+    match *GLOBAL.lock().unwrap() {
+        Some(ref sender) => sender.log(severity, msg),
+        None => eprintln!("squealogd: {}", msg),
+    }
+}
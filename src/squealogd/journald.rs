@@ -0,0 +1,179 @@
+//! Forwarding into systemd-journald with its native protocol, for Linux machines where the
+//! journal stays the authority:
+//!
+//! ```toml
+//! [journald]
+//! select = "*.info"
+//! ```
+//!
+//! Messages from the sockets in `skip_sockets` (by default `journal`, the usual name for
+//! `/run/systemd/journal/syslog`, where journald forwards to) aren't sent back, so nothing loops.
+
+use crate::selector::Selector;
+use crate::squealogd::config;
+use crate::squealogd::counters;
+use crate::squealogd::output::{Outgoing, Output};
+use std::fs::File;
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use syslog_loose::ProcId;
+
+const SOCKET: &str = "/run/systemd/journal/socket";
+
+pub struct Journald {
+    sock: UnixDatagram,
+    path: PathBuf,
+    select: Selector,
+    skip_sockets: Vec<String>,
+    buf: Vec<u8>,
+}
+
+/// Appends `KEY=value\n`, or the length-prefixed form for values with newlines in them.
+fn field(buf: &mut Vec<u8>, key: &str, value: &[u8]) {
+    buf.extend_from_slice(key.as_bytes());
+    if value.contains(&b'\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value);
+    buf.push(b'\n');
+}
+
+/// Journal field names are uppercase letters, digits and underscores, not starting with one.
+fn field_name(parts: &[&str]) -> String {
+    let mut name: String = parts
+        .join("_")
+        .chars()
+        .map(|c| match c {
+            'a'..='z' => c.to_ascii_uppercase(),
+            'A'..='Z' | '0'..='9' => c,
+            _ => '_',
+        })
+        .collect();
+    name.truncate(64);
+    name
+}
+
+/// What sd-journal does for messages too big for a datagram: put them into a sealed memfd and
+/// send that instead.
+fn send_memfd(sock: &UnixDatagram, path: &Path, data: &[u8]) -> io::Result<()> {
+    let fd = unsafe {
+        libc::memfd_create(
+            c"squealog".as_ptr(),
+            libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(data)?;
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+    if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    send_fd(sock, path, file.as_raw_fd())
+}
+
+fn send_fd(sock: &UnixDatagram, path: &Path, fd: RawFd) -> io::Result<()> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    let bytes = path.as_os_str().as_bytes();
+    if bytes.len() >= addr.sun_path.len() {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+    for (dst, &src) in addr.sun_path.iter_mut().zip(bytes) {
+        *dst = src as libc::c_char;
+    }
+    let fd_size = mem::size_of::<RawFd>() as u32;
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fd_size) } as usize];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut addr as *mut libc::sockaddr_un as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = control.len() as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fd_size) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+        if libc::sendmsg(sock.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+impl Journald {
+    pub fn new(cfg: &config::Journald) -> anyhow::Result<Journald> {
+        let sock = UnixDatagram::unbound()?;
+        sock.set_nonblocking(true)?;
+        Ok(Journald {
+            sock,
+            path: cfg.socket.clone().unwrap_or_else(|| SOCKET.into()),
+            select: cfg.select,
+            skip_sockets: cfg.skip_sockets.clone(),
+            buf: vec![],
+        })
+    }
+
+    fn format(&mut self, out: &Outgoing) {
+        let msg = out.msg;
+        let buf = &mut self.buf;
+        buf.clear();
+        field(buf, "MESSAGE", msg.msg.as_bytes());
+        let priority = out.severity().unwrap_or(5);
+        field(buf, "PRIORITY", priority.to_string().as_bytes());
+        if let Some(fac) = out.facility() {
+            field(buf, "SYSLOG_FACILITY", fac.to_string().as_bytes());
+        }
+        if let Some(app) = msg.appname {
+            field(buf, "SYSLOG_IDENTIFIER", app.as_bytes());
+        }
+        match &msg.procid {
+            Some(ProcId::PID(pid)) => field(buf, "SYSLOG_PID", pid.to_string().as_bytes()),
+            Some(ProcId::Name(name)) => field(buf, "SYSLOG_PID", name.as_bytes()),
+            None => (),
+        }
+        if let Some(host) = msg.hostname {
+            field(buf, "SQUEALOG_HOSTNAME", host.as_bytes());
+        }
+        field(buf, "SQUEALOG_SOCKET", out.socket.as_bytes());
+        for element in &msg.structured_data {
+            for (k, v) in &element.params {
+                field(buf, &field_name(&["SD", element.id, *k]), v.as_bytes());
+            }
+        }
+    }
+}
+
+impl Output for Journald {
+    fn send(&mut self, out: &Outgoing) {
+        if self.skip_sockets.iter().any(|s| s == out.socket)
+            || !self.select.matches(out.facility(), out.severity())
+        {
+            return;
+        }
+        self.format(out);
+        let sent = match self.sock.send_to(&self.buf, &self.path) {
+            Err(e)
+                if e.raw_os_error() == Some(libc::EMSGSIZE)
+                    || e.raw_os_error() == Some(libc::ENOBUFS) =>
+            {
+                send_memfd(&self.sock, &self.path, &self.buf)
+            }
+            r => r.map(|_| ()),
+        };
+        match sent {
+            Ok(()) => counters::JOURNALD_SENT.inc(),
+            Err(_) => counters::JOURNALD_FAILED.inc(),
+        }
+    }
+}
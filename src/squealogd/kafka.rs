@@ -0,0 +1,99 @@
+//! Publishing messages to Kafka as JSON (needs the `kafka` feature):
+//!
+//! ```toml
+//! [kafka]
+//! brokers = ["kafka1.example.com:9092", "kafka2.example.com:9092"]
+//! topic = "syslog"
+//! acks = "all"
+//! compression = "zstd"
+//! ```
+//!
+//! Messages are keyed by hostname, so one host's messages stay in order within a partition.
+//! librdkafka does the sending and retrying on its own threads; when its queue (`queue`
+//! messages) is full because the brokers are away, messages are dropped and counted instead of
+//! holding up ingestion. Delivery reports feed the `kafka_sent` and `kafka_failed` counters.
+
+use crate::selector::Selector;
+use crate::squealogd::config;
+use crate::squealogd::counters;
+use crate::squealogd::output::{self, Outgoing, Output};
+use rdkafka::config::ClientConfig;
+use rdkafka::error::KafkaError;
+use rdkafka::producer::{BaseRecord, DeliveryResult, Producer, ProducerContext, ThreadedProducer};
+use rdkafka::types::RDKafkaErrorCode;
+use rdkafka::ClientContext;
+use std::time::Duration;
+
+struct Reports;
+
+impl ClientContext for Reports {}
+
+impl ProducerContext for Reports {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+        match result {
+            Ok(_) => counters::KAFKA_SENT.inc(),
+            Err(_) => counters::KAFKA_FAILED.inc(),
+        }
+    }
+
+    fn finish(&mut self) {
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        // Whatever isn't delivered by then is counted as failed by the reports.
+        let _ = self.producer.flush(Duration::from_secs(5));
+    }
+}
+
+pub struct Kafka {
+    producer: ThreadedProducer<Reports>,
+    topic: String,
+    select: Selector,
+    hostname: String,
+    line: Vec<u8>,
+}
+
+impl Kafka {
+    pub fn new(cfg: &config::Kafka) -> anyhow::Result<Kafka> {
+        let mut client = ClientConfig::new();
+        client
+            .set("bootstrap.servers", cfg.brokers.join(","))
+            .set("acks", &cfg.acks)
+            .set("compression.codec", &cfg.compression)
+            .set("queue.buffering.max.messages", cfg.queue.to_string());
+        for (k, v) in &cfg.options {
+            client.set(k, v);
+        }
+        // Doesn't connect yet, so a broker that's down now is no reason not to start.
+        let producer = client.create_with_context(Reports)?;
+        Ok(Kafka {
+            producer,
+            topic: cfg.topic.clone(),
+            select: cfg.select,
+            hostname: crate::sys::hostname()?,
+            line: vec![],
+        })
+    }
+}
+
+impl Output for Kafka {
+    fn send(&mut self, out: &Outgoing) {
+        if !self.select.matches(out.facility(), out.severity()) {
+            return;
+        }
+        self.line.clear();
+        let _ = serde_json::to_writer(&mut self.line, &output::json(out));
+        let key = out.msg.hostname.unwrap_or(&self.hostname);
+        let record = BaseRecord::to(&self.topic).key(key).payload(&self.line);
+        match self.producer.send(record) {
+            Ok(()) => (),
+            Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _)) => {
+                counters::KAFKA_DROPPED.inc()
+            }
+            Err(_) => counters::KAFKA_FAILED.inc(),
+        }
+    }
+}
@@ -0,0 +1,430 @@
+//! Sockets squealogd binds itself when it wasn't started with socket activation:
+//!
+//! ```toml
+//! [[listen]]
+//! name = "log"
+//! unix = "/var/run/log"
+//!
+//! [[listen]]
+//! name = "udp"
+//! udp = "[::]:514"
+//! ```
+//!
+//! The name is what messages are stored with as their socket, same as `LISTEN_FDNAMES`. Unix
+//! sockets are writable by everyone unless `mode` says otherwise, and removed on shutdown.
+//! `buffer` (like `16K`) is the biggest datagram read from a socket, by default 64K for UDP and
+//! 8K for unix sockets; it also applies to an activated socket with the same name. So does
+//! `parser`, the name of what turns its datagrams into messages (see `crate::payload`),
+//! `syslog` by default, and `strict`: datagrams the parser can't make anything of are stored
+//! as they are unless it's `true`, when they're dropped (and counted as `payload_dropped`).
+//!
+//! Activated sockets are only used when `LISTEN_PID` (if set) is this process, and only the
+//! descriptors that really are datagram sockets: a stale environment inherited through some
+//! wrapper would otherwise have arbitrary descriptors polled.
+
+use crate::payload::{PayloadParser, Registry};
+use crate::squealogd::config;
+use crate::squealogd::internal;
+use crate::squealogd::preflight::{self, Problem};
+use crate::squealogd::upgrade;
+use crate::transport::LogTransport;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::sync::Arc;
+use syslog_loose::SyslogSeverity;
+
+/// The biggest datagram to read from the socket, if the config says.
+pub fn buffer_size(cfg: &config::Listen) -> anyhow::Result<Option<usize>> {
+    let size = match cfg.buffer {
+        Some(ref size) => size,
+        None => return Ok(None),
+    };
+    match crate::stats::parse_size(size) {
+        Some(n) if n > 0 && n <= 1 << 24 => Ok(Some(n as usize)),
+        _ => anyhow::bail!("[[listen]] {}: invalid buffer size '{}'", cfg.name, size),
+    }
+}
+
+/// The parser for the socket's datagrams, the one the config names or syslog's.
+pub fn parser(
+    cfg: Option<&config::Listen>,
+    registry: &Registry,
+) -> anyhow::Result<Arc<dyn PayloadParser>> {
+    let name = cfg.and_then(|c| c.parser.as_deref()).unwrap_or("syslog");
+    match registry.get(name) {
+        Some(parser) => Ok(parser),
+        None => anyhow::bail!(
+            "[[listen]] {}: unknown parser '{}' (there are {})",
+            cfg.map_or("", |c| c.name.as_str()),
+            name,
+            registry.names().collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+/// Where systemd's (and systemfd's) descriptors start.
+const LISTEN_FDS_START: RawFd = 3;
+
+pub struct Bound {
+    pub name: String,
+    pub xport: LogTransport,
+    /// Unix sockets' paths, to remove on shutdown.
+    pub path: Option<PathBuf>,
+}
+
+pub fn bind(listen: &[config::Listen]) -> anyhow::Result<Vec<Bound>> {
+    let mut bound = vec![];
+    for cfg in listen {
+        let (xport, path) = match (&cfg.unix, &cfg.udp) {
+            (Some(path), None) => {
+                // Left over from the last run, nothing can be listening on it anymore.
+                let _ = std::fs::remove_file(path);
+                let sock = UnixDatagram::bind(path)
+                    .map_err(|e| anyhow::format_err!("Could not bind {:?}: {}", path, e))?;
+                std::fs::set_permissions(
+                    path,
+                    std::fs::Permissions::from_mode(cfg.mode.unwrap_or(0o666)),
+                )?;
+                (LogTransport::UnixDgram(sock), Some(path.clone()))
+            }
+            (None, Some(addr)) => {
+                let sock = std::net::UdpSocket::bind(addr.as_str())
+                    .map_err(|e| anyhow::format_err!("Could not bind {}: {}", addr, e))?;
+                (LogTransport::Udp(sock), None)
+            }
+            _ => anyhow::bail!("[[listen]] {} needs exactly one of unix or udp", cfg.name),
+        };
+        bound.push(Bound {
+            name: cfg.name.clone(),
+            xport,
+            path,
+        });
+    }
+    Ok(bound)
+}
+
+/// Parser names that aren't.
+pub fn check_parsers(listen: &[config::Listen]) -> Vec<Problem> {
+    let registry = Registry::default();
+    listen
+        .iter()
+        .filter_map(|cfg| parser(Some(cfg), &registry).err())
+        .map(|e| Problem::new(e.to_string(), "use one of the parsers there are"))
+        .collect()
+}
+
+/// What would keep `bind` (or the activated sockets) from working.
+pub fn check(listen: &[config::Listen], activation: &Activation) -> Vec<Problem> {
+    let mut problems = check_parsers(listen);
+    match activation {
+        Activation::Fds { names, count } => {
+            for (i, name) in names.iter().take(*count).enumerate() {
+                let fd = LISTEN_FDS_START + i as RawFd;
+                let what = match socket_kind(fd) {
+                    Some((libc::SOCK_DGRAM, family))
+                        if [libc::AF_UNIX, libc::AF_INET, libc::AF_INET6]
+                            .iter()
+                            .any(|&f| family == f as libc::sa_family_t) =>
+                    {
+                        continue
+                    }
+                    Some((libc::SOCK_STREAM, _)) => "a stream socket",
+                    Some(_) => "not a unix or udp datagram socket",
+                    None => "not a socket",
+                };
+                problems.push(Problem::new(
+                    format!("Activated descriptor {} ({}) is {}", fd, name, what),
+                    "squealogd only reads datagrams: pass it ListenDatagram= sockets (not \
+                    ListenStream=), or leave it out of the socket unit",
+                ));
+            }
+            return problems;
+        }
+        Activation::None | Activation::Stale(_) => (),
+    }
+    if listen.is_empty() {
+        problems.push(Problem::new(
+            "No sockets to read from".to_owned(),
+            "use socket activation (with LISTEN_FDNAMES) or add [[listen]] sockets to the config",
+        ));
+    }
+    for cfg in listen {
+        match (&cfg.unix, &cfg.udp) {
+            (Some(path), None) => problems.extend(check_unix(path)),
+            (None, Some(addr)) => problems.extend(check_udp(&cfg.name, addr)),
+            _ => problems.push(Problem::new(
+                format!("[[listen]] {} needs exactly one of unix or udp", cfg.name),
+                "give it either a path (unix) or an address (udp)",
+            )),
+        }
+    }
+    problems
+}
+
+fn check_unix(path: &std::path::Path) -> Option<Problem> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::FileTypeExt;
+    let addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    if path.as_os_str().as_bytes().len() >= addr.sun_path.len() {
+        return Some(Problem::new(
+            format!("The socket path {:?} is too long", path),
+            "unix socket paths can't be longer than the kernel's limit, use a shorter one",
+        ));
+    }
+    // Replaced when binding, which is only fine for a socket left over from the last run.
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if !meta.file_type().is_socket() => {
+            return Some(Problem::new(
+                format!("{:?} exists and is not a socket", path),
+                "move it out of the way, or bind the socket somewhere else",
+            ))
+        }
+        _ => (),
+    }
+    preflight::directory("the socket", path)
+}
+
+fn check_udp(name: &str, addr: &str) -> Option<Problem> {
+    use std::net::ToSocketAddrs;
+    let addrs: Vec<_> = match addr.to_socket_addrs() {
+        Ok(addrs) => addrs.collect(),
+        Err(e) => {
+            return Some(Problem::new(
+                format!("[[listen]] {}: invalid address {}: {}", name, addr, e),
+                "write it as address:port, like [::]:514 or 0.0.0.0:514",
+            ))
+        }
+    };
+    match addrs.iter().map(|a| a.port()).find(|&p| p != 0) {
+        Some(port) if !may_bind(port) => Some(Problem::new(
+            format!("[[listen]] {}: binding port {} needs root", name, port),
+            "start squealogd as root (it gives up root after binding), or use a port above 1023",
+        )),
+        _ => None,
+    }
+}
+
+/// Whether this process can bind to `port`.
+fn may_bind(port: u16) -> bool {
+    if port >= 1024 || unsafe { libc::geteuid() } == 0 {
+        return true;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        const CAP_NET_BIND_SERVICE: u32 = 10;
+        let start = std::fs::read_to_string("/proc/sys/net/ipv4/ip_unprivileged_port_start")
+            .ok()
+            .and_then(|s| s.trim().parse::<u16>().ok());
+        if start.is_some_and(|start| port >= start) {
+            return true;
+        }
+        let capable = std::fs::read_to_string("/proc/self/status")
+            .ok()
+            .and_then(|status| {
+                let caps = status.lines().find_map(|l| l.strip_prefix("CapEff:"))?;
+                u64::from_str_radix(caps.trim(), 16).ok()
+            })
+            .is_some_and(|caps| caps & (1 << CAP_NET_BIND_SERVICE) != 0);
+        if capable {
+            return true;
+        }
+    }
+    false
+}
+
+/// The path a unix socket is bound to.
+pub fn unix_path(fd: RawFd) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+    let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockname(
+            fd,
+            &mut addr as *mut libc::sockaddr_un as *mut libc::sockaddr,
+            &mut len,
+        )
+    };
+    if ret == -1 || addr.sun_family != libc::AF_UNIX as libc::sa_family_t {
+        return None;
+    }
+    let path: Vec<u8> = addr
+        .sun_path
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8)
+        .collect();
+    if path.is_empty() {
+        return None;
+    }
+    Some(std::ffi::OsStr::from_bytes(&path).into())
+}
+
+/// A datagram socket that was passed in, `None` (after logging why) if it isn't one.
+fn datagram(fd: RawFd, name: &str, internal: &internal::Sender) -> Option<LogTransport> {
+    match socket_kind(fd) {
+        Some((libc::SOCK_DGRAM, family)) if family == libc::AF_UNIX as libc::sa_family_t => {
+            Some(LogTransport::UnixDgram(unsafe {
+                UnixDatagram::from_raw_fd(fd)
+            }))
+        }
+        Some((libc::SOCK_DGRAM, family))
+            if family == libc::AF_INET as libc::sa_family_t
+                || family == libc::AF_INET6 as libc::sa_family_t =>
+        {
+            Some(LogTransport::Udp(unsafe {
+                std::net::UdpSocket::from_raw_fd(fd)
+            }))
+        }
+        _ => {
+            // Not ours to close, but not passed on to anything either.
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+            internal.log(
+                SyslogSeverity::SEV_WARNING,
+                format!(
+                    "descriptor {} ({}) is not a unix or udp datagram socket, skipping it",
+                    fd, name
+                ),
+            );
+            None
+        }
+    }
+}
+
+/// The sources an upgrade handed over, with their paths if they were bound from the config,
+/// and how many messages they got so far.
+pub fn adopt(entries: Vec<upgrade::Entry>, internal: &internal::Sender) -> Vec<(Bound, u64)> {
+    let mut bound = vec![];
+    for entry in entries {
+        let path = match entry.kind {
+            upgrade::Kind::Bound => unix_path(entry.fd),
+            _ => None,
+        };
+        if let Some(xport) = datagram(entry.fd, &entry.name, internal) {
+            bound.push((
+                Bound {
+                    name: entry.name,
+                    xport,
+                    path,
+                },
+                entry.received,
+            ));
+        }
+    }
+    bound
+}
+
+/// What socket activation passed, if anything.
+pub enum Activation {
+    None,
+    /// For another process: `LISTEN_PID` says which.
+    Stale(String),
+    Fds {
+        names: Vec<String>,
+        count: usize,
+    },
+}
+
+/// Takes the activation variables out of the environment, so that nothing started later
+/// inherits them. Must come before `--daemonize` forks, as the pid they were meant for is the
+/// one from before (and before any threads are started).
+pub fn activation() -> Activation {
+    let names = std::env::var("LISTEN_FDNAMES").ok();
+    let pid = std::env::var("LISTEN_PID").ok();
+    let count = std::env::var("LISTEN_FDS").ok();
+    for var in ["LISTEN_FDNAMES", "LISTEN_PID", "LISTEN_FDS"] {
+        std::env::remove_var(var);
+    }
+    let names = match names {
+        Some(names) => names,
+        None => return Activation::None,
+    };
+    if let Some(pid) = pid.filter(|pid| *pid != std::process::id().to_string()) {
+        return Activation::Stale(pid);
+    }
+    let names: Vec<String> = names.split(':').map(str::to_owned).collect();
+    // systemfd doesn't set LISTEN_FDS, the names say how many there are then.
+    let count = count.and_then(|n| n.parse().ok()).unwrap_or(names.len());
+    Activation::Fds { names, count }
+}
+
+/// The type of socket `fd` is, if it's one at all.
+fn socket_kind(fd: RawFd) -> Option<(libc::c_int, libc::sa_family_t)> {
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut st) } == -1 || st.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+        return None;
+    }
+    let mut ty: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            &mut ty as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret == -1 {
+        return None;
+    }
+    let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockname(
+            fd,
+            &mut addr as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+            &mut len,
+        )
+    };
+    if ret == -1 {
+        return None;
+    }
+    Some((ty, addr.ss_family))
+}
+
+impl Activation {
+    /// The activated sockets, or `None` if the config's have to be bound instead.
+    pub fn sockets(self, internal: &internal::Sender) -> Option<Vec<Bound>> {
+        let (names, count) = match self {
+            Activation::None => return None,
+            Activation::Stale(pid) => {
+                internal.log(
+                    SyslogSeverity::SEV_WARNING,
+                    format!(
+                        "LISTEN_PID is {}, not {}: ignoring the inherited descriptors",
+                        pid,
+                        std::process::id()
+                    ),
+                );
+                return None;
+            }
+            Activation::Fds { names, count } => (names, count),
+        };
+        if names.len() != count {
+            internal.log(
+                SyslogSeverity::SEV_WARNING,
+                format!(
+                    "LISTEN_FDNAMES has {} names for {} descriptors",
+                    names.len(),
+                    count
+                ),
+            );
+        }
+        let mut bound = vec![];
+        for (i, name) in names.into_iter().take(count).enumerate() {
+            let fd = LISTEN_FDS_START + i as RawFd;
+            let xport = match datagram(fd, &name, internal) {
+                Some(xport) => xport,
+                None => continue,
+            };
+            bound.push(Bound {
+                name,
+                xport,
+                path: None,
+            });
+        }
+        Some(bound)
+    }
+}
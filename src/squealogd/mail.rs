@@ -0,0 +1,236 @@
+//! Emailed digests through sendmail, for small sites without any alerting setup:
+//!
+//! ```toml
+//! [[mail]]
+//! to = ["root@example.com"]
+//! select = "*.crit"
+//! window = "5m"
+//! max_per_hour = 4
+//! ```
+//!
+//! The first match starts a digest, everything else that matches within `window` goes into
+//! it, then it's mailed as one message with `sendmail -t -i`. No more than `max_per_hour`
+//! mails go out; past that the digest keeps growing until one may be sent. `subject` and
+//! `body` are templates with `{hostname}`, `{count}`, `{first}`, `{last}` (the first and last
+//! message as log lines) and `{messages}` (all of them).
+
+use crate::squealogd::config;
+use crate::squealogd::exec;
+use crate::squealogd::internal;
+use crate::squealogd::matcher::Matcher;
+use crate::squealogd::output::{self, Outgoing, Output};
+use std::io::Write;
+use std::sync::mpsc::{self, SyncSender};
+use std::time::{Duration, Instant};
+use syslog_loose::SyslogSeverity;
+
+pub const DEFAULT_COMMAND: &[&str] = &["/usr/sbin/sendmail", "-t", "-i"];
+const DEFAULT_SUBJECT: &str = "{count} log messages on {hostname}";
+const DEFAULT_BODY: &str = "{messages}";
+const DEFAULT_WINDOW: Duration = Duration::from_secs(300);
+const DEFAULT_MAX_PER_HOUR: usize = 4;
+const HOUR: Duration = Duration::from_secs(3600);
+/// How many lines a digest holds; the rest are only counted.
+const MAX_LINES: usize = 1000;
+
+/// The messages collected for one mail.
+#[derive(Debug, Default)]
+pub struct Digest {
+    pub hostname: String,
+    pub lines: Vec<String>,
+    /// Including the ones that didn't fit into `lines`.
+    pub count: usize,
+    pub last: String,
+}
+
+impl Digest {
+    fn push(&mut self, line: &str) {
+        self.count += 1;
+        if self.lines.len() < MAX_LINES {
+            self.lines.push(line.to_owned());
+        }
+        self.last = line.to_owned();
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "hostname" => self.hostname.clone(),
+            "count" => self.count.to_string(),
+            "first" => self.lines.first().cloned().unwrap_or_default(),
+            "last" => self.last.clone(),
+            "messages" => {
+                let mut all: String = self.lines.iter().map(|l| format!("{}\n", l)).collect();
+                if self.count > self.lines.len() {
+                    all.push_str(&format!("... and {} more\n", self.count - self.lines.len()));
+                }
+                all
+            }
+            _ => return None,
+        })
+    }
+}
+
+/// Fills in a subject template; header values can't have line breaks in them.
+pub fn render_subject(template: &str, digest: &Digest) -> String {
+    output::fill(
+        template,
+        |name| digest.get(name),
+        |out, value| out.extend(value.chars().map(|c| if c.is_control() { ' ' } else { c })),
+    )
+}
+
+pub fn render_body(template: &str, digest: &Digest) -> String {
+    output::fill(
+        template,
+        |name| digest.get(name),
+        |out, value| out.push_str(value),
+    )
+}
+
+pub struct Mail {
+    matcher: Matcher,
+    to: String,
+    from: Option<String>,
+    subject: String,
+    body: String,
+    window: Duration,
+    max_per_hour: usize,
+    hostname: String,
+    line: String,
+    /// The digest being collected, with when it was started.
+    digest: Option<(Instant, Digest)>,
+    /// When the mails of the last hour went out.
+    sent: Vec<Instant>,
+    tx: SyncSender<String>,
+    internal: internal::Sender,
+}
+
+impl Mail {
+    pub fn new(cfg: &config::Mail, internal: internal::Sender) -> anyhow::Result<Mail> {
+        if cfg.to.is_empty() {
+            anyhow::bail!("mail rule without recipients");
+        }
+        let matcher = Matcher::new(cfg.select, cfg.appname.clone(), cfg.regex.as_deref())?;
+        let window = match cfg.window {
+            Some(ref w) => crate::time::parse_duration(w)
+                .and_then(|d| d.to_std().ok())
+                .ok_or_else(|| anyhow::format_err!("Invalid mail window '{}'", w))?,
+            None => DEFAULT_WINDOW,
+        };
+        let command = match cfg.command {
+            Some(ref command) if !command.is_empty() => command.clone(),
+            Some(_) => anyhow::bail!("mail rule with an empty command"),
+            None => DEFAULT_COMMAND.iter().map(|s| s.to_string()).collect(),
+        };
+        // Only a few mails an hour, anything queued beyond that is never getting out anyway.
+        let (tx, rx) = mpsc::sync_channel::<String>(4);
+        let log = internal.clone();
+        std::thread::Builder::new()
+            .name("mail".to_owned())
+            .spawn(move || {
+                for mail in rx {
+                    if let Err(e) = deliver(&command, &mail, &log) {
+                        log.log(
+                            SyslogSeverity::SEV_ERR,
+                            format!("could not mail a digest with {}: {}", command[0], e),
+                        );
+                    }
+                }
+            })?;
+        Ok(Mail {
+            matcher,
+            to: cfg.to.join(", "),
+            from: cfg.from.clone(),
+            subject: cfg
+                .subject
+                .clone()
+                .unwrap_or_else(|| DEFAULT_SUBJECT.to_owned()),
+            body: cfg.body.clone().unwrap_or_else(|| DEFAULT_BODY.to_owned()),
+            window,
+            max_per_hour: cfg.max_per_hour.unwrap_or(DEFAULT_MAX_PER_HOUR),
+            hostname: crate::sys::hostname()?,
+            line: String::new(),
+            digest: None,
+            sent: vec![],
+            tx,
+            internal,
+        })
+    }
+
+    fn compose(&self, digest: &Digest) -> String {
+        let mut mail = format!("To: {}\n", self.to);
+        if let Some(ref from) = self.from {
+            mail.push_str(&format!("From: {}\n", from));
+        }
+        mail.push_str(&format!(
+            "Subject: {}\nAuto-Submitted: auto-generated\nContent-Type: text/plain; charset=utf-8\n\n",
+            render_subject(&self.subject, digest)
+        ));
+        mail.push_str(&render_body(&self.body, digest));
+        mail
+    }
+}
+
+fn deliver(command: &[String], mail: &str, internal: &internal::Sender) -> Result<(), String> {
+    let mut child =
+        exec::spawn(command, &Default::default(), internal).map_err(|e| e.to_string())?;
+    let written = child.stdin.take().unwrap().write_all(mail.as_bytes());
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(status.to_string());
+    }
+    written.map_err(|e| e.to_string())
+}
+
+impl Output for Mail {
+    fn send(&mut self, out: &Outgoing) {
+        // Complaints about the mail command itself must not set off more mails.
+        if out.socket == internal::SOCKET
+            || out.socket == exec::SOCKET
+            || !self.matcher.matches(out)
+        {
+            return;
+        }
+        output::rfc3164(&mut self.line, out, &self.hostname);
+        let line = self.line.trim_end();
+        let hostname = &self.hostname;
+        let (_, digest) = self.digest.get_or_insert_with(|| {
+            (
+                Instant::now(),
+                Digest {
+                    hostname: hostname.clone(),
+                    ..Default::default()
+                },
+            )
+        });
+        digest.push(line);
+    }
+
+    fn tick(&mut self) {
+        match self.digest {
+            Some((started, _)) if started.elapsed() >= self.window => (),
+            _ => return,
+        }
+        self.sent.retain(|t| t.elapsed() < HOUR);
+        if self.sent.len() >= self.max_per_hour {
+            return;
+        }
+        let (_, digest) = self.digest.take().unwrap();
+        self.sent.push(Instant::now());
+        if self.tx.try_send(self.compose(&digest)).is_err() {
+            self.internal.log(
+                SyslogSeverity::SEV_WARNING,
+                format!("mail queue is full, dropping a digest of {}", digest.count),
+            );
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        let (started, _) = self.digest.as_ref()?;
+        let due = *started + self.window;
+        if self.sent.len() >= self.max_per_hour {
+            return Some(due.max(self.sent[0] + HOUR));
+        }
+        Some(due)
+    }
+}
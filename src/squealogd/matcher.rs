@@ -0,0 +1,34 @@
+//! Which messages a rule applies to, for the outputs with `select`/`appname`/`regex` options.
+
+use crate::selector::Selector;
+use crate::squealogd::output::Outgoing;
+use regex::Regex;
+
+pub struct Matcher {
+    select: Selector,
+    appname: Option<String>,
+    regex: Option<Regex>,
+}
+
+impl Matcher {
+    pub fn new(
+        select: Selector,
+        appname: Option<String>,
+        regex: Option<&str>,
+    ) -> Result<Matcher, regex::Error> {
+        Ok(Matcher {
+            select,
+            appname,
+            regex: regex.map(Regex::new).transpose()?,
+        })
+    }
+
+    pub fn matches(&self, out: &Outgoing) -> bool {
+        self.select.matches(out.facility(), out.severity())
+            && self
+                .appname
+                .as_deref()
+                .is_none_or(|a| out.msg.appname == Some(a))
+            && self.regex.as_ref().is_none_or(|r| r.is_match(out.msg.msg))
+    }
+}
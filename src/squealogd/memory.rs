@@ -0,0 +1,287 @@
+//! A bound on what the daemon holds in memory for outputs that can't keep up:
+//!
+//! ```toml
+//! [memory]
+//! max = "64M"
+//! ```
+//!
+//! The exec hooks' queues and the pubsub subscribers' pending output count the bytes they hold,
+//! and when the total goes over `max` (64 MiB by default) outputs shed in a fixed order, going
+//! one step further every second the total stays over: exec hooks drop their queued lines
+//! first, then the pubsub subscribers furthest behind are disconnected, largest queue first.
+//! What's shed is counted as `exec_dropped` and `pubsub_disconnected`. Messages are written to
+//! the database as they come in, so nothing is ever kept from being stored by this.
+
+use crate::squealogd::config;
+use crate::squealogd::internal;
+use crate::squealogd::output::Output;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use syslog_loose::SyslogSeverity;
+
+const DEFAULT_MAX: usize = 64 << 20;
+/// How long shedding one way gets before the next step, as some of it happens on other threads.
+const ESCALATE_AFTER: Duration = Duration::from_secs(1);
+
+pub struct Usage {
+    pub name: &'static str,
+    bytes: AtomicUsize,
+}
+
+impl Usage {
+    const fn new(name: &'static str) -> Usage {
+        Usage {
+            name,
+            bytes: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn add(&self, n: usize) {
+        self.bytes.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn sub(&self, n: usize) {
+        self.bytes.fetch_sub(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+}
+
+pub static EXEC: Usage = Usage::new("exec");
+pub static PUBSUB: Usage = Usage::new("pubsub");
+pub static ALL: &[&Usage] = &[&EXEC, &PUBSUB];
+
+pub fn total() -> usize {
+    ALL.iter().map(|u| u.get()).sum()
+}
+
+/// What outputs give up when over the cap, in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Queues,
+    Subscribers,
+}
+
+const STAGES: &[Stage] = &[Stage::Queues, Stage::Subscribers];
+
+pub struct Cap {
+    pub max: usize,
+    /// The stage reached while being over the cap, and when.
+    shedding: Option<(usize, Instant)>,
+}
+
+impl Cap {
+    pub fn new(cfg: Option<&config::Memory>) -> anyhow::Result<Cap> {
+        let max = match cfg.and_then(|c| c.max.as_deref()) {
+            Some(max) => crate::stats::parse_size(max)
+                .ok_or_else(|| anyhow::format_err!("[memory] max: invalid size '{}'", max))?
+                as usize,
+            None => DEFAULT_MAX,
+        };
+        Ok(Cap {
+            max,
+            shedding: None,
+        })
+    }
+
+    /// Called every round of the main loop, cheap unless over the cap.
+    pub fn enforce(&mut self, outputs: &mut [Box<dyn Output>], internal: &internal::Sender) {
+        if total() <= self.max {
+            self.shedding = None;
+            return;
+        }
+        self.enforce_at(Instant::now(), outputs, internal);
+    }
+
+    fn enforce_at(
+        &mut self,
+        now: Instant,
+        outputs: &mut [Box<dyn Output>],
+        internal: &internal::Sender,
+    ) {
+        let used = total();
+        if used <= self.max {
+            self.shedding = None;
+            return;
+        }
+        let stage = match self.shedding {
+            None => 0,
+            Some((stage, since)) if now >= since + ESCALATE_AFTER => {
+                (stage + 1).min(STAGES.len() - 1)
+            }
+            Some(_) => return,
+        };
+        if self.shedding.map(|(s, _)| s) != Some(stage) {
+            internal.log(
+                SyslogSeverity::SEV_WARNING,
+                format!(
+                    "holding {} for outputs, over the {} [memory] max: shedding {:?}",
+                    crate::stats::human_size(used as u64),
+                    crate::stats::human_size(self.max as u64),
+                    STAGES[stage]
+                ),
+            );
+        }
+        self.shedding = Some((stage, now));
+        for output in outputs.iter_mut() {
+            while total() > self.max && output.shed(STAGES[stage]) {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::squealogd::output::Outgoing;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use syslog_loose::{Message, Protocol};
+
+    type Shed = Rc<RefCell<Vec<String>>>;
+
+    /// An exec hook whose child stopped reading: everything stays queued.
+    struct StalledHook {
+        queued: usize,
+        shed: Shed,
+    }
+
+    impl Output for StalledHook {
+        fn send(&mut self, out: &Outgoing) {
+            self.queued += out.raw.len();
+            EXEC.add(out.raw.len());
+        }
+
+        fn shed(&mut self, stage: Stage) -> bool {
+            if stage != Stage::Queues || self.queued == 0 {
+                return false;
+            }
+            self.shed
+                .borrow_mut()
+                .push(format!("hook: {}", self.queued));
+            EXEC.sub(std::mem::take(&mut self.queued));
+            true
+        }
+    }
+
+    /// Subscribers that miss one in every so many messages, and so fall behind by different
+    /// amounts.
+    struct StalledSubscribers {
+        pending: Vec<(usize, usize)>,
+        sent: usize,
+        shed: Shed,
+    }
+
+    impl Output for StalledSubscribers {
+        fn send(&mut self, out: &Outgoing) {
+            self.sent += 1;
+            for (behind, misses) in self.pending.iter_mut() {
+                if self.sent.is_multiple_of(*misses) {
+                    *behind += out.raw.len();
+                    PUBSUB.add(out.raw.len());
+                }
+            }
+        }
+
+        fn shed(&mut self, stage: Stage) -> bool {
+            if stage != Stage::Subscribers || self.pending.is_empty() {
+                return false;
+            }
+            let slowest = (0..self.pending.len())
+                .max_by_key(|&i| self.pending[i].0)
+                .unwrap();
+            let (behind, misses) = self.pending.remove(slowest);
+            self.shed
+                .borrow_mut()
+                .push(format!("subscriber missing 1 in {}: {}", misses, behind));
+            PUBSUB.sub(behind);
+            true
+        }
+    }
+
+    #[test]
+    fn sheds_in_order_down_to_the_cap() {
+        let shed = Shed::default();
+        let mut outputs: Vec<Box<dyn Output>> = vec![
+            Box::new(StalledSubscribers {
+                pending: vec![(0, 1), (0, 2), (0, 4), (0, 8)],
+                sent: 0,
+                shed: shed.clone(),
+            }),
+            Box::new(StalledHook {
+                queued: 0,
+                shed: shed.clone(),
+            }),
+        ];
+        let (internal, logged) =
+            internal::channel(Arc::new(polling::Poller::new().unwrap()), 7, false);
+        let mut cap = Cap {
+            max: 100_000,
+            shedding: None,
+        };
+
+        // 1000 lines of 100 bytes: 100000 for the hook, and 100000, 50000, 25000 and 12500
+        // behind for the subscribers.
+        let raw = "x".repeat(100);
+        let msg = Message {
+            protocol: Protocol::RFC3164,
+            facility: None,
+            severity: None,
+            timestamp: None,
+            hostname: None,
+            appname: None,
+            procid: None,
+            msgid: None,
+            structured_data: vec![],
+            msg: &raw[..],
+        };
+        for id in 0..1000 {
+            for output in outputs.iter_mut() {
+                output.send(&Outgoing {
+                    id,
+                    socket: "local",
+                    raw: &raw,
+                    msg: &msg,
+                    recv_time: chrono::Utc::now(),
+                });
+            }
+        }
+        assert_eq!(total(), 287_500);
+
+        let start = Instant::now();
+        cap.enforce_at(start, &mut outputs, &internal);
+        assert_eq!(*shed.borrow(), ["hook: 100000"]);
+        assert_eq!(total(), 187_500);
+
+        // The subscribers get a second before it's their turn.
+        cap.enforce_at(start + Duration::from_millis(500), &mut outputs, &internal);
+        assert_eq!(shed.borrow().len(), 1);
+        cap.enforce_at(start + ESCALATE_AFTER, &mut outputs, &internal);
+        assert_eq!(
+            *shed.borrow(),
+            ["hook: 100000", "subscriber missing 1 in 1: 100000",]
+        );
+        assert_eq!(total(), 87_500);
+        assert!(cap.shedding.is_some());
+
+        cap.enforce_at(start + ESCALATE_AFTER * 2, &mut outputs, &internal);
+        assert_eq!(shed.borrow().len(), 2);
+        assert_eq!(cap.shedding, None);
+
+        // The channel is also the global one, which other tests could log to.
+        let warnings: Vec<String> = logged
+            .try_iter()
+            .map(|m| m.msg)
+            .filter(|m| m.starts_with("holding"))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                "holding 280.8 KiB for outputs, over the 97.7 KiB [memory] max: shedding Queues",
+                "holding 183.1 KiB for outputs, over the 97.7 KiB [memory] max: shedding Subscribers",
+            ]
+        );
+    }
+}
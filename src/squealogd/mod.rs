@@ -0,0 +1,1727 @@
+//! squealogd, the daemon, which its binary only starts: setting up the sources, outputs and
+//! storage, and the event loop that receives, parses and stores messages. Here, tests can run
+//! it in-process.
+
+use crate::capture;
+use crate::payload::{self, PayloadParser, SourceCtx};
+use crate::storage::{Maintenance, Spill, Sqlite, Storage};
+use crate::transport::{self, LogTransport, Received};
+use chrono::prelude::*;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use syslog_loose::{Message, ProcId, SyslogFacility, SyslogSeverity};
+use systemstat::Platform;
+
+// Only klog uses it, but the arithmetic is tested everywhere.
+#[cfg(any(target_os = "freebsd", test))]
+#[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+mod boottime;
+mod config;
+mod console;
+mod control;
+mod counters;
+mod daemon;
+mod dbfile;
+mod dump;
+mod enrich;
+mod exec;
+mod failures;
+mod files;
+mod filters;
+#[cfg(feature = "http")]
+mod http;
+mod internal;
+#[cfg(target_os = "linux")]
+mod journald;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod listen;
+mod mail;
+mod matcher;
+mod memory;
+mod notify;
+mod output;
+mod parse;
+mod preflight;
+mod privileges;
+mod pubsub;
+#[cfg(feature = "tokio")]
+mod reactor;
+mod relay;
+mod rewrite;
+mod sampler;
+mod sandbox;
+mod settings;
+// For `squealog-bench lookup`.
+pub mod slab;
+mod status;
+mod upgrade;
+mod wall;
+mod watchdog;
+#[cfg(feature = "webhook")]
+mod webhook;
+
+pub use settings::Settings;
+
+/// Datagrams read from a socket per readiness event.
+const MAX_READS_PER_EVENT: usize = 64;
+
+/// Receive buffer sizes, unless `[[listen]] buffer` says otherwise: the largest UDP payload,
+/// what local daemons send over unix sockets with room to spare, and big reads of the
+/// kernel's message buffer.
+const UDP_BUFFER: usize = 65535;
+const UNIX_BUFFER: usize = 8192;
+#[cfg(target_os = "freebsd")]
+const KLOG_BUFFER: usize = 65536;
+
+struct LogSource {
+    xport: LogTransport,
+    event: polling::Event,
+    sockname: Arc<str>,
+    /// What its payloads are parsed with, and whether those that don't parse are dropped.
+    parser: Arc<dyn PayloadParser>,
+    strict: bool,
+    /// Broken for good, to be taken out of the poller.
+    lost: bool,
+    /// Messages since startup.
+    received: u64,
+    /// What it's read into, past which a datagram is cut off.
+    buf: Vec<u8>,
+}
+
+impl LogSource {
+    fn fd(&self) -> std::os::unix::io::RawFd {
+        self.xport.transport().as_raw_fd()
+    }
+
+    /// Whether it's a socket, which `--async-sources` leaves to the reactor.
+    fn socket(&self) -> bool {
+        matches!(
+            self.xport,
+            LogTransport::Udp(_) | LogTransport::UnixDgram(_)
+        )
+    }
+
+    fn register(&self, poller: &polling::Poller) -> std::io::Result<()> {
+        poller.add(self.fd(), self.event)
+    }
+
+    /// Polls for the next event (events are oneshot), or stops if the source is lost. A
+    /// source that can't be polled anymore is lost too.
+    fn rearm(&mut self, poller: &polling::Poller, internal: &internal::Sender) {
+        let rearmed = if self.lost {
+            poller.delete(self.fd())
+        } else {
+            poller.modify(self.fd(), self.event)
+        };
+        if let Err(e) = rearmed {
+            self.lost = true;
+            internal.log(
+                SyslogSeverity::SEV_CRIT,
+                format!(
+                    "could not poll {} anymore, no longer listening on it: {}",
+                    self.sockname, e
+                ),
+            );
+        }
+    }
+}
+
+/// Runs the daemon until it's told to stop.
+pub fn run(settings: Settings) -> anyhow::Result<()> {
+    if settings.handoff_version {
+        println!("{}", upgrade::VERSION);
+        return Ok(());
+    }
+    let config = settings.load_config()?;
+    let process = match Process::start(&settings, &config)? {
+        Some(process) => process,
+        None => return Ok(()),
+    };
+    let conn = open_db(&settings)?;
+    let mut daemon = Daemon::new(&settings, &config, &conn, process)?;
+    daemon.handle_signals()?;
+    daemon.confine()?;
+    daemon.replay()?;
+    daemon.serve()?;
+    daemon.shutdown();
+    Ok(())
+}
+
+/// What's set up before the database is opened: what the process was started with, in the
+/// foreground or in the background.
+struct Process {
+    /// Resolved at startup, so that upgrades exec the file at this path even once it's
+    /// replaced.
+    exe: Option<PathBuf>,
+    handoff: Option<upgrade::Handoff>,
+    activation: listen::Activation,
+    background: Option<daemon::Background>,
+    pidfile: Option<daemon::Pidfile>,
+    notifier: notify::Notifier,
+    boottime: DateTime<Utc>,
+}
+
+impl Process {
+    /// None when there's nothing more to do, for `--preflight-only`.
+    fn start(settings: &Settings, config: &config::Config) -> anyhow::Result<Option<Process>> {
+        let exe = std::env::current_exe().ok();
+        let mut handoff = upgrade::Handoff::from_env()?;
+        let activation = listen::activation();
+        // Where it can still be seen, before going into the background.
+        preflight::run(&preflight::Context {
+            settings,
+            config,
+            activation: &activation,
+            upgrade: handoff.is_some(),
+        })?;
+        if settings.preflight_only {
+            eprintln!("squealogd: {} checks passed", preflight::CHECKS.len());
+            return Ok(None);
+        }
+        // Before anything else: only the forking thread lives on in the child, and the pidfile
+        // needs the child's pid. A process exec'd by an upgrade already is in the background.
+        let background = if settings.daemon.daemonize && handoff.is_none() {
+            Some(daemon::daemonize()?)
+        } else {
+            None
+        };
+        let pidfile = match (
+            &settings.daemon.pidfile,
+            handed_over(&mut handoff, upgrade::Kind::Pidfile),
+        ) {
+            (Some(path), Some(fd)) => Some(daemon::Pidfile::adopt(path, fd)),
+            (Some(path), None) => Some(daemon::Pidfile::create(path)?),
+            (None, _) => None,
+        };
+        // Before any threads are started, as it takes its variables out of the environment.
+        let notifier = notify::Notifier::from_env();
+        let boottime = systemstat::System::new().boot_time()?;
+        Ok(Some(Process {
+            exe,
+            handoff,
+            activation,
+            background,
+            pidfile,
+            notifier,
+            boottime,
+        }))
+    }
+}
+
+fn handed_over(handoff: &mut Option<upgrade::Handoff>, kind: upgrade::Kind) -> Option<RawFd> {
+    handoff.as_mut().and_then(|h| h.take_fd(kind))
+}
+
+/// Opens the database and brings it up to date. Without one (`--no-db`), everything that
+/// needs a connection gets an empty one that nothing is stored in.
+fn open_db(settings: &Settings) -> anyhow::Result<rusqlite::Connection> {
+    let db = &settings.db;
+    let mut conn = if settings.no_db {
+        rusqlite::Connection::open_in_memory()?
+    } else {
+        dbfile::prepare(db, settings.db_file)?;
+        rusqlite::Connection::open(db)
+            .map_err(|e| anyhow::format_err!("Could not open the database {:?}: {}", db, e))?
+    };
+    // Only takes effect when creating the database, lets `squealog prune` shrink the file.
+    conn.pragma_update(None, "auto_vacuum", "INCREMENTAL")?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.pragma_update(None, "wal_autocheckpoint", "128")?;
+    crate::schema::migrations().to_latest(&mut conn)?;
+    Ok(conn)
+}
+
+/// The running daemon: its sources, what's registered with the poller besides them and the
+/// `Pipeline` what they receive goes through. `step` is one turn of the main loop.
+struct Daemon<'a> {
+    settings: &'a Settings,
+    config: &'a config::Config,
+    conn: &'a rusqlite::Connection,
+    exe: Option<PathBuf>,
+    background: Option<daemon::Background>,
+    pidfile: Option<daemon::Pidfile>,
+    notifier: notify::Notifier,
+    poller: Arc<polling::Poller>,
+    internal_tx: internal::Sender,
+    sources: slab::Slab<LogSource>,
+    /// The unix sockets bound here, removed when exiting (but not for an upgrade).
+    bound_paths: Vec<PathBuf>,
+    parsers: payload::Registry,
+    pubsub_fd: Option<RawFd>,
+    control: Option<control::Control>,
+    #[cfg(feature = "tokio")]
+    reactor: Option<reactor::Reactor>,
+    memory_cap: memory::Cap,
+    mark: Option<status::Mark>,
+    started: Instant,
+    report: counters::Report,
+    events: Vec<polling::Event>,
+    /// Set by SIGHUP and the control socket's `reload`, the others by the signal thread (see
+    /// `handle_signals`).
+    reload: Arc<AtomicBool>,
+    shutdown: Arc<AtomicBool>,
+    dump_stats: Arc<AtomicBool>,
+    upgrade: Arc<AtomicBool>,
+    pipeline: Pipeline<'a>,
+}
+
+/// What a message goes through once it's received, up to being stored and passed on: parsing
+/// (here or on the parse threads), the enrichers, the database (with the batch and the spill)
+/// and the outputs. Its methods take `&self`, as storing can come back around (the sampler
+/// releasing what it held back, the daemon's own messages).
+struct Pipeline<'a> {
+    settings: &'a Settings,
+    conn: &'a rusqlite::Connection,
+    internal_tx: internal::Sender,
+    internal_rx: std::sync::mpsc::Receiver<internal::Internal>,
+    boottime: DateTime<Utc>,
+    boot: i64,
+    enrichers: RefCell<enrich::Enrichers>,
+    /// Looked up again on SIGHUP.
+    hostname: RefCell<enrich::LocalHostname>,
+    dump: RefCell<dump::Dump>,
+    progress: Arc<watchdog::Progress>,
+    /// When what's being stored arrived, if that wasn't just now: for replays, and for
+    /// messages the sampler held back.
+    arrived: Cell<Option<DateTime<Utc>>>,
+    last_stored: Cell<Instant>,
+    storage: RefCell<Sqlite<'a>>,
+    /// Failing to store a message drops it, the daemon keeps going. Unless it's a failure
+    /// that passes, like the database being locked or the disk full: then the message waits
+    /// in the spill, and so do the ones after it until it's stored, to keep them in order.
+    failures: RefCell<failures::Failures>,
+    spill: RefCell<Spill>,
+    /// What's received between two waits is stored in one batch, see `begin_batch`. Its rows
+    /// are kept until it's committed, for the ones a failure undoes to wait in the spill too.
+    batch: RefCell<crate::storage::Batch>,
+    outputs: RefCell<Vec<Box<dyn output::Output>>>,
+    pool: RefCell<Option<parse::Pool>>,
+    recorder: Option<RefCell<capture::Writer>>,
+    #[cfg(target_os = "freebsd")]
+    klog_anchor: RefCell<boottime::Anchor>,
+}
+
+impl<'a> Daemon<'a> {
+    /// Sets everything up, from the sockets to the outputs, dropping root on the way.
+    fn new(
+        settings: &'a Settings,
+        config: &'a config::Config,
+        conn: &'a rusqlite::Connection,
+        process: Process,
+    ) -> anyhow::Result<Daemon<'a>> {
+        let Process {
+            exe,
+            mut handoff,
+            activation,
+            background,
+            pidfile,
+            notifier,
+            boottime,
+        } = process;
+        let db = &settings.db;
+        let boot = crate::boot::current(conn, boottime)?;
+
+        let poller = Arc::new(polling::Poller::new()?);
+        let (internal_tx, internal_rx) = internal::channel(
+            poller.clone(),
+            settings.log_level,
+            !settings.daemon.daemonize,
+        );
+
+        // What it conflicts with in the config was ruled out by the preflight.
+        sandbox::begin(settings.sandbox, config)?;
+        let enrichers = RefCell::new(enrich::Enrichers::new(config)?);
+        let reload = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, reload.clone())?;
+
+        // An upgrade's sockets win, then socket activation, the config only matters without
+        // them.
+        let mut sockets = vec![];
+        let mut bound_paths = vec![];
+        let bound = match handoff {
+            // Only the captures are read from.
+            _ if settings.replay.is_some() => vec![],
+            Some(ref mut handoff) => listen::adopt(
+                handoff.take(&[upgrade::Kind::Source, upgrade::Kind::Bound]),
+                &internal_tx,
+            ),
+            None => match activation.sockets(&internal_tx) {
+                Some(activated) => activated.into_iter().map(|b| (b, 0)).collect(),
+                None => listen::bind(&config.listen)?
+                    .into_iter()
+                    .map(|b| (b, 0))
+                    .collect(),
+            },
+        };
+        for (bound, received) in bound {
+            bound_paths.extend(bound.path);
+            sockets.push((bound.name, bound.xport, received));
+        }
+        if sockets.is_empty() && settings.replay.is_none() {
+            anyhow::bail!(
+                "No sockets to read from: use socket activation (with LISTEN_FDNAMES) or add \
+                [[listen]] sockets to the config"
+            );
+        }
+        // Keyed in the poller by their slots.
+        let parsers = payload::Registry::default();
+        let mut sources = slab::Slab::default();
+        for (n, xport, received) in sockets {
+            // Inherited descriptors don't have it, and exec hooks shouldn't get them.
+            let fd = match xport {
+                LogTransport::Udp(ref s) => s.as_raw_fd(),
+                LogTransport::UnixDgram(ref s) => s.as_raw_fd(),
+                #[cfg(target_os = "freebsd")]
+                LogTransport::Klog(_) => unreachable!(),
+            };
+            if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            match xport {
+                LogTransport::Udp(ref s) => s.set_nonblocking(true),
+                LogTransport::UnixDgram(ref s) => s.set_nonblocking(true),
+                #[cfg(target_os = "freebsd")]
+                LogTransport::Klog(_) => unreachable!(),
+            }?;
+            let cfg = config.listen.iter().find(|l| l.name == n);
+            let size = match cfg {
+                Some(cfg) => listen::buffer_size(cfg)?,
+                None => None,
+            };
+            let parser = listen::parser(cfg, &parsers)?;
+            let strict = cfg.is_some_and(|cfg| cfg.strict);
+            let size = size.unwrap_or(match xport {
+                LogTransport::Udp(_) => UDP_BUFFER,
+                _ => UNIX_BUFFER,
+            });
+            sources.insert_with(|key| LogSource {
+                xport,
+                event: polling::Event::readable(key),
+                sockname: n.into(),
+                parser,
+                strict,
+                lost: false,
+                received,
+                buf: vec![0; size],
+            });
+        }
+
+        #[cfg(target_os = "freebsd")]
+        if settings.klog {
+            use std::os::unix::fs::OpenOptionsExt;
+            use std::os::unix::io::FromRawFd;
+            let (file, received) = match handoff.as_mut().map(|h| h.take(&[upgrade::Kind::Klog])) {
+                Some(mut klog) if !klog.is_empty() => {
+                    let entry = klog.remove(0);
+                    (
+                        unsafe { std::fs::File::from_raw_fd(entry.fd) },
+                        entry.received,
+                    )
+                }
+                _ => (
+                    std::fs::OpenOptions::new()
+                        .read(true)
+                        .custom_flags(libc::O_NONBLOCK)
+                        .open("/dev/klog")
+                        .unwrap(),
+                    0,
+                ),
+            };
+            sources.insert_with(|key| LogSource {
+                xport: LogTransport::Klog(file),
+                event: polling::Event::readable(key),
+                sockname: "klog".into(),
+                parser: parsers.get("klog").unwrap(),
+                strict: false,
+                lost: false,
+                received,
+                buf: vec![0; KLOG_BUFFER],
+            });
+        };
+
+        // With --async-sources, tokio reads the sockets (once privileges are dropped, below).
+        for source in sources.iter() {
+            if !(settings.async_sources && source.socket()) {
+                source.register(&poller)?;
+            }
+        }
+
+        // Binds in a directory (/var/run) only root can usually write to, which is why an
+        // upgraded process (running as the unprivileged user already) takes the old listeners.
+        let pubsub = match (
+            &config.pubsub,
+            handed_over(&mut handoff, upgrade::Kind::PubSub),
+        ) {
+            _ if settings.replay.is_some() => None,
+            (Some(cfg), Some(fd)) => Some(pubsub::PubSub::adopt(
+                cfg,
+                fd,
+                poller.clone(),
+                sources.keys(),
+            )?),
+            (Some(cfg), None) => Some(pubsub::PubSub::new(cfg, poller.clone(), sources.keys())?),
+            (None, _) => None,
+        };
+        let pubsub_fd = pubsub.as_ref().map(|p| p.fd());
+        let control = match (
+            &config.control,
+            handed_over(&mut handoff, upgrade::Kind::Control),
+        ) {
+            _ if settings.replay.is_some() => None,
+            (Some(cfg), Some(fd)) => Some(control::Control::adopt(
+                cfg,
+                fd,
+                poller.clone(),
+                sources.keys(),
+            )?),
+            (Some(cfg), None) => Some(control::Control::new(cfg, poller.clone(), sources.keys())?),
+            (None, _) => None,
+        };
+        let upgraded = handoff.is_some();
+        if let Some(handoff) = handoff {
+            handoff.close_rest();
+        }
+        // A file of this process's own, while it can still be created as root. After an
+        // upgrade it may not be anymore, which only stops the recording.
+        let recorder = match settings.record {
+            Some(ref dir) => match capture::Writer::create(dir, boottime) {
+                Ok(writer) => {
+                    internal_tx.log(
+                        SyslogSeverity::SEV_INFO,
+                        format!("recording what's received to {:?}", writer.path()),
+                    );
+                    Some(RefCell::new(writer))
+                }
+                Err(e) if upgraded => {
+                    internal_tx.log(
+                        SyslogSeverity::SEV_ERR,
+                        format!(
+                            "could not create a capture file in {:?}, not recording: {}",
+                            dir, e
+                        ),
+                    );
+                    None
+                }
+                Err(e) => anyhow::bail!("Could not create a capture file in {:?}: {}", dir, e),
+            },
+            None => None,
+        };
+
+        // Everything that needs root happened above. Before any threads are started, so that
+        // none of them (or the programs they run) keep root either.
+        {
+            use privileges::Sys;
+            let mut sys = privileges::Real;
+            if sys.euid() == 0 && !settings.keep_root {
+                let name = config
+                    .privileges
+                    .user
+                    .as_deref()
+                    .unwrap_or(privileges::DEFAULT_USER);
+                let user = privileges::User::lookup(name)?.ok_or_else(|| {
+                    anyhow::format_err!(
+                        "There is no user {} to run as: create it, set another one as \
+                        [privileges] user, or pass --keep-root to stay root",
+                        name
+                    )
+                })?;
+                let db = Some(db.as_ref()).filter(|_| !settings.no_db);
+                privileges::drop_to(&mut sys, &user, db)?;
+            }
+        }
+
+        #[cfg(feature = "http")]
+        {
+            if let Some(ref addr) = settings.http {
+                http::spawn(&http::listen_addr(addr), db.clone())?;
+            }
+        }
+
+        let memory_cap = memory::Cap::new(config.memory.as_ref())?;
+        let progress = watchdog::spawn(&config.watchdog, internal_tx.clone())?;
+        // A replay parses on the main thread, to store each message with the time it arrived.
+        let threads = match settings.parse_threads {
+            _ if settings.replay.is_some() => 0,
+            Some(threads) => threads,
+            None => parse::default_threads(),
+        };
+        let pool = RefCell::new(parse::Pool::start(
+            threads,
+            boottime,
+            settings.backpressure,
+            poller.clone(),
+            internal_tx.clone(),
+        )?);
+        #[cfg(feature = "tokio")]
+        let reactor = if settings.async_sources {
+            let sockets = sources
+                .iter()
+                .filter(|s| s.socket())
+                .map(|s| (s.event.key, s.xport.transport(), s.buf.len()))
+                .collect();
+            Some(reactor::Reactor::start(sockets, poller.clone())?)
+        } else {
+            None
+        };
+        #[cfg(not(feature = "tokio"))]
+        if settings.async_sources {
+            anyhow::bail!("--async-sources needs the tokio feature");
+        }
+        let mut outputs: Vec<Box<dyn output::Output>> = vec![];
+        for relay in &config.relay.udp {
+            outputs.push(Box::new(relay::UdpRelay::new(relay)?));
+        }
+        if !config.file.is_empty() {
+            outputs.push(Box::new(files::FileOutput::new(&config.file)?));
+        }
+        for hook in &config.exec {
+            outputs.push(Box::new(exec::ExecHook::new(hook, internal_tx.clone())?));
+        }
+        if let Some(ref cfg) = config.journald {
+            #[cfg(target_os = "linux")]
+            outputs.push(Box::new(journald::Journald::new(cfg)?));
+            #[cfg(not(target_os = "linux"))]
+            anyhow::bail!(
+                "journald output configured, but this isn't Linux ({:?})",
+                cfg
+            );
+        }
+        #[cfg(feature = "webhook")]
+        for hook in &config.webhook {
+            outputs.push(Box::new(webhook::Webhook::new(hook, internal_tx.clone())?));
+        }
+        #[cfg(not(feature = "webhook"))]
+        if let Some(hook) = config.webhook.first() {
+            anyhow::bail!(
+                "webhook to {} configured, but built without the webhook feature",
+                hook.url
+            );
+        }
+        if let Some(ref cfg) = config.kafka {
+            #[cfg(feature = "kafka")]
+            outputs.push(Box::new(kafka::Kafka::new(cfg)?));
+            #[cfg(not(feature = "kafka"))]
+            anyhow::bail!(
+                "kafka topic {} configured, but built without the kafka feature",
+                cfg.topic
+            );
+        }
+        for rule in &config.mail {
+            outputs.push(Box::new(mail::Mail::new(rule, internal_tx.clone())?));
+        }
+        if config.wall.enabled {
+            outputs.push(Box::new(wall::Wall::new(&config.wall)?));
+        }
+        if let Some(ref cfg) = config.console {
+            outputs.push(Box::new(console::Console::new(cfg)?));
+        }
+        if let Some(pubsub) = pubsub {
+            outputs.push(Box::new(pubsub));
+        }
+        for relay in &config.relay.tcp {
+            relay::TcpRelay::spawn(relay, db.as_ref())?;
+        }
+
+        let mark = config.mark.as_ref().map(status::Mark::new).transpose()?;
+        let started = Instant::now();
+        let pipeline = Pipeline {
+            settings,
+            conn,
+            internal_tx: internal_tx.clone(),
+            internal_rx,
+            boottime,
+            boot,
+            enrichers,
+            hostname: RefCell::new(enrich::LocalHostname::new(crate::sys::hostname().ok())),
+            dump: RefCell::new(dump::Dump::default()),
+            progress,
+            arrived: Cell::new(None),
+            last_stored: Cell::new(started),
+            storage: RefCell::new(Sqlite::new(conn)?),
+            failures: RefCell::new(failures::Failures::default()),
+            spill: RefCell::new(Spill::new(
+                config
+                    .memory
+                    .as_ref()
+                    .and_then(|m| m.spill)
+                    .unwrap_or(10_000),
+            )),
+            batch: RefCell::new(crate::storage::Batch::default()),
+            outputs: RefCell::new(outputs),
+            pool,
+            recorder,
+            #[cfg(target_os = "freebsd")]
+            klog_anchor: RefCell::new(boottime::Anchor::new(boottime)),
+        };
+        Ok(Daemon {
+            settings,
+            config,
+            conn,
+            exe,
+            background,
+            pidfile,
+            notifier,
+            poller,
+            internal_tx,
+            sources,
+            bound_paths,
+            parsers,
+            pubsub_fd,
+            control,
+            #[cfg(feature = "tokio")]
+            reactor,
+            memory_cap,
+            mark,
+            started,
+            report: counters::Report::new(),
+            events: Vec::new(),
+            reload,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            dump_stats: Arc::new(AtomicBool::new(false)),
+            upgrade: Arc::new(AtomicBool::new(false)),
+            pipeline,
+        })
+    }
+
+    /// The first SIGTERM/SIGINT ends the main loop, a second one while shutting down exits
+    /// right away. SIGUSR1 asks for a stats snapshot, SIGUSR2 for an upgrade. A thread of its
+    /// own, so the signal can't get lost between the check and the wait.
+    fn handle_signals(&self) -> anyhow::Result<()> {
+        use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
+        let mut signals = signal_hook::iterator::Signals::new([SIGTERM, SIGINT, SIGUSR1, SIGUSR2])?;
+        let shutdown = self.shutdown.clone();
+        let dump_stats = self.dump_stats.clone();
+        let upgrade = self.upgrade.clone();
+        let poller = self.poller.clone();
+        std::thread::Builder::new()
+            .name("signals".to_owned())
+            .spawn(move || {
+                for signal in signals.forever() {
+                    if signal == SIGUSR1 {
+                        dump_stats.store(true, Ordering::SeqCst);
+                    } else if signal == SIGUSR2 {
+                        upgrade.store(true, Ordering::SeqCst);
+                    } else if shutdown.swap(true, Ordering::SeqCst) {
+                        std::process::exit(1);
+                    }
+                    let _ = poller.notify();
+                }
+            })?;
+        Ok(())
+    }
+
+    /// Last, once every thread is running: they all get the filter, but starting one (and the
+    /// signal handling) needs calls it doesn't allow. Then it's ready.
+    fn confine(&mut self) -> anyhow::Result<()> {
+        sandbox::confine(
+            self.settings.sandbox,
+            &sandbox::Setup {
+                config: self.config,
+                db: self.settings.db.as_ref(),
+                config_path: self.settings.config_path(),
+                conn: self.conn,
+                internal: &self.internal_tx,
+                sources: {
+                    let fds = self.sources.iter().map(|s| s.fd());
+                    #[cfg(feature = "tokio")]
+                    let fds = fds.chain(self.reactor.iter().flat_map(|r| r.fds()));
+                    fds.collect()
+                },
+            },
+        )?;
+        self.notifier.ready();
+        if let Some(ref mut background) = self.background {
+            background.ready();
+        }
+        Ok(())
+    }
+
+    /// `--replay`: what the captures hold goes through the same parsing and storing as what's
+    /// received, followed by the same shutdown.
+    fn replay(&self) -> anyhow::Result<()> {
+        let replay = match self.settings.replay {
+            Some(ref replay) => replay,
+            None => return Ok(()),
+        };
+        let pipeline = &self.pipeline;
+        let mut parsers_by_socket: HashMap<String, (Arc<dyn PayloadParser>, bool)> = HashMap::new();
+        'files: for path in capture::files(&replay.dir)? {
+            let mut reader = capture::Reader::open(&path)?;
+            // Each file is a run of its own, there's no waiting out the time between them.
+            let (start, mut first) = (Instant::now(), None);
+            while let Some(entry) = reader.next_entry()? {
+                if replay.original_timing {
+                    let since = entry.time - *first.get_or_insert(entry.time);
+                    if let Some(wait) = since
+                        .to_std()
+                        .ok()
+                        .and_then(|s| s.checked_sub(start.elapsed()))
+                    {
+                        std::thread::sleep(wait);
+                    }
+                }
+                let (parser, strict) = match parsers_by_socket.get(&entry.socket) {
+                    Some(parsing) => parsing.clone(),
+                    None => {
+                        let cfg = self.config.listen.iter().find(|l| l.name == entry.socket);
+                        let parsing = match entry.transport {
+                            capture::Transport::Klog => (self.parsers.get("klog").unwrap(), false),
+                            _ => (
+                                listen::parser(cfg, &self.parsers)?,
+                                cfg.is_some_and(|cfg| cfg.strict),
+                            ),
+                        };
+                        parsers_by_socket.insert(entry.socket.clone(), parsing.clone());
+                        parsing
+                    }
+                };
+                let socket: Arc<str> = entry.socket.into();
+                pipeline.arrived.set(Some(entry.time));
+                match entry.transport {
+                    capture::Transport::Klog => {
+                        pipeline.take_klog(&socket, &parser, reader.boot_time, &entry.payload);
+                    }
+                    transport => pipeline.take_datagram(
+                        &socket,
+                        transport == capture::Transport::Unix,
+                        &parser,
+                        strict,
+                        reader.boot_time,
+                        &entry.payload,
+                    ),
+                }
+                pipeline.release_sampled(entry.time, false);
+                pipeline.arrived.set(None);
+                pipeline.store_internal();
+                if self.shutdown.load(Ordering::SeqCst) {
+                    break 'files;
+                }
+            }
+        }
+        self.shutdown.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Until told to stop (right away after a replay).
+    fn serve(&mut self) -> anyhow::Result<()> {
+        while !self.shutdown.load(Ordering::SeqCst) {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// One turn of the main loop: waits for something to do (or something that's due), reads
+    /// and stores what came in one batch, answers the control socket and does what's due.
+    fn step(&mut self) -> anyhow::Result<()> {
+        let deadline = self
+            .pipeline
+            .outputs
+            .borrow()
+            .iter()
+            .filter_map(|o| o.deadline())
+            .chain(self.pipeline.enrichers.borrow().filters.deadline())
+            .chain(self.pipeline.enrichers.borrow().sampler.deadline())
+            .chain(self.notifier.deadline())
+            .chain(self.report.deadline())
+            .chain(
+                self.mark
+                    .as_ref()
+                    .map(|m| m.deadline(self.pipeline.last_stored.get())),
+            )
+            .chain(self.pipeline.spill.borrow().deadline())
+            .min();
+        let timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        self.events.clear();
+        match self.poller.wait(&mut self.events, timeout) {
+            // Signals (like the SIGHUP that reopens files) interrupt the wait.
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            r => {
+                r?;
+            }
+        }
+
+        let requests = self.receive();
+        let mut upgrade_to = self.answer(requests);
+        if self.reload.swap(false, Ordering::SeqCst) {
+            self.reload();
+        }
+        self.tick();
+        if self.dump_stats.swap(false, Ordering::SeqCst) {
+            self.internal_tx
+                .log_always(SyslogSeverity::SEV_INFO, self.snapshot());
+        }
+        if self.upgrade.swap(false, Ordering::SeqCst) {
+            match self.can_upgrade() {
+                Ok(exe) => upgrade_to = Some(exe),
+                Err(e) => self.internal_tx.log(SyslogSeverity::SEV_ERR, e),
+            }
+        }
+        if let Some(exe) = upgrade_to {
+            self.upgrade(&exe);
+        }
+        Ok(())
+    }
+
+    /// Reads from the sources the last wait found ready, and stores what they got in one
+    /// batch. Returns what came from the control socket.
+    fn receive(&mut self) -> Vec<control::Request> {
+        let pipeline = &self.pipeline;
+        let mut requests = vec![];
+        pipeline.begin_batch();
+        for ev in &self.events {
+            let source = match self.sources.get_mut(ev.key) {
+                Some(source) => source,
+                None => {
+                    if let Some(more) = self.control.as_mut().and_then(|c| c.event(ev)) {
+                        requests.extend(more);
+                        continue;
+                    }
+                    for output in pipeline.outputs.borrow_mut().iter_mut() {
+                        if output.event(ev) {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+            };
+            match source.xport {
+                // Up to a limit, so that a flood on one socket doesn't hold up the others:
+                // what's left makes it ready again right away once re-armed.
+                LogTransport::Udp(_) | LogTransport::UnixDgram(_) => {
+                    for _ in 0..MAX_READS_PER_EVENT {
+                        if !pipeline.receive(source) {
+                            break;
+                        }
+                    }
+                }
+                #[cfg(target_os = "freebsd")]
+                LogTransport::Klog(_) => while pipeline.receive(source) {},
+            }
+            source.rearm(&self.poller, &self.internal_tx);
+        }
+        #[cfg(feature = "tokio")]
+        self.from_reactor();
+        let pipeline = &self.pipeline;
+        pipeline.store_parsed(false);
+        pipeline.commit_batch();
+        // The other sources keep going without them.
+        self.sources.retain(|source| !source.lost);
+        requests
+    }
+
+    /// What the reactor read, handled like what's read here.
+    #[cfg(feature = "tokio")]
+    fn from_reactor(&mut self) {
+        let reactor = match self.reactor {
+            Some(ref mut reactor) => reactor,
+            None => return,
+        };
+        for (key, read) in reactor.ready() {
+            let read = match read {
+                Ok(ref data) => Ok(&data[..]),
+                Err(e) => Err(e),
+            };
+            if let Some(source) = self.sources.get_mut(key) {
+                self.pipeline.handle(source, Received::from(read));
+            }
+        }
+    }
+
+    /// Answers the control socket's requests. Returns where to upgrade to, if asked to.
+    fn answer(&mut self, requests: Vec<control::Request>) -> Option<PathBuf> {
+        let mut upgrade_to = None;
+        for request in requests {
+            let reply = match request.command {
+                Err(ref e) => Err(e.clone()),
+                Ok(control::Command::Ping) => Ok(vec![]),
+                Ok(control::Command::Stats) => Ok(vec![self.snapshot()]),
+                Ok(control::Command::Sources) => Ok(self
+                    .sources
+                    .iter()
+                    .map(|s| format!("{} {}", s.sockname, s.received))
+                    .collect()),
+                Ok(control::Command::Flush) => {
+                    for output in self.pipeline.outputs.borrow_mut().iter_mut() {
+                        output.flush();
+                    }
+                    self.pipeline
+                        .enrichers
+                        .borrow_mut()
+                        .filters
+                        .write(self.conn)
+                        .map(|_| vec![])
+                        .map_err(|e| format!("could not store the filter counts: {}", e))
+                }
+                Ok(control::Command::Checkpoint) => self
+                    .conn
+                    .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| {
+                        Ok(format!(
+                            "busy {} log {} checkpointed {}",
+                            row.get::<_, i64>(0)?,
+                            row.get::<_, i64>(1)?,
+                            row.get::<_, i64>(2)?
+                        ))
+                    })
+                    .map(|line| vec![line])
+                    .map_err(|e| e.to_string()),
+                Ok(control::Command::Rotate) => {
+                    for output in self.pipeline.outputs.borrow_mut().iter_mut() {
+                        output.reopen();
+                    }
+                    Ok(vec![])
+                }
+                Ok(control::Command::Reload) => {
+                    self.reload.store(true, Ordering::SeqCst);
+                    Ok(vec![])
+                }
+                Ok(control::Command::Level(level)) => {
+                    self.internal_tx.set_level(level);
+                    Ok(vec![])
+                }
+                Ok(control::Command::Upgrade) => self.can_upgrade().map(|exe| {
+                    let reply = vec![format!("upgrading to {:?}", exe)];
+                    upgrade_to = Some(exe);
+                    reply
+                }),
+            };
+            if let Some(ref mut control) = self.control {
+                control.reply(&request, reply);
+            }
+        }
+        upgrade_to
+    }
+
+    /// The stats snapshot, for SIGUSR1 and the control socket.
+    fn snapshot(&self) -> String {
+        let received: Vec<_> = self
+            .sources
+            .iter()
+            .map(|s| (&*s.sockname, s.received))
+            .collect();
+        status::snapshot(
+            self.started,
+            &received,
+            &self.settings.db,
+            self.memory_cap.max,
+        )
+    }
+
+    /// Looks the hostname up again and reloads the rules, keeping the old ones if the new
+    /// ones don't load.
+    fn reload(&mut self) {
+        self.notifier.reloading();
+        let hostname = &self.pipeline.hostname;
+        if hostname.borrow_mut().relookup(crate::sys::hostname().ok()) {
+            self.internal_tx.log(
+                SyslogSeverity::SEV_NOTICE,
+                format!(
+                    "the hostname is now {}",
+                    hostname.borrow().get().unwrap_or("unknown")
+                ),
+            );
+        }
+        let cfg = self.settings.load_config();
+        match cfg.and_then(|cfg| enrich::Enrichers::new(&cfg)) {
+            Ok(new) => {
+                self.pipeline.enrichers.borrow_mut().replace(new);
+                self.internal_tx
+                    .log(SyslogSeverity::SEV_INFO, "reloaded the rules".to_owned());
+            }
+            Err(e) => self.internal_tx.log(
+                SyslogSeverity::SEV_ERR,
+                format!("could not reload the rules, keeping the old ones: {:#}", e),
+            ),
+        }
+        self.notifier.ready();
+    }
+
+    /// What's done every turn, whether or not anything came in: the daemon's own messages,
+    /// what's due for the sampler, the spill and the outputs, and the periodic reports.
+    fn tick(&mut self) {
+        let pipeline = &self.pipeline;
+        pipeline.store_internal();
+        pipeline.release_sampled(Utc::now(), false);
+        pipeline.retry_spilled(Instant::now());
+        if let Err(e) = pipeline.enrichers.borrow_mut().filters.flush(self.conn) {
+            self.internal_tx.log(
+                SyslogSeverity::SEV_ERR,
+                format!("could not store the filter counts: {}", e),
+            );
+        }
+        for output in pipeline.outputs.borrow_mut().iter_mut() {
+            output.tick();
+        }
+        self.memory_cap
+            .enforce(&mut pipeline.outputs.borrow_mut(), &self.internal_tx);
+        self.notifier.tick(self.conn, pipeline.progress.stalled());
+        self.report.tick(&self.internal_tx);
+        if let Some(ref mark) = self.mark {
+            if Instant::now() >= mark.deadline(pipeline.last_stored.get()) {
+                let msg = internal::Internal {
+                    socket: internal::SOCKET,
+                    facility: SyslogFacility::LOG_SYSLOG,
+                    appname: "squealogd".to_owned(),
+                    pid: None,
+                    severity: SyslogSeverity::SEV_INFO,
+                    msg: status::MARK.to_owned(),
+                };
+                pipeline.stored(
+                    msg.socket,
+                    pipeline.ingest(msg.socket, true, &msg.msg, msg.message()),
+                );
+            }
+        }
+    }
+
+    /// Where an upgrade execs, unless something rules it out.
+    fn can_upgrade(&self) -> Result<PathBuf, String> {
+        let exe = self
+            .exe
+            .as_deref()
+            .ok_or_else(|| "could not tell which binary is running".to_owned())?;
+        if let Some(why) = sandbox::exec_blocked(self.settings.sandbox, self.config) {
+            return Err(format!("can't upgrade: {}", why));
+        }
+        upgrade::compatible(exe)?;
+        Ok(exe.to_owned())
+    }
+
+    /// Like shutting down, except that the sockets stay open and are handed to `exe`. Only
+    /// returns if it couldn't be exec'd, and keeps on.
+    fn upgrade(&mut self, exe: &std::path::Path) {
+        self.internal_tx.log(
+            SyslogSeverity::SEV_NOTICE,
+            format!("upgrading to {:?}", exe),
+        );
+        self.notifier.reloading();
+        #[cfg(feature = "tokio")]
+        if let Some(ref mut reactor) = self.reactor {
+            reactor.stop();
+        }
+        #[cfg(feature = "tokio")]
+        self.from_reactor();
+        self.pipeline.flush(false);
+        let _ = self
+            .pipeline
+            .storage
+            .borrow_mut()
+            .maintain(Maintenance::Checkpoint);
+        let mut handoff = upgrade::Handoff::default();
+        for source in self.sources.iter() {
+            let bound =
+                listen::unix_path(source.fd()).is_some_and(|p| self.bound_paths.contains(&p));
+            let kind = if bound {
+                upgrade::Kind::Bound
+            } else {
+                upgrade::Kind::Source
+            };
+            #[cfg(target_os = "freebsd")]
+            let kind = if matches!(source.xport, LogTransport::Klog(_)) {
+                upgrade::Kind::Klog
+            } else {
+                kind
+            };
+            handoff.add(kind, source.fd(), source.received, &source.sockname);
+        }
+        if let Some(fd) = self.pubsub_fd {
+            handoff.add(upgrade::Kind::PubSub, fd, 0, "");
+        }
+        if let Some(ref control) = self.control {
+            handoff.add(upgrade::Kind::Control, control.fd(), 0, "");
+        }
+        if let Some(ref pidfile) = self.pidfile {
+            handoff.add(upgrade::Kind::Pidfile, pidfile.fd(), 0, "");
+        }
+        self.notifier.restore_env();
+        let e = upgrade::exec(exe, &handoff);
+        notify::unset_env();
+        self.internal_tx.log(
+            SyslogSeverity::SEV_ERR,
+            format!("could not exec {:?}, keeping on: {}", exe, e),
+        );
+        // Stopped for the upgrade, the main loop reads the sockets from now on.
+        #[cfg(feature = "tokio")]
+        if self.reactor.take().is_some() {
+            for source in self.sources.iter().filter(|s| s.socket()) {
+                if let Err(e) = source.register(&self.poller) {
+                    self.internal_tx.log(
+                        SyslogSeverity::SEV_CRIT,
+                        format!("could not poll {}: {}", source.sockname, e),
+                    );
+                }
+            }
+        }
+        self.notifier.ready();
+    }
+
+    /// Stores and passes on whatever was received before being told to stop, and cleans up.
+    fn shutdown(mut self) {
+        self.notifier.stopping();
+        #[cfg(feature = "tokio")]
+        if let Some(ref mut reactor) = self.reactor {
+            reactor.stop();
+        }
+        #[cfg(feature = "tokio")]
+        self.from_reactor();
+        for source in self.sources.iter_mut() {
+            while self.pipeline.receive(source) {}
+        }
+        self.pipeline.flush(true);
+        if let Err(e) = self
+            .pipeline
+            .storage
+            .borrow_mut()
+            .maintain(Maintenance::Checkpoint)
+        {
+            eprintln!("squealogd: could not checkpoint the database: {}", e);
+        }
+        if let Some(ref mut control) = self.control {
+            control.finish();
+        }
+        for path in &self.bound_paths {
+            let _ = std::fs::remove_file(path);
+        }
+        if let Some(pidfile) = self.pidfile {
+            pidfile.remove();
+        }
+    }
+}
+
+impl Pipeline<'_> {
+    fn retry_spilled(&self, now: Instant) {
+        // Not into a batch, which a failure could undo.
+        if self.batch.borrow().is_open() {
+            return;
+        }
+        let mut spill = self.spill.borrow_mut();
+        let retried = spill.retry(&mut *self.storage.borrow_mut(), now);
+        match retried.error {
+            Some(e) => self
+                .failures
+                .borrow_mut()
+                .retry_failed(spill.len(), &e, &self.internal_tx),
+            None if retried.stored > 0 && spill.is_empty() => self.internal_tx.log(
+                SyslogSeverity::SEV_NOTICE,
+                format!(
+                    "stored the {} messages that had to wait for the database",
+                    retried.stored
+                ),
+            ),
+            None => {}
+        }
+    }
+
+    /// Opens the batch for what's received next. Without one (with --no-db, or when even that
+    /// fails), messages are stored one at a time.
+    fn begin_batch(&self) {
+        if !self.settings.no_db && self.storage.borrow_mut().begin_batch().is_ok() {
+            self.batch.borrow_mut().begin();
+        }
+    }
+
+    /// If committing fails, the whole batch waits in the spill, ahead of anything that came
+    /// after it.
+    fn commit_batch(&self) {
+        if !self.batch.borrow().is_open() {
+            return;
+        }
+        let committed = self.storage.borrow_mut().commit();
+        let rows = self.batch.borrow_mut().end(committed.is_err());
+        if let Err(f) = committed {
+            self.failures
+                .borrow_mut()
+                .commit_failed(rows.len(), &f.error, &self.internal_tx);
+            let dropped = self.spill.borrow_mut().put_back(rows, Instant::now());
+            counters::SPILL_DROPPED.add(dropped as u64);
+        }
+    }
+
+    /// One last try, before exec'ing or exiting. What's left after it is lost.
+    fn store_spilled(&self) {
+        let mut spill = self.spill.borrow_mut();
+        while let Some(e) = spill
+            .store(&mut *self.storage.borrow_mut(), Instant::now())
+            .error
+        {
+            if Sqlite::is_transient(&e) {
+                eprintln!(
+                    "squealogd: could not store the {} messages kept to try again: {}",
+                    spill.len(),
+                    e
+                );
+                break;
+            }
+            eprintln!(
+                "squealogd: could not store a message kept to try again: {}",
+                e
+            );
+        }
+    }
+
+    /// What's left before exec'ing (`last` false) or exiting: what the parse threads have,
+    /// the daemon's own messages, what the sampler held back, the spill and the filter
+    /// counts. The outputs are flushed, or finished when exiting. Whatever fails here goes on,
+    /// only stderr is left to say so.
+    fn flush(&self, last: bool) {
+        self.store_parsed(true);
+        self.store_internal();
+        self.release_sampled(Utc::now(), true);
+        self.store_spilled();
+        if let Err(e) = self.enrichers.borrow_mut().filters.write(self.conn) {
+            eprintln!("squealogd: could not store the filter counts: {}", e);
+        }
+        for output in self.outputs.borrow_mut().iter_mut() {
+            if last {
+                output.finish();
+            } else {
+                output.flush();
+            }
+        }
+    }
+
+    /// Enriches, stores and passes on a message. `local` is for sources on this machine,
+    /// whose messages can have its hostname.
+    fn ingest(
+        &self,
+        socket: &str,
+        local: bool,
+        raw: &str,
+        msg: Message<&str>,
+    ) -> rusqlite::Result<()> {
+        if self.settings.debug_ingest {
+            self.dump.borrow_mut().show(socket, &msg);
+        }
+        let local_hostname = self.hostname.borrow();
+        let mut msg: Message<&str> = msg;
+        let hostname_source = local_hostname.fill(&mut msg, local);
+        let _attempt = self.progress.attempt();
+        let recv_time = self.arrived.get().unwrap_or_else(Utc::now);
+        // Before anything is stored or sent, so secrets don't end up anywhere.
+        let mut rec = enrich::Record::new(msg, raw);
+        if self.enrichers.borrow().run(
+            &enrich::SourceInfo {
+                socket,
+                time: recv_time,
+            },
+            &mut rec,
+        ) == enrich::Action::Drop
+        {
+            self.progress.done();
+            return Ok(());
+        }
+        let (msg, raw, changes) = rec.into_parts();
+        let msg = changes.apply(msg);
+        let raw = changes.raw(raw);
+        // 0 with --no-db, which stores nothing, and for a message that has to wait to be.
+        let mut id = 0;
+        if !self.settings.no_db {
+            let row = crate::schema::Row {
+                facility: msg.facility.map(|x| x as i64),
+                severity: msg.severity.map(|x| x as i64),
+                socket,
+                hostname: msg.hostname,
+                hostname_source,
+                appname: msg.appname,
+                pid: msg.procid.as_ref().and_then(|p| match p {
+                    ProcId::PID(i) => Some(*i),
+                    _ => None,
+                }),
+                msgid: msg.msgid,
+                time: msg.timestamp,
+                recv_time,
+                boot: Some(self.boot),
+                msg: msg.msg,
+                sdata: crate::sdata::to_json(&msg.structured_data),
+            };
+            self.retry_spilled(Instant::now());
+            let mut spill = self.spill.borrow_mut();
+            let spilled = if spill.is_empty() {
+                let inserted = self.storage.borrow_mut().insert(&row);
+                match inserted {
+                    Ok(n) => {
+                        id = n;
+                        self.batch.borrow_mut().push(n, &row);
+                        false
+                    }
+                    Err(f) => {
+                        // The rows of the batch it undid go first, then this one, if it might
+                        // be stored later.
+                        if self.batch.borrow().is_open() {
+                            let undone = self.batch.borrow_mut().undone(f.undone as usize);
+                            let dropped = spill.put_back(undone, Instant::now());
+                            counters::SPILL_DROPPED.add(dropped as u64);
+                        }
+                        if !Sqlite::is_transient(&f.error) {
+                            return Err(f.error);
+                        }
+                        self.failures.borrow_mut().insert_spilled(
+                            socket,
+                            &f.error,
+                            &self.internal_tx,
+                        );
+                        true
+                    }
+                }
+            } else {
+                // After the ones waiting already.
+                true
+            };
+            if spilled && !spill.push(&row, Instant::now()) {
+                counters::SPILL_DROPPED.inc();
+            }
+        }
+        let out = output::Outgoing {
+            id,
+            socket,
+            raw,
+            msg: &msg,
+            recv_time,
+        };
+        self.last_stored.set(Instant::now());
+        self.progress.done();
+        for output in self.outputs.borrow_mut().iter_mut() {
+            output.send(&out);
+        }
+        Ok(())
+    }
+
+    fn stored(&self, socket: &str, r: rusqlite::Result<()>) {
+        if let Err(e) = r {
+            self.failures
+                .borrow_mut()
+                .insert_failed(socket, &e, &self.internal_tx);
+        }
+    }
+
+    /// A failure to store the daemon's own messages only goes to stderr: logging it would just
+    /// make another one.
+    fn store_internal(&self) {
+        for m in self.internal_rx.try_iter() {
+            if let Err(e) = self.ingest(m.socket, true, &m.msg, m.message()) {
+                eprintln!("squealogd: could not store {:?}: {}", m.msg, e);
+            }
+        }
+    }
+
+    /// Runs the sampler ended by `now` (or all of them): their held back last messages, each
+    /// stored with the time it arrived, then a notice of what was left out.
+    fn release_sampled(&self, now: DateTime<Utc>, all: bool) {
+        let runs = {
+            let enrichers = self.enrichers.borrow();
+            if all {
+                enrichers.sampler.finish_all()
+            } else {
+                enrichers.sampler.finish(now)
+            }
+        };
+        if runs.is_empty() {
+            return;
+        }
+        for run in &runs {
+            if let Some(ref held) = run.held {
+                let was = self.arrived.replace(Some(held.time));
+                let r = self.enrichers.borrow().sampler.release(|| {
+                    self.ingest(&run.socket, false, &held.raw, payload::borrowed(&held.msg))
+                });
+                self.arrived.set(was);
+                self.stored(&run.socket, r);
+            }
+            let msg = internal::Internal {
+                socket: internal::SOCKET,
+                facility: SyslogFacility::LOG_SYSLOG,
+                appname: "squealogd".to_owned(),
+                pid: None,
+                severity: SyslogSeverity::SEV_NOTICE,
+                msg: run.summary(),
+            };
+            self.stored(
+                msg.socket,
+                self.ingest(msg.socket, true, &msg.msg, msg.message()),
+            );
+        }
+        if let Err(e) = sampler::write(self.conn, &runs) {
+            self.internal_tx.log(
+                SyslogSeverity::SEV_ERR,
+                format!("could not store the sampling counts: {}", e),
+            );
+        }
+    }
+
+    /// Parses and stores a datagram `socket` received, or hands it to the parse threads.
+    fn take_datagram(
+        &self,
+        socket: &Arc<str>,
+        local: bool,
+        parser: &Arc<dyn PayloadParser>,
+        strict: bool,
+        boot_time: DateTime<Utc>,
+        data: &[u8],
+    ) {
+        if let Some(ref mut pool) = *self.pool.borrow_mut() {
+            pool.submit(socket.clone(), local, parser.clone(), strict, data);
+            return;
+        }
+        let line = String::from_utf8_lossy(data);
+        let ctx = SourceCtx {
+            socket,
+            local,
+            boot_time,
+            received: self.arrived.get().unwrap_or_else(Utc::now),
+        };
+        let (msg, failed) = payload::parse_or_keep(&**parser, data, &line, &ctx);
+        if failed.is_some() {
+            counters::PAYLOAD_PARSE_FAILED.inc();
+            if strict {
+                counters::PAYLOAD_DROPPED.inc();
+                return;
+            }
+        }
+        let r = self.ingest(socket, local, &line, payload::borrowed(&msg));
+        self.stored(socket, r);
+    }
+
+    /// The same for what a read from klog got, a line per message. Returns how many there
+    /// were.
+    fn take_klog(
+        &self,
+        socket: &Arc<str>,
+        parser: &Arc<dyn PayloadParser>,
+        boot_time: DateTime<Utc>,
+        data: &[u8],
+    ) -> u64 {
+        let msgs = String::from_utf8_lossy(data);
+        let ctx = SourceCtx {
+            socket,
+            local: true,
+            boot_time,
+            received: self.arrived.get().unwrap_or_else(Utc::now),
+        };
+        let mut count = 0;
+        for line in msgs.lines() {
+            count += 1;
+            let (msg, failed) = payload::parse_or_keep(&**parser, line.as_bytes(), line, &ctx);
+            if failed.is_some() {
+                counters::PAYLOAD_PARSE_FAILED.inc();
+            }
+            let r = self.ingest(socket, true, line, payload::borrowed(&msg));
+            self.stored(socket, r);
+        }
+        count
+    }
+
+    /// Ingests what one read got from `source` (here or in the reactor), false if there was
+    /// nothing (more) to read.
+    fn handle(&self, source: &mut LogSource, read: Received) -> bool {
+        let data = match read {
+            Received::Data(data) => data,
+            Received::Empty => return false,
+            Received::Lost(e) => {
+                source.lost = true;
+                self.internal_tx.log(
+                    SyslogSeverity::SEV_CRIT,
+                    format!(
+                        "{} can't be read from anymore, no longer listening on it: {}",
+                        source.sockname, e
+                    ),
+                );
+                return false;
+            }
+            Received::Failed(e) => {
+                self.failures
+                    .borrow_mut()
+                    .recv_failed(&source.sockname, &e, &self.internal_tx);
+                return false;
+            }
+        };
+        if let Some(ref recorder) = self.recorder {
+            let transport = match source.xport {
+                LogTransport::Udp(_) => capture::Transport::Udp,
+                LogTransport::UnixDgram(_) => capture::Transport::Unix,
+                #[cfg(target_os = "freebsd")]
+                LogTransport::Klog(_) => capture::Transport::Klog,
+            };
+            let written =
+                recorder
+                    .borrow_mut()
+                    .write(Utc::now(), &source.sockname, transport, data);
+            if written.is_err() {
+                counters::RECORD_FAILED.inc();
+            }
+        }
+        match source.xport {
+            LogTransport::Udp(_) | LogTransport::UnixDgram(_) => {
+                source.received += 1;
+                let local = matches!(source.xport, LogTransport::UnixDgram(_));
+                self.take_datagram(
+                    &source.sockname,
+                    local,
+                    &source.parser,
+                    source.strict,
+                    self.boottime,
+                    data,
+                );
+            }
+            #[cfg(target_os = "freebsd")]
+            LogTransport::Klog(_) => {
+                let boottime = {
+                    let mut anchor = self.klog_anchor.borrow_mut();
+                    if let Some(step) = anchor.update(boottime::measure()) {
+                        self.internal_tx.log(
+                            SyslogSeverity::SEV_NOTICE,
+                            format!(
+                                "the clock stepped by {}s, klog timestamps now count from {}",
+                                step.num_seconds(),
+                                anchor.get().to_rfc3339()
+                            ),
+                        );
+                    }
+                    anchor.get()
+                };
+                source.received += self.take_klog(&source.sockname, &source.parser, boottime, data);
+            }
+        }
+        true
+    }
+
+    /// Reads from `source` once, see `handle`.
+    fn receive(&self, source: &mut LogSource) -> bool {
+        // Taken out while it's handled, and put back for the next read.
+        let mut buf = std::mem::take(&mut source.buf);
+        let read = transport::recv(source.xport.transport(), &mut buf);
+        let more = self.handle(source, read);
+        source.buf = buf;
+        more
+    }
+
+    /// What the parse threads give back, in the order it was received.
+    fn store_parsed(&self, wait: bool) {
+        let parsed = match *self.pool.borrow_mut() {
+            Some(ref mut pool) if wait => pool.wait(),
+            Some(ref mut pool) => pool.ready(),
+            None => return,
+        };
+        for p in parsed {
+            if !p.rejected {
+                let r = self.ingest(&p.socket, p.local, &p.line, p.message());
+                self.stored(&p.socket, r);
+            }
+            if let Some(ref mut pool) = *self.pool.borrow_mut() {
+                pool.recycle(p);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+    use std::path::Path;
+
+    /// A daemon of its own in `dir`, reading from `dir/log`, parsing on the main thread.
+    fn settings(dir: &Path) -> Settings {
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let config = dir.join("squealogd.toml");
+        std::fs::write(&config, "").unwrap();
+        let args: [std::ffi::OsString; 9] = [
+            "squealogd".into(),
+            "--db".into(),
+            dir.join("log.db").into(),
+            "--config".into(),
+            config.into(),
+            format!("--listen-unix=test={}", dir.join("log").display()).into(),
+            "--no-klog".into(),
+            "--keep-root".into(),
+            "--parse-threads=0".into(),
+        ];
+        Settings::parse_from(args)
+    }
+
+    fn dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("squealogd-{}-{}", name, std::process::id()))
+    }
+
+    fn send(dir: &Path, msgs: &[&str]) {
+        let client = UnixDatagram::unbound().unwrap();
+        for msg in msgs {
+            client.send_to(msg.as_bytes(), dir.join("log")).unwrap();
+        }
+    }
+
+    fn stored(conn: &rusqlite::Connection, socket: &str) -> Vec<String> {
+        let mut stmt = conn
+            .prepare("SELECT msg FROM log WHERE socket = ? ORDER BY id")
+            .unwrap();
+        let msgs = stmt.query_map([socket], |row| row.get(0)).unwrap();
+        msgs.collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn what_a_step_receives_is_stored_in_order() {
+        let dir = dir("step");
+        let settings = settings(&dir);
+        let config = settings.load_config().unwrap();
+        let process = Process::start(&settings, &config).unwrap().unwrap();
+        let conn = open_db(&settings).unwrap();
+        let mut daemon = Daemon::new(&settings, &config, &conn, process).unwrap();
+
+        send(
+            &dir,
+            &[
+                "<13>Oct 14 10:00:00 host app: one",
+                "<13>Oct 14 10:00:01 host app: two",
+            ],
+        );
+        daemon.step().unwrap();
+        assert_eq!(stored(&conn, "test"), ["one", "two"]);
+        assert_eq!(daemon.sources.iter().next().unwrap().received, 2);
+
+        daemon.shutdown();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shutting_down_stores_what_was_not_read_yet_and_removes_the_socket() {
+        let dir = dir("shutdown");
+        let settings = settings(&dir);
+        let config = settings.load_config().unwrap();
+        let process = Process::start(&settings, &config).unwrap().unwrap();
+        let conn = open_db(&settings).unwrap();
+        let daemon = Daemon::new(&settings, &config, &conn, process).unwrap();
+
+        send(&dir, &["<13>Oct 14 10:00:00 host app: late"]);
+        daemon.shutdown();
+        assert_eq!(stored(&conn, "test"), ["late"]);
+        assert!(!dir.join("log").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_daemons_own_messages_are_stored_on_the_next_step() {
+        let dir = dir("internal");
+        let settings = settings(&dir);
+        let config = settings.load_config().unwrap();
+        let process = Process::start(&settings, &config).unwrap().unwrap();
+        let conn = open_db(&settings).unwrap();
+        let mut daemon = Daemon::new(&settings, &config, &conn, process).unwrap();
+
+        daemon
+            .internal_tx
+            .log(SyslogSeverity::SEV_ERR, "something broke".to_owned());
+        daemon.step().unwrap();
+        assert!(stored(&conn, internal::SOCKET)
+            .iter()
+            .any(|msg| msg == "something broke"));
+
+        daemon.shutdown();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+//! The `sd_notify` protocol, for `Type=notify` units: state changes are datagrams like
+//! `READY=1` sent to the unix socket in `NOTIFY_SOCKET`. Does nothing when that isn't set.
+//!
+//! With `WatchdogSec=`, the main loop pings twice per `WATCHDOG_USEC`, and only after checking
+//! that it can still take the database's write lock, so a wedged database (not just a wedged
+//! loop) gets the daemon restarted. Pings also stop while the `[watchdog]` finds messages
+//! aren't being stored.
+
+use crate::squealogd::internal;
+use rusqlite::Connection;
+use std::os::unix::net::UnixDatagram;
+use std::time::{Duration, Instant};
+use syslog_loose::SyslogSeverity;
+
+pub struct Notifier {
+    sock: Option<(UnixDatagram, String)>,
+    watchdog: Option<Duration>,
+    next_ping: Option<Instant>,
+}
+
+/// `CLOCK_MONOTONIC` in microseconds, which `RELOADING=1` wants to be sent along with.
+fn monotonic_usec() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000 + ts.tv_nsec as u64 / 1000
+}
+
+const VARS: [&str; 3] = ["NOTIFY_SOCKET", "WATCHDOG_USEC", "WATCHDOG_PID"];
+
+/// Keeps what's meant for squealogd from programs it runs.
+pub fn unset_env() {
+    for var in VARS {
+        std::env::remove_var(var);
+    }
+}
+
+impl Notifier {
+    pub fn from_env() -> Notifier {
+        let sock = std::env::var("NOTIFY_SOCKET")
+            .ok()
+            .filter(|path| !path.is_empty())
+            .and_then(|path| Some((UnixDatagram::unbound().ok()?, path)));
+        // The watchdog settings are meant for the main process only, not for anything it runs.
+        let ours =
+            std::env::var("WATCHDOG_PID").map_or(true, |pid| pid == std::process::id().to_string());
+        let watchdog = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse().ok())
+            .filter(|&usec: &u64| ours && usec > 0)
+            .map(|usec| Duration::from_micros(usec / 2));
+        unset_env();
+        Notifier {
+            sock,
+            watchdog,
+            next_ping: None,
+        }
+    }
+
+    /// Sends a state change, failures are not worth more than a complaint on stderr.
+    pub fn notify(&self, state: &str) {
+        let (sock, path) = match self.sock {
+            Some(ref sock) => sock,
+            None => return,
+        };
+        let sent = match path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                std::os::unix::net::SocketAddr::from_abstract_name(name)
+                    .and_then(|addr| sock.send_to_addr(state.as_bytes(), &addr))
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => Err(std::io::ErrorKind::Unsupported.into()),
+            None => sock.send_to(state.as_bytes(), path),
+        };
+        if let Err(e) = sent {
+            internal::log(
+                SyslogSeverity::SEV_WARNING,
+                format!("could not notify {}: {}", path, e),
+            );
+        }
+    }
+
+    pub fn ready(&mut self) {
+        self.notify("READY=1");
+        self.next_ping = self.watchdog.map(|interval| Instant::now() + interval);
+    }
+
+    pub fn reloading(&self) {
+        self.notify(&format!("RELOADING=1\nMONOTONIC_USEC={}", monotonic_usec()));
+    }
+
+    /// Puts the variables back, for the process an upgrade execs (which has the same pid).
+    pub fn restore_env(&self) {
+        if let Some((_, ref path)) = self.sock {
+            std::env::set_var("NOTIFY_SOCKET", path);
+        }
+        if let Some(interval) = self.watchdog {
+            std::env::set_var("WATCHDOG_USEC", (interval.as_micros() * 2).to_string());
+            std::env::set_var("WATCHDOG_PID", std::process::id().to_string());
+        }
+    }
+
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1");
+    }
+
+    /// When the main loop has to wake up for the next ping.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.next_ping
+    }
+
+    /// Pings the watchdog if it's time, and the database is still writable and not `stalled`.
+    pub fn tick(&mut self, conn: &Connection, stalled: bool) {
+        let (interval, next) = match (self.watchdog, self.next_ping) {
+            (Some(interval), Some(next)) => (interval, next),
+            _ => return,
+        };
+        if Instant::now() < next {
+            return;
+        }
+        self.next_ping = Some(Instant::now() + interval);
+        if stalled {
+            return;
+        }
+        match conn.execute_batch("BEGIN IMMEDIATE; ROLLBACK") {
+            Ok(()) => self.notify("WATCHDOG=1"),
+            Err(e) => internal::log(
+                SyslogSeverity::SEV_ERR,
+                format!("database is not writable, not pinging the watchdog: {}", e),
+            ),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Places other than the database that messages go to.
+
+use crate::names;
+use crate::serialize::{self, Record};
+use crate::squealogd::memory;
+use chrono::prelude::*;
+use std::borrow::Cow;
+use std::time::Instant;
+use syslog_loose::{Message, ProcId};
+
+/// A message that was just stored, on its way to the outputs.
+pub struct Outgoing<'a> {
+    /// The row id it got in the database.
+    pub id: i64,
+    pub socket: &'a str,
+    /// The datagram or line as it was received.
+    pub raw: &'a str,
+    pub msg: &'a Message<&'a str>,
+    pub recv_time: DateTime<Utc>,
+}
+
+impl Outgoing<'_> {
+    pub fn facility(&self) -> Option<u8> {
+        self.msg.facility.map(|f| f as u8)
+    }
+
+    pub fn severity(&self) -> Option<u8> {
+        self.msg.severity.map(|s| s as u8)
+    }
+}
+
+/// An output never fails as far as ingestion is concerned: it deals with (and counts) its own
+/// errors, and must not block.
+pub trait Output {
+    fn send(&mut self, msg: &Outgoing);
+
+    /// Called on every wakeup of the main loop, for buffered outputs to flush and such.
+    fn tick(&mut self) {}
+
+    /// How soon the output wants `tick` to be called even if no messages come in.
+    fn deadline(&self) -> Option<Instant> {
+        None
+    }
+
+    /// For outputs with descriptors of their own in the main poller: handles the event and
+    /// returns true if it was for one of them.
+    fn event(&mut self, _ev: &polling::Event) -> bool {
+        false
+    }
+
+    /// Called once when the daemon shuts down, for a last flush and cleaning up.
+    fn finish(&mut self) {}
+
+    /// Writes out whatever is buffered, when asked to.
+    fn flush(&mut self) {}
+
+    /// Reopens files, after they were rotated.
+    fn reopen(&mut self) {}
+
+    /// Gives up some of what's held in memory the way `stage` says, when over the `[memory]`
+    /// cap. Returns false if there was nothing (more) to give up.
+    fn shed(&mut self, _stage: memory::Stage) -> bool {
+        false
+    }
+}
+
+/// What the serializers get for an outgoing message: the receive time if it had no timestamp,
+/// and `hostname` if it had no hostname.
+pub fn record<'a>(out: &Outgoing<'a>, hostname: &'a str) -> Record<'a> {
+    let mut rec = Record::from_message(out.msg, out.recv_time.into());
+    rec.hostname.get_or_insert(Cow::Borrowed(hostname));
+    rec
+}
+
+/// The traditional syslogd file format, `Jan  2 15:04:05 host app[pid]: msg\n`.
+pub fn rfc3164(buf: &mut String, out: &Outgoing, hostname: &str) {
+    serialize::rfc3164(buf, &record(out, hostname), false, None);
+    buf.push('\n');
+}
+
+/// The JSON object subscribers and the like get for a message.
+pub fn json(out: &Outgoing) -> serde_json::Value {
+    let msg = out.msg;
+    let sdata = crate::sdata::to_json(&msg.structured_data)
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or(serde_json::Value::Null);
+    let time = msg.timestamp.unwrap_or_else(|| out.recv_time.into());
+    serde_json::json!({
+        "id": out.id,
+        "time": time.to_rfc3339(),
+        "severity": out.severity(),
+        "severity_name": out.severity().and_then(|s| names::severity_name(s.into())),
+        "socket": out.socket,
+        "hostname": msg.hostname,
+        "appname": msg.appname,
+        "pid": match &msg.procid {
+            Some(ProcId::PID(pid)) => Some(*pid),
+            _ => None,
+        },
+        "msgid": msg.msgid,
+        "msg": msg.msg,
+        "sdata": sdata,
+    })
+}
+
+/// Fills in the `{name}` placeholders of `template` with whatever `value` has for them, passed
+/// through `escape`. Braces that aren't a known placeholder are left alone.
+pub fn fill(
+    template: &str,
+    value: impl Fn(&str) -> Option<String>,
+    escape: impl Fn(&mut String, &str),
+) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after
+            .find('}')
+            .and_then(|end| Some((end, value(&after[..end])?)))
+        {
+            Some((end, value)) => {
+                escape(&mut out, &value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
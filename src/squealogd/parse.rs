@@ -0,0 +1,695 @@
+//! Parsing datagrams on worker threads (`--parse-threads`, by default one less than there are
+//! CPUs), for when parsing saturates the main loop's core before the disk is the limit. The
+//! main loop hands each datagram over with a sequence number and stays the only one storing
+//! messages: results are put back into that order before they're stored, so messages end up in
+//! the database (and in outputs) in the order they were received. With 0 threads, datagrams
+//! are parsed right where they're received, as before.
+//!
+//! Datagrams are copied into buffers that are reused once their messages are stored, and the
+//! parsed fields point into them instead of being copied out, so a message costs no
+//! allocations of its own (structured data aside) once things are warmed up. Only text a
+//! parser had to decode (like `gelf`'s) is copied, and syslog_loose lowercases RFC 3164 months
+//! into a `String`. What's left is per wakeup, not per message: `squealog-bench ingest` counts
+//! about half an allocation per message for a mix of RFC 3164 and 5424 parsed right where
+//! they're received, and a quarter more with a parse thread.
+//!
+//! When the workers fall `QUEUE` datagrams behind, `--backpressure` says what gives:
+//!
+//! - `block` (the default): receiving waits for them, and datagrams pile up in the sockets'
+//!   buffers instead, where the kernel drops them once those are full too (which isn't
+//!   counted here, see `netstat -s`).
+//! - `drop-new`: the datagram just received is dropped.
+//! - `drop-old`: the oldest one still waiting is dropped to make room.
+//!
+//! Messages at crit or above (by the priority in front) aren't dropped: the queue keeps
+//! `RESERVED` places for them, and past those they're parsed on the main thread. Drops are
+//! counted per policy (`parse_newest_dropped`, `parse_oldest_dropped`, waiting as
+//! `parse_queue_blocked`), `parse_queue_high_water` is the most that were ever waiting, and
+//! each episode of the queue being full is summed up as a row once it's drained again.
+
+use crate::payload::{self, PayloadParser, SourceCtx};
+use crate::squealogd::counters;
+use crate::squealogd::internal;
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use polling::Poller;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use syslog_loose::{Message, ProcId, Protocol, StructuredElement, SyslogFacility, SyslogSeverity};
+
+/// Datagrams waiting for a worker, past which `Policy` applies.
+const QUEUE: usize = 1024;
+/// Of those, the places kept for messages at crit or above.
+const RESERVED: usize = 64;
+
+/// What happens to datagrams the workers are too far behind for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    Block,
+    DropNew,
+    DropOld,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(Policy::Block),
+            "drop-new" => Ok(Policy::DropNew),
+            "drop-old" => Ok(Policy::DropOld),
+            _ => Err(format!("invalid backpressure policy '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Policy::Block => "block",
+            Policy::DropNew => "drop-new",
+            Policy::DropOld => "drop-old",
+        })
+    }
+}
+
+/// Whether a datagram's priority says crit or above, going by just the `<PRI>` in front.
+fn urgent(data: &[u8]) -> bool {
+    let end = match data.iter().take(5).position(|&b| b == b'>') {
+        Some(end) if data.first() == Some(&b'<') && end > 1 => end,
+        _ => return false,
+    };
+    std::str::from_utf8(&data[1..end])
+        .ok()
+        .and_then(|pri| pri.parse::<u8>().ok())
+        .is_some_and(|pri| pri & 7 <= SyslogSeverity::SEV_CRIT as u8)
+}
+
+struct Job {
+    seq: u64,
+    socket: Arc<str>,
+    local: bool,
+    parser: Arc<dyn PayloadParser>,
+    strict: bool,
+    boot_time: DateTime<Utc>,
+    data: Vec<u8>,
+}
+
+fn parse(job: Job) -> Parsed {
+    let (data, line) = match String::from_utf8(job.data) {
+        Ok(line) => (None, line),
+        Err(e) => {
+            let data = e.into_bytes();
+            let line = String::from_utf8_lossy(&data).into_owned();
+            (Some(data), line)
+        }
+    };
+    let mut rejected = false;
+    let fields = {
+        let ctx = SourceCtx {
+            socket: &job.socket,
+            local: job.local,
+            boot_time: job.boot_time,
+            received: Utc::now(),
+        };
+        let bytes = data.as_deref().unwrap_or(line.as_bytes());
+        let (msg, failed) = payload::parse_or_keep(&*job.parser, bytes, &line, &ctx);
+        if failed.is_some() {
+            counters::PAYLOAD_PARSE_FAILED.inc();
+            if job.strict {
+                counters::PAYLOAD_DROPPED.inc();
+                rejected = true;
+            }
+        }
+        Fields::of(&line, payload::borrowed(&msg))
+    };
+    Parsed {
+        seq: job.seq,
+        socket: job.socket,
+        local: job.local,
+        line,
+        fields,
+        rejected,
+    }
+}
+
+/// Where a parsed field is: a slice of the line, mostly, which saves copying it out.
+enum Span {
+    In(usize, usize),
+    Other(String),
+}
+
+impl Span {
+    fn of(line: &str, field: &str) -> Span {
+        let start = (field.as_ptr() as usize).wrapping_sub(line.as_ptr() as usize);
+        match line.get(start..start.wrapping_add(field.len())) {
+            Some(_) => Span::In(start, start + field.len()),
+            None => Span::Other(field.to_owned()),
+        }
+    }
+
+    fn get<'a>(&'a self, line: &'a str) -> &'a str {
+        match *self {
+            Span::In(start, end) => &line[start..end],
+            Span::Other(ref s) => s,
+        }
+    }
+}
+
+enum Pid {
+    Pid(i32),
+    Name(Span),
+}
+
+/// The message the parser found in a line, without borrowing the line.
+struct Fields {
+    protocol: Protocol,
+    facility: Option<SyslogFacility>,
+    severity: Option<SyslogSeverity>,
+    timestamp: Option<DateTime<FixedOffset>>,
+    hostname: Option<Span>,
+    appname: Option<Span>,
+    procid: Option<Pid>,
+    msgid: Option<Span>,
+    structured_data: Vec<(Span, Vec<(Span, Span)>)>,
+    msg: Span,
+}
+
+impl Fields {
+    fn of(line: &str, m: Message<&str>) -> Fields {
+        let span = |field: &str| Span::of(line, field);
+        Fields {
+            protocol: m.protocol,
+            facility: m.facility,
+            severity: m.severity,
+            timestamp: m.timestamp,
+            hostname: m.hostname.map(span),
+            appname: m.appname.map(span),
+            procid: m.procid.map(|p| match p {
+                ProcId::PID(pid) => Pid::Pid(pid),
+                ProcId::Name(name) => Pid::Name(span(name)),
+            }),
+            msgid: m.msgid.map(span),
+            structured_data: m
+                .structured_data
+                .into_iter()
+                .map(|e| {
+                    let params = e.params.into_iter().map(|(k, v)| (span(k), span(v)));
+                    (span(e.id), params.collect())
+                })
+                .collect(),
+            msg: span(m.msg),
+        }
+    }
+}
+
+/// A datagram, parsed.
+pub struct Parsed {
+    seq: u64,
+    pub socket: Arc<str>,
+    pub local: bool,
+    pub line: String,
+    fields: Fields,
+    /// Didn't parse, from a `strict` source: not to be stored.
+    pub rejected: bool,
+}
+
+impl Parsed {
+    pub fn message(&self) -> Message<&str> {
+        let (f, line) = (&self.fields, self.line.as_str());
+        fn get<'a>(span: &'a Option<Span>, line: &'a str) -> Option<&'a str> {
+            span.as_ref().map(|s| s.get(line))
+        }
+        Message {
+            protocol: match f.protocol {
+                Protocol::RFC3164 => Protocol::RFC3164,
+                Protocol::RFC5424(version) => Protocol::RFC5424(version),
+            },
+            facility: f.facility,
+            severity: f.severity,
+            timestamp: f.timestamp,
+            hostname: get(&f.hostname, line),
+            appname: get(&f.appname, line),
+            procid: f.procid.as_ref().map(|p| match p {
+                Pid::Pid(pid) => ProcId::PID(*pid),
+                Pid::Name(name) => ProcId::Name(name.get(line)),
+            }),
+            msgid: get(&f.msgid, line),
+            structured_data: f
+                .structured_data
+                .iter()
+                .map(|(id, params)| StructuredElement {
+                    id: id.get(line),
+                    params: params
+                        .iter()
+                        .map(|(k, v)| (k.get(line), v.get(line)))
+                        .collect(),
+                })
+                .collect(),
+            msg: f.msg.get(line),
+        }
+    }
+}
+
+/// A stretch of the queue being full.
+struct Episode {
+    since: DateTime<Local>,
+    count: u64,
+}
+
+pub struct Pool {
+    jobs: SyncSender<Job>,
+    /// The workers' end, for taking the oldest back out with `drop-old`.
+    queue: Arc<Mutex<Receiver<Job>>>,
+    /// Jobs handed out that no worker has taken yet.
+    queued: Arc<AtomicUsize>,
+    results: Receiver<Parsed>,
+    policy: Policy,
+    episode: Option<Episode>,
+    internal: internal::Sender,
+    /// For parsers that count from it.
+    boot_time: DateTime<Utc>,
+    /// The next sequence number to hand out, and the next one to store.
+    next_seq: u64,
+    next_ready: u64,
+    /// Results that came back before one handed out earlier, `None` for those dropped.
+    early: BTreeMap<u64, Option<Parsed>>,
+    /// Buffers of stored messages, for the next datagrams.
+    spare: Vec<Vec<u8>>,
+}
+
+/// The number of threads `--parse-threads` defaults to.
+pub fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(0, |n| n.get() - 1)
+}
+
+impl Pool {
+    /// Starts `threads` workers, `None` for 0. Workers wake the main loop through `poller`.
+    pub fn start(
+        threads: usize,
+        boot_time: DateTime<Utc>,
+        policy: Policy,
+        poller: Arc<Poller>,
+        internal: internal::Sender,
+    ) -> anyhow::Result<Option<Pool>> {
+        if threads == 0 {
+            return Ok(None);
+        }
+        let (jobs, queue) = mpsc::sync_channel::<Job>(QUEUE);
+        let queue = Arc::new(Mutex::new(queue));
+        let queued = Arc::new(AtomicUsize::new(0));
+        let (done, results) = mpsc::channel();
+        for i in 0..threads {
+            let queue = queue.clone();
+            let queued = queued.clone();
+            let done: Sender<Parsed> = done.clone();
+            let poller = poller.clone();
+            std::thread::Builder::new()
+                .name(format!("parse {}", i))
+                .spawn(move || loop {
+                    // Ends once the main loop is gone.
+                    let job = match queue.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => return,
+                    };
+                    queued.fetch_sub(1, Ordering::Relaxed);
+                    if done.send(parse(job)).is_err() {
+                        return;
+                    }
+                    let _ = poller.notify();
+                })?;
+        }
+        Ok(Some(Pool {
+            jobs,
+            queue,
+            queued,
+            results,
+            policy,
+            episode: None,
+            internal,
+            boot_time,
+            next_seq: 0,
+            next_ready: 0,
+            early: BTreeMap::new(),
+            spare: vec![],
+        }))
+    }
+
+    pub fn submit(
+        &mut self,
+        socket: Arc<str>,
+        local: bool,
+        parser: Arc<dyn PayloadParser>,
+        strict: bool,
+        datagram: &[u8],
+    ) {
+        let urgent = urgent(datagram);
+        if !urgent && self.queued.load(Ordering::Relaxed) >= QUEUE - RESERVED {
+            match self.policy {
+                Policy::Block => {}
+                Policy::DropNew => {
+                    counters::PARSE_NEWEST_DROPPED.inc();
+                    self.pressure();
+                    return;
+                }
+                Policy::DropOld => self.drop_oldest(),
+            }
+        }
+        let mut data = self.spare.pop().unwrap_or_default();
+        data.clear();
+        data.extend_from_slice(datagram);
+        let job = Job {
+            seq: self.next_seq,
+            socket,
+            local,
+            parser,
+            strict,
+            boot_time: self.boot_time,
+            data,
+        };
+        self.next_seq += 1;
+        // Counted before a worker can take it, so it never goes below 0.
+        let queued = self.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        counters::PARSE_QUEUE_HIGH_WATER.raise(queued as u64);
+        let job = match self.jobs.try_send(job) {
+            Ok(()) => return,
+            Err(TrySendError::Full(job)) => job,
+            // Only if every worker panicked, which leaves nothing to parse the rest.
+            Err(TrySendError::Disconnected(_)) => panic!("the parse threads are gone"),
+        };
+        match self.policy {
+            Policy::Block => {
+                counters::PARSE_QUEUE_BLOCKED.inc();
+                self.pressure();
+                self.jobs.send(job).expect("the parse threads are gone");
+            }
+            // Not even a reserved place left.
+            _ if urgent => {
+                self.queued.fetch_sub(1, Ordering::Relaxed);
+                self.parsed_here(job);
+            }
+            // Full before anything could be taken out (the workers had the queue).
+            _ => {
+                self.queued.fetch_sub(1, Ordering::Relaxed);
+                counters::PARSE_NEWEST_DROPPED.inc();
+                self.pressure();
+                self.dropped(job);
+            }
+        }
+    }
+
+    /// With `drop-old`, makes room by dropping the oldest job nobody has taken yet.
+    fn drop_oldest(&mut self) {
+        // A worker holding the queue is waiting for a job, room will be there.
+        let job = match self.queue.try_lock() {
+            Ok(queue) => queue.try_recv().ok(),
+            Err(_) => None,
+        };
+        let job = match job {
+            Some(job) => job,
+            None => return,
+        };
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        if urgent(&job.data) {
+            self.parsed_here(job);
+        } else {
+            counters::PARSE_OLDEST_DROPPED.inc();
+            self.pressure();
+            self.dropped(job);
+        }
+    }
+
+    fn parsed_here(&mut self, job: Job) {
+        let parsed = parse(job);
+        self.early.insert(parsed.seq, Some(parsed));
+    }
+
+    fn dropped(&mut self, job: Job) {
+        self.early.insert(job.seq, None);
+        if self.spare.len() < QUEUE {
+            self.spare.push(job.data);
+        }
+    }
+
+    fn pressure(&mut self) {
+        self.episode
+            .get_or_insert_with(|| Episode {
+                since: Local::now(),
+                count: 0,
+            })
+            .count += 1;
+    }
+
+    /// Sums up the latest episode once the queue is down to half (or `now`).
+    fn relieved(&mut self, now: bool) {
+        if self.episode.is_none()
+            || !now && self.queued.load(Ordering::Relaxed) > (QUEUE - RESERVED) / 2
+        {
+            return;
+        }
+        let episode = self.episode.take().unwrap();
+        let (severity, what) = match self.policy {
+            Policy::Block => (SyslogSeverity::SEV_NOTICE, "waited for"),
+            _ => (SyslogSeverity::SEV_WARNING, "dropped by"),
+        };
+        self.internal.log(
+            severity,
+            format!(
+                "the parse queue was full from {} to {}: {} message{} {} the {} policy",
+                episode.since.format("%F %T"),
+                Local::now().format("%T"),
+                episode.count,
+                if episode.count == 1 { "" } else { "s" },
+                what,
+                self.policy
+            ),
+        );
+    }
+
+    /// Takes back what's done with, to reuse its buffer.
+    pub fn recycle(&mut self, parsed: Parsed) {
+        if self.spare.len() < QUEUE {
+            self.spare.push(parsed.line.into_bytes());
+        }
+    }
+
+    /// Whether anything handed out hasn't been taken back yet.
+    fn busy(&self) -> bool {
+        self.next_ready < self.next_seq
+    }
+
+    /// Moves what's next in order from `early` to `ready`.
+    fn take(&mut self, ready: &mut Vec<Parsed>) {
+        while let Some(parsed) = self.early.remove(&self.next_ready) {
+            self.next_ready += 1;
+            ready.extend(parsed);
+        }
+    }
+
+    /// What's parsed, in the order it was handed out, up to the first that isn't yet.
+    pub fn ready(&mut self) -> Vec<Parsed> {
+        let mut ready = vec![];
+        while let Ok(parsed) = self.results.try_recv() {
+            self.early.insert(parsed.seq, Some(parsed));
+        }
+        self.take(&mut ready);
+        self.relieved(false);
+        ready
+    }
+
+    /// Waits for everything handed out so far, in order.
+    pub fn wait(&mut self) -> Vec<Parsed> {
+        let mut ready = vec![];
+        self.take(&mut ready);
+        while self.busy() {
+            match self.results.recv() {
+                Ok(parsed) => {
+                    self.early.insert(parsed.seq, Some(parsed));
+                    self.take(&mut ready);
+                }
+                Err(_) => break,
+            }
+        }
+        self.relieved(true);
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload::ParseError;
+    use crate::squealogd::internal::Internal;
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+
+    /// Syslog, except that a message ending in "stall" keeps its worker until it's let go.
+    struct Stall(Arc<AtomicBool>);
+
+    impl PayloadParser for Stall {
+        fn parse<'a>(
+            &self,
+            bytes: &'a [u8],
+            _ctx: &SourceCtx,
+        ) -> Result<payload::Message<'a>, ParseError> {
+            let line = payload::text(bytes)?;
+            while line.ends_with("stall") && self.0.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            Ok(payload::from_borrowed(payload::parse_syslog(line, 2024)))
+        }
+    }
+
+    /// A pool with its one worker stuck on the first message.
+    struct Stalled {
+        pool: Pool,
+        parser: Arc<dyn PayloadParser>,
+        stalled: Arc<AtomicBool>,
+        internal: Receiver<Internal>,
+    }
+
+    impl Stalled {
+        fn new(policy: Policy) -> Stalled {
+            let poller = Arc::new(Poller::new().unwrap());
+            let (internal, rx) = internal::channel(poller.clone(), 7, false);
+            let pool = Pool::start(1, Utc::now(), policy, poller, internal)
+                .unwrap()
+                .unwrap();
+            let stalled = Arc::new(AtomicBool::new(true));
+            let mut s = Stalled {
+                pool,
+                parser: Arc::new(Stall(stalled.clone())),
+                stalled,
+                internal: rx,
+            };
+            s.submit("<14>Jan  2 03:04:05 host app: stall");
+            while s.pool.queued.load(Ordering::Relaxed) > 0 {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            s
+        }
+
+        fn submit(&mut self, line: &str) {
+            self.pool.submit(
+                "test".into(),
+                true,
+                self.parser.clone(),
+                false,
+                line.as_bytes(),
+            );
+        }
+
+        fn info(&mut self, n: usize) {
+            for i in 0..n {
+                self.submit(&format!("<14>Jan  2 03:04:05 host app: info {}", i));
+            }
+        }
+
+        fn crit(&mut self, n: usize) {
+            for i in 0..n {
+                self.submit(&format!("<10>Jan  2 03:04:05 host app: crit {}", i));
+            }
+        }
+
+        fn release_after(&self, after: Duration) {
+            let stalled = self.stalled.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(after);
+                stalled.store(false, Ordering::Relaxed);
+            });
+        }
+
+        /// Everything that made it, in order, and what the summary row said.
+        fn finish(mut self) -> (Vec<String>, Vec<String>) {
+            self.release_after(Duration::ZERO);
+            let kept = self
+                .pool
+                .wait()
+                .iter()
+                .map(|p| p.message().msg.to_owned())
+                .collect();
+            let rows = self.internal.try_iter().map(|i| i.msg).collect();
+            (kept, rows)
+        }
+    }
+
+    fn numbered(what: &str, range: std::ops::Range<usize>) -> Vec<String> {
+        range.map(|i| format!("{} {}", what, i)).collect()
+    }
+
+    /// Room for this many at info before the policy applies, the stalled one aside.
+    const ROOM: usize = QUEUE - RESERVED;
+
+    #[test]
+    fn drop_new_keeps_the_oldest() {
+        let mut s = Stalled::new(Policy::DropNew);
+        s.info(ROOM + 100);
+        s.crit(10);
+        let (kept, rows) = s.finish();
+        let mut expected = vec!["stall".to_owned()];
+        expected.extend(numbered("info", 0..ROOM));
+        expected.extend(numbered("crit", 0..10));
+        assert_eq!(kept, expected);
+        assert_eq!(rows.len(), 1, "{:?}", rows);
+        assert!(
+            rows[0].ends_with(": 100 messages dropped by the drop-new policy"),
+            "{:?}",
+            rows
+        );
+        assert!(counters::PARSE_NEWEST_DROPPED.get() >= 100);
+    }
+
+    #[test]
+    fn drop_old_keeps_the_newest() {
+        let mut s = Stalled::new(Policy::DropOld);
+        s.info(ROOM + 100);
+        s.crit(10);
+        let (kept, rows) = s.finish();
+        let mut expected = vec!["stall".to_owned()];
+        expected.extend(numbered("info", 100..ROOM + 100));
+        expected.extend(numbered("crit", 0..10));
+        assert_eq!(kept, expected);
+        assert_eq!(rows.len(), 1, "{:?}", rows);
+        assert!(
+            rows[0].ends_with(": 100 messages dropped by the drop-old policy"),
+            "{:?}",
+            rows
+        );
+        assert!(counters::PARSE_OLDEST_DROPPED.get() >= 100);
+    }
+
+    #[test]
+    fn crit_is_kept_past_the_reserved_places() {
+        let mut s = Stalled::new(Policy::DropNew);
+        s.info(ROOM + 10);
+        // More than there are reserved places, so the last ones are parsed right away.
+        s.crit(RESERVED + 10);
+        let (kept, _) = s.finish();
+        let mut expected = vec!["stall".to_owned()];
+        expected.extend(numbered("info", 0..ROOM));
+        expected.extend(numbered("crit", 0..RESERVED + 10));
+        assert_eq!(kept, expected);
+    }
+
+    #[test]
+    fn block_waits_and_keeps_everything() {
+        let mut s = Stalled::new(Policy::Block);
+        let blocked = counters::PARSE_QUEUE_BLOCKED.get();
+        s.release_after(Duration::from_millis(100));
+        s.info(QUEUE + 100);
+        let (kept, rows) = s.finish();
+        let mut expected = vec!["stall".to_owned()];
+        expected.extend(numbered("info", 0..QUEUE + 100));
+        assert_eq!(kept, expected);
+        assert!(counters::PARSE_QUEUE_BLOCKED.get() > blocked);
+        assert_eq!(rows.len(), 1, "{:?}", rows);
+        assert!(
+            rows[0].contains(" waited for the block policy"),
+            "{:?}",
+            rows
+        );
+    }
+}
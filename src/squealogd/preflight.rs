@@ -0,0 +1,329 @@
+//! Checking everything that's configured before anything is set up, so that a broken
+//! environment (a missing database directory, a port that needs root, a stream socket passed
+//! by socket activation) shows up as a list of what's wrong with what to do about each, rather
+//! than as the first error from deep inside setup. `--preflight-only` runs just the checks, for
+//! rc scripts and deployment pipelines.
+//!
+//! A check is a name and a function in `CHECKS`; features that need something from the
+//! environment add theirs there.
+
+use crate::squealogd::config::Config;
+use crate::squealogd::listen;
+use crate::squealogd::settings::Settings;
+use crate::squealogd::{dbfile, privileges, sandbox};
+use std::ffi::CString;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Something that would keep the daemon from starting, and how to fix it.
+#[derive(Debug)]
+pub struct Problem {
+    pub what: String,
+    pub hint: String,
+}
+
+impl Problem {
+    pub fn new(what: String, hint: &str) -> Problem {
+        Problem {
+            what,
+            hint: hint.to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.what, self.hint)
+    }
+}
+
+/// What the checks look at.
+pub struct Context<'a> {
+    pub settings: &'a Settings,
+    pub config: &'a Config,
+    pub activation: &'a listen::Activation,
+    /// An upgrade handed over the sockets, listeners and pidfile, which were fine before.
+    pub upgrade: bool,
+}
+
+pub struct Check {
+    pub name: &'static str,
+    run: fn(&Context) -> Vec<Problem>,
+}
+
+pub static CHECKS: &[Check] = &[
+    Check {
+        name: "database",
+        run: database,
+    },
+    Check {
+        name: "sockets",
+        run: sockets,
+    },
+    #[cfg(target_os = "freebsd")]
+    Check {
+        name: "klog",
+        run: klog,
+    },
+    Check {
+        name: "listeners",
+        run: listeners,
+    },
+    Check {
+        name: "pidfile",
+        run: pidfile,
+    },
+    Check {
+        name: "privileges",
+        run: user,
+    },
+    Check {
+        name: "sandbox",
+        run: confinement,
+    },
+    Check {
+        name: "outputs",
+        run: outputs,
+    },
+    Check {
+        name: "capture",
+        run: capture,
+    },
+    Check {
+        name: "enrich",
+        run: enrich,
+    },
+];
+
+/// Whether this process can write to `path`.
+pub fn writable(path: &Path) -> bool {
+    match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 },
+        Err(_) => false,
+    }
+}
+
+/// The directory something is created in, which has to exist and be writable.
+pub fn directory(what: &str, path: &Path) -> Option<Problem> {
+    let dir = dbfile::dir(path);
+    if !dir.is_dir() {
+        Some(Problem::new(
+            format!(
+                "The directory {:?} for {} {:?} doesn't exist",
+                dir, what, path
+            ),
+            "create it, or configure another path",
+        ))
+    } else if !writable(dir) {
+        Some(Problem::new(
+            format!(
+                "Can't create {} {:?}: {:?} is not writable",
+                what, path, dir
+            ),
+            "start squealogd as a user that can write there (usually root)",
+        ))
+    } else {
+        None
+    }
+}
+
+fn database(ctx: &Context) -> Vec<Problem> {
+    if ctx.settings.no_db {
+        return match ctx.config.relay.tcp.first() {
+            Some(relay) => vec![Problem::new(
+                format!("[[relay.tcp]] to {} relays from the database", relay.to),
+                "leave out --no-db, or the relay",
+            )],
+            None => vec![],
+        };
+    }
+    dbfile::check(&ctx.settings.db, ctx.settings.db_file)
+        .into_iter()
+        .collect()
+}
+
+fn sockets(ctx: &Context) -> Vec<Problem> {
+    if ctx.upgrade {
+        return vec![];
+    }
+    // Nothing is bound for a replay, the config only says how to parse.
+    if ctx.settings.replay.is_some() {
+        return listen::check_parsers(&ctx.config.listen);
+    }
+    listen::check(&ctx.config.listen, ctx.activation)
+}
+
+fn capture(ctx: &Context) -> Vec<Problem> {
+    let mut problems = vec![];
+    if let Some(ref dir) = ctx.settings.record {
+        if !dir.is_dir() {
+            problems.push(Problem::new(
+                format!("The capture directory {:?} doesn't exist", dir),
+                "create it, or record somewhere else",
+            ));
+        } else if !writable(dir) {
+            problems.push(Problem::new(
+                format!("Can't create capture files in {:?}", dir),
+                "make it writable by the user squealogd starts as",
+            ));
+        }
+    }
+    if let Some(ref replay) = ctx.settings.replay {
+        match crate::capture::files(&replay.dir) {
+            Ok(files) if files.is_empty() => problems.push(Problem::new(
+                format!("There are no capture files in {:?}", replay.dir),
+                "record some with --record, or replay another directory",
+            )),
+            Ok(_) => (),
+            Err(e) => problems.push(Problem::new(
+                format!("Can't read the capture directory {:?}: {}", replay.dir, e),
+                "replay a directory --record wrote capture files to",
+            )),
+        }
+    }
+    problems
+}
+
+#[cfg(target_os = "freebsd")]
+fn klog(ctx: &Context) -> Vec<Problem> {
+    if !ctx.settings.klog || ctx.upgrade {
+        return vec![];
+    }
+    use std::os::unix::fs::OpenOptionsExt;
+    // Only one process can have it open, this also finds another syslogd holding it.
+    match std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open("/dev/klog")
+    {
+        Ok(_) => vec![],
+        Err(e) => vec![Problem::new(
+            format!("Can't read kernel messages from /dev/klog: {}", e),
+            "stop the syslogd reading it, or pass --no-klog",
+        )],
+    }
+}
+
+fn listeners(ctx: &Context) -> Vec<Problem> {
+    // A replay doesn't open them.
+    if ctx.upgrade || ctx.settings.replay.is_some() {
+        return vec![];
+    }
+    let mut problems = vec![];
+    if let Some(ref cfg) = ctx.config.pubsub {
+        let path = cfg
+            .path
+            .as_deref()
+            .unwrap_or(Path::new(crate::squealogd::pubsub::DEFAULT_PATH));
+        problems.extend(directory("the [pubsub] socket", path));
+    }
+    if let Some(ref cfg) = ctx.config.control {
+        let path = cfg
+            .path
+            .as_deref()
+            .unwrap_or(Path::new(crate::squealogd::control::DEFAULT_PATH));
+        problems.extend(directory("the [control] socket", path));
+    }
+    problems
+}
+
+fn enrich(ctx: &Context) -> Vec<Problem> {
+    crate::squealogd::enrich::check(ctx.config)
+}
+
+fn pidfile(ctx: &Context) -> Vec<Problem> {
+    match ctx.settings.daemon.pidfile {
+        Some(ref path) if !ctx.upgrade => directory("the pidfile", path).into_iter().collect(),
+        _ => vec![],
+    }
+}
+
+fn user(ctx: &Context) -> Vec<Problem> {
+    if unsafe { libc::geteuid() } != 0 || ctx.settings.keep_root {
+        return vec![];
+    }
+    let name = ctx
+        .config
+        .privileges
+        .user
+        .as_deref()
+        .unwrap_or(privileges::DEFAULT_USER);
+    match privileges::User::lookup(name) {
+        Ok(Some(_)) => vec![],
+        Ok(None) => vec![Problem::new(
+            format!("There is no user {} to run as", name),
+            "create it, set another one as [privileges] user, or pass --keep-root to stay root",
+        )],
+        Err(e) => vec![Problem::new(
+            format!("Could not look up the user {}: {}", name, e),
+            "check the password database (nsswitch.conf)",
+        )],
+    }
+}
+
+fn confinement(ctx: &Context) -> Vec<Problem> {
+    match sandbox::check(ctx.settings.sandbox, ctx.config) {
+        Ok(()) => vec![],
+        Err(e) => vec![Problem::new(
+            e.to_string(),
+            "leave out what it names, or the sandbox flag",
+        )],
+    }
+}
+
+fn outputs(ctx: &Context) -> Vec<Problem> {
+    let mut problems = vec![];
+    for rule in &ctx.config.file {
+        let dir = dbfile::dir(&rule.path);
+        if !dir.is_dir() {
+            problems.push(Problem::new(
+                format!(
+                    "The directory {:?} for [[file]] {:?} doesn't exist",
+                    dir, rule.path
+                ),
+                "create it, or point the rule somewhere else",
+            ));
+        }
+    }
+    for hook in &ctx.config.exec {
+        let program = match hook.command.first() {
+            Some(program) => Path::new(program),
+            None => {
+                problems.push(Problem::new(
+                    "An [[exec]] hook has an empty command".to_owned(),
+                    "give it the program to run and its arguments",
+                ));
+                continue;
+            }
+        };
+        // Bare names are looked up in PATH when started.
+        if program.components().count() > 1 && !program.is_file() {
+            problems.push(Problem::new(
+                format!("The [[exec]] program {:?} doesn't exist", program),
+                "install it, or fix the command",
+            ));
+        }
+    }
+    problems
+}
+
+/// Runs every check, describing what's wrong on stderr. Fails if anything is.
+pub fn run(ctx: &Context) -> anyhow::Result<()> {
+    let mut failed = 0;
+    for check in CHECKS {
+        for problem in (check.run)(ctx) {
+            eprintln!("squealogd: {}: {}", check.name, problem.what);
+            eprintln!("    {}", problem.hint);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!(
+            "{} problem{} found before starting",
+            failed,
+            if failed == 1 { "" } else { "s" }
+        );
+    }
+    Ok(())
+}
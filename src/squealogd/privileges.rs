@@ -0,0 +1,289 @@
+//! Giving up root once everything that needs it is done: the sources are open, the database is
+//! migrated and the pubsub socket is bound. The user is `_squealog` unless configured otherwise:
+//!
+//! ```toml
+//! [privileges]
+//! user = "_squealog"
+//! ```
+//!
+//! The database and its `-wal`/`-shm` files are handed over to that user. Its directory has to
+//! be writable too (SQLite and the relay position files create files there); if it isn't, it's
+//! only handed over when it has nothing but the database in it, so that pointing `SQUEALOG_DB`
+//! at something like `/var/log/log.db` doesn't give away `/var/log`. Outputs that open files
+//! later (on SIGHUP, say) do that as the user as well.
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+pub const DEFAULT_USER: &str = "_squealog";
+
+pub struct User {
+    pub name: CString,
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+}
+
+impl User {
+    /// Looks the user up in the password database, `None` if there's no such user.
+    pub fn lookup(name: &str) -> io::Result<Option<User>> {
+        let cname =
+            CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut buf = vec![0 as libc::c_char; 16384];
+        let mut result = std::ptr::null_mut();
+        let err = unsafe {
+            libc::getpwnam_r(
+                cname.as_ptr(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if err != 0 {
+            return Err(io::Error::from_raw_os_error(err));
+        }
+        if result.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(User {
+            name: unsafe { CStr::from_ptr(pwd.pw_name) }.to_owned(),
+            uid: pwd.pw_uid,
+            gid: pwd.pw_gid,
+        }))
+    }
+
+    /// Whether the user can write to something with this owner and mode, going by its primary
+    /// group only.
+    fn can_write(&self, meta: &std::fs::Metadata) -> bool {
+        let mode = meta.mode();
+        (meta.uid() == self.uid && mode & 0o200 != 0)
+            || (meta.gid() == self.gid && mode & 0o020 != 0)
+            || mode & 0o002 != 0
+    }
+}
+
+/// The system calls involved, so the sequence can be followed without actually being root.
+pub trait Sys {
+    fn euid(&self) -> libc::uid_t;
+    fn chown(&mut self, path: &Path, uid: libc::uid_t, gid: libc::gid_t) -> io::Result<()>;
+    fn initgroups(&mut self, user: &User) -> io::Result<()>;
+    fn setgid(&mut self, gid: libc::gid_t) -> io::Result<()>;
+    fn setuid(&mut self, uid: libc::uid_t) -> io::Result<()>;
+}
+
+pub struct Real;
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl Sys for Real {
+    fn euid(&self) -> libc::uid_t {
+        unsafe { libc::geteuid() }
+    }
+
+    fn chown(&mut self, path: &Path, uid: libc::uid_t, gid: libc::gid_t) -> io::Result<()> {
+        let path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        check(unsafe { libc::chown(path.as_ptr(), uid, gid) })
+    }
+
+    fn initgroups(&mut self, user: &User) -> io::Result<()> {
+        // The type of the group argument differs between platforms.
+        #[allow(clippy::unnecessary_cast)]
+        check(unsafe { libc::initgroups(user.name.as_ptr(), user.gid as _) })
+    }
+
+    fn setgid(&mut self, gid: libc::gid_t) -> io::Result<()> {
+        check(unsafe { libc::setgid(gid) })
+    }
+
+    fn setuid(&mut self, uid: libc::uid_t) -> io::Result<()> {
+        check(unsafe { libc::setuid(uid) })
+    }
+}
+
+/// Whether `dir` only has the database's own files in it.
+fn dedicated(dir: &Path, db: &Path) -> io::Result<bool> {
+    let name = match db.file_name() {
+        Some(name) => name.as_bytes(),
+        None => return Ok(false),
+    };
+    for entry in std::fs::read_dir(dir)? {
+        if !entry?.file_name().as_bytes().starts_with(name) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Makes `db` and its directory writable by `user`.
+fn give(sys: &mut impl Sys, user: &User, db: &Path) -> anyhow::Result<()> {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut path = db.as_os_str().to_owned();
+        path.push(suffix);
+        let path = Path::new(&path);
+        match std::fs::metadata(path) {
+            Ok(meta) if meta.uid() == user.uid => (),
+            Ok(_) => sys.chown(path, user.uid, user.gid)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let dir = crate::squealogd::dbfile::dir(db);
+    if !user.can_write(&std::fs::metadata(dir)?) {
+        if !dedicated(dir, db)? {
+            anyhow::bail!(
+                "{:?} is not writable by {:?} and has other files in it; \
+                move the database into a directory of its own or pass --keep-root",
+                dir,
+                user.name
+            );
+        }
+        sys.chown(dir, user.uid, user.gid)?;
+    }
+    Ok(())
+}
+
+/// Hands the database (if there is one) over to `user` and switches to it for good.
+pub fn drop_to(sys: &mut impl Sys, user: &User, db: Option<&Path>) -> anyhow::Result<()> {
+    if let Some(db) = db {
+        give(sys, user, db)?;
+    }
+    // Groups first: once the uid is gone, so is the permission to change them.
+    sys.initgroups(user)?;
+    sys.setgid(user.gid)?;
+    sys.setuid(user.uid)?;
+    if sys.setuid(0).is_ok() {
+        anyhow::bail!("Still able to switch back to root after dropping privileges");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Writes down the calls instead of making them. Switching back to root works if
+    /// `root_again`, like it would after only the effective uid was changed.
+    #[derive(Default)]
+    struct Fake {
+        calls: Vec<String>,
+        root_again: bool,
+    }
+
+    impl Sys for Fake {
+        fn euid(&self) -> libc::uid_t {
+            0
+        }
+
+        fn chown(&mut self, path: &Path, uid: libc::uid_t, gid: libc::gid_t) -> io::Result<()> {
+            let name = path.file_name().unwrap().to_string_lossy();
+            self.calls.push(format!("chown {} {}:{}", name, uid, gid));
+            Ok(())
+        }
+
+        fn initgroups(&mut self, user: &User) -> io::Result<()> {
+            self.calls
+                .push(format!("initgroups {}", user.name.to_string_lossy()));
+            Ok(())
+        }
+
+        fn setgid(&mut self, gid: libc::gid_t) -> io::Result<()> {
+            self.calls.push(format!("setgid {}", gid));
+            Ok(())
+        }
+
+        fn setuid(&mut self, uid: libc::uid_t) -> io::Result<()> {
+            self.calls.push(format!("setuid {}", uid));
+            if uid == 0 && !self.root_again {
+                return Err(io::ErrorKind::PermissionDenied.into());
+            }
+            Ok(())
+        }
+    }
+
+    fn user() -> User {
+        User {
+            name: CString::new("_squealog").unwrap(),
+            uid: 1234,
+            gid: 5678,
+        }
+    }
+
+    /// A directory with `files` in it, the database being the first one.
+    fn files(name: &str, files: &[&str]) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!(
+            "squealog-privileges-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        // Not writable by the user, whatever the umask.
+        std::fs::set_permissions(&dir, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+        for file in files {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let db = dir.join(files[0]);
+        (dir, db)
+    }
+
+    #[test]
+    fn hands_over_the_database_then_groups_then_the_uid() {
+        let (dir, db) = files("order", &["log.db", "log.db-wal"]);
+        let mut sys = Fake::default();
+        drop_to(&mut sys, &user(), Some(&db)).unwrap();
+        let dirname = dir.file_name().unwrap().to_string_lossy();
+        assert_eq!(
+            sys.calls,
+            [
+                "chown log.db 1234:5678",
+                "chown log.db-wal 1234:5678",
+                &format!("chown {} 1234:5678", dirname),
+                "initgroups _squealog",
+                "setgid 5678",
+                "setuid 1234",
+                "setuid 0",
+            ]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fails_if_root_can_be_had_back() {
+        let mut sys = Fake {
+            root_again: true,
+            ..Default::default()
+        };
+        let e = drop_to(&mut sys, &user(), None).unwrap_err();
+        assert!(e.to_string().contains("back to root"), "{}", e);
+        assert_eq!(sys.calls.last().unwrap(), "setuid 0");
+    }
+
+    #[test]
+    fn keeps_a_shared_directory() {
+        // Like /var/log, with the database next to everything else.
+        let (dir, db) = files("shared", &["log.db", "messages", "auth.log"]);
+        assert!(!dedicated(&dir, &db).unwrap());
+        let mut sys = Fake::default();
+        let e = drop_to(&mut sys, &user(), Some(&db)).unwrap_err();
+        assert!(e.to_string().contains("has other files in it"), "{}", e);
+        // Without having given up anything yet.
+        assert_eq!(sys.calls, ["chown log.db 1234:5678"]);
+
+        let (own, db) = files("own", &["log.db", "log.db-shm", "log.db.relay-upstream"]);
+        assert!(dedicated(&own, &db).unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_dir_all(own).unwrap();
+    }
+}
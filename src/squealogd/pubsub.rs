@@ -0,0 +1,308 @@
+//! Live feed of new messages as JSON lines on a unix socket, for followers and alerting
+//! scripts that shouldn't have to poll the database:
+//!
+//! ```toml
+//! [pubsub]
+//! path = "/var/run/squealogd.events"
+//! ```
+//!
+//! A client can send one line of `key=value` terms to narrow it down, e.g.
+//! `severity=warning appname=sshd appname=sudo` (same values as the `squealog` options:
+//! `severity`, `facility`, `appname`, `socket`; repeated keys mean any of them). Until then, and
+//! without one, it gets everything. A client that can't keep up gets disconnected once
+//! `MAX_QUEUE` bytes are waiting for it, or sooner when the `[memory]` cap is reached, and
+//! counted.
+
+use crate::filter::{Facility, SeverityRange};
+use crate::squealogd::config;
+use crate::squealogd::counters;
+use crate::squealogd::memory;
+use crate::squealogd::output::{self, Outgoing, Output};
+use crate::squealogd::slab::Keys;
+use polling::{Event, Poller};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub const DEFAULT_PATH: &str = "/var/run/squealogd.events";
+const MAX_QUEUE: usize = 1024 * 1024;
+const MAX_FILTER_LINE: usize = 4096;
+
+#[derive(Default)]
+struct Subscription {
+    severity: Option<SeverityRange>,
+    facilities: Vec<u8>,
+    appnames: Vec<String>,
+    sockets: Vec<String>,
+}
+
+impl Subscription {
+    fn parse(line: &str) -> Result<Subscription, String> {
+        let mut sub = Subscription::default();
+        for term in line.split_whitespace() {
+            let (k, v) = term
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got '{}'", term))?;
+            match k {
+                "severity" | "priority" => sub.severity = Some(v.parse()?),
+                "facility" => sub.facilities.push(v.parse::<Facility>()?.0),
+                "appname" => sub.appnames.push(v.to_owned()),
+                "socket" => sub.sockets.push(v.to_owned()),
+                _ => return Err(format!("unknown key '{}'", k)),
+            }
+        }
+        Ok(sub)
+    }
+
+    fn matches(&self, out: &Outgoing) -> bool {
+        if let Some(SeverityRange(lo, hi)) = self.severity {
+            match out.severity() {
+                Some(sev) if (lo..=hi).contains(&sev) => (),
+                _ => return false,
+            }
+        }
+        let any = |list: &[String], value: Option<&str>| {
+            list.is_empty() || value.is_some_and(|v| list.iter().any(|x| x == v))
+        };
+        (self.facilities.is_empty() || out.facility().is_some_and(|f| self.facilities.contains(&f)))
+            && any(&self.appnames, out.msg.appname)
+            && any(&self.sockets, Some(out.socket))
+    }
+}
+
+struct Client {
+    stream: UnixStream,
+    key: usize,
+    sub: Subscription,
+    /// What the client sent so far, until a full filter line.
+    input: Vec<u8>,
+    /// What couldn't be written to it yet.
+    pending: Vec<u8>,
+}
+
+impl Client {
+    fn interest(&self) -> Event {
+        Event {
+            key: self.key,
+            readable: true,
+            writable: !self.pending.is_empty(),
+        }
+    }
+
+    /// Writes as much of `pending` as the socket takes. Err means the client is gone.
+    fn drain(&mut self) -> io::Result<()> {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.pending.drain(..n);
+                    memory::PUBSUB.sub(n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the filter line. Ok(false) means the client hung up or sent something invalid.
+    fn read(&mut self) -> io::Result<bool> {
+        let mut buf = [0u8; 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Ok(false),
+                Ok(n) => self.input.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        if let Some(end) = self.input.iter().position(|&b| b == b'\n') {
+            let line = String::from_utf8_lossy(&self.input[..end]).into_owned();
+            self.input.clear();
+            match Subscription::parse(&line) {
+                Ok(sub) => self.sub = sub,
+                Err(e) => {
+                    let _ = writeln!(self.stream, "{}", serde_json::json!({ "error": e }));
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(self.input.len() <= MAX_FILTER_LINE)
+    }
+}
+
+pub struct PubSub {
+    path: PathBuf,
+    listener: UnixListener,
+    poller: Arc<Poller>,
+    /// Where the listener's and the clients' poller keys come from.
+    keys: Keys,
+    key: usize,
+    clients: Vec<Client>,
+    line: Vec<u8>,
+}
+
+impl PubSub {
+    pub fn new(cfg: &config::PubSub, poller: Arc<Poller>, keys: Keys) -> anyhow::Result<PubSub> {
+        let path: PathBuf = cfg.path.clone().unwrap_or_else(|| DEFAULT_PATH.into());
+        // Left over from the last run, nothing can be listening on it anymore.
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        PubSub::listen(path, listener, poller, keys)
+    }
+
+    /// Takes over the listener a previous process (in an upgrade) bound.
+    pub fn adopt(
+        cfg: &config::PubSub,
+        fd: RawFd,
+        poller: Arc<Poller>,
+        keys: Keys,
+    ) -> anyhow::Result<PubSub> {
+        let path: PathBuf = cfg.path.clone().unwrap_or_else(|| DEFAULT_PATH.into());
+        PubSub::listen(path, unsafe { UnixListener::from_raw_fd(fd) }, poller, keys)
+    }
+
+    fn listen(
+        path: PathBuf,
+        listener: UnixListener,
+        poller: Arc<Poller>,
+        keys: Keys,
+    ) -> anyhow::Result<PubSub> {
+        listener.set_nonblocking(true)?;
+        let key = keys.take();
+        if let Err(e) = poller.add(&listener, Event::readable(key)) {
+            keys.give_back(key);
+            return Err(e.into());
+        }
+        Ok(PubSub {
+            path,
+            listener,
+            poller,
+            keys,
+            key,
+            clients: vec![],
+            line: vec![],
+        })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+
+    fn accept(&mut self) {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                // WouldBlock, or something like EMFILE that retrying right away won't fix.
+                Err(_) => break,
+            };
+            if stream.set_nonblocking(true).is_err() {
+                continue;
+            }
+            let client = Client {
+                stream,
+                key: self.keys.take(),
+                sub: Subscription::default(),
+                input: vec![],
+                pending: vec![],
+            };
+            match self.poller.add(&client.stream, client.interest()) {
+                Ok(()) => self.clients.push(client),
+                Err(_) => self.keys.give_back(client.key),
+            }
+        }
+        let _ = self
+            .poller
+            .modify(&self.listener, Event::readable(self.key));
+    }
+
+    fn remove(&mut self, i: usize) {
+        let client = self.clients.swap_remove(i);
+        memory::PUBSUB.sub(client.pending.len());
+        let _ = self.poller.delete(&client.stream);
+        self.keys.give_back(client.key);
+    }
+
+    fn format(&mut self, out: &Outgoing) {
+        self.line.clear();
+        let _ = serde_json::to_writer(&mut self.line, &output::json(out));
+        self.line.push(b'\n');
+    }
+}
+
+impl Output for PubSub {
+    fn send(&mut self, out: &Outgoing) {
+        if !self.clients.iter().any(|c| c.sub.matches(out)) {
+            return;
+        }
+        self.format(out);
+        let mut i = 0;
+        while i < self.clients.len() {
+            let client = &mut self.clients[i];
+            if !client.sub.matches(out) {
+                i += 1;
+                continue;
+            }
+            let was_idle = client.pending.is_empty();
+            client.pending.extend_from_slice(&self.line);
+            memory::PUBSUB.add(self.line.len());
+            let ok = client.drain().is_ok() && client.pending.len() <= MAX_QUEUE;
+            if !ok {
+                counters::PUBSUB_DISCONNECTED.inc();
+                self.remove(i);
+                continue;
+            }
+            if was_idle && !client.pending.is_empty() {
+                let _ = self.poller.modify(&client.stream, client.interest());
+            }
+            i += 1;
+        }
+    }
+
+    fn finish(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+
+    fn shed(&mut self, stage: memory::Stage) -> bool {
+        if stage != memory::Stage::Subscribers {
+            return false;
+        }
+        let slowest = (0..self.clients.len())
+            .filter(|&i| !self.clients[i].pending.is_empty())
+            .max_by_key(|&i| self.clients[i].pending.len());
+        match slowest {
+            Some(i) => {
+                counters::PUBSUB_DISCONNECTED.inc();
+                self.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn event(&mut self, ev: &Event) -> bool {
+        if ev.key == self.key {
+            self.accept();
+            return true;
+        }
+        let i = match self.clients.iter().position(|c| c.key == ev.key) {
+            Some(i) => i,
+            None => return false,
+        };
+        let client = &mut self.clients[i];
+        let alive = (!ev.readable || client.read().unwrap_or(false))
+            && (!ev.writable || client.drain().is_ok());
+        if !alive {
+            self.remove(i);
+        } else {
+            let _ = self.poller.modify(&client.stream, client.interest());
+        }
+        true
+    }
+}
@@ -0,0 +1,256 @@
+//! `--async-sources` (with the `tokio` feature): the sockets are read by tokio tasks on a
+//! thread of their own instead of the main loop's poller, each with its own handle on its
+//! socket. What they read goes through a bounded channel to the main loop, which wakes up for
+//! it and stores it like anything it read itself, so the database stays on the main thread.
+//! Kernel messages are still read by the main loop.
+//!
+//! A task takes a place in the channel before reading, so once the main loop is behind,
+//! datagrams wait in the socket's buffer rather than in a task, and nothing read is lost when
+//! the runtime stops.
+
+use crate::transport::Transport;
+use polling::Poller;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::sync::mpsc;
+
+/// Reads not yet taken by the main loop.
+const QUEUE: usize = 1024;
+
+/// What a read got: from the source with the key, a datagram or why there wasn't one.
+pub type Read = (usize, io::Result<Vec<u8>>);
+
+struct Source(Box<dyn Transport>);
+
+impl AsRawFd for Source {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+pub struct Reactor {
+    runtime: Option<tokio::runtime::Runtime>,
+    reads: mpsc::Receiver<Read>,
+    fds: Vec<RawFd>,
+}
+
+impl Reactor {
+    /// Starts reading the sources (a key and a buffer size each), waking up the main loop
+    /// through `poller`.
+    pub fn start(
+        sources: Vec<(usize, &dyn Transport, usize)>,
+        poller: Arc<Poller>,
+    ) -> io::Result<Reactor> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("sources")
+            .enable_io()
+            .build()?;
+        let (tx, reads) = mpsc::channel(QUEUE);
+        let mut fds = vec![];
+        for (key, source, size) in sources {
+            let source = source.try_clone()?;
+            fds.push(source.as_raw_fd());
+            // Registered here, so that failing to shows up as failing to start.
+            let source = {
+                let _entered = runtime.enter();
+                AsyncFd::with_interest(Source(source), Interest::READABLE)?
+            };
+            runtime.spawn(pump(key, source, size, tx.clone(), poller.clone()));
+        }
+        Ok(Reactor {
+            runtime: Some(runtime),
+            reads,
+            fds,
+        })
+    }
+
+    /// The descriptors the tasks read from, for the sandbox.
+    pub fn fds(&self) -> &[RawFd] {
+        &self.fds
+    }
+
+    /// What's been read, as much as there is.
+    pub fn ready(&mut self) -> Vec<Read> {
+        let mut ready = vec![];
+        while let Ok(read) = self.reads.try_recv() {
+            ready.push(read);
+        }
+        ready
+    }
+
+    /// Stops reading, waiting for a read going on right now to be queued, for the main loop
+    /// to take what's queued and read the rest itself.
+    pub fn stop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_timeout(Duration::from_secs(1));
+        }
+    }
+}
+
+async fn pump(
+    key: usize,
+    source: AsyncFd<Source>,
+    size: usize,
+    tx: mpsc::Sender<Read>,
+    poller: Arc<Poller>,
+) {
+    let mut buf = vec![0u8; size];
+    loop {
+        let permit = match tx.reserve().await {
+            Ok(permit) => permit,
+            // The main loop is gone.
+            Err(_) => return,
+        };
+        let mut lost = false;
+        let read = loop {
+            let mut ready = match source.readable().await {
+                Ok(ready) => ready,
+                Err(e) => {
+                    lost = true;
+                    break Err(e);
+                }
+            };
+            match ready.try_io(|s| s.get_ref().0.recv(&mut buf)) {
+                Ok(Err(e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Ok(read) => break read,
+                // Not readable after all, waits again.
+                Err(_) => continue,
+            }
+        };
+        // Broken for good: the main loop says so and drops the source.
+        lost |= matches!(read, Err(ref e) if matches!(e.raw_os_error(), Some(libc::EBADF | libc::ENOTSOCK)));
+        permit.send((key, read.map(|len| buf[..len].to_vec())));
+        let _ = poller.notify();
+        if lost {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{self, Received};
+    use std::os::unix::net::UnixDatagram;
+    use std::time::Instant;
+
+    /// The datagrams each scenario sends, and the buffer size they're read with.
+    fn scenarios() -> Vec<(&'static str, Vec<Vec<u8>>, usize)> {
+        vec![
+            (
+                "in order",
+                (0..100)
+                    .map(|i| format!("<14>message {}", i).into_bytes())
+                    .collect(),
+                1024,
+            ),
+            (
+                "empty",
+                vec![b"<14>before".to_vec(), vec![], b"<14>after".to_vec()],
+                1024,
+            ),
+            (
+                "truncated",
+                vec![vec![b'x'; 300], b"<14>short".to_vec()],
+                64,
+            ),
+            ("not text", vec![vec![0xff, 0, 0xfe, b'\n']], 1024),
+        ]
+    }
+
+    fn pair() -> (UnixDatagram, UnixDatagram) {
+        let (tx, rx) = UnixDatagram::pair().unwrap();
+        rx.set_nonblocking(true).unwrap();
+        (tx, rx)
+    }
+
+    /// What the main loop reads itself, until there's nothing more.
+    fn read_here(rx: &UnixDatagram, size: usize) -> Vec<Vec<u8>> {
+        let mut buf = vec![0; size];
+        let mut reads = vec![];
+        loop {
+            match transport::recv(rx, &mut buf) {
+                Received::Data(data) => reads.push(data.to_vec()),
+                Received::Empty => return reads,
+                Received::Lost(e) | Received::Failed(e) => panic!("{}", e),
+            }
+        }
+    }
+
+    /// What the reactor hands the main loop, for `n` reads.
+    fn read_in_reactor(rx: &UnixDatagram, size: usize, n: usize) -> Vec<Vec<u8>> {
+        let poller = Arc::new(Poller::new().unwrap());
+        let mut reactor =
+            Reactor::start(vec![(7, rx as &dyn Transport, size)], poller.clone()).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut reads = vec![];
+        let mut events = vec![];
+        while reads.len() < n {
+            assert!(
+                Instant::now() < deadline,
+                "only {} reads of {}",
+                reads.len(),
+                n
+            );
+            poller
+                .wait(&mut events, Some(Duration::from_millis(100)))
+                .unwrap();
+            for (key, read) in reactor.ready() {
+                assert_eq!(key, 7);
+                reads.push(read.unwrap());
+            }
+        }
+        reactor.stop();
+        assert!(reactor.ready().is_empty());
+        reads
+    }
+
+    #[test]
+    fn reads_what_the_main_loop_reads() {
+        for (name, datagrams, size) in scenarios() {
+            let (tx, rx) = pair();
+            for datagram in &datagrams {
+                tx.send(datagram).unwrap();
+            }
+            let here = read_here(&rx, size);
+            assert_eq!(here.len(), datagrams.len(), "{}", name);
+            for datagram in &datagrams {
+                tx.send(datagram).unwrap();
+            }
+            assert_eq!(read_in_reactor(&rx, size, here.len()), here, "{}", name);
+        }
+    }
+
+    #[test]
+    fn stopping_leaves_the_rest_to_the_main_loop() {
+        for (name, datagrams, size) in scenarios() {
+            let (tx, rx) = pair();
+            for datagram in &datagrams {
+                tx.send(datagram).unwrap();
+            }
+            let mut reactor = Reactor::start(
+                vec![(7, &rx as &dyn Transport, size)],
+                Arc::new(Poller::new().unwrap()),
+            )
+            .unwrap();
+            reactor.stop();
+            let mut reads: Vec<Vec<u8>> = reactor
+                .ready()
+                .into_iter()
+                .map(|(_, read)| read.unwrap())
+                .collect();
+            reads.extend(read_here(&rx, size));
+
+            let (tx, rx) = pair();
+            for datagram in &datagrams {
+                tx.send(datagram).unwrap();
+            }
+            assert_eq!(reads, read_here(&rx, size), "{}", name);
+        }
+    }
+}
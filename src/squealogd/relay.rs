@@ -0,0 +1,295 @@
+//! Forwarding to a central collector.
+
+use crate::selector::Selector;
+use crate::serialize::{self, Record};
+use crate::squealogd::config;
+use crate::squealogd::counters;
+use crate::squealogd::internal;
+use crate::squealogd::output::{self, Outgoing, Output};
+use chrono::prelude::*;
+use rusqlite::{Connection, OpenFlags};
+use std::borrow::Cow;
+use std::io::{BufWriter, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use syslog_loose::SyslogSeverity;
+
+/// The most a UDP datagram can carry; longer messages are cut rather than not sent at all.
+const MAX_DATAGRAM: usize = 65507;
+
+/// Fire-and-forget UDP, one datagram per message.
+pub struct UdpRelay {
+    sock: UdpSocket,
+    select: Selector,
+    verbatim: bool,
+    hostname: String,
+    buf: String,
+}
+
+impl UdpRelay {
+    pub fn new(cfg: &config::UdpRelay) -> anyhow::Result<UdpRelay> {
+        let dest = cfg
+            .to
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow::format_err!("Could not resolve {}", cfg.to))?;
+        let sock = UdpSocket::bind(if dest.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })?;
+        sock.connect(dest)?;
+        sock.set_nonblocking(true)?;
+        Ok(UdpRelay {
+            sock,
+            select: cfg.select,
+            verbatim: cfg.verbatim,
+            hostname: crate::sys::hostname()?,
+            buf: String::new(),
+        })
+    }
+}
+
+impl Output for UdpRelay {
+    fn send(&mut self, msg: &Outgoing) {
+        if !self.select.matches(msg.facility(), msg.severity()) {
+            return;
+        }
+        let payload = if self.verbatim {
+            msg.raw
+        } else {
+            let rec = output::record(msg, &self.hostname);
+            serialize::rfc5424(&mut self.buf, &rec, Some(MAX_DATAGRAM));
+            &self.buf
+        };
+        // A full send buffer or an unreachable collector is not worth waiting for.
+        match self.sock.send(payload.as_bytes()) {
+            Ok(_) => counters::RELAY_UDP_SENT.inc(),
+            Err(_) => counters::RELAY_UDP_FAILED.inc(),
+        }
+    }
+}
+
+/// Rows read from the database per round.
+const BATCH: usize = 500;
+/// How often to look for new rows when caught up.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const DEFAULT_MAX_BACKLOG: i64 = 1_000_000;
+
+/// Stream relay with RFC 6587 octet-counting framing, optionally over TLS.
+///
+/// Doesn't hook into ingestion at all: a thread of its own reads the log table in id order
+/// and keeps the last id it sent in a file next to the database. So the database is the queue,
+/// the collector being down or slow never holds anything up, and after a restart it carries on
+/// where it left off. When it falls more than `max_backlog` rows behind, the oldest ones are
+/// skipped (and counted).
+pub struct TcpRelay {
+    to: String,
+    tls: bool,
+    select: Selector,
+    max_backlog: i64,
+    position: PathBuf,
+    hostname: String,
+}
+
+impl TcpRelay {
+    pub fn spawn(cfg: &config::TcpRelay, db: &Path) -> anyhow::Result<()> {
+        #[cfg(not(feature = "tls"))]
+        if cfg.tls {
+            anyhow::bail!("TLS relay to {} needs the tls feature", cfg.to);
+        }
+        let name: String = cfg
+            .name
+            .as_deref()
+            .unwrap_or(&cfg.to)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let mut position = db.as_os_str().to_owned();
+        position.push(format!(".relay-{}", name));
+        let relay = TcpRelay {
+            to: cfg.to.clone(),
+            tls: cfg.tls,
+            select: cfg.select,
+            max_backlog: cfg.max_backlog.unwrap_or(DEFAULT_MAX_BACKLOG),
+            position: position.into(),
+            hostname: crate::sys::hostname()?,
+        };
+        let conn = Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        std::thread::Builder::new()
+            .name(format!("relay {}", cfg.to))
+            .spawn(move || relay.run(conn))?;
+        Ok(())
+    }
+
+    fn load_position(&self, conn: &Connection) -> rusqlite::Result<i64> {
+        if let Some(last) = std::fs::read_to_string(&self.position)
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+        {
+            return Ok(last);
+        }
+        // A new relay starts with what comes in from now on, not with all of history.
+        conn.query_row("SELECT coalesce(max(id), 0) FROM log", [], |row| row.get(0))
+    }
+
+    /// Written to a temporary file and renamed, so a crash leaves either the old or the new one.
+    fn save_position(&self, last: i64) -> std::io::Result<()> {
+        let mut tmp = self.position.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, format!("{}\n", last))?;
+        std::fs::rename(&tmp, &self.position)
+    }
+
+    fn connect(&self) -> anyhow::Result<Box<dyn Write + Send>> {
+        let addr = self
+            .to
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow::format_err!("Could not resolve {}", self.to))?;
+        let stream = TcpStream::connect_timeout(&addr, Duration::from_secs(10))?;
+        stream.set_write_timeout(Some(Duration::from_secs(30)))?;
+        if self.tls {
+            #[cfg(feature = "tls")]
+            {
+                let host = self.to.rsplit_once(':').map_or(&*self.to, |(h, _)| h);
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                let stream = native_tls::TlsConnector::new()?.connect(host, stream)?;
+                return Ok(Box::new(BufWriter::new(stream)));
+            }
+        }
+        Ok(Box::new(BufWriter::new(stream)))
+    }
+
+    fn format(&self, buf: &mut String, row: &rusqlite::Row) -> rusqlite::Result<()> {
+        let facility: Option<u8> = row.get(1)?;
+        let severity: Option<u8> = row.get(2)?;
+        let time: DateTime<FixedOffset> = row.get(3)?;
+        let hostname: Option<String> = row.get(4)?;
+        let appname: Option<String> = row.get(5)?;
+        let pid: Option<i64> = row.get(6)?;
+        let msgid: Option<String> = row.get(7)?;
+        let sdata: Option<String> = row.get(8)?;
+        let msg: String = row.get(9)?;
+        let rec = Record {
+            facility,
+            severity,
+            time,
+            hostname: Some(hostname.as_deref().unwrap_or(&self.hostname).into()),
+            appname: appname.as_deref().map(Cow::Borrowed),
+            procid: pid.map(|p| p.to_string().into()),
+            msgid: msgid.as_deref().map(Cow::Borrowed),
+            sdata: sdata
+                .as_deref()
+                .map(Record::sdata_from_json)
+                .unwrap_or_default(),
+            msg: msg.into(),
+        };
+        serialize::rfc5424(buf, &rec, None);
+        Ok(())
+    }
+
+    /// Sends the rows after `last`, returning the new last id.
+    fn send_batch(
+        &self,
+        conn: &Connection,
+        out: &mut dyn Write,
+        mut last: i64,
+    ) -> anyhow::Result<i64> {
+        let max: i64 = conn.query_row("SELECT coalesce(max(id), 0) FROM log", [], |r| r.get(0))?;
+        if max - last > self.max_backlog {
+            let skip = max - self.max_backlog;
+            counters::RELAY_TCP_DROPPED.add((skip - last) as u64);
+            last = skip;
+        }
+        let mut stmt = conn.prepare_cached(
+            "SELECT id, facility, severity, coalesce(time, recv_time), hostname, appname, pid,
+                msgid, sdata, msg
+            FROM log WHERE id > ? ORDER BY id LIMIT ?",
+        )?;
+        let mut rows = stmt.query(rusqlite::params![last, BATCH as i64])?;
+        let mut buf = String::new();
+        let mut sent = 0;
+        let mut next = last;
+        while let Some(row) = rows.next()? {
+            next = row.get(0)?;
+            if !self
+                .select
+                .matches(row.get(1)?, row.get::<_, Option<u8>>(2)?)
+            {
+                continue;
+            }
+            self.format(&mut buf, row)?;
+            write!(out, "{} {}", buf.len(), buf)?;
+            sent += 1;
+        }
+        out.flush()?;
+        counters::RELAY_TCP_SENT.add(sent);
+        Ok(next)
+    }
+
+    fn run(self, conn: Connection) {
+        let mut last = match self.load_position(&conn) {
+            Ok(last) => last,
+            Err(e) => {
+                internal::log(
+                    SyslogSeverity::SEV_ERR,
+                    format!("relay {}: could not read the database: {}", self.to, e),
+                );
+                return;
+            }
+        };
+        let mut out = None;
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            let stream = match out {
+                Some(ref mut stream) => stream,
+                None => match self.connect() {
+                    Ok(stream) => {
+                        backoff = MIN_BACKOFF;
+                        out.insert(stream)
+                    }
+                    Err(e) => {
+                        internal::log(
+                            SyslogSeverity::SEV_WARNING,
+                            format!("relay {}: {}, retrying in {:?}", self.to, e, backoff),
+                        );
+                        counters::RELAY_TCP_RECONNECTS.inc();
+                        std::thread::sleep(backoff);
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                        continue;
+                    }
+                },
+            };
+            match self.send_batch(&conn, &mut **stream, last) {
+                Ok(next) if next == last => std::thread::sleep(POLL_INTERVAL),
+                Ok(next) => {
+                    last = next;
+                    if let Err(e) = self.save_position(last) {
+                        internal::log(
+                            SyslogSeverity::SEV_ERR,
+                            format!("relay {}: could not save position: {}", self.to, e),
+                        );
+                    }
+                }
+                Err(e) => {
+                    // Whatever was in flight gets sent again after reconnecting.
+                    internal::log(
+                        SyslogSeverity::SEV_WARNING,
+                        format!("relay {}: {}", self.to, e),
+                    );
+                    out = None;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Rules for masking secrets before messages are stored or sent anywhere:
+//!
+//! ```toml
+//! [[rewrite]]
+//! regex = 'Authorization: Bearer \S+'
+//! replace = "Authorization: Bearer [redacted]"
+//!
+//! [[rewrite]]
+//! appname = "webapp"
+//! regex = '(?P<user>[\w.+-]+)@[\w-]+\.[\w.-]+'
+//! replace = "$user@[redacted]"
+//! sdata = true
+//! ```
+//!
+//! `replace` can refer to capture groups as `$1` or `$name`. The rules apply in order, each to
+//! the result of the previous ones, to the message text (and with `sdata = true` to structured
+//! data values) of messages in its `select`/`appname` scope. The received text that verbatim
+//! relays send on is rewritten the same way, so nothing downstream sees the original. Like the
+//! filters, the rules are read again on SIGHUP. They're the `rewrite` enricher, see `enrich`.
+
+use crate::selector::Selector;
+use crate::squealogd::config;
+use crate::squealogd::enrich::{Action, Enricher, Record, SourceInfo};
+use regex::Regex;
+use std::borrow::Cow;
+
+struct Rule {
+    select: Selector,
+    appname: Option<String>,
+    regex: Regex,
+    replace: String,
+    sdata: bool,
+}
+
+pub struct Rewrites {
+    rules: Vec<Rule>,
+}
+
+/// The text with `rule` applied, if it changed anything.
+fn replaced(rule: &Rule, text: &str) -> Option<String> {
+    match rule.regex.replace_all(text, rule.replace.as_str()) {
+        Cow::Owned(new) => Some(new),
+        Cow::Borrowed(_) => None,
+    }
+}
+
+impl Rewrites {
+    pub fn new(cfg: &[config::Rewrite]) -> anyhow::Result<Rewrites> {
+        let rules = cfg
+            .iter()
+            .map(|rule| {
+                Ok(Rule {
+                    select: rule.select,
+                    appname: rule.appname.clone(),
+                    regex: Regex::new(&rule.regex)?,
+                    replace: rule.replace.clone(),
+                    sdata: rule.sdata,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Rewrites { rules })
+    }
+}
+
+impl Enricher for Rewrites {
+    fn enrich(&self, _: &SourceInfo, rec: &mut Record) -> Action {
+        let (fac, sev) = (
+            rec.msg.facility.map(|f| f as u8),
+            rec.msg.severity.map(|s| s as u8),
+        );
+        for rule in &self.rules {
+            if !rule.select.matches(fac, sev)
+                || rule
+                    .appname
+                    .as_deref()
+                    .is_some_and(|a| rec.appname() != Some(a))
+            {
+                continue;
+            }
+            if let Some(new) = replaced(rule, rec.text()) {
+                rec.set_text(new);
+            }
+            if let Some(new) = replaced(rule, rec.raw()) {
+                rec.set_raw(new);
+            }
+            if !rule.sdata {
+                continue;
+            }
+            for e in 0..rec.msg.structured_data.len() {
+                for p in 0..rec.msg.structured_data[e].params.len() {
+                    if let Some(new) = replaced(rule, rec.param(e, p)) {
+                        rec.set_param(e, p, new);
+                    }
+                }
+            }
+        }
+        Action::Keep
+    }
+}
@@ -0,0 +1,474 @@
+//! The `sample` enricher: during a storm from one source, storing only some of its info and
+//! debug messages, so they don't fill the disk and bury everything else:
+//!
+//! ```toml
+//! [enrich]
+//! chain = ["filter", "sample", "rewrite"]
+//!
+//! [enrich.sample]
+//! rate = 1000
+//! ```
+//!
+//! A source is a socket and appname. Once one sent more than `rate` messages in a second, its
+//! info and debug messages are kept 1 in N from the next second on, for as long as that goes
+//! on: N is how many times over `rate` the second before was. Such a run always keeps its
+//! first message, and its last one too, which is held back until the run ends (with the first
+//! second under `rate`) and then stored with the time it arrived. Notice and more severe, and
+//! messages without a severity, are never sampled.
+//!
+//! When a run ends, squealogd stores a notice like `sampled: kept 12 of 1200 messages from
+//! nginx between ... and ...`, and adds the counts to the `sample_stats` table, which `squealog
+//! stats` shows. The decisions only depend on the messages' order and receive times, so a
+//! replayed capture is sampled the same way.
+
+use crate::payload::Message;
+use crate::squealogd::config;
+use crate::squealogd::counters;
+use crate::squealogd::enrich::{Action, Enricher, Record, SourceInfo};
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::Connection;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::time::Instant;
+use syslog_loose::{ProcId, Protocol, StructuredElement, SyslogSeverity};
+
+/// How long the rate is counted over.
+fn window() -> Duration {
+    Duration::seconds(1)
+}
+
+/// A message left out for now, stored after all if it turns out to be the run's last.
+pub struct Held {
+    pub time: DateTime<Utc>,
+    pub raw: String,
+    pub msg: Message<'static>,
+}
+
+impl Held {
+    /// The message as the enrichers before the sampler left it.
+    fn new(time: DateTime<Utc>, rec: &Record) -> Held {
+        let owned = |s: &str| Cow::Owned(s.to_owned());
+        let msg = &rec.msg;
+        Held {
+            time,
+            raw: rec.raw().to_owned(),
+            msg: Message {
+                protocol: match msg.protocol {
+                    Protocol::RFC3164 => Protocol::RFC3164,
+                    Protocol::RFC5424(version) => Protocol::RFC5424(version),
+                },
+                facility: msg.facility,
+                severity: msg.severity,
+                timestamp: msg.timestamp,
+                hostname: msg.hostname.map(owned),
+                appname: rec.appname().map(owned),
+                procid: msg.procid.as_ref().map(|p| match p {
+                    ProcId::PID(pid) => ProcId::PID(*pid),
+                    ProcId::Name(name) => ProcId::Name(owned(name)),
+                }),
+                msgid: msg.msgid.map(owned),
+                structured_data: msg
+                    .structured_data
+                    .iter()
+                    .enumerate()
+                    .map(|(e, element)| StructuredElement {
+                        id: owned(element.id),
+                        params: element
+                            .params
+                            .iter()
+                            .enumerate()
+                            .map(|(p, &(k, _))| (owned(k), owned(rec.param(e, p))))
+                            .collect(),
+                    })
+                    .collect(),
+                msg: owned(rec.text()),
+            },
+        }
+    }
+}
+
+/// A stretch of a source being sampled.
+pub struct Run {
+    pub socket: String,
+    pub appname: Option<String>,
+    /// When the first and last of its sampled severities arrived.
+    pub first: DateTime<Utc>,
+    pub last: DateTime<Utc>,
+    /// Messages of the sampled severities, and how many of them are stored.
+    pub seen: u64,
+    pub kept: u64,
+    /// The last one, if it was left out.
+    pub held: Option<Held>,
+}
+
+impl Run {
+    fn new(socket: &str, appname: &str, now: DateTime<Utc>) -> Run {
+        Run {
+            socket: socket.to_owned(),
+            appname: Some(appname).filter(|a| !a.is_empty()).map(str::to_owned),
+            first: now,
+            last: now,
+            seen: 0,
+            kept: 0,
+            held: None,
+        }
+    }
+
+    /// The run as it ended, with the held message counted as kept.
+    fn ended(mut self) -> Run {
+        if self.held.is_some() {
+            self.kept += 1;
+        }
+        self
+    }
+
+    /// The notice stored for the run.
+    pub fn summary(&self) -> String {
+        format!(
+            "sampled: kept {} of {} messages from {} between {} and {} (socket {})",
+            self.kept,
+            self.seen,
+            self.appname.as_deref().unwrap_or("-"),
+            self.first.to_rfc3339(),
+            self.last.to_rfc3339(),
+            self.socket
+        )
+    }
+}
+
+struct Source {
+    /// When the current second started, and how many messages it had so far.
+    window: DateTime<Utc>,
+    count: u64,
+    /// Keeping 1 in `n` of the sampled severities, 1 while not sampling.
+    n: u64,
+    run: Option<Run>,
+}
+
+impl Source {
+    fn new(now: DateTime<Utc>) -> Source {
+        Source {
+            window: now,
+            count: 0,
+            n: 1,
+            run: None,
+        }
+    }
+
+    /// Starts the next second once `now` is past the current one, sampling in it if the one
+    /// before was over `rate`. Returns the run that ended, if that ended one.
+    fn roll(&mut self, socket: &str, appname: &str, rate: u64, now: DateTime<Utc>) -> Option<Run> {
+        if now < self.window + window() {
+            return None;
+        }
+        // With a quiet second in between, the one before doesn't count.
+        self.n = if now < self.window + window() * 2 && self.count > rate {
+            self.count.div_ceil(rate)
+        } else {
+            1
+        };
+        self.window = now;
+        self.count = 0;
+        if self.n == 1 {
+            return self.run.take().map(Run::ended);
+        }
+        if self.run.is_none() {
+            self.run = Some(Run::new(socket, appname, now));
+        }
+        None
+    }
+}
+
+pub struct Sampler {
+    rate: u64,
+    /// By socket, then appname ("" for none).
+    sources: RefCell<HashMap<String, HashMap<String, Source>>>,
+    /// Runs that ended as a message came in, for `finish` to hand out.
+    ended: RefCell<Vec<Run>>,
+    /// When `finish` last looked at every source.
+    finished: Cell<Option<DateTime<Utc>>>,
+    /// While held messages are stored, which go through the chain again.
+    releasing: Cell<bool>,
+}
+
+impl Sampler {
+    pub fn new(cfg: &config::EnrichSample) -> anyhow::Result<Sampler> {
+        if cfg.rate == 0 {
+            anyhow::bail!("[enrich.sample] rate has to be more than 0");
+        }
+        Ok(Sampler {
+            rate: cfg.rate,
+            sources: RefCell::new(HashMap::new()),
+            ended: RefCell::new(vec![]),
+            finished: Cell::new(None),
+            releasing: Cell::new(false),
+        })
+    }
+
+    /// Takes over the runs going on, with the new rate.
+    pub fn replace(&mut self, new: Sampler) {
+        self.rate = new.rate;
+    }
+
+    /// When runs without messages might have to be ended.
+    pub fn deadline(&self) -> Option<Instant> {
+        let sources = self.sources.borrow();
+        if !sources
+            .values()
+            .flat_map(|apps| apps.values())
+            .any(|s| s.run.is_some())
+        {
+            return None;
+        }
+        let left = match self.finished.get() {
+            Some(t) => (t + window() - Utc::now()).to_std().unwrap_or_default(),
+            None => std::time::Duration::ZERO,
+        };
+        Some(Instant::now() + left)
+    }
+
+    /// The runs that ended by `now`. Sources only get looked at once a second, so this can be
+    /// called as often as there's a chance.
+    pub fn finish(&self, now: DateTime<Utc>) -> Vec<Run> {
+        let mut ended = std::mem::take(&mut *self.ended.borrow_mut());
+        ended.retain(|run| run.seen > 0);
+        if self.finished.get().is_some_and(|t| now < t + window()) {
+            return ended;
+        }
+        self.finished.set(Some(now));
+        let mut sources = self.sources.borrow_mut();
+        for (socket, apps) in sources.iter_mut() {
+            for (appname, source) in apps.iter_mut() {
+                ended.extend(source.roll(socket, appname, self.rate, now));
+            }
+            // Quiet ones start over when they send again.
+            apps.retain(|_, source| source.run.is_some() || source.count > 0);
+        }
+        sources.retain(|_, apps| !apps.is_empty());
+        ended.retain(|run| run.seen > 0);
+        ended
+    }
+
+    /// Ends every run, when shutting down.
+    pub fn finish_all(&self) -> Vec<Run> {
+        let mut ended = std::mem::take(&mut *self.ended.borrow_mut());
+        let sources = std::mem::take(&mut *self.sources.borrow_mut());
+        for (_, apps) in sources {
+            ended.extend(apps.into_values().filter_map(|s| s.run.map(Run::ended)));
+        }
+        ended.retain(|run| run.seen > 0);
+        ended
+    }
+
+    /// Runs `store` with the sampler letting everything through, for storing held messages.
+    pub fn release<R>(&self, store: impl FnOnce() -> R) -> R {
+        self.releasing.set(true);
+        let r = store();
+        self.releasing.set(false);
+        r
+    }
+}
+
+impl Enricher for Sampler {
+    fn enrich(&self, source: &SourceInfo, rec: &mut Record) -> Action {
+        if self.releasing.get() {
+            return Action::Keep;
+        }
+        let now = source.time;
+        let appname = rec.appname().unwrap_or("");
+        let mut sources = self.sources.borrow_mut();
+        if !sources.contains_key(source.socket) {
+            sources.insert(source.socket.to_owned(), HashMap::new());
+        }
+        let apps = sources.get_mut(source.socket).unwrap();
+        if !apps.contains_key(appname) {
+            apps.insert(appname.to_owned(), Source::new(now));
+        }
+        let state = apps.get_mut(appname).unwrap();
+        if let Some(run) = state.roll(source.socket, appname, self.rate, now) {
+            self.ended.borrow_mut().push(run);
+        }
+        state.count += 1;
+        let sampled = rec
+            .msg
+            .severity
+            .is_some_and(|s| s as u8 >= SyslogSeverity::SEV_INFO as u8);
+        let run = match state.run {
+            Some(ref mut run) if sampled => run,
+            _ => return Action::Keep,
+        };
+        if run.seen == 0 {
+            run.first = now;
+        }
+        run.last = now;
+        run.seen += 1;
+        let left_out = if (run.seen - 1) % state.n == 0 {
+            run.kept += 1;
+            run.held.take()
+        } else {
+            run.held.replace(Held::new(now, rec))
+        };
+        if left_out.is_some() {
+            counters::SAMPLE_SKIPPED.inc();
+        }
+        if run.held.is_some() {
+            Action::Drop
+        } else {
+            Action::Keep
+        }
+    }
+}
+
+/// Adds the counts of runs that ended to `sample_stats`.
+pub fn write(conn: &Connection, runs: &[Run]) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO sample_stats (socket, appname, runs, seen, kept, last_time)
+        VALUES (?, ?, 1, ?, ?, ?)
+        ON CONFLICT (socket, appname) DO UPDATE
+        SET runs = runs + 1, seen = seen + excluded.seen, kept = kept + excluded.kept,
+            last_time = excluded.last_time",
+    )?;
+    for run in runs {
+        stmt.execute(rusqlite::params![
+            run.socket,
+            run.appname.as_deref().unwrap_or(""),
+            run.seen as i64,
+            run.kept as i64,
+            run.last
+        ])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A message from `appname` at `severity`, arriving `ms` milliseconds in.
+    struct Sent {
+        ms: i64,
+        appname: &'static str,
+        severity: u8,
+        text: String,
+    }
+
+    /// The texts of what gets stored, in the order it is: kept messages as they come, and held
+    /// ones once their run ends. And the summaries of the runs.
+    fn sample(rate: u64, sent: &[Sent]) -> (Vec<String>, Vec<String>) {
+        let sampler = Sampler::new(&config::EnrichSample { rate }).unwrap();
+        let start: DateTime<Utc> = "2024-01-01T00:00:00Z".parse().unwrap();
+        let mut stored = vec![];
+        let mut summaries = vec![];
+        let mut ended = |runs: Vec<Run>, stored: &mut Vec<String>| {
+            for run in runs {
+                summaries.push(run.summary());
+                stored.extend(run.held.map(|held| held.msg.msg.into_owned()));
+            }
+        };
+        for s in sent {
+            let now = start + Duration::milliseconds(s.ms);
+            let line = format!(
+                "<{}>Jan  1 00:00:00 host {}: {}",
+                8 + s.severity,
+                s.appname,
+                s.text
+            );
+            let mut rec = Record::new(crate::payload::parse_syslog(&line, 2024), &line);
+            let source = SourceInfo {
+                socket: "test",
+                time: now,
+            };
+            if sampler.enrich(&source, &mut rec) == Action::Keep {
+                stored.push(rec.text().to_owned());
+            }
+            ended(sampler.finish(now), &mut stored);
+        }
+        ended(sampler.finish_all(), &mut stored);
+        (stored, summaries)
+    }
+
+    fn burst(from_ms: i64, appname: &'static str, severity: u8, n: usize) -> Vec<Sent> {
+        (0..n)
+            .map(|i| Sent {
+                ms: from_ms + i as i64 * 900 / n as i64,
+                appname,
+                severity,
+                text: format!("{} {}", appname, from_ms + i as i64),
+            })
+            .collect()
+    }
+
+    /// 50 messages in the first second, five times the rate: the storm is sampled 1 in 5 in
+    /// the next one, info and debug alike, but not its notices or another appname's messages.
+    fn storm() -> Vec<Sent> {
+        let mut sent = burst(0, "app", SyslogSeverity::SEV_INFO as u8, 50);
+        for (i, mut s) in burst(1000, "app", 0, 120).into_iter().enumerate() {
+            s.severity = match i % 12 {
+                11 => SyslogSeverity::SEV_NOTICE as u8,
+                n if n % 2 == 0 => SyslogSeverity::SEV_INFO as u8,
+                _ => SyslogSeverity::SEV_DEBUG as u8,
+            };
+            sent.push(s);
+        }
+        sent.extend(burst(1500, "quiet", SyslogSeverity::SEV_DEBUG as u8, 5));
+        sent.sort_by_key(|s| s.ms);
+        // Then a quiet second, which ends the run.
+        sent.extend(burst(3500, "app", SyslogSeverity::SEV_INFO as u8, 1));
+        sent
+    }
+
+    #[test]
+    fn the_same_messages_are_kept_every_time() {
+        let first = sample(10, &storm());
+        for _ in 0..3 {
+            assert_eq!(sample(10, &storm()), first);
+        }
+    }
+
+    #[test]
+    fn info_and_debug_are_kept_at_the_rate() {
+        let sent = storm();
+        let (stored, summaries) = sample(10, &sent);
+        let sampled: Vec<&Sent> = sent
+            .iter()
+            .filter(|s| (1000..2000).contains(&s.ms) && s.appname == "app")
+            .filter(|s| s.severity >= SyslogSeverity::SEV_INFO as u8)
+            .collect();
+        assert_eq!(sampled.len(), 110);
+        // 1 in 5, and the last one.
+        let mut expected: Vec<&str> = sent
+            .iter()
+            .filter(|s| !(1000..2000).contains(&s.ms) || s.appname != "app")
+            .chain(sent.iter().filter(|s| {
+                (1000..2000).contains(&s.ms)
+                    && s.appname == "app"
+                    && s.severity == SyslogSeverity::SEV_NOTICE as u8
+            }))
+            .chain(sampled.iter().copied().step_by(5))
+            .chain(sampled.last().copied())
+            .map(|s| s.text.as_str())
+            .collect();
+        let mut got: Vec<&str> = stored.iter().map(String::as_str).collect();
+        expected.sort_unstable();
+        got.sort_unstable();
+        assert_eq!(got, expected);
+        // The held one is stored once the run ends, after the message that ended it.
+        assert_eq!(stored.last().map(String::as_str), Some(&*sampled[109].text));
+        assert_eq!(summaries.len(), 1, "{:?}", summaries);
+        assert!(
+            summaries[0].starts_with("sampled: kept 23 of 110 messages from app between "),
+            "{:?}",
+            summaries
+        );
+    }
+
+    #[test]
+    fn notice_and_above_are_never_sampled() {
+        let mut sent = burst(0, "app", SyslogSeverity::SEV_ERR as u8, 100);
+        sent.extend(burst(1000, "app", SyslogSeverity::SEV_NOTICE as u8, 100));
+        let (stored, summaries) = sample(10, &sent);
+        assert_eq!(stored.len(), 200);
+        assert!(summaries.is_empty(), "{:?}", summaries);
+    }
+}
@@ -0,0 +1,580 @@
+//! Confining the daemon once setup is done, with whatever the platform has for it.
+//!
+//! Everything privileged (opening the sources and the database, binding sockets, dropping
+//! root) happens before `confine`, which is the last thing before the main loop. From then on
+//! the daemon only reads from descriptors it already has and writes to the database.
+//!
+//! - FreeBSD, with `--capsicum`: enters capability mode, after limiting the sources to reading
+//!   and polling. Outputs that open files, connect or run programs after setup can't work in
+//!   capability mode, so `check` refuses to start with them configured. Neither can the config
+//!   file be read again, so SIGHUP keeps the rules loaded at startup.
+//! - OpenBSD, always: `begin` pledges what setup needs, and `confine` unveils only the paths
+//!   the configured features use and pledges down to what they need at runtime. A violation
+//!   kills the daemon in debug builds, and makes the system call fail in release builds.
+//! - Linux, with `--seccomp`: installs a seccomp filter on every thread that only allows the
+//!   system calls in one table, and kills the daemon on anything else. Exec hooks, mail and
+//!   Kafka run code that needs far more than that, so the filter is left out (with a warning)
+//!   when any of them is configured.
+
+use crate::squealogd::config::Config;
+use rusqlite::Connection;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+/// What confining needs to know about the daemon. Only the BSDs' sandboxes use all of it.
+#[cfg_attr(
+    not(any(target_os = "freebsd", target_os = "openbsd")),
+    allow(dead_code)
+)]
+pub struct Setup<'a> {
+    pub config: &'a Config,
+    pub db: &'a Path,
+    pub config_path: &'a Path,
+    pub conn: &'a Connection,
+    pub internal: &'a crate::squealogd::internal::Sender,
+    /// Descriptors messages are only read from.
+    pub sources: Vec<RawFd>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    pub capsicum: bool,
+    pub seccomp: bool,
+    /// Whether the HTTP API is served.
+    pub http: bool,
+}
+
+/// What's configured that can't work once confined.
+fn incompatible(opts: Options, config: &Config) -> Vec<&'static str> {
+    let mut found = vec![];
+    let mut check = |configured: bool, name| {
+        if configured {
+            found.push(name);
+        }
+    };
+    check(!config.file.is_empty(), "[[file]]");
+    check(!config.exec.is_empty(), "[[exec]]");
+    check(!config.mail.is_empty(), "[[mail]]");
+    check(!config.relay.tcp.is_empty(), "[[relay.tcp]]");
+    check(!config.webhook.is_empty(), "[[webhook]]");
+    check(config.kafka.is_some(), "[kafka]");
+    check(config.journald.is_some(), "[journald]");
+    check(config.console.is_some(), "[console]");
+    check(config.wall.enabled, "[wall] (set enabled = false)");
+    check(opts.http, "--http");
+    found
+}
+
+/// Refuses configurations that confining would break, before anything is set up.
+pub fn check(opts: Options, config: &Config) -> anyhow::Result<()> {
+    if opts.capsicum {
+        if !cfg!(target_os = "freebsd") {
+            anyhow::bail!("--capsicum only works on FreeBSD");
+        }
+        let found = incompatible(opts, config);
+        if !found.is_empty() {
+            anyhow::bail!(
+                "--capsicum can't be used with {}: they need to open files, connect or run \
+                programs after setup",
+                found.join(", ")
+            );
+        }
+    }
+    if opts.seccomp
+        && !cfg!(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))
+    {
+        anyhow::bail!("--seccomp only works on Linux, on x86_64 and aarch64");
+    }
+    Ok(())
+}
+
+/// Why the daemon can't exec itself for an upgrade once confined, if it can't.
+pub fn exec_blocked(opts: Options, config: &Config) -> Option<&'static str> {
+    if cfg!(target_os = "openbsd") {
+        return Some("pledge and unveil don't allow running squealogd");
+    }
+    if opts.capsicum {
+        return Some("capability mode (--capsicum) doesn't allow running programs");
+    }
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    if opts.seccomp && seccomp::unfilterable(config).is_empty() {
+        return Some("the seccomp filter (--seccomp) doesn't allow execve");
+    }
+    let _ = config;
+    None
+}
+
+/// Called right after the config is loaded, before setup starts.
+pub fn begin(opts: Options, config: &Config) -> anyhow::Result<()> {
+    #[cfg(target_os = "openbsd")]
+    openbsd::pledge(&openbsd::promises(opts, config, true))?;
+    let _ = (opts, config);
+    Ok(())
+}
+
+/// Called once setup is done and every thread is running, right before the main loop.
+pub fn confine(opts: Options, setup: &Setup) -> anyhow::Result<()> {
+    if opts.capsicum {
+        #[cfg(target_os = "freebsd")]
+        capsicum::enter(setup)?;
+    }
+    #[cfg(target_os = "openbsd")]
+    openbsd::confine(opts, setup)?;
+    if opts.seccomp {
+        #[cfg(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))]
+        seccomp::install(opts, setup)?;
+    }
+    let _ = setup;
+    Ok(())
+}
+
+#[cfg(target_os = "freebsd")]
+mod capsicum {
+    use super::Setup;
+    use std::io;
+
+    // From <sys/capsicum.h>: a right is its bit plus a bit for which of the two words it's in.
+    const fn right(idx: u32, bit: u64) -> u64 {
+        (1 << (57 + idx)) | bit
+    }
+    const CAP_READ: u64 = right(0, 0x1);
+    const CAP_FCNTL: u64 = right(0, 0x8000);
+    const CAP_FSTAT: u64 = right(0, 0x80000);
+    const CAP_EVENT: u64 = right(1, 0x20);
+
+    /// A `cap_rights_t` (version 0, two words) holding `rights`.
+    fn rights(rights: &[u64]) -> libc::cap_rights_t {
+        let mut words = [right(0, 0), right(1, 0)];
+        for &r in rights {
+            words[((r >> 57) & 0x1f) as usize >> 1] |= r;
+        }
+        libc::cap_rights_t { cr_rights: words }
+    }
+
+    fn check(ret: libc::c_int) -> io::Result<()> {
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn enter(setup: &Setup) -> anyhow::Result<()> {
+        let read = rights(&[CAP_READ, CAP_EVENT, CAP_FSTAT, CAP_FCNTL]);
+        for &fd in &setup.sources {
+            check(unsafe { libc::cap_rights_limit(fd, &read) })?;
+        }
+        // The database's files (including the WAL and shared memory) are already open, but
+        // temporary files couldn't be created anymore.
+        setup.conn.pragma_update(None, "temp_store", &"MEMORY")?;
+        check(unsafe { libc::cap_enter() })?;
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "openbsd")]
+mod openbsd {
+    use super::{Options, Setup};
+    use crate::squealogd::config::Config;
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Need {
+        Always,
+        /// Only until setup is done.
+        Setup,
+        /// Connecting to things after setup: TCP relays, webhooks, Kafka, the HTTP API.
+        Network,
+        /// Exec hooks and mail.
+        Programs,
+        /// Accepting pubsub and control clients.
+        Clients,
+    }
+
+    /// Every promise the daemon makes, by what it's for. New features that need more go here,
+    /// and nowhere else.
+    const PROMISES: &[(Need, &str)] = &[
+        // SQLite reading, writing, creating its WAL and locking; reading the config on SIGHUP.
+        (Need::Always, "stdio rpath wpath cpath flock"),
+        // Taking over the sockets, looking up the user and dropping root.
+        (Need::Setup, "inet unix recvfd getpw id"),
+        (Need::Network, "inet dns"),
+        (Need::Programs, "proc exec"),
+        (Need::Clients, "unix"),
+    ];
+
+    fn needs(opts: Options, config: &Config, need: Need) -> bool {
+        match need {
+            Need::Always | Need::Setup => true,
+            Need::Network => {
+                !config.relay.tcp.is_empty()
+                    || !config.webhook.is_empty()
+                    || config.kafka.is_some()
+                    || opts.http
+            }
+            Need::Programs => !config.exec.is_empty() || !config.mail.is_empty(),
+            Need::Clients => config.pubsub.is_some() || config.control.is_some(),
+        }
+    }
+
+    pub fn promises(opts: Options, config: &Config, setup: bool) -> String {
+        let mut words: Vec<&str> = vec![];
+        for &(need, promises) in PROMISES {
+            if (setup || need != Need::Setup) && needs(opts, config, need) {
+                words.extend(promises.split_whitespace());
+            }
+        }
+        // Release builds keep running (with the call failing) rather than die on a violation.
+        if !cfg!(debug_assertions) {
+            words.push("error");
+        }
+        let mut seen = vec![];
+        words.retain(|w| {
+            let new = !seen.contains(w);
+            seen.push(*w);
+            new
+        });
+        words.join(" ")
+    }
+
+    fn check(ret: libc::c_int) -> io::Result<()> {
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn pledge(promises: &str) -> anyhow::Result<()> {
+        let promises = CString::new(promises)?;
+        // Programs that are run get to do whatever they like.
+        check(unsafe { libc::pledge(promises.as_ptr(), std::ptr::null()) })?;
+        Ok(())
+    }
+
+    fn unveil(path: &Path, permissions: &str) -> anyhow::Result<()> {
+        let cpath = CString::new(path.as_os_str().as_bytes())?;
+        let cperm = CString::new(permissions)?;
+        match check(unsafe { libc::unveil(cpath.as_ptr(), cperm.as_ptr()) }) {
+            // Paths that don't exist (like a missing config file) just stay hidden.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            r => Ok(r?),
+        }
+    }
+
+    pub fn confine(opts: Options, setup: &Setup) -> anyhow::Result<()> {
+        let config = setup.config;
+        let dir = match setup.db.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        unveil(dir, "rwc")?;
+        unveil(setup.config_path, "r")?;
+        unveil(Path::new("/etc/localtime"), "r")?;
+        unveil(Path::new("/usr/share/zoneinfo"), "r")?;
+        if let Some(ref pubsub) = config.pubsub {
+            let path = pubsub
+                .path
+                .as_deref()
+                .unwrap_or(Path::new(crate::squealogd::pubsub::DEFAULT_PATH));
+            unveil(path, "rwc")?;
+        }
+        if let Some(ref control) = config.control {
+            let path = control
+                .path
+                .as_deref()
+                .unwrap_or(Path::new(crate::squealogd::control::DEFAULT_PATH));
+            unveil(path, "rwc")?;
+        }
+        for rule in &config.file {
+            unveil(&rule.path, "wc")?;
+        }
+        if let Some(ref console) = config.console {
+            unveil(
+                console.path.as_deref().unwrap_or(Path::new("/dev/console")),
+                "w",
+            )?;
+        }
+        for program in config.exec.iter().filter_map(|hook| hook.command.first()) {
+            unveil(Path::new(program), "x")?;
+        }
+        for rule in &config.mail {
+            let command = rule.command.as_ref().and_then(|c| c.first());
+            unveil(
+                Path::new(
+                    command.map_or(crate::squealogd::mail::DEFAULT_COMMAND[0], |c| c.as_str()),
+                ),
+                "x",
+            )?;
+        }
+        check(unsafe { libc::unveil(std::ptr::null(), std::ptr::null()) })?;
+        pledge(&promises(opts, config, false))
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod seccomp {
+    use super::{Options, Setup};
+    use crate::squealogd::config::Config;
+    use libc::c_long;
+    use std::io;
+    use syslog_loose::SyslogSeverity;
+
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Need {
+        Always,
+        /// TCP relays and webhooks connecting, the HTTP API accepting.
+        Network,
+        /// Accepting pubsub and control clients, checking the latter's credentials.
+        Clients,
+        /// Forwarding to journald.
+        Journald,
+    }
+
+    /// Every system call the daemon makes once set up, by what it's for: the main loop, SQLite,
+    /// the signal thread and the relay threads. When the daemon gets killed with SIGSYS, `strace
+    /// -f` it to see which call it was (or look for the `syscall=` of the kernel's audit message
+    /// about it, in dmesg), and add that here.
+    const SYSCALLS: &[(Need, &[c_long])] = &[
+        (
+            Need::Always,
+            &[
+                // Reading messages and waiting for them.
+                libc::SYS_read,
+                libc::SYS_readv,
+                libc::SYS_recvfrom,
+                libc::SYS_recvmsg,
+                libc::SYS_epoll_ctl,
+                libc::SYS_epoll_pwait,
+                #[cfg(target_arch = "x86_64")]
+                libc::SYS_epoll_wait,
+                // polling's timeouts.
+                libc::SYS_timerfd_settime,
+                // SQLite: its files, locks and WAL.
+                libc::SYS_write,
+                libc::SYS_writev,
+                libc::SYS_pread64,
+                libc::SYS_pwrite64,
+                libc::SYS_lseek,
+                libc::SYS_fsync,
+                libc::SYS_fdatasync,
+                libc::SYS_ftruncate,
+                libc::SYS_fcntl,
+                libc::SYS_fstat,
+                libc::SYS_newfstatat,
+                libc::SYS_statx,
+                libc::SYS_openat,
+                libc::SYS_close,
+                libc::SYS_unlinkat,
+                libc::SYS_faccessat,
+                // As root, SQLite gives the files it creates the owner of the database.
+                libc::SYS_geteuid,
+                libc::SYS_fchown,
+                #[cfg(target_arch = "x86_64")]
+                libc::SYS_stat,
+                #[cfg(target_arch = "x86_64")]
+                libc::SYS_lstat,
+                #[cfg(target_arch = "x86_64")]
+                libc::SYS_open,
+                #[cfg(target_arch = "x86_64")]
+                libc::SYS_unlink,
+                #[cfg(target_arch = "x86_64")]
+                libc::SYS_access,
+                // Sending datagrams (UDP relays) and writing to subscribers.
+                libc::SYS_sendto,
+                libc::SYS_sendmsg,
+                // Making sockets (accepted clients, reconnected relays) nonblocking.
+                libc::SYS_ioctl,
+                // TCP relays saving their position.
+                libc::SYS_renameat2,
+                #[cfg(target_arch = "x86_64")]
+                libc::SYS_rename,
+                // Memory, time, threads and signals.
+                libc::SYS_brk,
+                libc::SYS_mmap,
+                libc::SYS_munmap,
+                libc::SYS_mremap,
+                libc::SYS_mprotect,
+                libc::SYS_madvise,
+                libc::SYS_futex,
+                libc::SYS_sched_yield,
+                libc::SYS_clock_gettime,
+                libc::SYS_clock_nanosleep,
+                libc::SYS_nanosleep,
+                libc::SYS_getrandom,
+                libc::SYS_getpid,
+                libc::SYS_gettid,
+                libc::SYS_uname,
+                libc::SYS_rt_sigaction,
+                libc::SYS_rt_sigprocmask,
+                libc::SYS_rt_sigreturn,
+                libc::SYS_sigaltstack,
+                libc::SYS_restart_syscall,
+                libc::SYS_exit,
+                libc::SYS_exit_group,
+                // Aborting, for the watchdog's `abort`.
+                libc::SYS_tgkill,
+                // The end of starting a thread, which the threads started last can still be
+                // in when the filter is installed: glibc's and Rust's setup for it.
+                libc::SYS_set_robust_list,
+                libc::SYS_rseq,
+                libc::SYS_prctl,
+                libc::SYS_sched_getaffinity,
+            ],
+        ),
+        (
+            Need::Network,
+            &[
+                libc::SYS_socket,
+                libc::SYS_connect,
+                libc::SYS_accept4,
+                libc::SYS_shutdown,
+                libc::SYS_getsockopt,
+                libc::SYS_setsockopt,
+                libc::SYS_getsockname,
+                libc::SYS_getpeername,
+                libc::SYS_ppoll,
+                #[cfg(target_arch = "x86_64")]
+                libc::SYS_poll,
+            ],
+        ),
+        (Need::Clients, &[libc::SYS_accept4, libc::SYS_getsockopt]),
+        // Sending messages too big for a datagram.
+        (Need::Journald, &[libc::SYS_memfd_create]),
+    ];
+
+    fn needs(opts: Options, config: &Config, need: Need) -> bool {
+        match need {
+            Need::Always => true,
+            Need::Network => {
+                !config.relay.tcp.is_empty() || !config.webhook.is_empty() || opts.http
+            }
+            Need::Clients => config.pubsub.is_some() || config.control.is_some(),
+            Need::Journald => config.journald.is_some(),
+        }
+    }
+
+    /// What's configured that runs code the filter can't know about.
+    pub fn unfilterable(config: &Config) -> Vec<&'static str> {
+        let mut found = vec![];
+        if !config.exec.is_empty() {
+            found.push("[[exec]]");
+        }
+        if !config.mail.is_empty() {
+            found.push("[[mail]]");
+        }
+        if config.kafka.is_some() {
+            found.push("[kafka]");
+        }
+        found
+    }
+
+    // From <linux/audit.h>, <linux/seccomp.h> and <linux/filter.h>.
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+    const SECCOMP_SET_MODE_FILTER: libc::c_uint = 1;
+    const SECCOMP_FILTER_FLAG_TSYNC: libc::c_uint = 1;
+    const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+    const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+    /// Offsets into `struct seccomp_data`.
+    const DATA_NR: u32 = 0;
+    const DATA_ARCH: u32 = 4;
+    const BPF_LD_W_ABS: u16 = 0x20;
+    const BPF_JEQ_K: u16 = 0x15;
+    const BPF_RET_K: u16 = 0x06;
+
+    #[repr(C)]
+    struct SockFilter {
+        code: u16,
+        jt: u8,
+        jf: u8,
+        k: u32,
+    }
+
+    #[repr(C)]
+    struct SockFprog {
+        len: libc::c_ushort,
+        filter: *const SockFilter,
+    }
+
+    fn op(code: u16, jt: u8, jf: u8, k: u32) -> SockFilter {
+        SockFilter { code, jt, jf, k }
+    }
+
+    /// Kills on a foreign architecture (whose numbers mean other calls), allows what's in
+    /// `allowed`, and kills on everything else.
+    fn program(allowed: &[c_long]) -> Vec<SockFilter> {
+        let mut prog = vec![
+            op(BPF_LD_W_ABS, 0, 0, DATA_ARCH),
+            op(BPF_JEQ_K, 1, 0, AUDIT_ARCH),
+            op(BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS),
+            op(BPF_LD_W_ABS, 0, 0, DATA_NR),
+        ];
+        for &nr in allowed {
+            prog.push(op(BPF_JEQ_K, 0, 1, nr as u32));
+            prog.push(op(BPF_RET_K, 0, 0, SECCOMP_RET_ALLOW));
+        }
+        prog.push(op(BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS));
+        prog
+    }
+
+    fn check(ret: libc::c_long) -> io::Result<()> {
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn install(opts: Options, setup: &Setup) -> anyhow::Result<()> {
+        let config = setup.config;
+        let found = unfilterable(config);
+        if !found.is_empty() {
+            setup.internal.log(
+                SyslogSeverity::SEV_WARNING,
+                format!(
+                    "not installing the seccomp filter: {} run code it doesn't cover",
+                    found.join(", ")
+                ),
+            );
+            return Ok(());
+        }
+        let mut allowed: Vec<c_long> = SYSCALLS
+            .iter()
+            .filter(|(need, _)| needs(opts, config, *need))
+            .flat_map(|(_, calls)| calls.iter().copied())
+            .collect();
+        allowed.sort_unstable();
+        allowed.dedup();
+        let prog = program(&allowed);
+        let fprog = SockFprog {
+            len: prog.len() as libc::c_ushort,
+            filter: prog.as_ptr(),
+        };
+        check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) }.into())?;
+        // On every thread, including the ones already running.
+        check(unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                SECCOMP_SET_MODE_FILTER,
+                SECCOMP_FILTER_FLAG_TSYNC,
+                &fprog as *const SockFprog,
+            )
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+//! squealogd's command line, and the settings it comes down to. The environment variables from
+//! before there were flags (`SQUEALOG_DB`, `SQUEALOG_CONFIG`, `SQUEALOG_HTTP`) still work, a
+//! flag wins over its variable.
+
+use crate::squealogd::{config, daemon, dbfile, parse, sandbox};
+use clap::Parser;
+use std::path::{Path, PathBuf};
+
+const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("SQUEALOG_GIT_HASH"),
+    ")"
+);
+
+/// Store syslog messages in a SQLite database
+#[derive(Parser)]
+#[clap(version = VERSION)]
+pub struct Args {
+    /// Path to the database
+    #[clap(long, env = "SQUEALOG_DB", default_value = "/var/log/log.db")]
+    db: PathBuf,
+    /// Mode the database is created with (the -wal and -shm files get the same)
+    #[clap(long, default_value = "0640", value_name = "MODE", parse(try_from_str = dbfile::parse_mode))]
+    db_mode: u32,
+    /// Create the database's directory if it doesn't exist
+    #[clap(long)]
+    create_db_dir: bool,
+    /// Path to the config file [default: /etc/squealog.toml]
+    #[clap(long, env = "SQUEALOG_CONFIG")]
+    config: Option<PathBuf>,
+    /// Bind a unix datagram socket when not socket activated, in addition to [[listen]]
+    #[clap(long, value_name = "NAME=PATH", parse(try_from_str = parse_unix))]
+    listen_unix: Vec<config::Listen>,
+    /// Bind a UDP socket when not socket activated, in addition to [[listen]]
+    #[clap(long, value_name = "NAME=ADDR", parse(try_from_str = parse_udp))]
+    listen_udp: Vec<config::Listen>,
+    /// Don't read kernel messages (FreeBSD's /dev/klog)
+    #[clap(long)]
+    no_klog: bool,
+    /// Fork into the background once set up
+    #[clap(long)]
+    daemonize: bool,
+    /// Write the pid to this file, refusing to start if another instance holds it
+    #[clap(long)]
+    pidfile: Option<PathBuf>,
+    /// Least severe of the daemon's own messages to log
+    #[clap(
+        long,
+        default_value = "info",
+        value_name = "SEVERITY",
+        parse(try_from_str = parse_severity)
+    )]
+    log_level: u8,
+    /// Serve the HTTP API on a port (on localhost) or address:port, with the http feature
+    #[clap(long, env = "SQUEALOG_HTTP", value_name = "ADDR")]
+    http: Option<String>,
+    /// Don't give up root after setup
+    #[clap(long)]
+    keep_root: bool,
+    /// Enter Capsicum capability mode after setup (FreeBSD)
+    #[clap(long)]
+    capsicum: bool,
+    /// Install a seccomp filter after setup (Linux)
+    #[clap(long)]
+    seccomp: bool,
+    /// Threads parsing datagrams, 0 to parse them on the main thread [default: CPUs - 1]
+    #[clap(long, value_name = "N")]
+    parse_threads: Option<usize>,
+    /// What happens when the parse threads fall behind: block, drop-new or drop-old
+    #[clap(long, default_value = "block", value_name = "POLICY")]
+    backpressure: parse::Policy,
+    /// Read the sockets in tokio tasks instead of the main loop, with the tokio feature
+    #[clap(long)]
+    async_sources: bool,
+    /// Print every message as parsed to stderr (in the foreground, at most 20 a second)
+    #[clap(long, conflicts_with = "daemonize")]
+    debug_ingest: bool,
+    /// Don't open the database or store anything, for trying out parsing and outputs
+    #[clap(long, conflicts_with = "http")]
+    no_db: bool,
+    /// Append every payload received to a capture file in this directory, for replaying
+    #[clap(long, value_name = "DIR")]
+    record: Option<PathBuf>,
+    /// Parse and store what the capture files in this directory hold instead of listening,
+    /// and exit
+    #[clap(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = &["record", "daemonize", "http", "capsicum"]
+    )]
+    replay: Option<PathBuf>,
+    /// With --replay, wait between payloads as long as they arrived apart
+    #[clap(long, requires = "replay")]
+    original_timing: bool,
+    /// Check the database, sockets and the rest of the config, and exit
+    #[clap(long)]
+    preflight_only: bool,
+    /// Print the upgrade handoff version this binary takes, and exit
+    #[clap(long, hide = true)]
+    handoff_version: bool,
+}
+
+fn split<'a>(arg: &'a str, what: &str) -> Result<(&'a str, &'a str), String> {
+    arg.split_once('=')
+        .filter(|(name, value)| !name.is_empty() && !value.is_empty())
+        .ok_or_else(|| format!("'{}' should look like NAME={}", arg, what))
+}
+
+fn parse_unix(arg: &str) -> Result<config::Listen, String> {
+    let (name, path) = split(arg, "PATH")?;
+    Ok(config::Listen {
+        name: name.to_owned(),
+        unix: Some(path.into()),
+        mode: None,
+        udp: None,
+        buffer: None,
+        parser: None,
+        strict: false,
+    })
+}
+
+fn parse_udp(arg: &str) -> Result<config::Listen, String> {
+    let (name, addr) = split(arg, "ADDR")?;
+    Ok(config::Listen {
+        name: name.to_owned(),
+        unix: None,
+        mode: None,
+        udp: Some(addr.to_owned()),
+        buffer: None,
+        parser: None,
+        strict: false,
+    })
+}
+
+fn parse_severity(arg: &str) -> Result<u8, String> {
+    crate::names::parse_severity(arg).ok_or_else(|| format!("invalid severity '{}'", arg))
+}
+
+/// `--replay`: where from, and how fast.
+pub struct Replay {
+    pub dir: PathBuf,
+    pub original_timing: bool,
+}
+
+pub struct Settings {
+    pub db: PathBuf,
+    pub db_file: dbfile::Options,
+    /// Given explicitly, so it has to exist.
+    pub config: Option<PathBuf>,
+    pub listen: Vec<config::Listen>,
+    #[cfg_attr(not(target_os = "freebsd"), allow(dead_code))]
+    pub klog: bool,
+    pub daemon: daemon::Options,
+    /// The daemon's own messages less severe than this aren't logged.
+    pub log_level: u8,
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub http: Option<String>,
+    pub keep_root: bool,
+    pub sandbox: sandbox::Options,
+    pub parse_threads: Option<usize>,
+    pub backpressure: parse::Policy,
+    pub async_sources: bool,
+    pub debug_ingest: bool,
+    pub no_db: bool,
+    pub record: Option<PathBuf>,
+    pub replay: Option<Replay>,
+    pub preflight_only: bool,
+    pub handoff_version: bool,
+}
+
+impl From<Args> for Settings {
+    fn from(args: Args) -> Settings {
+        let mut listen = args.listen_unix;
+        listen.extend(args.listen_udp);
+        Settings {
+            db: args.db,
+            db_file: dbfile::Options {
+                mode: args.db_mode,
+                create_dir: args.create_db_dir,
+            },
+            config: args.config,
+            listen,
+            // Replayed from the captures instead.
+            klog: !args.no_klog && args.replay.is_none(),
+            daemon: daemon::Options {
+                daemonize: args.daemonize,
+                pidfile: args.pidfile,
+            },
+            log_level: args.log_level,
+            sandbox: sandbox::Options {
+                capsicum: args.capsicum,
+                seccomp: args.seccomp,
+                http: args.http.is_some(),
+            },
+            http: args.http,
+            keep_root: args.keep_root,
+            parse_threads: args.parse_threads,
+            backpressure: args.backpressure,
+            async_sources: args.async_sources,
+            debug_ingest: args.debug_ingest,
+            no_db: args.no_db,
+            record: args.record,
+            replay: args.replay.map(|dir| Replay {
+                dir,
+                original_timing: args.original_timing,
+            }),
+            preflight_only: args.preflight_only,
+            handoff_version: args.handoff_version,
+        }
+    }
+}
+
+impl Settings {
+    pub fn from_args() -> Settings {
+        Args::parse().into()
+    }
+
+    /// Like `from_args`, for running the daemon inside another program (`squealog-bench
+    /// ingest`).
+    pub fn parse_from<I, T>(args: I) -> Settings
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        Args::parse_from(args).into()
+    }
+
+    pub fn config_path(&self) -> &Path {
+        self.config
+            .as_deref()
+            .unwrap_or_else(|| Path::new(crate::config::DEFAULT_PATH))
+    }
+
+    /// The config file, with the `--listen-*` sockets added to its own.
+    pub fn load_config(&self) -> anyhow::Result<config::Config> {
+        let mut config: config::Config = crate::config::load_from(self.config.as_deref())?;
+        config.listen.extend(self.listen.iter().cloned());
+        Ok(config)
+    }
+}
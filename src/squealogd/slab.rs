@@ -0,0 +1,108 @@
+//! Slots with stable keys, for things registered with the poller: an entry's key is its index,
+//! so an event finds its entry without a search, and removed entries' slots are reused by the
+//! next insert. The keys come from `Keys`, which pubsub and the control socket take theirs
+//! from too, so that nothing else registered with the poller can get a source's key.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The poller's keys, shared by everything registered with it. A key that's given back gets
+/// taken again next, so they stay small.
+#[derive(Clone, Default)]
+pub struct Keys(Rc<RefCell<KeySpace>>);
+
+#[derive(Default)]
+struct KeySpace {
+    next: usize,
+    free: Vec<usize>,
+}
+
+impl Keys {
+    pub fn take(&self) -> usize {
+        let mut keys = self.0.borrow_mut();
+        keys.free.pop().unwrap_or_else(|| {
+            keys.next += 1;
+            keys.next - 1
+        })
+    }
+
+    pub fn give_back(&self, key: usize) {
+        self.0.borrow_mut().free.push(key);
+    }
+}
+
+pub struct Slab<T> {
+    /// By key, `None` for keys that aren't this slab's.
+    slots: Vec<Option<T>>,
+    keys: Keys,
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Slab<T> {
+        Slab {
+            slots: vec![],
+            keys: Keys::default(),
+        }
+    }
+}
+
+impl<T> Slab<T> {
+    /// Where its keys come from, for everything else registered with the same poller.
+    pub fn keys(&self) -> Keys {
+        self.keys.clone()
+    }
+
+    /// Inserts what `make` makes of the key it gets.
+    pub fn insert_with(&mut self, make: impl FnOnce(usize) -> T) -> usize {
+        let key = self.keys.take();
+        if key >= self.slots.len() {
+            self.slots.resize_with(key + 1, || None);
+        }
+        self.slots[key] = Some(make(key));
+        key
+    }
+
+    pub fn get_mut(&mut self, key: usize) -> Option<&mut T> {
+        self.slots.get_mut(key).and_then(Option::as_mut)
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        for (key, slot) in self.slots.iter_mut().enumerate() {
+            if matches!(slot, Some(entry) if !keep(entry)) {
+                *slot = None;
+                self.keys.give_back(key);
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter().flatten()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.slots.iter_mut().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_taken_elsewhere_are_never_a_slots() {
+        let mut slab = Slab::default();
+        let keys = slab.keys();
+        let a = slab.insert_with(|key| key);
+        let listener = keys.take();
+        let b = slab.insert_with(|key| key);
+        assert_eq!((a, listener, b), (0, 1, 2));
+        assert_eq!(slab.get_mut(listener), None);
+
+        slab.retain(|&key| key != a);
+        keys.give_back(listener);
+        let c = slab.insert_with(|key| key);
+        let d = slab.insert_with(|key| key);
+        assert_eq!((c, d), (listener, a));
+        assert_eq!(slab.iter().copied().collect::<Vec<_>>(), [d, c, b]);
+    }
+}
@@ -0,0 +1,85 @@
+//! Signs of life: `-- MARK --` rows when nothing else was stored for a while, like syslogd's
+//! `-m`, and the stats snapshot that SIGUSR1 logs.
+//!
+//! ```toml
+//! [mark]
+//! interval = "20m"
+//! ```
+
+use crate::squealogd::config;
+use crate::squealogd::counters;
+use crate::squealogd::memory;
+use crate::stats::human_size;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+pub const MARK: &str = "-- MARK --";
+
+pub struct Mark {
+    interval: Duration,
+}
+
+impl Mark {
+    pub fn new(cfg: &config::Mark) -> anyhow::Result<Mark> {
+        let interval = crate::time::parse_duration(&cfg.interval)
+            .and_then(|d| d.to_std().ok())
+            .filter(|d| !d.is_zero())
+            .ok_or_else(|| anyhow::format_err!("Invalid mark interval '{}'", cfg.interval))?;
+        Ok(Mark { interval })
+    }
+
+    /// When a mark is due, given when the last message was stored.
+    pub fn deadline(&self, last_stored: Instant) -> Instant {
+        last_stored + self.interval
+    }
+}
+
+/// The database's size, including the WAL.
+fn size(path: &Path) -> u64 {
+    let mut wal = path.as_os_str().to_owned();
+    wal.push("-wal");
+    [path, Path::new(&wal)]
+        .iter()
+        .filter_map(|p| std::fs::metadata(p).ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// One line about how things have been going since `started`: messages per source, every
+/// counter that isn't zero, what outputs hold in memory (against `memory_max`) and the size of
+/// the database (with its WAL).
+pub fn snapshot(started: Instant, sources: &[(&str, u64)], db: &Path, memory_max: usize) -> String {
+    let up = started.elapsed().as_secs();
+    let received: Vec<_> = sources
+        .iter()
+        .map(|(name, n)| format!("{} {}", name, n))
+        .collect();
+    let counted: Vec<_> = counters::ALL
+        .iter()
+        .filter(|c| c.get() != 0)
+        .map(|c| format!("{} {}", c.name, c.get()))
+        .collect();
+    let held: Vec<_> = memory::ALL
+        .iter()
+        .map(|u| format!("{} {}", u.name, human_size(u.get() as u64)))
+        .collect();
+    format!(
+        "stats: up {}h{:02}m; received: {}; counters: {}; memory {} of {} ({}); database {}",
+        up / 3600,
+        up / 60 % 60,
+        if received.is_empty() {
+            "-".to_owned()
+        } else {
+            received.join(", ")
+        },
+        if counted.is_empty() {
+            "-".to_owned()
+        } else {
+            counted.join(", ")
+        },
+        human_size(memory::total() as u64),
+        human_size(memory_max as u64),
+        held.join(", "),
+        human_size(size(db)),
+    )
+}
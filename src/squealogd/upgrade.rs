@@ -0,0 +1,218 @@
+//! Replacing the running binary without closing a socket: on SIGUSR2 (or `UPGRADE` on the
+//! control socket), the daemon writes out what it has buffered and execs the binary it was
+//! started from again, which by then is the new one. The pid stays the same, and so do the
+//! descriptors: the sources (klog included), the control and pubsub listeners and the pidfile
+//! are listed in `SQUEALOGD_HANDOFF` for the new process to pick up instead of opening its own.
+//! What the kernel receives in between waits in the sockets' buffers.
+//!
+//! The list starts with a version. Before exec'ing, the old process asks the new binary which
+//! one it understands (`--handoff-version`), and keeps running if it's a different one (or
+//! the binary is too old to know the flag).
+//!
+//! Pubsub subscribers and control clients are disconnected, and programs run by exec hooks
+//! see their input closed and are started again by the new process.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+pub const VERSION: u32 = 1;
+const VAR: &str = "SQUEALOGD_HANDOFF";
+const MAGIC: &str = "squealogd-handoff";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// A datagram socket that was passed in, or bound from the config (`Bound`, its path gets
+    /// removed on shutdown).
+    Source,
+    Bound,
+    Klog,
+    Control,
+    PubSub,
+    Pidfile,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Source => "source",
+            Kind::Bound => "bound",
+            Kind::Klog => "klog",
+            Kind::Control => "control",
+            Kind::PubSub => "pubsub",
+            Kind::Pidfile => "pidfile",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Kind> {
+        [
+            Kind::Source,
+            Kind::Bound,
+            Kind::Klog,
+            Kind::Control,
+            Kind::PubSub,
+            Kind::Pidfile,
+        ]
+        .into_iter()
+        .find(|kind| kind.name() == s)
+    }
+}
+
+#[derive(Debug)]
+pub struct Entry {
+    pub kind: Kind,
+    pub fd: RawFd,
+    /// Messages received, for sources, so `STATS` keeps counting.
+    pub received: u64,
+    /// The source's name, empty for the rest.
+    pub name: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Handoff {
+    entries: Vec<Entry>,
+}
+
+impl Handoff {
+    pub fn add(&mut self, kind: Kind, fd: RawFd, received: u64, name: &str) {
+        self.entries.push(Entry {
+            kind,
+            fd,
+            received,
+            name: name.to_owned(),
+        });
+    }
+
+    /// Takes out the entries of one kind.
+    pub fn take(&mut self, kinds: &[Kind]) -> Vec<Entry> {
+        let (taken, rest) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition(|e| kinds.contains(&e.kind));
+        self.entries = rest;
+        taken
+    }
+
+    pub fn take_fd(&mut self, kind: Kind) -> Option<RawFd> {
+        self.take(&[kind]).first().map(|e| e.fd)
+    }
+
+    /// Closes whatever nothing took, like the pubsub listener when pubsub isn't configured
+    /// anymore.
+    pub fn close_rest(self) {
+        for entry in self.entries {
+            unsafe { libc::close(entry.fd) };
+        }
+    }
+
+    fn encode(&self) -> String {
+        let mut s = format!("{} {}\n", MAGIC, VERSION);
+        for e in &self.entries {
+            s.push_str(&format!(
+                "{} {} {} {}\n",
+                e.kind.name(),
+                e.fd,
+                e.received,
+                e.name
+            ));
+        }
+        s
+    }
+
+    fn decode(s: &str) -> anyhow::Result<Handoff> {
+        let mut lines = s.lines();
+        let version = lines
+            .next()
+            .and_then(|l| l.strip_prefix(MAGIC))
+            .and_then(|v| v.trim().parse::<u32>().ok())
+            .ok_or_else(|| anyhow::format_err!("{} is not a handoff", VAR))?;
+        if version != VERSION {
+            anyhow::bail!(
+                "handed over state version {}, but this squealogd only understands {}",
+                version,
+                VERSION
+            );
+        }
+        let mut handoff = Handoff::default();
+        for line in lines {
+            let mut fields = line.splitn(4, ' ');
+            let entry = (|| {
+                Some(Entry {
+                    kind: Kind::parse(fields.next()?)?,
+                    fd: fields.next()?.parse().ok()?,
+                    received: fields.next()?.parse().ok()?,
+                    name: fields.next().unwrap_or("").to_owned(),
+                })
+            })()
+            .ok_or_else(|| anyhow::format_err!("invalid handoff entry '{}'", line))?;
+            handoff.entries.push(entry);
+        }
+        Ok(handoff)
+    }
+
+    /// What the previous process handed over, if this one was exec'd by an upgrade. Must come
+    /// before anything else opens descriptors or starts threads.
+    pub fn from_env() -> anyhow::Result<Option<Handoff>> {
+        let s = match std::env::var(VAR) {
+            Ok(s) => s,
+            Err(_) => return Ok(None),
+        };
+        std::env::remove_var(VAR);
+        let handoff = Handoff::decode(&s)?;
+        // Not for programs this one runs.
+        for e in &handoff.entries {
+            unsafe { libc::fcntl(e.fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+        Ok(Some(handoff))
+    }
+}
+
+/// Checks that `exe` takes handoffs of this version, without starting a daemon.
+pub fn compatible(exe: &Path) -> Result<(), String> {
+    let out = std::process::Command::new(exe)
+        .arg("--handoff-version")
+        .output()
+        .map_err(|e| format!("could not run {:?}: {}", exe, e))?;
+    let theirs = String::from_utf8_lossy(&out.stdout);
+    match theirs.trim().parse::<u32>() {
+        Ok(v) if out.status.success() && v == VERSION => Ok(()),
+        Ok(v) if out.status.success() => Err(format!(
+            "{:?} takes handoff version {}, this one hands over {}",
+            exe, v, VERSION
+        )),
+        _ => Err(format!("{:?} doesn't support upgrades", exe)),
+    }
+}
+
+/// Execs `exe` with the same arguments, passing `handoff` along. Only returns if that failed.
+pub fn exec(exe: &Path, handoff: &Handoff) -> io::Error {
+    for e in &handoff.entries {
+        if unsafe { libc::fcntl(e.fd, libc::F_SETFD, 0) } == -1 {
+            return io::Error::last_os_error();
+        }
+    }
+    let cstr =
+        |s: &[u8]| CString::new(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e));
+    let path = match cstr(exe.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(e) => return e,
+    };
+    let args = match std::env::args_os()
+        .map(|a| cstr(a.as_bytes()))
+        .collect::<io::Result<Vec<_>>>()
+    {
+        Ok(args) => args,
+        Err(e) => return e,
+    };
+    let mut argv: Vec<*const libc::c_char> = args.iter().map(|a| a.as_ptr()).collect();
+    argv.push(std::ptr::null());
+    std::env::set_var(VAR, handoff.encode());
+    unsafe { libc::execv(path.as_ptr(), argv.as_ptr()) };
+    let e = io::Error::last_os_error();
+    std::env::remove_var(VAR);
+    for entry in &handoff.entries {
+        unsafe { libc::fcntl(entry.fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    e
+}
@@ -0,0 +1,107 @@
+//! Writing emergencies to every logged in user's terminal, like syslogd does for `*.emerg`.
+//! On by default; turn it off with `[wall] enabled = false`, or add alert with `alert = true`.
+//!
+//! The terminals are written to from a helper thread, non-blocking, so a hung one costs a
+//! dropped banner rather than a stuck daemon.
+
+use crate::squealogd::config;
+use crate::squealogd::output::{Outgoing, Output};
+use chrono::prelude::*;
+use std::ffi::CStr;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::mpsc::{self, SyncSender};
+use std::time::{Duration, Instant};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+pub struct Wall {
+    max_severity: u8,
+    max_per_minute: usize,
+    hostname: String,
+    /// When the banners of the current minute went out.
+    recent: Vec<Instant>,
+    tx: SyncSender<String>,
+}
+
+/// The terminals of logged in users, from utmpx.
+fn terminals() -> Vec<String> {
+    let mut ttys = vec![];
+    unsafe {
+        libc::setutxent();
+        loop {
+            let entry = libc::getutxent();
+            if entry.is_null() {
+                break;
+            }
+            if (*entry).ut_type != libc::USER_PROCESS {
+                continue;
+            }
+            let line = CStr::from_ptr((*entry).ut_line.as_ptr()).to_string_lossy();
+            if !line.is_empty() && !line.contains("..") {
+                ttys.push(format!("/dev/{}", line));
+            }
+        }
+        libc::endutxent();
+    }
+    ttys.sort();
+    ttys.dedup();
+    ttys
+}
+
+fn broadcast(banner: &str) {
+    for tty in terminals() {
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(&tty);
+        // Whatever doesn't fit into the terminal's buffer right now is lost, same as syslogd.
+        if let Ok(mut file) = file {
+            let _ = file.write_all(banner.as_bytes());
+        }
+    }
+}
+
+impl Wall {
+    pub fn new(cfg: &config::Wall) -> anyhow::Result<Wall> {
+        let (tx, rx) = mpsc::sync_channel::<String>(16);
+        std::thread::Builder::new()
+            .name("wall".to_owned())
+            .spawn(move || rx.into_iter().for_each(|banner| broadcast(&banner)))?;
+        Ok(Wall {
+            max_severity: if cfg.alert { 1 } else { 0 },
+            max_per_minute: cfg.max_per_minute,
+            hostname: crate::sys::hostname()?,
+            recent: vec![],
+            tx,
+        })
+    }
+}
+
+impl Output for Wall {
+    fn send(&mut self, out: &Outgoing) {
+        match out.severity() {
+            Some(sev) if sev <= self.max_severity => (),
+            _ => return,
+        }
+        self.recent.retain(|t| t.elapsed() < RATE_WINDOW);
+        if self.recent.len() >= self.max_per_minute {
+            return;
+        }
+        self.recent.push(Instant::now());
+        let msg = out.msg;
+        let time = msg
+            .timestamp
+            .map_or(out.recv_time, |t| t.with_timezone(&Utc))
+            .with_timezone(&Local);
+        let banner = format!(
+            "\r\n\x07Message from squealogd@{} at {} ...\r\n{}: {}\r\n",
+            msg.hostname.unwrap_or(&self.hostname),
+            time.format("%a %b %e %H:%M:%S"),
+            msg.appname.unwrap_or(out.socket),
+            msg.msg.replace('\n', "\r\n"),
+        );
+        let _ = self.tx.try_send(banner);
+    }
+}
@@ -0,0 +1,184 @@
+//! Noticing when messages stop making it into the database while the daemon otherwise looks
+//! alive, like when an fsync hangs on a dying disk:
+//!
+//! ```toml
+//! [watchdog]
+//! stall = "5m"
+//! console = true
+//! abort = true
+//! ```
+//!
+//! A thread of its own keeps track of when a message last made it into the database (or was
+//! filtered out), so it still runs when the main loop is the thing that's stuck. Messages
+//! waiting for longer than `stall` (5 minutes by default) mean stalled, as long as one is
+//! being stored right now or the latest attempt was less than `stall` ago. It says so at CRIT
+//! (on stderr in the foreground, and as a row once the database takes one again), on
+//! `/dev/console` with `console`, stops pinging the systemd watchdog, and with `abort` aborts
+//! for a supervisor to restart the daemon. Being idle never counts: with nothing received,
+//! there's nothing waiting. `enabled = false` turns it off.
+
+use crate::squealogd::config;
+use crate::squealogd::internal;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use syslog_loose::SyslogSeverity;
+
+/// The most the thread sleeps between checks.
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What the main loop reports, and the thread looks at. Times are milliseconds since `base`
+/// plus one, so that 0 can mean never.
+pub struct Progress {
+    base: Instant,
+    /// The oldest attempt to store a message since the last one that worked.
+    waiting_since: AtomicU64,
+    /// The latest attempt.
+    attempted: AtomicU64,
+    /// Whether one is going on right now.
+    storing: AtomicBool,
+    stalled: AtomicBool,
+}
+
+/// A message on its way to the database, until dropped.
+pub struct Attempt<'a>(&'a Progress);
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        self.0.storing.store(false, Ordering::Relaxed);
+    }
+}
+
+impl Progress {
+    fn now(&self) -> u64 {
+        self.base.elapsed().as_millis() as u64 + 1
+    }
+
+    pub fn attempt(&self) -> Attempt<'_> {
+        let now = self.now();
+        if self.waiting_since.load(Ordering::Relaxed) == 0 {
+            self.waiting_since.store(now, Ordering::Relaxed);
+        }
+        self.attempted.store(now, Ordering::Relaxed);
+        self.storing.store(true, Ordering::Relaxed);
+        Attempt(self)
+    }
+
+    /// The message got there (or didn't need to).
+    pub fn done(&self) {
+        self.waiting_since.store(0, Ordering::Relaxed);
+    }
+
+    fn ago(&self, t: &AtomicU64) -> Option<Duration> {
+        match t.load(Ordering::Relaxed) {
+            0 => None,
+            t => Some(Duration::from_millis(self.now().saturating_sub(t))),
+        }
+    }
+
+    pub fn stalled(&self) -> bool {
+        self.stalled.load(Ordering::Relaxed)
+    }
+}
+
+/// Sets up the check, returning what the main loop reports to (which is there without a
+/// thread when the watchdog is off).
+pub fn spawn(cfg: &config::Watchdog, internal: internal::Sender) -> anyhow::Result<Arc<Progress>> {
+    let progress = Arc::new(Progress {
+        base: Instant::now(),
+        waiting_since: AtomicU64::new(0),
+        attempted: AtomicU64::new(0),
+        storing: AtomicBool::new(false),
+        stalled: AtomicBool::new(false),
+    });
+    if !cfg.enabled {
+        return Ok(progress);
+    }
+    let stall = crate::time::parse_duration(&cfg.stall)
+        .and_then(|d| d.to_std().ok())
+        .filter(|d| !d.is_zero())
+        .ok_or_else(|| anyhow::format_err!("Invalid watchdog stall time '{}'", cfg.stall))?;
+    // Opened now, as sandboxes don't allow it later.
+    let console = if cfg.console {
+        Some(
+            OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY)
+                .open("/dev/console")?,
+        )
+    } else {
+        None
+    };
+    let watch = Watch {
+        progress: progress.clone(),
+        stall,
+        console,
+        abort: cfg.abort,
+        internal,
+    };
+    std::thread::Builder::new()
+        .name("watchdog".to_owned())
+        .spawn(move || watch.run())?;
+    Ok(progress)
+}
+
+struct Watch {
+    progress: Arc<Progress>,
+    stall: Duration,
+    console: Option<File>,
+    abort: bool,
+    internal: internal::Sender,
+}
+
+impl Watch {
+    fn run(mut self) {
+        let interval = (self.stall / 10).clamp(Duration::from_millis(100), MAX_CHECK_INTERVAL);
+        loop {
+            std::thread::sleep(interval);
+            let progress = &self.progress;
+            let waiting = progress.ago(&progress.waiting_since).unwrap_or_default();
+            // A failure that nothing came after since is not a stall, just quiet.
+            let trying = progress.storing.load(Ordering::Relaxed)
+                || progress
+                    .ago(&progress.attempted)
+                    .is_some_and(|ago| ago < self.stall);
+            let stalled = waiting > self.stall && trying;
+            if stalled == self.progress.stalled() {
+                continue;
+            }
+            self.progress.stalled.store(stalled, Ordering::Relaxed);
+            if stalled {
+                self.alarm(waiting);
+            } else {
+                self.internal.log(
+                    SyslogSeverity::SEV_NOTICE,
+                    "messages are being stored again".to_owned(),
+                );
+            }
+        }
+    }
+
+    fn alarm(&mut self, waiting: Duration) {
+        let msg = format!(
+            "nothing stored for {}s while messages are waiting: the database (or the disk \
+            under it) is stuck{}",
+            waiting.as_secs(),
+            if self.abort {
+                ", aborting"
+            } else {
+                ", no longer pinging the watchdog"
+            }
+        );
+        // Goes to stderr right away in the foreground, and into the database if it recovers.
+        self.internal.log(SyslogSeverity::SEV_CRIT, msg.clone());
+        if let Some(ref mut console) = self.console {
+            let _ = writeln!(console, "squealogd: {}\r", msg);
+        }
+        if self.abort {
+            std::process::abort();
+        }
+    }
+}
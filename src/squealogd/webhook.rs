@@ -0,0 +1,210 @@
+//! HTTP POSTs to an alerting endpoint for messages matching a rule (needs the `webhook`
+//! feature):
+//!
+//! ```toml
+//! [[webhook]]
+//! url = "https://alerts.example.com/hooks/squealog"
+//! token_file = "/etc/squealog/alerts.token"
+//! select = "*.err"
+//! regex = "I/O error|Failed password"
+//! body = '{"text": "{hostname} {appname}: {msg} ({count}x)"}'
+//! window = "5m"
+//! ```
+//!
+//! The first match sends right away, then the rule holds back whatever else matches until
+//! `window` is over, and sends one more alert for the last of those with `{count}` of them.
+//! So a storm is two alerts, not ten thousand. `body` can use `{hostname}`, `{appname}`,
+//! `{severity}`, `{time}`, `{msg}` and `{count}`, which get JSON-escaped. Delivery happens on a
+//! thread of its own with a few retries; failures are logged as the daemon's own messages.
+
+use crate::names;
+use crate::squealogd::config;
+use crate::squealogd::internal;
+use crate::squealogd::matcher::Matcher;
+use crate::squealogd::output::{self, Outgoing, Output};
+use chrono::prelude::*;
+use std::sync::mpsc::{self, SyncSender};
+use std::time::{Duration, Instant};
+use syslog_loose::SyslogSeverity;
+
+const DEFAULT_BODY: &str = r#"{"text": "{hostname} {appname}: {msg}"}"#;
+const DEFAULT_WINDOW: Duration = Duration::from_secs(300);
+/// Alerts waiting for delivery; more than that and the endpoint is hopeless anyway.
+const QUEUE: usize = 100;
+const ATTEMPTS: u32 = 3;
+
+/// The values a body template can refer to.
+#[derive(Clone, Debug, Default)]
+pub struct Vars {
+    pub hostname: String,
+    pub appname: String,
+    pub severity: String,
+    pub time: String,
+    pub msg: String,
+    pub count: usize,
+}
+
+impl Vars {
+    fn new(out: &Outgoing, hostname: &str) -> Vars {
+        let time = out.msg.timestamp.unwrap_or_else(|| out.recv_time.into());
+        Vars {
+            hostname: out.msg.hostname.unwrap_or(hostname).to_owned(),
+            appname: out.msg.appname.unwrap_or(out.socket).to_owned(),
+            severity: out
+                .severity()
+                .and_then(|s| names::severity_name(s.into()))
+                .unwrap_or("-")
+                .to_owned(),
+            time: time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            msg: out.msg.msg.to_owned(),
+            count: 1,
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "hostname" => self.hostname.clone(),
+            "appname" => self.appname.clone(),
+            "severity" => self.severity.clone(),
+            "time" => self.time.clone(),
+            "msg" => self.msg.clone(),
+            "count" => self.count.to_string(),
+            _ => return None,
+        })
+    }
+}
+
+/// Fills in the `{name}` placeholders of a JSON template, escaped for use inside a JSON string.
+/// Braces that aren't a known placeholder are left alone, they're most likely JSON.
+pub fn render(template: &str, vars: &Vars) -> String {
+    output::fill(
+        template,
+        |name| vars.get(name),
+        |out, value| {
+            let quoted = serde_json::Value::String(value.to_owned()).to_string();
+            out.push_str(&quoted[1..quoted.len() - 1]);
+        },
+    )
+}
+
+pub struct Webhook {
+    matcher: Matcher,
+    body: String,
+    window: Duration,
+    hostname: String,
+    /// When the last alert went out, while its window lasts.
+    sent: Option<Instant>,
+    /// The newest message held back since then, with the number of them.
+    held: Option<Vars>,
+    tx: SyncSender<String>,
+    internal: internal::Sender,
+}
+
+impl Webhook {
+    pub fn new(cfg: &config::Webhook, internal: internal::Sender) -> anyhow::Result<Webhook> {
+        let matcher = Matcher::new(cfg.select, cfg.appname.clone(), cfg.regex.as_deref())?;
+        let window = match cfg.window {
+            Some(ref w) => crate::time::parse_duration(w)
+                .and_then(|d| d.to_std().ok())
+                .ok_or_else(|| anyhow::format_err!("Invalid webhook window '{}'", w))?,
+            None => DEFAULT_WINDOW,
+        };
+        let token = match cfg.token_file {
+            Some(ref path) => Some(std::fs::read_to_string(path)?.trim().to_owned()),
+            None => None,
+        };
+        let (tx, rx) = mpsc::sync_channel::<String>(QUEUE);
+        let url = cfg.url.clone();
+        let log = internal.clone();
+        std::thread::Builder::new()
+            .name(format!("webhook {}", url))
+            .spawn(move || {
+                let agent = ureq::AgentBuilder::new()
+                    .timeout(Duration::from_secs(15))
+                    .build();
+                for body in rx {
+                    if let Err(e) = deliver(&agent, &url, token.as_deref(), &body) {
+                        log.log(
+                            SyslogSeverity::SEV_ERR,
+                            format!("webhook {} failed: {}", url, e),
+                        );
+                    }
+                }
+            })?;
+        Ok(Webhook {
+            matcher,
+            body: cfg.body.clone().unwrap_or_else(|| DEFAULT_BODY.to_owned()),
+            window,
+            hostname: crate::sys::hostname()?,
+            sent: None,
+            held: None,
+            tx,
+            internal,
+        })
+    }
+
+    fn queue(&mut self, vars: &Vars) {
+        self.sent = Some(Instant::now());
+        if self.tx.try_send(render(&self.body, vars)).is_err() {
+            self.internal.log(
+                SyslogSeverity::SEV_WARNING,
+                "webhook queue is full, dropping an alert".to_owned(),
+            );
+        }
+    }
+}
+
+fn deliver(agent: &ureq::Agent, url: &str, token: Option<&str>, body: &str) -> Result<(), String> {
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        let mut req = agent.post(url).set("Content-Type", "application/json");
+        if let Some(token) = token {
+            req = req.set("Authorization", &format!("Bearer {}", token));
+        }
+        let err = match req.send_string(body) {
+            Ok(_) => return Ok(()),
+            // The endpoint doesn't like the request, asking again won't change its mind.
+            Err(ureq::Error::Status(code, _)) if code < 500 => {
+                return Err(format!("HTTP status {}", code))
+            }
+            Err(e) => e.to_string(),
+        };
+        if attempt == ATTEMPTS {
+            return Err(err);
+        }
+        std::thread::sleep(delay);
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+impl Output for Webhook {
+    fn send(&mut self, out: &Outgoing) {
+        // Our own complaints about failed deliveries must not trigger more deliveries.
+        if out.socket == internal::SOCKET || !self.matcher.matches(out) {
+            return;
+        }
+        let mut vars = Vars::new(out, &self.hostname);
+        if self.sent.map_or(false, |t| t.elapsed() < self.window) {
+            vars.count = self.held.as_ref().map_or(0, |h| h.count) + 1;
+            self.held = Some(vars);
+            return;
+        }
+        self.queue(&vars);
+    }
+
+    fn tick(&mut self) {
+        if self.sent.map_or(false, |t| t.elapsed() >= self.window) {
+            self.sent = None;
+            if let Some(held) = self.held.take() {
+                self.queue(&held);
+            }
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.held.as_ref()?;
+        self.sent.map(|t| t + self.window)
+    }
+}
@@ -0,0 +1,293 @@
+use crate::{names, schema};
+use chrono::prelude::*;
+use rusqlite::{types::ToSql, Connection};
+use std::path::Path;
+
+/// The counting window, read from the hourly summary table when it's available.
+///
+/// The summary only has hour granularity, so the partial hours at either end of the window are
+/// counted directly from the log table (which is cheap thanks to the recv_time index).
+pub struct Window {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    summary: bool,
+}
+
+fn hour_floor(t: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.from_utc_datetime(&t.date_naive().and_hms_opt(t.hour(), 0, 0).unwrap())
+}
+
+fn hour_ceil(t: DateTime<Utc>) -> DateTime<Utc> {
+    let floor = hour_floor(t);
+    if floor < t {
+        floor + chrono::Duration::hours(1)
+    } else {
+        floor
+    }
+}
+
+/// How the summary table spells an hour.
+pub fn hour_text(t: DateTime<Utc>) -> String {
+    t.format("%Y-%m-%d %H:00:00").to_string()
+}
+
+type Params = Vec<(&'static str, Box<dyn ToSql>)>;
+
+impl Window {
+    pub fn new(
+        conn: &Connection,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> rusqlite::Result<Window> {
+        Ok(Window {
+            since,
+            until,
+            summary: schema::has_column(conn, "log_summary", "hostname")?,
+        })
+    }
+
+    /// A query for rows of `hour, socket, hostname, appname, severity, count` adding up to the
+    /// messages in the window, and its parameters.
+    pub fn counts(&self) -> (String, Params) {
+        let direct = |cond: &str| {
+            format!(
+                "SELECT strftime('%Y-%m-%d %H:00:00', coalesce(recv_time, time)) AS hour,
+                    socket, hostname, appname, severity, 1 AS count FROM log{}",
+                cond
+            )
+        };
+        let mut params: Params = vec![];
+        if let Some(since) = self.since {
+            params.push((":since", Box::new(since)));
+        }
+        if let Some(until) = self.until {
+            params.push((":until", Box::new(until)));
+        }
+        let first_hour = self.since.map(hour_ceil);
+        let end_hour = self.until.map(hour_floor);
+        let whole_hours = match (first_hour, end_hour) {
+            (Some(first), Some(end)) => first < end,
+            _ => true,
+        };
+        if !self.summary || !whole_hours {
+            let cond = match (self.since, self.until) {
+                (None, None) => "",
+                (Some(_), None) => " WHERE recv_time >= :since",
+                (None, Some(_)) => " WHERE recv_time < :until",
+                (Some(_), Some(_)) => " WHERE recv_time >= :since AND recv_time < :until",
+            };
+            return (direct(cond), params);
+        }
+        let mut conds = vec![];
+        let mut parts = vec![];
+        if let Some(first) = first_hour {
+            conds.push("hour >= :first_hour_text");
+            parts.push(direct(
+                " WHERE recv_time >= :since AND recv_time < :first_hour",
+            ));
+            params.push((":first_hour", Box::new(first)));
+            params.push((":first_hour_text", Box::new(hour_text(first))));
+        }
+        if let Some(end) = end_hour {
+            conds.push("hour < :end_hour_text");
+            parts.push(direct(
+                " WHERE recv_time >= :end_hour AND recv_time < :until",
+            ));
+            params.push((":end_hour", Box::new(end)));
+            params.push((":end_hour_text", Box::new(hour_text(end))));
+        }
+        let mut summary =
+            "SELECT hour, socket, hostname, appname, severity, count FROM log_summary".to_owned();
+        if !conds.is_empty() {
+            summary += " WHERE ";
+            summary += &conds.join(" AND ");
+        }
+        parts.insert(0, summary);
+        (parts.join(" UNION ALL "), params)
+    }
+
+    /// Message counts grouped by `column` (any expression over the `counts` columns).
+    pub fn group_by<T: rusqlite::types::FromSql>(
+        &self,
+        conn: &Connection,
+        column: &str,
+        order: &str,
+    ) -> rusqlite::Result<Vec<(T, i64)>> {
+        let (counts, params) = self.counts();
+        let params: Vec<(&str, &dyn ToSql)> =
+            params.iter().map(|(k, v)| (*k, v.as_ref())).collect();
+        conn.prepare(&format!(
+            "SELECT {0}, sum(count) AS n FROM ({1}) GROUP BY {0} ORDER BY {2}",
+            column, counts, order
+        ))?
+        .query_map(&*params, |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect()
+    }
+}
+
+pub struct Stats {
+    pub since: Option<DateTime<Utc>>,
+    pub rows: i64,
+    pub severities: Vec<(Option<i64>, i64)>,
+    pub appnames: Vec<(Option<String>, i64)>,
+    pub sockets: Vec<(String, i64)>,
+    pub oldest: Option<DateTime<Utc>>,
+    pub newest: Option<DateTime<Utc>>,
+    pub db_size: u64,
+    pub wal_size: u64,
+    pub schema_version: usize,
+    /// Messages dropped by each of the daemon's filter rules (ever, not just in the window),
+    /// with when the last one was.
+    pub filters: Vec<(String, i64, Option<DateTime<Utc>>)>,
+    /// What the daemon's sampler left out (ever), by source.
+    pub sampled: Vec<Sampled>,
+}
+
+pub struct Sampled {
+    pub socket: String,
+    pub appname: String,
+    pub runs: i64,
+    pub seen: i64,
+    pub kept: i64,
+    pub last: Option<DateTime<Utc>>,
+}
+
+impl Stats {
+    pub fn collect(
+        conn: &Connection,
+        path: &Path,
+        since: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Stats> {
+        let window = Window::new(conn, since, None)?;
+        let severities = window.group_by(conn, "severity", "severity")?;
+        let appnames = window.group_by(conn, "appname", "n DESC LIMIT 20")?;
+        let sockets = window.group_by(conn, "socket", "n DESC LIMIT 20")?;
+        // Separate queries, since SQLite only uses the index for a lone min/max.
+        let oldest = match since {
+            Some(since) => conn.query_row(
+                "SELECT min(recv_time) FROM log WHERE recv_time >= ?",
+                [since],
+                |row| row.get(0),
+            )?,
+            None => conn.query_row("SELECT min(recv_time) FROM log", [], |row| row.get(0))?,
+        };
+        let newest = conn.query_row("SELECT max(recv_time) FROM log", [], |row| row.get(0))?;
+        let filters = if schema::has_table(conn, "filter_stats")? {
+            conn.prepare("SELECT rule, dropped, last_time FROM filter_stats ORDER BY dropped DESC")?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<rusqlite::Result<_>>()?
+        } else {
+            vec![]
+        };
+        let sampled = if schema::has_table(conn, "sample_stats")? {
+            conn.prepare(
+                "SELECT socket, appname, runs, seen, kept, last_time FROM sample_stats
+                ORDER BY seen - kept DESC",
+            )?
+            .query_map([], |row| {
+                Ok(Sampled {
+                    socket: row.get(0)?,
+                    appname: row.get(1)?,
+                    runs: row.get(2)?,
+                    seen: row.get(3)?,
+                    kept: row.get(4)?,
+                    last: row.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?
+        } else {
+            vec![]
+        };
+        let mut wal = path.as_os_str().to_owned();
+        wal.push("-wal");
+        Ok(Stats {
+            since,
+            rows: severities.iter().map(|(_, n)| n).sum(),
+            severities,
+            appnames,
+            sockets,
+            oldest,
+            newest,
+            db_size: std::fs::metadata(path)?.len(),
+            wal_size: std::fs::metadata(wal).map(|m| m.len()).unwrap_or(0),
+            schema_version: schema::current_version(conn)?,
+            filters,
+            sampled,
+        })
+    }
+
+    /// Messages per second from the start of the window until now.
+    pub fn rate(&self) -> Option<f64> {
+        let secs = (Utc::now() - self.since.or(self.oldest)?).num_milliseconds() as f64 / 1000.0;
+        Some(if secs > 0.0 {
+            self.rows as f64 / secs
+        } else {
+            0.0
+        })
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "since": self.since.map(|t| t.to_rfc3339()),
+            "rows": self.rows,
+            "rate": self.rate(),
+            "severities": self.severities.iter().map(|(sev, n)| serde_json::json!({
+                "severity": sev,
+                "name": sev.and_then(names::severity_name),
+                "count": n,
+            })).collect::<Vec<_>>(),
+            "appnames": self.appnames.iter().map(|(app, n)| serde_json::json!({
+                "appname": app,
+                "count": n,
+            })).collect::<Vec<_>>(),
+            "sockets": self.sockets.iter().map(|(sock, n)| serde_json::json!({
+                "socket": sock,
+                "count": n,
+            })).collect::<Vec<_>>(),
+            "oldest": self.oldest.map(|t| t.to_rfc3339()),
+            "newest": self.newest.map(|t| t.to_rfc3339()),
+            "db_size": self.db_size,
+            "wal_size": self.wal_size,
+            "schema_version": self.schema_version,
+            "latest_schema_version": schema::latest_version(),
+            "filters": self.filters.iter().map(|(rule, n, last)| serde_json::json!({
+                "rule": rule,
+                "dropped": n,
+                "last": last.map(|t| t.to_rfc3339()),
+            })).collect::<Vec<_>>(),
+            "sampled": self.sampled.iter().map(|s| serde_json::json!({
+                "socket": s.socket,
+                "appname": s.appname,
+                "runs": s.runs,
+                "seen": s.seen,
+                "kept": s.kept,
+                "last": s.last.map(|t| t.to_rfc3339()),
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+pub fn human_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if size < 1024.0 {
+            return format!("{:.1} {}", size, unit);
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} TiB", size)
+}
+
+/// Parses sizes like `64M` or `1.5GiB` (powers of 1024, the B optional).
+pub fn parse_size(s: &str) -> Option<u64> {
+    let t = s.trim_end_matches(['B', 'b']).trim_end_matches(['I', 'i']);
+    let (num, mult) = match t.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&t[..i], 1u64 << 10),
+        Some((i, 'M' | 'm')) => (&t[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&t[..i], 1 << 30),
+        Some((i, 'T' | 't')) => (&t[..i], 1 << 40),
+        _ => (t, 1),
+    };
+    let n = num.trim().parse::<f64>().ok().filter(|n| *n >= 0.0)?;
+    Some((n * mult as f64) as u64)
+}
@@ -0,0 +1,757 @@
+//! Where messages are stored, behind a trait so that the rest doesn't depend on SQLite: `Sqlite`
+//! is the real thing, `Memory` keeps rows in a `Vec`, for trying out parsing and pipelines
+//! without a database.
+//!
+//! Backends differ mostly in what a failure costs, so that's part of the interface. Rows are
+//! stored one at a time unless a batch is open, in which case they're stored together when it's
+//! committed. A failed insert costs its row, and sometimes more: SQLite rolls back the whole
+//! transaction on some errors (a full disk, an I/O error, running out of memory), which undoes
+//! the rows inserted into the batch before. `Failure::undone` says how many that was, for the
+//! caller to insert again or count as lost. Either way the batch is still open afterwards, and
+//! goes on with the next row.
+//!
+//! Some failures pass by themselves, like the database being locked or the disk full. A row
+//! that failed like that can go into a `Spill`, which stores it later.
+
+use crate::schema::{self, Row};
+use chrono::{DateTime, FixedOffset, Utc};
+use rusqlite::{Connection, ErrorCode, Statement};
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub trait Storage {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Starts a batch, which `commit` ends.
+    fn begin_batch(&mut self) -> Result<(), Self::Error>;
+
+    /// Stores a row, returning its id.
+    fn insert(&mut self, row: &Row) -> Result<i64, Failure<Self::Error>>;
+
+    /// Stores the batch, returning how many rows were in it. A batch that fails to commit is
+    /// gone, `undone` being all of it.
+    fn commit(&mut self) -> Result<u64, Failure<Self::Error>>;
+
+    fn maintain(&mut self, kind: Maintenance) -> Result<(), Self::Error>;
+
+    fn stats(&self) -> Stats;
+
+    /// Whether trying again later might work.
+    fn is_transient(_error: &Self::Error) -> bool {
+        false
+    }
+}
+
+#[derive(Debug)]
+pub struct Failure<E> {
+    pub error: E,
+    /// Rows of the open batch the failure undid, besides the one that failed.
+    pub undone: u64,
+}
+
+impl<E: fmt::Display> fmt::Display for Failure<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.undone {
+            0 => write!(f, "{}", self.error),
+            n => write!(
+                f,
+                "{} (undoing {} earlier rows of the batch)",
+                self.error, n
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Maintenance {
+    /// Moves what's been written into the database proper, like SQLite's WAL.
+    Checkpoint,
+    /// Gives unused space back.
+    Vacuum,
+}
+
+/// What a backend did since it was opened.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    pub inserted: u64,
+    pub failed: u64,
+    /// Rows undone by failures, see `Failure`.
+    pub undone: u64,
+    pub batches: u64,
+}
+
+pub struct Sqlite<'c> {
+    conn: &'c Connection,
+    insert: Statement<'c>,
+    /// Rows in the open batch, if there is one.
+    batch: Option<u64>,
+    stats: Stats,
+}
+
+impl<'c> Sqlite<'c> {
+    /// Stores into `log`, the schema being up to date already.
+    pub fn new(conn: &'c Connection) -> rusqlite::Result<Sqlite<'c>> {
+        Ok(Sqlite {
+            conn,
+            insert: conn.prepare(schema::INSERT)?,
+            batch: None,
+            stats: Stats::default(),
+        })
+    }
+
+    /// The batch was rolled back by SQLite: says how many rows that was, and starts again.
+    fn rolled_back(&mut self) -> u64 {
+        let undone = match self.batch {
+            Some(ref mut rows) if self.conn.is_autocommit() => std::mem::take(rows),
+            _ => return 0,
+        };
+        self.stats.undone += undone;
+        // Failing again here fails the next insert, which tries once more.
+        if self.conn.execute_batch("BEGIN").is_err() {
+            self.batch = None;
+        }
+        undone
+    }
+}
+
+impl Storage for Sqlite<'_> {
+    type Error = rusqlite::Error;
+
+    fn begin_batch(&mut self) -> rusqlite::Result<()> {
+        if self.batch.is_none() {
+            self.conn.execute_batch("BEGIN")?;
+            self.batch = Some(0);
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, row: &Row) -> Result<i64, Failure<rusqlite::Error>> {
+        match row.insert(&mut self.insert) {
+            Ok(_) => {
+                self.stats.inserted += 1;
+                if let Some(ref mut rows) = self.batch {
+                    *rows += 1;
+                }
+                Ok(self.conn.last_insert_rowid())
+            }
+            Err(error) => {
+                self.stats.failed += 1;
+                let undone = self.rolled_back();
+                Err(Failure { error, undone })
+            }
+        }
+    }
+
+    fn commit(&mut self) -> Result<u64, Failure<rusqlite::Error>> {
+        let rows = match self.batch.take() {
+            Some(rows) => rows,
+            None => return Ok(0),
+        };
+        match self.conn.execute_batch("COMMIT") {
+            Ok(()) => {
+                self.stats.batches += 1;
+                Ok(rows)
+            }
+            Err(error) => {
+                if !self.conn.is_autocommit() {
+                    let _ = self.conn.execute_batch("ROLLBACK");
+                }
+                self.stats.undone += rows;
+                Err(Failure {
+                    error,
+                    undone: rows,
+                })
+            }
+        }
+    }
+
+    fn maintain(&mut self, kind: Maintenance) -> rusqlite::Result<()> {
+        match kind {
+            Maintenance::Checkpoint => {
+                self.conn
+                    .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+            }
+            Maintenance::Vacuum => self.conn.execute_batch("PRAGMA incremental_vacuum"),
+        }
+    }
+
+    fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    fn is_transient(error: &rusqlite::Error) -> bool {
+        matches!(
+            error,
+            rusqlite::Error::SqliteFailure(e, _) if matches!(
+                e.code,
+                ErrorCode::DatabaseBusy
+                    | ErrorCode::DatabaseLocked
+                    | ErrorCode::DiskFull
+                    | ErrorCode::SystemIoFailure
+            )
+        )
+    }
+}
+
+/// A row kept by `Memory`, owning what a `Row` borrows.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub id: i64,
+    pub facility: Option<i64>,
+    pub severity: Option<i64>,
+    pub socket: String,
+    pub hostname: Option<String>,
+    pub hostname_source: Option<String>,
+    pub appname: Option<String>,
+    pub pid: Option<i32>,
+    pub msgid: Option<String>,
+    pub time: Option<DateTime<FixedOffset>>,
+    pub recv_time: DateTime<Utc>,
+    pub boot: Option<i64>,
+    pub msg: String,
+    pub sdata: Option<String>,
+}
+
+impl Record {
+    pub(crate) fn new(id: i64, row: &Row) -> Record {
+        let owned = |s: Option<&str>| s.map(str::to_owned);
+        Record {
+            id,
+            facility: row.facility,
+            severity: row.severity,
+            socket: row.socket.to_owned(),
+            hostname: owned(row.hostname),
+            hostname_source: owned(row.hostname_source),
+            appname: owned(row.appname),
+            pid: row.pid,
+            msgid: owned(row.msgid),
+            time: row.time,
+            recv_time: row.recv_time,
+            boot: row.boot,
+            msg: row.msg.to_owned(),
+            sdata: row.sdata.clone(),
+        }
+    }
+
+    /// Makes it `row`, reusing the buffers it has.
+    fn set(&mut self, id: i64, row: &Row) {
+        fn set(to: &mut Option<String>, from: Option<&str>) {
+            match (to.as_mut(), from) {
+                (Some(to), Some(from)) => {
+                    to.clear();
+                    to.push_str(from);
+                }
+                _ => *to = from.map(str::to_owned),
+            }
+        }
+        self.id = id;
+        self.facility = row.facility;
+        self.severity = row.severity;
+        self.socket.clear();
+        self.socket.push_str(row.socket);
+        set(&mut self.hostname, row.hostname);
+        set(&mut self.hostname_source, row.hostname_source);
+        set(&mut self.appname, row.appname);
+        self.pid = row.pid;
+        set(&mut self.msgid, row.msgid);
+        self.time = row.time;
+        self.recv_time = row.recv_time;
+        self.boot = row.boot;
+        self.msg.clear();
+        self.msg.push_str(row.msg);
+        self.sdata.clone_from(&row.sdata);
+    }
+
+    /// The row again, to store it somewhere else.
+    pub fn row(&self) -> Row<'_> {
+        Row {
+            facility: self.facility,
+            severity: self.severity,
+            socket: &self.socket,
+            hostname: self.hostname.as_deref(),
+            hostname_source: self.hostname_source.as_deref(),
+            appname: self.appname.as_deref(),
+            pid: self.pid,
+            msgid: self.msgid.as_deref(),
+            time: self.time,
+            recv_time: self.recv_time,
+            boot: self.boot,
+            msg: &self.msg,
+            sdata: self.sdata.clone(),
+        }
+    }
+}
+
+/// The rows inserted into a batch that's open, kept until it's committed for the ones a
+/// failure undoes (see `Failure`) to be stored again. Once it is, their buffers are reused for
+/// the next batch's rows, so that keeping them doesn't cost allocations of its own.
+#[derive(Default)]
+pub struct Batch {
+    rows: Vec<Record>,
+    len: usize,
+    open: bool,
+}
+
+impl Batch {
+    pub fn begin(&mut self) {
+        self.open = true;
+        self.len = 0;
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Keeps a row that was inserted, if the batch is open.
+    pub fn push(&mut self, id: i64, row: &Row) {
+        if !self.open {
+            return;
+        }
+        match self.rows.get_mut(self.len) {
+            Some(record) => record.set(id, row),
+            None => self.rows.push(Record::new(id, row)),
+        }
+        self.len += 1;
+    }
+
+    /// Takes out the last `n` rows, which a failure undid.
+    pub fn undone(&mut self, n: usize) -> Vec<Record> {
+        let from = self.len.saturating_sub(n);
+        let undone = self.rows.drain(from..self.len).collect();
+        self.len = from;
+        undone
+    }
+
+    /// Closes the batch, and takes out its rows if committing it failed.
+    pub fn end(&mut self, failed: bool) -> Vec<Record> {
+        let rows = if failed {
+            self.undone(self.len)
+        } else {
+            vec![]
+        };
+        self.open = false;
+        self.len = 0;
+        rows
+    }
+}
+
+/// Rows in a `Vec`, which never fails. Ids count from 1, like in `log`.
+#[derive(Default)]
+pub struct Memory {
+    pub rows: Vec<Record>,
+    /// Where the open batch starts in `rows`.
+    batch: Option<usize>,
+    stats: Stats,
+}
+
+impl Storage for Memory {
+    type Error = std::convert::Infallible;
+
+    fn begin_batch(&mut self) -> Result<(), Self::Error> {
+        self.batch.get_or_insert(self.rows.len());
+        Ok(())
+    }
+
+    fn insert(&mut self, row: &Row) -> Result<i64, Failure<Self::Error>> {
+        let id = self.rows.len() as i64 + 1;
+        self.rows.push(Record::new(id, row));
+        self.stats.inserted += 1;
+        Ok(id)
+    }
+
+    fn commit(&mut self) -> Result<u64, Failure<Self::Error>> {
+        match self.batch.take() {
+            Some(start) => {
+                self.stats.batches += 1;
+                Ok((self.rows.len() - start) as u64)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn maintain(&mut self, _: Maintenance) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn stats(&self) -> Stats {
+        self.stats
+    }
+}
+
+/// How long a `Spill` waits before trying again, at first and at most.
+const SPILL_BACKOFF: (Duration, Duration) = (Duration::from_millis(100), Duration::from_secs(10));
+
+/// Rows that couldn't be stored for now, to store later: oldest first, and up to a number of
+/// them, past which the oldest are dropped. While storing them keeps failing, it waits longer
+/// and longer between tries.
+pub struct Spill {
+    rows: VecDeque<Record>,
+    capacity: usize,
+    /// When to try again.
+    retry_at: Option<Instant>,
+    backoff: Duration,
+}
+
+impl Spill {
+    pub fn new(capacity: usize) -> Spill {
+        Spill {
+            rows: VecDeque::new(),
+            capacity: capacity.max(1),
+            retry_at: None,
+            backoff: SPILL_BACKOFF.0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Keeps a row, returning false if that took dropping the oldest one.
+    pub fn push(&mut self, row: &Row, now: Instant) -> bool {
+        self.retry_at.get_or_insert(now + self.backoff);
+        let room = self.rows.len() < self.capacity;
+        if !room {
+            self.rows.pop_front();
+        }
+        self.rows.push_back(Record::new(0, row));
+        room
+    }
+
+    /// Keeps rows that were stored and then undone (see `Failure`), oldest first, ahead of the
+    /// ones it holds already, which came after them. Returns how many of them it had to drop.
+    pub fn put_back(&mut self, rows: Vec<Record>, now: Instant) -> usize {
+        self.retry_at.get_or_insert(now + self.backoff);
+        let mut dropped = 0;
+        for record in rows.into_iter().rev() {
+            if self.rows.len() < self.capacity {
+                self.rows.push_front(record);
+            } else {
+                dropped += 1;
+            }
+        }
+        dropped
+    }
+
+    /// When `retry` has something to do.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.retry_at.filter(|_| !self.rows.is_empty())
+    }
+
+    /// Stores what it holds, if it's time to try again.
+    pub fn retry<S: Storage>(&mut self, storage: &mut S, now: Instant) -> Retried<S::Error> {
+        match self.deadline() {
+            Some(at) if at <= now => self.store(storage, now),
+            _ => Retried::default(),
+        }
+    }
+
+    /// Stores what it holds, oldest first, until a row fails. One that fails for good is
+    /// dropped and returned as the error, for the next call to go on after it, one that might
+    /// not later on is kept for then. Rows go in one at a time, so a failure can't undo others.
+    pub fn store<S: Storage>(&mut self, storage: &mut S, now: Instant) -> Retried<S::Error> {
+        let mut retried = Retried::default();
+        while let Some(record) = self.rows.front() {
+            match storage.insert(&record.row()) {
+                Ok(_) => {
+                    self.rows.pop_front();
+                    retried.stored += 1;
+                }
+                Err(f) => {
+                    if S::is_transient(&f.error) {
+                        self.backoff = (self.backoff * 2).min(SPILL_BACKOFF.1);
+                        self.retry_at = Some(now + self.backoff);
+                    } else {
+                        self.rows.pop_front();
+                        self.retry_at = Some(now);
+                    }
+                    retried.error = Some(f.error);
+                    return retried;
+                }
+            }
+        }
+        self.retry_at = None;
+        self.backoff = SPILL_BACKOFF.0;
+        retried
+    }
+}
+
+/// What `Spill::retry` stored, and why it stopped if it didn't store everything.
+#[derive(Debug)]
+pub struct Retried<E> {
+    pub stored: u64,
+    pub error: Option<E>,
+}
+
+impl<E> Default for Retried<E> {
+    fn default() -> Self {
+        Retried {
+            stored: 0,
+            error: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn row(msg: &str) -> Row<'_> {
+        Row {
+            facility: Some(1),
+            severity: Some(6),
+            socket: "test",
+            hostname: None,
+            hostname_source: None,
+            appname: Some("app"),
+            pid: None,
+            msgid: None,
+            time: None,
+            recv_time: Utc::now(),
+            boot: None,
+            msg,
+            sdata: None,
+        }
+    }
+
+    /// A database file of its own, for a second connection to lock.
+    fn db(name: &str) -> (PathBuf, Connection) {
+        let path = std::env::temp_dir().join(format!(
+            "squealog-storage-{}-{}.db",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut conn = Connection::open(&path).unwrap();
+        schema::migrations().to_latest(&mut conn).unwrap();
+        (path, conn)
+    }
+
+    fn msgs(conn: &Connection) -> Vec<String> {
+        conn.prepare("SELECT msg FROM log ORDER BY id")
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    /// Stores `msg`, or spills it like the daemon does.
+    fn store<S: Storage>(storage: &mut S, spill: &mut Spill, msg: &str, now: Instant) {
+        if !spill.is_empty() || storage.insert(&row(msg)).is_err() {
+            spill.push(&row(msg), now);
+        }
+    }
+
+    #[test]
+    fn spilled_rows_are_stored_once_the_database_isnt_locked() {
+        let (path, conn) = db("busy");
+        conn.busy_timeout(std::time::Duration::ZERO).unwrap();
+        let other = Connection::open(&path).unwrap();
+        let mut storage = Sqlite::new(&conn).unwrap();
+        let mut spill = Spill::new(100);
+        let start = Instant::now();
+
+        store(&mut storage, &mut spill, "before", start);
+        other.execute_batch("BEGIN EXCLUSIVE").unwrap();
+        let e = storage.insert(&row("locked")).unwrap_err();
+        assert!(Sqlite::is_transient(&e.error), "{}", e);
+        spill.push(&row("locked"), start);
+        store(&mut storage, &mut spill, "after", start);
+        assert_eq!(spill.len(), 2);
+
+        // Not before it's time, and then not while it's still locked.
+        assert_eq!(spill.retry(&mut storage, start).stored, 0);
+        let at = spill.deadline().unwrap();
+        let retried = spill.retry(&mut storage, at);
+        assert!(retried.error.is_some_and(|e| Sqlite::is_transient(&e)));
+        assert_eq!(spill.len(), 2);
+        let later = spill.deadline().unwrap();
+        assert!(later - at > at - start, "should back off");
+
+        other.execute_batch("COMMIT").unwrap();
+        let retried = spill.retry(&mut storage, later);
+        assert!(retried.error.is_none());
+        assert_eq!(retried.stored, 2);
+        assert!(spill.is_empty());
+        assert_eq!(spill.deadline(), None);
+        assert_eq!(msgs(&conn), ["before", "locked", "after"]);
+        drop(storage);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn spilled_rows_are_stored_once_there_is_room() {
+        let (path, conn) = db("full");
+        let mut storage = Sqlite::new(&conn).unwrap();
+        let mut spill = Spill::new(100);
+        let now = Instant::now();
+        let big = "x".repeat(16 * 1024);
+
+        store(&mut storage, &mut spill, "before", now);
+        let pages: i64 = conn
+            .query_row("PRAGMA page_count", [], |r| r.get(0))
+            .unwrap();
+        conn.execute_batch(&format!("PRAGMA max_page_count = {}", pages))
+            .unwrap();
+        let e = storage.insert(&row(&big)).unwrap_err();
+        assert!(
+            matches!(e.error, rusqlite::Error::SqliteFailure(ref e, _) if e.code == ErrorCode::DiskFull),
+            "{}",
+            e
+        );
+        assert!(Sqlite::is_transient(&e.error));
+        spill.push(&row(&big), now);
+        store(&mut storage, &mut spill, "after", now);
+        let at = spill.deadline().unwrap();
+        assert!(spill.retry(&mut storage, at).error.is_some());
+        assert_eq!(spill.len(), 2);
+
+        conn.execute_batch("PRAGMA max_page_count = 1000000")
+            .unwrap();
+        let at = spill.deadline().unwrap();
+        assert_eq!(spill.retry(&mut storage, at).stored, 2);
+        assert_eq!(msgs(&conn), ["before", &big, "after"]);
+        drop(storage);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// `Memory`, except that the next insert of `fail` fails and undoes the open batch, like
+    /// SQLite does on some errors.
+    #[derive(Default)]
+    struct Undoing {
+        memory: Memory,
+        fail: Option<&'static str>,
+    }
+
+    impl Storage for Undoing {
+        type Error = std::io::Error;
+
+        fn begin_batch(&mut self) -> Result<(), Self::Error> {
+            self.memory.begin_batch().unwrap();
+            Ok(())
+        }
+
+        fn insert(&mut self, row: &Row) -> Result<i64, Failure<Self::Error>> {
+            if self.fail == Some(row.msg) {
+                self.fail = None;
+                let start = self.memory.batch.unwrap_or(self.memory.rows.len());
+                let undone = self.memory.rows.split_off(start).len() as u64;
+                let error = std::io::ErrorKind::StorageFull.into();
+                return Err(Failure { error, undone });
+            }
+            Ok(self.memory.insert(row).unwrap())
+        }
+
+        fn commit(&mut self) -> Result<u64, Failure<Self::Error>> {
+            Ok(self.memory.commit().unwrap())
+        }
+
+        fn maintain(&mut self, _: Maintenance) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn stats(&self) -> Stats {
+            self.memory.stats()
+        }
+
+        fn is_transient(_: &Self::Error) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn rows_a_failure_undoes_are_put_back_before_the_rest() {
+        let mut storage = Undoing {
+            fail: Some("3"),
+            ..Default::default()
+        };
+        let mut spill = Spill::new(100);
+        let now = Instant::now();
+
+        storage.insert(&row("before")).unwrap();
+        storage.begin_batch().unwrap();
+        let mut batch = vec![];
+        for msg in ["1", "2"] {
+            let id = storage.insert(&row(msg)).unwrap();
+            batch.push(Record::new(id, &row(msg)));
+        }
+        let f = storage.insert(&row("3")).unwrap_err();
+        assert_eq!(f.undone, 2);
+        let undone = batch.split_off(batch.len() - f.undone as usize);
+        assert_eq!(spill.put_back(undone, now), 0);
+        spill.push(&row("3"), now);
+        spill.push(&row("after"), now);
+        storage.commit().unwrap();
+
+        let at = spill.deadline().unwrap();
+        assert_eq!(spill.retry(&mut storage, at).stored, 4);
+        let msgs: Vec<_> = storage.memory.rows.iter().map(|r| r.msg.as_str()).collect();
+        assert_eq!(msgs, ["before", "1", "2", "3", "after"]);
+    }
+
+    #[test]
+    fn batches_give_back_their_rows_whatever_the_rows_before_them() {
+        let now = Utc::now();
+        let plain = |msg| Row {
+            recv_time: now,
+            ..row(msg)
+        };
+        let full = |msg| Row {
+            hostname: Some("web1"),
+            hostname_source: Some("claimed"),
+            msgid: Some("ID1"),
+            sdata: Some(r#"{"a@1":{"b":"c"}}"#.to_owned()),
+            ..plain(msg)
+        };
+        let mut batch = Batch::default();
+        batch.push(1, &full("closed"));
+        assert_eq!(batch.end(true), []);
+        batch.begin();
+        for (id, msg) in [(1, "a"), (2, "b"), (3, "c")] {
+            batch.push(id, &full(msg));
+        }
+        assert_eq!(batch.undone(1), [Record::new(3, &full("c"))]);
+        assert_eq!(batch.end(false), []);
+        // Into the rows of the last batch, where each field was set.
+        batch.begin();
+        batch.push(4, &plain("d"));
+        batch.push(5, &full("e"));
+        assert_eq!(
+            batch.end(true),
+            [Record::new(4, &plain("d")), Record::new(5, &full("e"))]
+        );
+    }
+
+    #[test]
+    fn put_back_drops_the_oldest_when_full() {
+        let mut spill = Spill::new(3);
+        let now = Instant::now();
+        spill.push(&row("3"), now);
+        spill.push(&row("4"), now);
+        let undone = ["1", "2"].map(|m| Record::new(0, &row(m))).to_vec();
+        assert_eq!(spill.put_back(undone, now), 1);
+        let mut memory = Memory::default();
+        assert_eq!(spill.store(&mut memory, now).stored, 3);
+        let msgs: Vec<_> = memory.rows.iter().map(|r| r.msg.as_str()).collect();
+        assert_eq!(msgs, ["2", "3", "4"]);
+    }
+
+    #[test]
+    fn spill_drops_the_oldest_when_full() {
+        let mut spill = Spill::new(2);
+        let now = Instant::now();
+        assert!(spill.push(&row("1"), now));
+        assert!(spill.push(&row("2"), now));
+        assert!(!spill.push(&row("3"), now));
+        let mut memory = Memory::default();
+        assert_eq!(spill.store(&mut memory, now).stored, 2);
+        let msgs: Vec<_> = memory.rows.iter().map(|r| r.msg.as_str()).collect();
+        assert_eq!(msgs, ["2", "3"]);
+    }
+}
@@ -0,0 +1,210 @@
+//! A `tracing` layer (with the `tracing` feature) for Rust services to log into squealog with
+//! their fields intact, instead of to stderr:
+//!
+//! ```no_run
+//! use tracing_subscriber::prelude::*;
+//! let layer = squealog::subscriber::Layer::socket("/var/run/log")?;
+//! tracing_subscriber::registry().with(layer).init();
+//! tracing::info!(user = "greg", "logged in");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Every event becomes one RFC 5424 message: the level is the severity (`TRACE` and `DEBUG`
+//! both being debug), the target the appname, the `message` field the MSG and the process id
+//! the PROCID. The other fields go into the `fields@32473` structured data element, and the
+//! span the event is in into `span@32473`, with its `name`, `id`, the `parent` span's id and
+//! the span's own fields. (32473 is the enterprise number RFC 5612 set aside for examples,
+//! there being none of our own.)
+//!
+//! The messages are sent to a unix datagram socket the daemon listens on, where they're parsed
+//! and stored like any other, or stored directly with an `Ingestor`, one at a time: a layer
+//! installed globally is never dropped, so there would be nothing to flush what's left of a
+//! batch. A message that can't be sent or stored is lost, there being nowhere to report it.
+//! For `log` records, `tracing-log`'s `LogTracer` turns them into events first.
+
+use crate::ingest::Ingestor;
+use crate::serialize::{self, Element};
+use chrono::Local;
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use syslog_loose::{SyslogFacility, SyslogSeverity};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+pub const FIELDS_ID: &str = crate::client::FIELDS_ID;
+pub const SPAN_ID: &str = "span@32473";
+/// The socket name for messages stored with an `Ingestor`.
+pub const SOCKET: &str = "tracing";
+/// The daemon's default receive buffer for unix sockets, longer messages would be cut off.
+const DATAGRAM_LIMIT: usize = 8192;
+
+enum Sink {
+    Socket(UnixDatagram, PathBuf),
+    Ingestor(Mutex<Ingestor>),
+}
+
+pub struct Layer {
+    sink: Sink,
+    facility: SyslogFacility,
+}
+
+impl Layer {
+    /// Sends to the daemon's socket at `path`.
+    pub fn socket(path: impl AsRef<Path>) -> io::Result<Layer> {
+        Ok(Layer::new(Sink::Socket(
+            UnixDatagram::unbound()?,
+            path.as_ref().to_owned(),
+        )))
+    }
+
+    /// Stores with `ingestor`, as messages from the `tracing` socket.
+    pub fn ingestor(ingestor: Ingestor) -> Layer {
+        Layer::new(Sink::Ingestor(Mutex::new(ingestor.batch(1))))
+    }
+
+    fn new(sink: Sink) -> Layer {
+        Layer {
+            sink,
+            facility: SyslogFacility::LOG_USER,
+        }
+    }
+
+    /// Sends with `facility` instead of user.
+    pub fn facility(mut self, facility: SyslogFacility) -> Layer {
+        self.facility = facility;
+        self
+    }
+
+    fn send(&self, line: &str) {
+        match self.sink {
+            Sink::Socket(ref sock, ref path) => {
+                let _ = sock.send_to(line.as_bytes(), path);
+            }
+            Sink::Ingestor(ref ingestor) => {
+                if let Ok(mut ingestor) = ingestor.lock() {
+                    let _ = ingestor.ingest_line(SOCKET, line);
+                }
+            }
+        }
+    }
+}
+
+fn severity(level: &Level) -> SyslogSeverity {
+    match *level {
+        Level::ERROR => SyslogSeverity::SEV_ERR,
+        Level::WARN => SyslogSeverity::SEV_WARNING,
+        Level::INFO => SyslogSeverity::SEV_INFO,
+        _ => SyslogSeverity::SEV_DEBUG,
+    }
+}
+
+/// An event's or a span's fields, as text.
+#[derive(Default)]
+struct Fields {
+    message: Option<String>,
+    params: Vec<(String, String)>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field, value.to_owned());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.push(field, format!("{:?}", value));
+    }
+}
+
+impl Fields {
+    fn push(&mut self, field: &Field, value: String) {
+        match field.name() {
+            "message" => self.message = Some(value),
+            name => self.params.push((name.to_owned(), value)),
+        }
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for Layer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let mut sdata = vec![];
+        if !fields.params.is_empty() {
+            sdata.push(Element {
+                id: Cow::Borrowed(FIELDS_ID),
+                params: fields
+                    .params
+                    .into_iter()
+                    .map(|(k, v)| (k.into(), v.into()))
+                    .collect(),
+            });
+        }
+        if let Some(span) = ctx.event_span(event) {
+            let mut params: Vec<(Cow<str>, Cow<str>)> = vec![
+                ("name".into(), span.name().into()),
+                ("id".into(), span.id().into_u64().to_string().into()),
+            ];
+            if let Some(parent) = span.parent() {
+                params.push(("parent".into(), parent.id().into_u64().to_string().into()));
+            }
+            if let Some(fields) = span.extensions().get::<Fields>() {
+                params.extend(
+                    fields
+                        .params
+                        .iter()
+                        .map(|(k, v)| (k.clone().into(), v.clone().into())),
+                );
+            }
+            sdata.push(Element {
+                id: Cow::Borrowed(SPAN_ID),
+                params,
+            });
+        }
+        let rec = serialize::Record {
+            facility: Some(self.facility as u8),
+            severity: Some(severity(meta.level()) as u8),
+            time: Local::now().into(),
+            // Filled in by the daemon (or the ingestor), as for any local message.
+            hostname: None,
+            appname: Some(meta.target().into()),
+            procid: Some(std::process::id().to_string().into()),
+            msgid: None,
+            sdata,
+            msg: fields.message.unwrap_or_default().into(),
+        };
+        let limit = match self.sink {
+            Sink::Socket(..) => Some(DATAGRAM_LIMIT),
+            Sink::Ingestor(_) => None,
+        };
+        let mut line = String::new();
+        serialize::rfc5424(&mut line, &rec, limit);
+        self.send(&line);
+    }
+}
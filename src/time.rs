@@ -0,0 +1,146 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// The zone times are shown in and local times on the command line are read in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Zone {
+    /// Whatever `TZ` or the system says. The offset is looked up for each time, so output
+    /// spanning a DST change gets both sides of it right.
+    Local,
+    Utc,
+    Named(chrono_tz::Tz),
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(Zone::Local),
+            "UTC" | "utc" => Ok(Zone::Utc),
+            _ => s
+                .parse()
+                .map(Zone::Named)
+                .map_err(|_| format!("unknown time zone '{}'", s)),
+        }
+    }
+}
+
+static ZONE: OnceLock<Zone> = OnceLock::new();
+
+/// Sets the zone for the rest of the process, once (before anything is shown).
+pub fn set_zone(zone: Zone) {
+    let _ = ZONE.set(zone);
+}
+
+pub fn zone() -> Zone {
+    ZONE.get().copied().unwrap_or(Zone::Local)
+}
+
+/// `t` in the display zone. Everything shown to people goes through this, so no output ends up
+/// in some other zone.
+pub fn display<Tz: TimeZone>(t: &DateTime<Tz>) -> DateTime<FixedOffset> {
+    match zone() {
+        Zone::Local => t.with_timezone(&Local).into(),
+        Zone::Utc => t.with_timezone(&Utc).into(),
+        Zone::Named(tz) => {
+            let t = t.with_timezone(&tz);
+            t.with_timezone(&t.offset().fix())
+        }
+    }
+}
+
+/// A wall clock time in the display zone (the earlier one if a DST change makes it ambiguous,
+/// `None` if one skips it).
+pub fn from_display(t: &NaiveDateTime) -> Option<DateTime<Utc>> {
+    match zone() {
+        Zone::Local => Local
+            .from_local_datetime(t)
+            .earliest()
+            .map(|t| t.with_timezone(&Utc)),
+        Zone::Utc => Some(Utc.from_utc_datetime(t)),
+        Zone::Named(tz) => tz
+            .from_local_datetime(t)
+            .earliest()
+            .map(|t| t.with_timezone(&Utc)),
+    }
+}
+
+/// A point in time given on the command line.
+///
+/// Accepts RFC 3339, `YYYY-MM-DD[ HH:MM[:SS]]` in the display zone, `now`, `today`,
+/// `yesterday`, and relative offsets into the past like `-1h`, `30min` or `2days`. Wall clock
+/// times are only turned into points in time by `resolve`, once the zone is known.
+#[derive(Clone, Copy, Debug)]
+pub enum TimeSpec {
+    At(DateTime<Utc>),
+    Wall(NaiveDateTime),
+    /// Midnight this many days ago.
+    DaysAgo(u32),
+}
+
+impl TimeSpec {
+    pub fn resolve(self) -> DateTime<Utc> {
+        let wall = match self {
+            TimeSpec::At(t) => return t,
+            TimeSpec::Wall(t) => t,
+            TimeSpec::DaysAgo(n) => (display(&Utc::now()).date_naive() - Duration::days(n.into()))
+                .and_hms_opt(0, 0, 0)
+                .expect("midnight exists"),
+        };
+        // A wall clock time that doesn't exist (skipped by DST) is taken as the offset before.
+        from_display(&wall).unwrap_or_else(|| {
+            from_display(&(wall - Duration::hours(1)))
+                .map_or_else(Utc::now, |t| t + Duration::hours(1))
+        })
+    }
+}
+
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let split = s.find(|c: char| !c.is_ascii_digit())?;
+    let (num, unit) = s.split_at(split);
+    let n: i64 = num.parse().ok()?;
+    Some(match unit.trim() {
+        "s" | "sec" | "secs" | "second" | "seconds" => Duration::seconds(n),
+        "m" | "min" | "mins" | "minute" | "minutes" => Duration::minutes(n),
+        "h" | "hour" | "hours" => Duration::hours(n),
+        "d" | "day" | "days" => Duration::days(n),
+        "w" | "week" | "weeks" => Duration::weeks(n),
+        _ => return None,
+    })
+}
+
+impl FromStr for TimeSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let now = Utc::now();
+        let spec = match s {
+            "now" => Some(TimeSpec::At(now)),
+            "today" => Some(TimeSpec::DaysAgo(0)),
+            "yesterday" => Some(TimeSpec::DaysAgo(1)),
+            _ => parse_duration(s.strip_prefix('-').unwrap_or(s))
+                .map(|d| TimeSpec::At(now - d))
+                .or_else(|| {
+                    DateTime::parse_from_rfc3339(s)
+                        .ok()
+                        .map(|t| TimeSpec::At(t.with_timezone(&Utc)))
+                })
+                .or_else(|| {
+                    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+                        .iter()
+                        .find_map(|fmt| NaiveDateTime::parse_from_str(s, fmt).ok())
+                        .map(TimeSpec::Wall)
+                })
+                .or_else(|| {
+                    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                        .ok()
+                        .and_then(|d| d.and_hms_opt(0, 0, 0))
+                        .map(TimeSpec::Wall)
+                }),
+        };
+        spec.ok_or_else(|| format!("invalid time '{}'", s))
+    }
+}
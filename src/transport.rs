@@ -0,0 +1,228 @@
+//! Reading from a source, once for each kind of source, however squealogd waits for them: with
+//! its main loop's poller by default, or with tokio for `--async-sources`. Either way, a source
+//! is read from once it's readable, without blocking, until it would block.
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixDatagram;
+
+/// The kinds of sources there are.
+#[derive(Debug)]
+pub enum LogTransport {
+    Udp(std::net::UdpSocket),
+    UnixDgram(UnixDatagram),
+    #[cfg(target_os = "freebsd")]
+    Klog(std::fs::File),
+}
+
+impl LogTransport {
+    pub fn transport(&self) -> &dyn Transport {
+        match self {
+            LogTransport::Udp(s) => s,
+            LogTransport::UnixDgram(s) => s,
+            #[cfg(target_os = "freebsd")]
+            LogTransport::Klog(f) => f,
+        }
+    }
+}
+
+/// What one read from a source came to.
+#[derive(Debug)]
+pub enum Received<'a> {
+    Data(&'a [u8]),
+    /// Nothing (more) to read for now.
+    Empty,
+    /// The descriptor can't be read from anymore, the source is done for.
+    Lost(io::Error),
+    /// Only this read failed, like with ECONNRESET after an ICMP error, or running out of
+    /// buffers.
+    Failed(io::Error),
+}
+
+impl<'a> Received<'a> {
+    pub fn from(read: io::Result<&'a [u8]>) -> Received<'a> {
+        match read {
+            Ok(data) => Received::Data(data),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Received::Empty,
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EBADF | libc::ENOTSOCK)) => {
+                Received::Lost(e)
+            }
+            Err(e) => Received::Failed(e),
+        }
+    }
+}
+
+/// Reads once from `transport` into `buf`, again if a signal interrupted it.
+pub fn recv<'a>(transport: &dyn Transport, buf: &'a mut [u8]) -> Received<'a> {
+    loop {
+        match transport.recv(buf) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Ok(len) => return Received::Data(&buf[..len]),
+            Err(e) => return Received::from(Err(e)),
+        }
+    }
+}
+
+pub trait Transport: AsRawFd + Send + Sync {
+    /// Reads one datagram (or what one read gets), `WouldBlock` if there's nothing.
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Another handle on the same socket, to read it from elsewhere.
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>>;
+}
+
+impl Transport for std::net::UdpSocket {
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        std::net::UdpSocket::recv(self, buf)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(std::net::UdpSocket::try_clone(self)?))
+    }
+}
+
+impl Transport for UnixDatagram {
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        UnixDatagram::recv(self, buf)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(UnixDatagram::try_clone(self)?))
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+impl Transport for std::fs::File {
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::io::Read;
+        (&*self).read(buf)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(std::fs::File::try_clone(self)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// Gives what it's told to, in order, and then would block.
+    struct Scripted(Mutex<VecDeque<io::Result<&'static [u8]>>>);
+
+    impl Scripted {
+        fn new(reads: Vec<io::Result<&'static [u8]>>) -> Scripted {
+            Scripted(Mutex::new(reads.into()))
+        }
+    }
+
+    impl AsRawFd for Scripted {
+        fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+            -1
+        }
+    }
+
+    impl Transport for Scripted {
+        fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+            let data = self
+                .0
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or_else(|| Err(io::ErrorKind::WouldBlock.into()))?;
+            buf[..data.len()].copy_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    fn os(errno: i32) -> io::Result<&'static [u8]> {
+        Err(io::Error::from_raw_os_error(errno))
+    }
+
+    /// What reading until it would block gets, with `!` for a failed read and `lost` at the
+    /// end if it was lost.
+    fn drain(transport: &dyn Transport) -> Vec<String> {
+        let mut buf = [0; 64];
+        let mut got = vec![];
+        loop {
+            match recv(transport, &mut buf) {
+                Received::Data(data) => got.push(String::from_utf8_lossy(data).into_owned()),
+                Received::Empty => return got,
+                Received::Lost(_) => {
+                    got.push("lost".to_owned());
+                    return got;
+                }
+                Received::Failed(_) => got.push("!".to_owned()),
+            }
+        }
+    }
+
+    #[test]
+    fn interrupted_reads_are_tried_again() {
+        let t = Scripted::new(vec![
+            os(libc::EINTR),
+            Ok(b"a"),
+            os(libc::EINTR),
+            os(libc::EINTR),
+        ]);
+        assert_eq!(drain(&t), ["a"]);
+    }
+
+    #[test]
+    fn a_reset_only_costs_that_read() {
+        let t = Scripted::new(vec![
+            Ok(b"a"),
+            os(libc::ECONNRESET),
+            os(libc::ENOBUFS),
+            Ok(b"b"),
+        ]);
+        assert_eq!(drain(&t), ["a", "!", "!", "b"]);
+    }
+
+    #[test]
+    fn a_bad_descriptor_loses_the_source() {
+        for errno in [libc::EBADF, libc::ENOTSOCK] {
+            let t = Scripted::new(vec![Ok(b"a"), os(errno), Ok(b"never read")]);
+            assert_eq!(drain(&t), ["a", "lost"]);
+        }
+    }
+
+    #[test]
+    fn the_other_sources_keep_going_without_a_lost_one() {
+        // Read in turns, one datagram each, like the main loop does with its events.
+        let mut sources = vec![
+            ("a", Scripted::new(vec![Ok(b"a1"), Ok(b"a2"), Ok(b"a3")])),
+            ("bad", Scripted::new(vec![Ok(b"bad1"), os(libc::EBADF)])),
+            (
+                "c",
+                Scripted::new(vec![os(libc::ECONNRESET), Ok(b"c1"), Ok(b"c2")]),
+            ),
+        ];
+        let mut buf = [0; 64];
+        let mut got = vec![];
+        while !sources.is_empty() {
+            let mut lost = vec![];
+            let mut empty = 0;
+            for (name, transport) in &sources {
+                match recv(transport, &mut buf) {
+                    Received::Data(data) => got.push(String::from_utf8_lossy(data).into_owned()),
+                    Received::Empty => empty += 1,
+                    Received::Lost(_) => lost.push(*name),
+                    Received::Failed(_) => got.push(format!("{}!", name)),
+                }
+            }
+            sources.retain(|(name, _)| !lost.contains(name));
+            if empty == sources.len() {
+                break;
+            }
+        }
+        assert_eq!(sources.len(), 2);
+        assert_eq!(got, ["a1", "bad1", "c!", "a2", "c1", "a3", "c2"]);
+    }
+}
@@ -0,0 +1,466 @@
+//! squealogd from start to finish: receiving a datagram on each kind of socket, storing it,
+//! and shutting down on SIGTERM.
+
+use std::net::UdpSocket;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+struct Daemon {
+    dir: PathBuf,
+    child: Child,
+    /// Whether the sockets were handed over, rather than bound by the daemon.
+    activated: bool,
+    /// What an activated daemon says it's ready (and then stopping) on.
+    notify: Option<UnixDatagram>,
+}
+
+impl Daemon {
+    fn start(name: &str, args: &[&str]) -> Daemon {
+        Daemon::configured(name, "", args)
+    }
+
+    /// Like `start`, with `config` (where `{dir}` is the daemon's directory) for `--config`.
+    fn configured(name: &str, config: &str, args: &[&str]) -> Daemon {
+        let dir = Daemon::dir(name);
+        let config = config.replace("{dir}", &dir.display().to_string());
+        std::fs::write(dir.join("squealogd.toml"), config).unwrap();
+        let mut cmd = Daemon::command(&dir);
+        cmd.arg(format!("--listen-unix=local={}", dir.join("log").display()))
+            .args(args);
+        let daemon = Daemon::spawn(dir, cmd, false);
+        daemon.wait_for("the socket", || daemon.socket().exists());
+        daemon
+    }
+
+    /// Like `start`, with the sockets handed over by socket activation instead: one at
+    /// `socket()` named local, and `udp` named remote. Returns once the daemon says it's ready.
+    fn activated(name: &str, args: &[&str], udp: &UdpSocket) -> Daemon {
+        let dir = Daemon::dir(name);
+        let unix = UnixDatagram::bind(dir.join("log")).unwrap();
+        let notify = UnixDatagram::bind(dir.join("notify")).unwrap();
+        notify
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let fds = [unix.as_raw_fd(), udp.as_raw_fd()];
+        let mut cmd = Daemon::command(&dir);
+        cmd.args(args)
+            .env("LISTEN_FDNAMES", "local:remote")
+            .env("LISTEN_FDS", "2")
+            .env("NOTIFY_SOCKET", dir.join("notify"));
+        unsafe {
+            cmd.pre_exec(move || {
+                // Out of the way first, in case one of them is already 3 or 4.
+                let mut high = [0; 2];
+                for (high, &fd) in high.iter_mut().zip(&fds) {
+                    *high = libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10);
+                }
+                for (i, &fd) in high.iter().enumerate() {
+                    if fd == -1 || libc::dup2(fd, 3 + i as RawFd) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        let mut daemon = Daemon::spawn(dir, cmd, true);
+        let mut buf = [0; 1024];
+        loop {
+            let len = notify.recv(&mut buf).expect("READY=1 within 10 seconds");
+            if buf[..len].split(|&b| b == b'\n').any(|l| l == b"READY=1") {
+                daemon.notify = Some(notify);
+                return daemon;
+            }
+        }
+    }
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("squealogd-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("squealogd.toml"), "").unwrap();
+        dir
+    }
+
+    fn command(dir: &Path) -> Command {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_squealogd"));
+        cmd.arg("--db")
+            .arg(dir.join("log.db"))
+            .arg("--config")
+            .arg(dir.join("squealogd.toml"))
+            .args(["--no-klog", "--keep-root"])
+            .env_remove("SQUEALOG_HTTP")
+            .env_remove("NOTIFY_SOCKET")
+            .env_remove("LISTEN_FDS")
+            .env_remove("LISTEN_FDNAMES")
+            .env_remove("LISTEN_PID");
+        cmd
+    }
+
+    fn spawn(dir: PathBuf, mut cmd: Command, activated: bool) -> Daemon {
+        let child = cmd.spawn().unwrap();
+        Daemon {
+            dir,
+            child,
+            activated,
+            notify: None,
+        }
+    }
+
+    fn socket(&self) -> PathBuf {
+        self.dir.join("log")
+    }
+
+    fn wait_for(&self, what: &str, mut done: impl FnMut() -> bool) {
+        let start = Instant::now();
+        while !done() {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "no {} after 10 seconds",
+                what
+            );
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// The messages stored from `socket`, as `appname: msg` (`-` for none).
+    fn stored(&self, socket: &str) -> Vec<String> {
+        let conn = rusqlite::Connection::open_with_flags(
+            self.dir.join("log.db"),
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )
+        .unwrap();
+        let mut stmt = conn
+            .prepare("SELECT coalesce(appname, '-'), msg FROM log WHERE socket = ? ORDER BY id")
+            .unwrap();
+        let rows = stmt
+            .query_map([socket], |r| {
+                Ok(format!(
+                    "{}: {}",
+                    r.get::<_, String>(0)?,
+                    r.get::<_, String>(1)?
+                ))
+            })
+            .unwrap();
+        rows.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    fn wait_stored(&self, socket: &str, n: usize) -> Vec<String> {
+        let mut stored = vec![];
+        self.wait_for(&format!("{} messages from {}", n, socket), || {
+            stored = self.stored(socket);
+            stored.len() >= n
+        });
+        stored
+    }
+
+    fn stop(mut self) {
+        unsafe { libc::kill(self.child.id() as libc::pid_t, libc::SIGTERM) };
+        let status = self.child.wait().unwrap();
+        assert!(status.success(), "squealogd exited with {}", status);
+        if !self.activated {
+            assert!(!self.socket().exists(), "the socket should be removed");
+        }
+        std::fs::remove_dir_all(&self.dir).unwrap();
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+fn free_udp_port() -> u16 {
+    UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn send_unix(path: &Path, msg: &str) {
+    UnixDatagram::unbound()
+        .unwrap()
+        .send_to(msg.as_bytes(), path)
+        .unwrap();
+}
+
+#[test]
+fn stores_what_it_receives() {
+    let port = free_udp_port();
+    let udp = format!("--listen-udp=remote=127.0.0.1:{}", port);
+    let daemon = Daemon::start("receive", &[&udp]);
+
+    send_unix(
+        &daemon.socket(),
+        "<14>Jun 11 22:14:15 app[42]: over the unix socket",
+    );
+    send_unix(
+        &daemon.socket(),
+        "<13>1 2024-01-02T03:04:05Z host other 7 ID1 [x@32473 k=\"v\"] with structured data",
+    );
+    let sent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sent.send_to(
+        b"<30>Jun 11 22:14:16 router dnsmasq[1187]: over UDP",
+        ("127.0.0.1", port),
+    )
+    .unwrap();
+
+    assert_eq!(
+        daemon.wait_stored("local", 2),
+        ["app: over the unix socket", "other: with structured data"]
+    );
+    assert_eq!(daemon.wait_stored("remote", 1), ["dnsmasq: over UDP"]);
+    daemon.stop();
+}
+
+#[test]
+fn parses_on_the_main_thread_too() {
+    let daemon = Daemon::start("main-thread", &["--parse-threads", "0"]);
+    for i in 0..100 {
+        send_unix(
+            &daemon.socket(),
+            &format!("<14>Jun 11 22:14:15 app[1]: message {}", i),
+        );
+    }
+    let stored = daemon.wait_stored("local", 100);
+    assert_eq!(stored[0], "app: message 0");
+    assert_eq!(stored[99], "app: message 99");
+    daemon.stop();
+}
+
+#[test]
+fn takes_json_from_programs() {
+    let daemon = Daemon::configured(
+        "json",
+        r#"
+[[listen]]
+name = "json"
+unix = "{dir}/json"
+parser = "json"
+
+[[listen]]
+name = "strict"
+unix = "{dir}/strict"
+parser = "json"
+strict = true
+"#,
+        &[],
+    );
+    let (json, strict) = (daemon.dir.join("json"), daemon.dir.join("strict"));
+    daemon.wait_for("the json sockets", || json.exists() && strict.exists());
+
+    for socket in [&json, &strict] {
+        send_unix(socket, "{\"msg\":\"hi\"}\n");
+        send_unix(socket, "not json");
+        send_unix(
+            socket,
+            r#"{"app": "backup", "severity": "warning", "msg": "disk almost full"}"#,
+        );
+    }
+
+    assert_eq!(
+        daemon.wait_stored("json", 3),
+        ["-: hi", "-: not json", "backup: disk almost full"]
+    );
+    // What doesn't parse is dropped, before what came after it.
+    assert_eq!(
+        daemon.wait_stored("strict", 2),
+        ["-: hi", "backup: disk almost full"]
+    );
+    daemon.stop();
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+#[test]
+fn runs_under_the_seccomp_filter() {
+    let port = free_udp_port();
+    let udp = format!("--listen-udp=remote=127.0.0.1:{}", port);
+    let daemon = Daemon::start("seccomp", &[&udp, "--seccomp"]);
+
+    send_unix(&daemon.socket(), "<14>Jun 11 22:14:15 app[42]: confined");
+    UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .send_to(
+            b"<30>Jun 11 22:14:16 router dnsmasq[1187]: confined too",
+            ("127.0.0.1", port),
+        )
+        .unwrap();
+
+    assert_eq!(daemon.wait_stored("local", 1), ["app: confined"]);
+    assert_eq!(daemon.wait_stored("remote", 1), ["dnsmasq: confined too"]);
+    daemon.stop();
+}
+
+#[test]
+fn health_probe_sees_its_message_stored() {
+    let daemon = Daemon::start("health", &[]);
+    // Without anything stored yet, it's critical however the probe goes.
+    send_unix(
+        &daemon.socket(),
+        "<14>Jun 11 22:14:15 app[42]: before the probe",
+    );
+    daemon.wait_stored("local", 1);
+    let out = Command::new(env!("CARGO_BIN_EXE_squealog"))
+        .arg("--db")
+        .arg(daemon.dir.join("log.db"))
+        .args(["health", "--probe", "--socket"])
+        .arg(daemon.socket())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{}", stdout);
+    assert!(stdout.starts_with("SQUEALOG OK - "), "{}", stdout);
+    assert!(stdout.contains("probe stored in"), "{}", stdout);
+    let stored = daemon.wait_stored("local", 2);
+    assert!(
+        stored[1].starts_with("squealog-health: probe "),
+        "{:?}",
+        stored
+    );
+    daemon.stop();
+}
+
+/// Undoes `connect` on a UDP socket, for it to receive from anyone again.
+fn disconnect(udp: &UdpSocket) {
+    let mut addr: libc::sockaddr = unsafe { std::mem::zeroed() };
+    addr.sa_family = libc::AF_UNSPEC as libc::sa_family_t;
+    let len = std::mem::size_of::<libc::sockaddr>() as libc::socklen_t;
+    assert_eq!(unsafe { libc::connect(udp.as_raw_fd(), &addr, len) }, 0);
+}
+
+#[test]
+fn keeps_going_after_what_it_cant_receive_or_store() {
+    // A port of its own, which (unlike one given by the system) it keeps when disconnected.
+    let udp = UdpSocket::bind(("127.0.0.1", free_udp_port())).unwrap();
+    let addr = udp.local_addr().unwrap();
+    let daemon = Daemon::activated("failures", &[], &udp);
+    send_unix(&daemon.socket(), "<14>Jun 11 22:14:15 app[1]: first");
+    daemon.wait_stored("local", 1);
+
+    // A constraint failing, for one message only.
+    let conn = rusqlite::Connection::open(daemon.dir.join("log.db")).unwrap();
+    conn.busy_timeout(Duration::from_secs(10)).unwrap();
+    conn.execute_batch(
+        "CREATE TRIGGER reject BEFORE INSERT ON log WHEN NEW.msg = 'rejected' \
+        BEGIN SELECT RAISE(ABORT, 'rejected'); END",
+    )
+    .unwrap();
+    send_unix(&daemon.socket(), "<14>Jun 11 22:14:15 app[1]: rejected");
+    UnixDatagram::unbound()
+        .unwrap()
+        .send_to(b"<14>Jun 11 22:14:15 app[1]: caf\xe9", daemon.socket())
+        .unwrap();
+    send_unix(&daemon.socket(), "<14>Jun 11 22:14:15 app[1]: after them");
+
+    // The daemon's socket (a copy of it) sending to a closed port, which makes the ICMP error
+    // coming back the daemon's to receive.
+    udp.connect(("127.0.0.1", free_udp_port())).unwrap();
+    udp.send(b"to nobody").unwrap();
+    daemon.wait_for("the receive error", || {
+        daemon
+            .stored("squealogd")
+            .iter()
+            .any(|m| m.contains("could not receive from remote"))
+    });
+    disconnect(&udp);
+    UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .send_to(
+            b"<30>Jun 11 22:14:16 router dnsmasq[1187]: after the error",
+            addr,
+        )
+        .unwrap();
+
+    assert_eq!(
+        daemon.wait_stored("local", 3),
+        ["app: first", "app: caf\u{fffd}", "app: after them"]
+    );
+    assert_eq!(
+        daemon.wait_stored("remote", 1),
+        ["dnsmasq: after the error"]
+    );
+    let internal = daemon.stored("squealogd");
+    assert!(
+        internal
+            .iter()
+            .any(|m| m.contains("could not store a message from local, dropped it")),
+        "{:?}",
+        internal
+    );
+    drop(conn);
+    daemon.stop();
+}
+
+#[test]
+fn redacts_before_any_output_sees_it() {
+    let relayed = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let verbatim = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for relay in [&relayed, &verbatim] {
+        relay
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+    }
+    let config = format!(
+        r#"
+[[rewrite]]
+regex = 'Bearer \S+'
+replace = "Bearer [redacted]"
+
+[[relay.udp]]
+to = "{}"
+
+[[relay.udp]]
+to = "{}"
+verbatim = true
+
+[[file]]
+select = "*.*"
+path = "{{dir}}/file.log"
+
+[[exec]]
+command = ["sh", "-c", "cat >> {{dir}}/exec.log"]
+"#,
+        relayed.local_addr().unwrap(),
+        verbatim.local_addr().unwrap()
+    );
+    let daemon = Daemon::configured("redact", &config, &[]);
+    send_unix(
+        &daemon.socket(),
+        "<14>Jun 11 22:14:15 app[42]: Authorization: Bearer s3cr3t-token sent",
+    );
+
+    let redacted = "Authorization: Bearer [redacted] sent";
+    assert_eq!(
+        daemon.wait_stored("local", 1),
+        [format!("app: {}", redacted)]
+    );
+    let mut buf = [0; 2048];
+    for (relay, what) in [(&relayed, "relayed"), (&verbatim, "relayed verbatim")] {
+        let len = relay.recv(&mut buf).unwrap();
+        let datagram = String::from_utf8_lossy(&buf[..len]);
+        assert!(datagram.ends_with(redacted), "{}: {}", what, datagram);
+    }
+    for name in ["file.log", "exec.log"] {
+        let path = daemon.dir.join(name);
+        let mut written = String::new();
+        daemon.wait_for(name, || {
+            written = std::fs::read_to_string(&path).unwrap_or_default();
+            written.ends_with('\n')
+        });
+        assert!(
+            written.trim_end().ends_with(redacted),
+            "{}: {}",
+            name,
+            written
+        );
+    }
+    daemon.stop();
+}
@@ -0,0 +1,110 @@
+//! Replays each capture in `tests/replay` through squealogd, and compares the rows it stores
+//! with the `.golden` file next to it. After a change that's meant to change them,
+//! `UPDATE_GOLDEN=1 cargo test --test replay` writes them anew, for the diff to show what did.
+
+use rusqlite::types::Value;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const COLUMNS: &[&str] = &[
+    "socket",
+    "facility",
+    "severity",
+    "time",
+    "recv_time",
+    "hostname",
+    "hostname_source",
+    "appname",
+    "pid",
+    "msgid",
+    "msg",
+    "sdata",
+];
+
+/// The rows, a `column: value` line for each one that isn't NULL. This machine's hostname
+/// shows as `(local)`, so that it's the same wherever the test runs.
+fn rows(db: &Path) -> String {
+    let conn = rusqlite::Connection::open(db).unwrap();
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM log ORDER BY id",
+            COLUMNS.join(", ")
+        ))
+        .unwrap();
+    let mut out = String::new();
+    let mut rows = stmt.query([]).unwrap();
+    while let Some(row) = rows.next().unwrap() {
+        let local = row.get_ref("hostname_source").unwrap().as_str_or_null() == Ok(Some("local"));
+        for (i, column) in COLUMNS.iter().enumerate() {
+            let value = match row.get::<_, Value>(i).unwrap() {
+                Value::Null => continue,
+                _ if *column == "hostname" && local => "(local)".to_owned(),
+                Value::Integer(n) => n.to_string(),
+                Value::Text(s) => s,
+                other => panic!("{} is {:?}", column, other),
+            };
+            out += &format!("{}: {}\n", column, value);
+        }
+        out += "\n";
+    }
+    out
+}
+
+fn replay(capture: &Path) -> String {
+    let dir = std::env::temp_dir().join(format!(
+        "squealog-replay-{}-{}",
+        std::process::id(),
+        capture.file_stem().unwrap().to_string_lossy()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("capture")).unwrap();
+    std::fs::copy(
+        capture,
+        dir.join("capture").join(capture.file_name().unwrap()),
+    )
+    .unwrap();
+    let db = dir.join("replayed.db");
+    let status = Command::new(env!("CARGO_BIN_EXE_squealogd"))
+        .arg("--replay")
+        .arg(dir.join("capture"))
+        .arg("--db")
+        .arg(&db)
+        .arg("--config")
+        .arg("/dev/null")
+        .arg("--keep-root")
+        // RFC 3164 timestamps are in local time.
+        .env("TZ", "UTC")
+        .env_remove("SQUEALOG_HTTP")
+        .env_remove("NOTIFY_SOCKET")
+        .status()
+        .unwrap();
+    assert!(status.success(), "squealogd --replay failed: {}", status);
+    let rows = rows(&db);
+    std::fs::remove_dir_all(&dir).unwrap();
+    rows
+}
+
+#[test]
+fn captures_replay_to_the_golden_rows() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/replay");
+    let mut captures: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "cap"))
+        .collect();
+    captures.sort();
+    assert!(captures.len() >= 3);
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut wrong = vec![];
+    for capture in &captures {
+        let got = replay(capture);
+        let golden = capture.with_extension("golden");
+        if update {
+            std::fs::write(&golden, &got).unwrap();
+        } else if std::fs::read_to_string(&golden).ok().as_deref() != Some(&got[..]) {
+            eprintln!("{:?} now comes out as:\n{}", capture, got);
+            wrong.push(golden);
+        }
+    }
+    assert!(wrong.is_empty(), "not the same as {:?}", wrong);
+}